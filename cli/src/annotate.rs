@@ -0,0 +1,185 @@
+//! Annotated-shell explanation renderer
+//!
+//! The model returns the command split into segments, one per line, each
+//! followed by ` # explanation`. This module lays the segments back out as
+//! valid shell: every pipeline stage gets its own line (a trailing comment
+//! after `|`, `&&`, `||` or `;` is legal), and per-flag notes become aligned
+//! comment lines above the stage they belong to. Pasting the output into a
+//! shell still runs the original command.
+
+/// Operators that end a pipeline stage
+const OPERATORS: &[&str] = &["&&", "||", "|", ";"];
+
+/// One piece of the command with its optional explanation
+struct Segment {
+    code: String,
+    note: Option<String>,
+}
+
+/// Segments between two operators (`find . -type f` in `find . -type f | wc -l`)
+#[derive(Default)]
+struct Stage {
+    segments: Vec<Segment>,
+    operator: Option<String>,
+}
+
+/// Render the model's segment list as annotated, runnable shell
+pub fn render(code: &str) -> String {
+    let stages = parse_stages(code);
+    if stages.is_empty() {
+        return code.trim_end().to_string();
+    }
+
+    // Build each stage line first so trailing comments can be aligned
+    let stage_lines: Vec<String> = stages
+        .iter()
+        .enumerate()
+        .map(|(i, stage)| {
+            let mut line = if i == 0 { String::new() } else { "  ".to_string() };
+            let words: Vec<&str> = stage.segments.iter().map(|s| s.code.as_str()).collect();
+            line.push_str(&words.join(" "));
+            if let Some(op) = &stage.operator {
+                line.push(' ');
+                line.push_str(op);
+            }
+            line
+        })
+        .collect();
+    let comment_column = stage_lines.iter().map(|l| l.chars().count()).max().unwrap_or(0) + 2;
+
+    let mut result = String::new();
+    for (stage, line) in stages.iter().zip(&stage_lines) {
+        // Flag notes go above the stage, aligned among themselves
+        let flags: Vec<&Segment> =
+            stage.segments.iter().skip(1).filter(|s| s.note.is_some()).collect();
+        let flag_width = flags.iter().map(|s| s.code.chars().count()).max().unwrap_or(0);
+        for flag in flags {
+            result.push_str(&format!(
+                "#   {:<width$}  {}\n",
+                flag.code,
+                flag.note.as_deref().unwrap_or_default(),
+                width = flag_width
+            ));
+        }
+
+        result.push_str(line);
+        if let Some(note) = stage.segments.first().and_then(|s| s.note.as_deref()) {
+            let padding = comment_column - line.chars().count();
+            result.push_str(&format!("{}# {}", " ".repeat(padding), note));
+        }
+        result.push('\n');
+    }
+
+    result.trim_end().to_string()
+}
+
+/// Split a line of the model's annotated code into the code and its
+/// trailing `# note`. A " # " inside quotes (`grep "a # b"`) is code.
+pub fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let bytes = line.as_bytes();
+    let mut quote = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        match (quote, b) {
+            (Some(b'\''), b'\'') | (Some(b'"'), b'"') => quote = None,
+            (Some(b'"'), b'\\') | (None, b'\\') => i += 1,
+            (Some(_), _) => {}
+            (None, b'\'' | b'"') => quote = Some(b),
+            (None, b'#') if line[..i].ends_with(' ') && line[i + 1..].starts_with(' ') => {
+                return (&line[..i - 1], Some(line[i + 1..].trim()));
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    (line, None)
+}
+
+fn parse_stages(code: &str) -> Vec<Stage> {
+    let mut stages = Vec::new();
+    let mut current = Stage::default();
+
+    for line in code.lines() {
+        let (segment, note) = split_comment(line);
+        let note = note.map(str::to_string);
+        // Line continuations from the model are layout, not part of the command
+        let mut segment = segment.trim().trim_end_matches('\\').trim();
+        if segment.is_empty() {
+            continue;
+        }
+
+        // Operator at the start closes the previous stage: "| sort -h"
+        if let Some(op) = OPERATORS.iter().find(|op| segment.starts_with(**op)) {
+            if !current.segments.is_empty() {
+                current.operator = Some(op.to_string());
+                stages.push(std::mem::take(&mut current));
+            }
+            segment = segment[op.len()..].trim_start();
+        }
+
+        // Operator at the end closes this stage: "-size +100M |"
+        let trailing = OPERATORS
+            .iter()
+            .find(|op| segment.ends_with(**op) && !segment.ends_with(&format!("\\{}", op)));
+        let body = match trailing {
+            Some(op) => segment[..segment.len() - op.len()].trim_end(),
+            None => segment,
+        };
+
+        if !body.is_empty() {
+            current.segments.push(Segment {
+                code: body.to_string(),
+                note: note.clone(),
+            });
+        }
+        if let Some(op) = trailing {
+            if !current.segments.is_empty() {
+                current.operator = Some(op.to_string());
+                stages.push(std::mem::take(&mut current));
+            }
+        }
+    }
+
+    if !current.segments.is_empty() {
+        stages.push(current);
+    }
+    stages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_pipeline_stays_runnable() {
+        let code = "find . # search the current directory\n\
+                    -type f # regular files only\n\
+                    -size +100M | # larger than 100 MB\n\
+                    sort -hr # largest first\n\
+                    | head -5 # keep the top five";
+        let rendered = render(code);
+        let command: Vec<&str> = rendered
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| l.split("  #").next().unwrap().trim())
+            .collect();
+        assert_eq!(command.join(" "), "find . -type f -size +100M | sort -hr | head -5");
+        assert!(rendered.contains("#   -type f      regular files only"));
+        assert!(rendered.contains("#   -size +100M  larger than 100 MB"));
+    }
+
+    #[test]
+    fn test_split_comment_skips_quoted_hash() {
+        assert_eq!(split_comment("grep \"a # b\" log # lines with it"), ("grep \"a # b\" log", Some("lines with it")));
+        assert_eq!(split_comment("echo 'x # y'"), ("echo 'x # y'", None));
+        assert_eq!(split_comment("sort -n # don't mind the quote"), ("sort -n", Some("don't mind the quote")));
+    }
+
+    #[test]
+    fn test_render_aligns_stage_comments() {
+        let rendered = render("ls -la && # list\ncd /tmp # go to tmp");
+        let columns: Vec<usize> = rendered.lines().map(|l| l.find("# ").unwrap()).collect();
+        assert_eq!(columns[0], columns[1]);
+    }
+}
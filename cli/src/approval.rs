@@ -0,0 +1,300 @@
+//! Webhook approval for risky commands
+//!
+//! With an `[approval]` webhook (in config.toml, or in the machine policy,
+//! which wins) every command the local safety rules don't mark SAFE is
+//! POSTed to it before running, and held until an approver answers:
+//!
+//! ```json
+//! {"id": "9f2c…", "level": "caution", "query": "…", "command": "…", "user": "…", "host": "…", "cwd": "…"}
+//! ```
+//!
+//! The webhook, and then `poll_url` (default `<webhook>/<id>`) every few
+//! seconds while the answer is "pending", replies
+//!
+//! ```json
+//! {"status": "approved", "token": "…"}
+//! {"status": "denied", "reason": "…"}
+//! {"status": "pending", "poll_url": "…"}
+//! ```
+//!
+//! The model's own SAFE label doesn't count here; `[safety] safe` patterns
+//! are how routine commands skip approval. An approval needs a token. While
+//! waiting, the user can press `o` to run anyway (unless `allow_override =
+//! false`), and the webhook is told with status "overridden". A webhook that
+//! can't be reached, answers anything else or doesn't decide within
+//! `timeout_secs` means the command doesn't run.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::audit;
+use crate::logs;
+use crate::policy::{SafetyRules, Verdict};
+
+/// How long to wait for a decision unless configured
+const DEFAULT_TIMEOUT_SECS: u64 = 600;
+
+/// Pause between polls while the answer is "pending"
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Webhook settings and the rules deciding what needs approval, set once at startup
+static APPROVAL: OnceLock<(ApprovalConfig, SafetyRules)> = OnceLock::new();
+
+/// The `[approval]` section of config.toml or the policy file
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApprovalConfig {
+    /// URL commands are POSTed to; unset turns approval off
+    pub webhook: Option<String>,
+    /// Extra headers, e.g. the approval service's Authorization
+    pub headers: BTreeMap<String, String>,
+    /// Give up, and don't run the command, after this many seconds
+    pub timeout_secs: Option<u64>,
+    /// Whether `o` runs the command without an answer (default true)
+    pub allow_override: Option<bool>,
+}
+
+/// Why a command may run
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    /// The rules call it SAFE, or approval is off
+    NotNeeded,
+    /// The approver's token
+    Approved(String),
+    /// The user pressed `o`
+    Overridden,
+}
+
+#[derive(Serialize)]
+struct ApprovalRequest<'a> {
+    id: &'a str,
+    level: &'a str,
+    query: &'a str,
+    command: &'a str,
+    user: String,
+    host: String,
+    cwd: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Reply {
+    status: String,
+    token: Option<String>,
+    reason: Option<String>,
+    poll_url: Option<String>,
+}
+
+/// Require approval for the rest of this process (a no-op without a webhook)
+pub fn enable(config: ApprovalConfig, rules: SafetyRules) {
+    if config.webhook.is_some() {
+        let _ = APPROVAL.set((config, rules));
+    }
+}
+
+/// Hold `command` until it's approved. Err says why it must not run.
+pub fn require(query: &str, command: &str) -> Result<Decision, String> {
+    let Some((config, rules)) = APPROVAL.get() else {
+        return Ok(Decision::NotNeeded);
+    };
+    let Some(level) = level(rules, command) else {
+        return Ok(Decision::NotNeeded);
+    };
+    let webhook = config.webhook.as_deref().unwrap_or_default();
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(5))
+        .timeout_read(Duration::from_secs(30))
+        .build();
+    let with_headers = |mut request: ureq::Request| {
+        for (name, value) in &config.headers {
+            request = request.set(name, value);
+        }
+        request
+    };
+
+    let id = request_id(query, command);
+    let request = ApprovalRequest {
+        id: &id,
+        level,
+        query: crate::prompt::strip_context(query),
+        command,
+        user: audit::current_user(),
+        host: audit::hostname(),
+        cwd: std::env::current_dir()
+            .map(|d| d.display().to_string())
+            .unwrap_or_default(),
+    };
+    let can_override = config.allow_override.unwrap_or(true) && can_read_keys();
+    let keys = if can_override {
+        " (o: run anyway, Esc: cancel)"
+    } else {
+        ""
+    };
+    eprintln!("Waiting for approval of this {} command{}", level, keys);
+
+    let timeout_secs = config.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS);
+    let deadline = Instant::now() + Duration::from_secs(timeout_secs);
+    let mut poll_url = format!("{}/{}", webhook.trim_end_matches('/'), id);
+    let mut reply = fetch(with_headers(agent.post(webhook)).send_json(&request))?;
+    loop {
+        if let Some(token) = decide(&reply)? {
+            return Ok(Decision::Approved(token));
+        }
+        if Instant::now() >= deadline {
+            return Err(format!("No approval after {}s", timeout_secs));
+        }
+        if can_override && wait_for_override(POLL_INTERVAL)? {
+            let overridden = serde_json::json!({ "id": id, "status": "overridden", "command": command });
+            // Best effort: the override stands even if the webhook doesn't hear of it
+            let _ = with_headers(agent.post(webhook)).send_json(overridden);
+            return Ok(Decision::Overridden);
+        } else if !can_override {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        if let Some(url) = reply.poll_url.take().filter(|u| !u.is_empty()) {
+            poll_url = url;
+        }
+        reply = fetch(with_headers(agent.get(&poll_url)).call())?;
+    }
+}
+
+/// "caution" or "danger" for commands that need approval, None for SAFE ones
+fn level(rules: &SafetyRules, command: &str) -> Option<&'static str> {
+    match rules.check(command) {
+        Verdict::Safe => None,
+        Verdict::Blocked | Verdict::Denied | Verdict::Danger => Some("danger"),
+        Verdict::Confirm | Verdict::Unknown => Some("caution"),
+    }
+}
+
+/// The token once approved, None while pending
+fn decide(reply: &Reply) -> Result<Option<String>, String> {
+    match reply.status.as_str() {
+        "approved" => match reply.token.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+            Some(token) => Ok(Some(token.to_string())),
+            None => Err("Approval came without a token".to_string()),
+        },
+        "denied" => Err(match reply.reason.as_deref() {
+            Some(reason) => format!("Denied by the approver: {}", reason),
+            None => "Denied by the approver".to_string(),
+        }),
+        "pending" => Ok(None),
+        other => Err(format!("Unexpected approval status {:?}", other)),
+    }
+}
+
+fn fetch(response: Result<ureq::Response, ureq::Error>) -> Result<Reply, String> {
+    let response = response.map_err(|e| format!("Approval webhook failed: {}", e))?;
+    response
+        .into_json()
+        .map_err(|e| format!("Invalid approval reply: {}", e))
+}
+
+/// Unique per request, so approvers can't confuse two runs of one command
+fn request_id(query: &str, command: &str) -> String {
+    let mut hasher = Sha256::new();
+    for field in [
+        logs::now().to_string().as_str(),
+        &std::process::id().to_string(),
+        query,
+        command,
+    ] {
+        hasher.update((field.len() as u64).to_le_bytes());
+        hasher.update(field.as_bytes());
+    }
+    hasher.finalize()[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(feature = "tui")]
+fn can_read_keys() -> bool {
+    use std::io::IsTerminal;
+    std::io::stdin().is_terminal()
+}
+
+#[cfg(not(feature = "tui"))]
+fn can_read_keys() -> bool {
+    false
+}
+
+/// Wait up to `timeout` for `o` (true) or Esc/Ctrl+C (Err)
+#[cfg(feature = "tui")]
+fn wait_for_override(timeout: Duration) -> Result<bool, String> {
+    use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+    use crossterm::terminal;
+
+    terminal::enable_raw_mode().map_err(|e| e.to_string())?;
+    let deadline = Instant::now() + timeout;
+    let mut result = Ok(false);
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        if !event::poll(left).unwrap_or(false) {
+            break;
+        }
+        match event::read() {
+            Ok(Event::Key(KeyEvent {
+                kind: KeyEventKind::Release,
+                ..
+            })) => {}
+            Ok(Event::Key(KeyEvent {
+                code: KeyCode::Char('o'),
+                ..
+            })) => {
+                result = Ok(true);
+                break;
+            }
+            Ok(Event::Key(KeyEvent { code: KeyCode::Esc, .. }))
+            | Ok(Event::Key(KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            })) => {
+                result = Err("Cancelled while waiting for approval".to_string());
+                break;
+            }
+            _ => {}
+        }
+    }
+    terminal::disable_raw_mode().ok();
+    result
+}
+
+#[cfg(not(feature = "tui"))]
+fn wait_for_override(_timeout: Duration) -> Result<bool, String> {
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{Policy, SafetyConfig};
+
+    #[test]
+    fn test_level_and_decide() {
+        let config = SafetyConfig {
+            safe: vec![r"^git status$".to_string()],
+            ..Default::default()
+        };
+        let rules = SafetyRules::new(&config, &Policy::default()).unwrap();
+        assert_eq!(level(&rules, "git status"), None);
+        assert_eq!(level(&rules, "git push"), Some("caution"));
+        assert_eq!(level(&rules, "rm -rf ~"), Some("danger"));
+
+        let reply = |json: &str| serde_json::from_str::<Reply>(json).unwrap();
+        assert_eq!(
+            decide(&reply(r#"{"status": "approved", "token": "abc"}"#)),
+            Ok(Some("abc".to_string()))
+        );
+        assert_eq!(
+            decide(&reply(r#"{"status": "pending", "poll_url": "https://x/1"}"#)),
+            Ok(None)
+        );
+        assert!(decide(&reply(r#"{"status": "approved", "token": " "}"#)).is_err());
+        assert_eq!(
+            decide(&reply(r#"{"status": "denied", "reason": "change freeze"}"#)).unwrap_err(),
+            "Denied by the approver: change freeze"
+        );
+        assert!(decide(&reply(r#"{"status": "maybe"}"#)).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Files referenced in a query with `@path`
+//!
+//! `slashcmd convert @schema.sql to a postgres migration` sends the start of
+//! schema.sql along with the query. Nothing is read or sent without a yes
+//! for each file, so references are only expanded on a terminal. Tokens that
+//! don't name a file (`@types/node`, `user@host`) are left alone.
+
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::highlight::dim;
+
+/// Bytes of each file sent; the model only needs its shape
+const MAX_FILE_BYTES: usize = 16 * 1024;
+
+/// A file the query refers to
+#[derive(Debug, PartialEq)]
+pub struct Reference {
+    /// The token as typed, `@` included
+    pub token: String,
+    pub path: PathBuf,
+}
+
+/// The `@path` tokens in `query` that name existing files
+pub fn references(query: &str) -> Vec<Reference> {
+    let mut found: Vec<Reference> = Vec::new();
+    for word in query.split_whitespace() {
+        let Some(path) = word.strip_prefix('@') else {
+            continue;
+        };
+        // "@notes.txt," or "(@a.sql)" as written in a sentence
+        let path = path.trim_end_matches([',', ';', ':', '!', '?', ')', '.']);
+        if path.is_empty() || !Path::new(path).is_file() || found.iter().any(|r| r.path == Path::new(path)) {
+            continue;
+        }
+        found.push(Reference { token: format!("@{}", path), path: PathBuf::from(path) });
+    }
+    found
+}
+
+/// The first `MAX_FILE_BYTES` of a file, and its full size
+fn read_start(path: &Path) -> io::Result<(Vec<u8>, u64)> {
+    let file = fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut bytes = Vec::new();
+    file.take(MAX_FILE_BYTES as u64).read_to_end(&mut bytes)?;
+    Ok((bytes, size))
+}
+
+/// The context sent for one file, given its start and full size: the
+/// start, and how much was left out. None for binary files.
+fn file_context(path: &Path, shown: &[u8], size: u64) -> Option<String> {
+    if shown.contains(&0) {
+        return None;
+    }
+    let text = String::from_utf8_lossy(shown);
+    let cut = if (shown.len() as u64) < size {
+        format!(" (the first {} KB of {} KB)", shown.len() / 1024, size.div_ceil(1024))
+    } else {
+        String::new()
+    };
+    Some(format!("Contents of {}{}:\n```\n{}\n```", path.display(), cut, text.trim_end()))
+}
+
+/// `query` with its `@path` references written as plain paths and the
+/// contents of each file the user agrees to send added as context
+pub fn expand(query: String) -> String {
+    let references = references(&query);
+    if references.is_empty() {
+        return query;
+    }
+    let ask = io::stdin().is_terminal() && io::stderr().is_terminal();
+    let mut expanded = query;
+    let mut contexts: Vec<String> = Vec::new();
+    for reference in references {
+        expanded = expanded.replace(&reference.token, &reference.path.display().to_string());
+        let name = reference.path.display();
+        if !ask {
+            eprintln!("{}", dim(&format!("Not sending {}: attaching files needs a yes on a terminal.", name)));
+            continue;
+        }
+        let (bytes, size) = match read_start(&reference.path) {
+            Ok(read) => read,
+            Err(e) => {
+                eprintln!("{}", dim(&format!("Can't read {}: {}", name, e)));
+                continue;
+            }
+        };
+        let Some(context) = file_context(&reference.path, &bytes, size) else {
+            eprintln!("{}", dim(&format!("Not sending {}: it isn't text.", name)));
+            continue;
+        };
+        eprint!("Send {} ({} KB) to the model with this query? [y/N] ", name, bytes.len().div_ceil(1024));
+        io::stderr().flush().ok();
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer).ok();
+        if answer.trim().eq_ignore_ascii_case("y") {
+            contexts.push(context);
+        }
+    }
+    for context in contexts {
+        expanded = crate::prompt::with_context(&expanded, &context);
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_references() {
+        let dir = std::env::temp_dir().join(format!("slashcmd-attach-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let schema = dir.join("schema.sql");
+        fs::write(&schema, "create table users (id int);\n").unwrap();
+
+        let query = format!("convert @{}, to postgres for me@example.com @types/node @{0}", schema.display());
+        let found = references(&query);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, schema);
+
+        let (bytes, size) = read_start(&schema).unwrap();
+        let context = file_context(&schema, &bytes, size).unwrap();
+        assert!(context.ends_with("```\ncreate table users (id int);\n```"));
+        // The file went with one request; the logs keep only the words
+        let logged = crate::prompt::with_context("convert schema.sql", &context);
+        let entry = crate::logs::create_entry(&logged, "psql", None, &crate::ipc::ExplainStyle::Human, None);
+        assert_eq!(entry.query, "convert schema.sql");
+        fs::write(&schema, vec![b'x'; MAX_FILE_BYTES + 1]).unwrap();
+        let (bytes, size) = read_start(&schema).unwrap();
+        assert_eq!(bytes.len(), MAX_FILE_BYTES);
+        assert!(file_context(&schema, &bytes, size).unwrap().contains("(the first 16 KB of 17 KB)"));
+        assert_eq!(file_context(&schema, b"\x7fELF\0\0", 6), None);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,213 @@
+//! Tamper-evident audit log
+//!
+//! An append-only JSON-lines file, separate from the per-command logs, that
+//! records every generated and executed command. Each entry stores the hash
+//! of the previous one, so editing or deleting a line breaks the chain and
+//! `slashcmd audit` reports where.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::logs;
+
+/// `prev` of the first entry in a chain
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Audit file path, set once at startup when auditing is on
+static AUDIT_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// What happened to a command
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditEvent {
+    Generated,
+    Executed,
+}
+
+/// One line of the audit log
+#[derive(Serialize, Deserialize)]
+struct AuditEntry {
+    timestamp: u64,
+    user: String,
+    hostname: String,
+    event: AuditEvent,
+    query: String,
+    command: String,
+    /// Hash of the previous entry
+    prev: String,
+    /// SHA-256 over `prev` and every field above
+    hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        for field in [
+            self.prev.as_str(),
+            &self.timestamp.to_string(),
+            &self.user,
+            &self.hostname,
+            match self.event {
+                AuditEvent::Generated => "generated",
+                AuditEvent::Executed => "executed",
+            },
+            &self.query,
+            &self.command,
+        ] {
+            // Length-prefix each field so shifting text between fields changes the hash
+            hasher.update((field.len() as u64).to_le_bytes());
+            hasher.update(field.as_bytes());
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// Default audit file, next to the command logs
+pub fn default_audit_file() -> PathBuf {
+    logs::logs_dir()
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("audit.log")
+}
+
+/// Turn auditing on for this process
+pub fn enable(path: PathBuf) {
+    let _ = AUDIT_FILE.set(path);
+}
+
+/// Append an entry to the chain. A no-op when auditing is off.
+pub fn record(event: AuditEvent, query: &str, command: &str) -> Result<(), String> {
+    let Some(path) = AUDIT_FILE.get() else {
+        return Ok(());
+    };
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let prev = last_hash(path)?;
+
+    let mut entry = AuditEntry {
+        timestamp: logs::now(),
+        user: current_user(),
+        hostname: hostname(),
+        event,
+        query: query.to_string(),
+        command: command.to_string(),
+        prev,
+        hash: String::new(),
+    };
+    entry.hash = entry.compute_hash();
+
+    let line = serde_json::to_string(&entry).map_err(|e| format!("Failed to encode audit entry: {}", e))?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open audit log {}: {}", path.display(), e))?;
+    writeln!(file, "{}", line).map_err(|e| format!("Failed to write audit log: {}", e))
+}
+
+/// Check every entry's hash and link. Returns the number of entries.
+pub fn verify(path: &Path) -> Result<usize, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+
+    let mut prev = GENESIS.to_string();
+    for (i, line) in content.lines().enumerate() {
+        let entry: AuditEntry = serde_json::from_str(line)
+            .map_err(|e| format!("Line {}: not a valid audit entry ({})", i + 1, e))?;
+        if entry.prev != prev {
+            return Err(format!("Line {}: chain broken (an entry before it was changed or removed)", i + 1));
+        }
+        if entry.compute_hash() != entry.hash {
+            return Err(format!("Line {}: entry was modified", i + 1));
+        }
+        prev = entry.hash;
+    }
+    Ok(content.lines().count())
+}
+
+fn last_hash(path: &Path) -> Result<String, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => match content.lines().last() {
+            Some(line) => serde_json::from_str::<AuditEntry>(line)
+                .map(|e| e.hash)
+                .map_err(|e| format!("Audit log {} is corrupt: {}", path.display(), e)),
+            None => Ok(GENESIS.to_string()),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(GENESIS.to_string()),
+        Err(e) => Err(format!("Failed to read audit log {}: {}", path.display(), e)),
+    }
+}
+
+pub fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+pub fn hostname() -> String {
+    if let Ok(name) = fs::read_to_string("/etc/hostname") {
+        if !name.trim().is_empty() {
+            return name.trim().to_string();
+        }
+    }
+    std::process::Command::new("hostname")
+        .output()
+        .ok()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(prev: &str, command: &str) -> AuditEntry {
+        let mut entry = AuditEntry {
+            timestamp: 1700000000,
+            user: "alice".to_string(),
+            hostname: "box".to_string(),
+            event: AuditEvent::Executed,
+            query: "clean up".to_string(),
+            command: command.to_string(),
+            prev: prev.to_string(),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+        entry
+    }
+
+    #[test]
+    fn test_verify_detects_tampering() {
+        let path = std::env::temp_dir().join(format!("slashcmd-audit-{}.log", std::process::id()));
+        let first = entry(GENESIS, "ls");
+        let second = entry(&first.hash, "rm -rf build");
+        let lines = [&first, &second]
+            .iter()
+            .map(|e| serde_json::to_string(e).unwrap())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        fs::write(&path, &lines).unwrap();
+        assert_eq!(verify(&path), Ok(2));
+
+        fs::write(&path, lines.replace("rm -rf build", "rm -rf tmp")).unwrap();
+        assert!(verify(&path).unwrap_err().starts_with("Line 2"));
+
+        fs::remove_file(&path).ok();
+    }
+}
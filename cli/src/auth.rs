@@ -4,30 +4,63 @@
 
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
 use std::path::PathBuf;
 use std::time::Duration;
 
+use crate::config::config_dir;
+use crate::error::{SlashcmdError, EXIT_NOT_LOGGED_IN, EXIT_QUOTA_EXHAUSTED};
+
 const API_URL: &str = "https://groq-warm-proxy.gozdak.workers.dev";
 const POLL_INTERVAL: Duration = Duration::from_secs(2);
 const POLL_TIMEOUT: Duration = Duration::from_secs(300); // 5 minutes
 
+/// Don't trust a cached status older than this (other machines may have used the quota)
+const STATUS_CACHE_MAX_AGE_SECS: u64 = 24 * 60 * 60;
+/// Show remaining quota before requests once this few are left
+pub const LOW_QUOTA: i32 = 10;
+
 /// Stored authentication data
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StoredAuth {
     pub token: String,
     pub user: String,
     pub github_id: String,
+    /// Organization slug when logged in to a team plan
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
 }
 
 /// User status from API
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UserStatus {
-    pub user: String,
+    /// User id, e.g. "github:123"
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
     pub tier: String,
     pub usage: i32,
     pub limit: i32,
     pub remaining: i32,
+    #[serde(default)]
+    pub org: Option<OrgStatus>,
+}
+
+/// Team plan details
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrgStatus {
+    pub slug: String,
+    pub name: String,
+    pub tier: String,
+    pub seats: u32,
+    pub used: u32,
+}
+
+/// Org join response
+#[derive(Deserialize)]
+struct OrgJoinResponse {
+    org: OrgStatus,
 }
 
 /// Auth start response
@@ -35,6 +68,16 @@ pub struct UserStatus {
 struct AuthStartResponse {
     session_id: String,
     auth_url: String,
+    /// Device code for authorizing from another machine
+    user_code: Option<String>,
+    verification_url: Option<String>,
+}
+
+/// Billing link response
+#[derive(Deserialize)]
+struct BillingResponse {
+    url: String,
+    tier: String,
 }
 
 /// Auth poll response
@@ -48,11 +91,17 @@ struct AuthPollResponse {
     error: Option<String>,
 }
 
-/// Get the config directory for slashcmd
-fn config_dir() -> PathBuf {
-    dirs::config_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("slashcmd")
+/// Last `/status` response, updated with the usage each `/command` reports,
+/// cached for the quota pre-flight check
+#[derive(Serialize, Deserialize)]
+struct CachedStatus {
+    fetched_at: u64,
+    status: UserStatus,
+}
+
+/// Get the status cache path
+fn status_cache_file() -> PathBuf {
+    config_dir().join("status-cache.json")
 }
 
 /// Get the auth file path
@@ -75,6 +124,8 @@ fn save_auth(auth: &StoredAuth) -> Result<(), String> {
     let path = auth_file();
     let json = serde_json::to_string_pretty(auth).unwrap();
     fs::write(&path, json).map_err(|e| format!("Failed to save auth: {}", e))?;
+    // A new login (or org) invalidates the cached quota
+    let _ = fs::remove_file(status_cache_file());
 
     // Set restrictive permissions on the auth file (Unix only)
     #[cfg(unix)]
@@ -91,12 +142,18 @@ fn save_auth(auth: &StoredAuth) -> Result<(), String> {
 pub fn delete_auth() {
     let path = auth_file();
     let _ = fs::remove_file(path);
+    let _ = fs::remove_file(status_cache_file());
 }
 
-/// Start the login flow
-pub fn login() -> Result<(), String> {
+/// Start the login flow, then join `org`'s team plan if given. Without a
+/// local browser (SSH, `--no-browser`) the user authorizes from any device
+/// with the printed code.
+pub fn login(org: Option<&str>, no_browser: bool) -> Result<(), SlashcmdError> {
     // Check if already logged in
     if let Some(auth) = load_auth() {
+        if let Some(org) = org {
+            return join_org(auth, org);
+        }
         println!("Already logged in as {}.", auth.user);
         println!("Use 'slashcmd logout' to sign out first.");
         return Ok(());
@@ -113,33 +170,34 @@ pub fn login() -> Result<(), String> {
     let start_resp: AuthStartResponse = agent
         .post(&format!("{}/auth/start", API_URL))
         .call()
-        .map_err(|e| format!("Failed to start auth: {}", e))?
+        .map_err(SlashcmdError::from_ureq)?
         .into_json()
-        .map_err(|e| format!("Invalid response: {}", e))?;
-
-    // Step 2: Open browser
-    println!("Opening browser for authentication...");
-    println!("If browser doesn't open, visit:");
-    println!("  {}\n", start_resp.auth_url);
-
-    // Try to open browser
-    #[cfg(target_os = "macos")]
-    {
-        let _ = std::process::Command::new("open")
-            .arg(&start_resp.auth_url)
-            .spawn();
-    }
-    #[cfg(target_os = "linux")]
-    {
-        let _ = std::process::Command::new("xdg-open")
-            .arg(&start_resp.auth_url)
-            .spawn();
-    }
-    #[cfg(target_os = "windows")]
-    {
-        let _ = std::process::Command::new("cmd")
-            .args(["/c", "start", &start_resp.auth_url])
-            .spawn();
+        .map_err(parse_error)?;
+
+    // Step 2: Open browser, or show the device code
+    let device = start_resp.user_code.as_ref().zip(start_resp.verification_url.as_ref());
+    if no_browser || !has_local_browser() {
+        match device {
+            Some((code, url)) => {
+                println!("On any device, open:");
+                println!("  {}", url);
+                println!("and enter the code:");
+                println!("  {}\n", code);
+            }
+            None => {
+                println!("Open this URL on any device to authenticate:");
+                println!("  {}\n", start_resp.auth_url);
+            }
+        }
+    } else {
+        println!("Opening browser for authentication...");
+        println!("If browser doesn't open, visit:");
+        println!("  {}", start_resp.auth_url);
+        if let Some((code, url)) = device {
+            println!("or open {} on any device and enter {}", url, code);
+        }
+        println!();
+        open_browser(&start_resp.auth_url);
     }
 
     // Step 3: Poll for completion
@@ -150,7 +208,7 @@ pub fn login() -> Result<(), String> {
     loop {
         if start_time.elapsed() > POLL_TIMEOUT {
             println!("\n\nAuthentication timed out. Please try again.");
-            return Err("Timeout".to_string());
+            return Err("Timeout".into());
         }
 
         std::thread::sleep(POLL_INTERVAL);
@@ -173,7 +231,7 @@ pub fn login() -> Result<(), String> {
 
         if let Some(error) = poll_resp.error {
             println!("\n\nAuthentication failed: {}", error);
-            return Err(error);
+            return Err(SlashcmdError::Auth(error));
         }
 
         if poll_resp.pending {
@@ -188,6 +246,7 @@ pub fn login() -> Result<(), String> {
                 token,
                 user: user.clone(),
                 github_id,
+                org: None,
             };
             save_auth(&auth)?;
 
@@ -204,11 +263,115 @@ pub fn login() -> Result<(), String> {
                 );
             }
 
+            if let Some(org) = org {
+                println!();
+                return join_org(auth, org);
+            }
             return Ok(());
         }
     }
 }
 
+/// Log in with a token pasted at a hidden prompt or piped on stdin (CI).
+/// The token is checked against `/status` before it is saved.
+pub fn login_with_token() -> Result<(), SlashcmdError> {
+    let token = read_secret("Paste token: ")?;
+    if token.is_empty() {
+        return Err("No token given".into());
+    }
+
+    let mut auth = StoredAuth {
+        token,
+        user: String::new(),
+        github_id: String::new(),
+        org: None,
+    };
+    let status = get_status_with_auth(&auth).map_err(|e| match e {
+        SlashcmdError::Auth(message) => SlashcmdError::Auth(format!("Token rejected: {}", message)),
+        other => other,
+    })?;
+    let user_id = status.user.unwrap_or_default();
+    auth.github_id = user_id.strip_prefix("github:").unwrap_or(&user_id).to_string();
+    auth.user = status.username.unwrap_or(user_id);
+    auth.org = status.org.map(|org| org.slug);
+    save_auth(&auth)?;
+
+    println!("✓ Logged in as {} ({} tier)", auth.user, status.tier);
+    println!("  Token saved to {:?}", auth_file());
+    Ok(())
+}
+
+/// A secret from a hidden prompt, or the first line of piped stdin; never
+/// from argv, where shell history and `ps` would keep it
+pub fn read_secret(prompt: &str) -> Result<String, String> {
+    let secret = if io::stdin().is_terminal() {
+        read_hidden(prompt)?
+    } else {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line).map_err(|e| format!("Failed to read stdin: {}", e))?;
+        line
+    };
+    Ok(secret.trim().to_string())
+}
+
+/// Read a line without echoing it
+fn read_hidden(prompt: &str) -> Result<String, String> {
+    use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+    use crossterm::terminal;
+
+    eprint!("{}", prompt);
+    io::stderr().flush().ok();
+    terminal::enable_raw_mode().map_err(|e| format!("Terminal error: {}", e))?;
+
+    let mut input = String::new();
+    let result = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => match key.code {
+                KeyCode::Enter => break Ok(input),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    break Err("Cancelled".to_string())
+                }
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Esc => break Err("Cancelled".to_string()),
+                _ => {}
+            },
+            // Pasting may arrive as a paste event
+            Ok(Event::Paste(text)) => input.push_str(&text),
+            Ok(_) => {}
+            Err(e) => break Err(format!("Terminal error: {}", e)),
+        }
+    };
+
+    terminal::disable_raw_mode().ok();
+    eprintln!();
+    result
+}
+
+/// Join an organization's team plan. An admin has to invite the user first;
+/// the server checks membership on every request, so the token is unchanged.
+fn join_org(mut auth: StoredAuth, slug: &str) -> Result<(), SlashcmdError> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(5))
+        .timeout_read(Duration::from_secs(10))
+        .build();
+
+    let response = agent
+        .post(&format!("{}/org/join", API_URL))
+        .set("Authorization", &format!("Bearer {}", auth.token))
+        .send_json(serde_json::json!({ "org": slug }));
+    let joined: OrgJoinResponse = response.map_err(SlashcmdError::from_ureq)?.into_json().map_err(parse_error)?;
+
+    auth.org = Some(joined.org.slug.clone());
+    save_auth(&auth)?;
+
+    println!("✓ Joined {} ({} tier)", joined.org.name, joined.org.tier);
+    println!("  Seats: {}/{}", joined.org.used, joined.org.seats);
+    Ok(())
+}
+
 /// Logout - delete stored credentials
 pub fn logout() -> Result<(), String> {
     if load_auth().is_none() {
@@ -222,15 +385,37 @@ pub fn logout() -> Result<(), String> {
 }
 
 /// Get user status
-pub fn status() -> Result<(), String> {
-    let auth = load_auth().ok_or_else(|| {
-        "Not logged in. Run 'slashcmd login' to authenticate.".to_string()
-    })?;
+/// Show the user's status and return the exit code: 0 when requests can be
+/// made, EXIT_NOT_LOGGED_IN or EXIT_QUOTA_EXHAUSTED otherwise. With `json`
+/// the status is printed as one JSON object for scripts and prompts.
+pub fn status(json: bool) -> Result<i32, SlashcmdError> {
+    let Some(auth) = load_auth() else {
+        if json {
+            println!("{}", serde_json::json!({ "logged_in": false }));
+        } else {
+            eprintln!("Not logged in. Run 'slashcmd login' to authenticate.");
+        }
+        return Ok(EXIT_NOT_LOGGED_IN);
+    };
 
     let status = get_status_with_auth(&auth)?;
+    save_status_cache(&status);
+    let exhausted = status.tier != "pro" && status.remaining <= 0;
+    let code = if exhausted { EXIT_QUOTA_EXHAUSTED } else { 0 };
+
+    if json {
+        let mut value = serde_json::to_value(&status).map_err(|e| format!("Failed to encode status: {}", e))?;
+        value["logged_in"] = true.into();
+        value["username"] = auth.user.clone().into();
+        println!("{}", value);
+        return Ok(code);
+    }
 
     println!("User: {}", auth.user);
     println!("Tier: {}", status.tier);
+    if let Some(org) = &status.org {
+        println!("Org:  {} ({}), seats {}/{}", org.name, org.slug, org.used, org.seats);
+    }
 
     if status.tier == "pro" {
         println!("Usage: {} (unlimited)", status.usage);
@@ -238,18 +423,77 @@ pub fn status() -> Result<(), String> {
         println!("Usage: {}/{}", status.usage, status.limit);
         if status.remaining <= 10 && status.remaining > 0 {
             println!("\n⚠️  Only {} requests remaining!", status.remaining);
-            println!("   Upgrade: https://slashcmd.lgandecki.net/upgrade");
+            println!("   Upgrade: slashcmd upgrade");
         } else if status.remaining <= 0 {
             println!("\n❌ Free tier limit reached!");
-            println!("   Upgrade: https://slashcmd.lgandecki.net/upgrade");
+            println!("   Upgrade: slashcmd upgrade");
         }
     }
 
+    Ok(code)
+}
+
+/// Open the checkout page (free tier) or billing portal (pro) in the browser
+pub fn upgrade() -> Result<(), SlashcmdError> {
+    let auth = load_auth().ok_or(SlashcmdError::NotLoggedIn)?;
+
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(5))
+        .timeout_read(Duration::from_secs(10))
+        .build();
+
+    let billing: BillingResponse = agent
+        .get(&format!("{}/billing", API_URL))
+        .set("Authorization", &format!("Bearer {}", auth.token))
+        .call()
+        .map_err(SlashcmdError::from_ureq)?
+        .into_json()
+        .map_err(parse_error)?;
+
+    if billing.tier == "pro" {
+        println!("Opening your billing portal...");
+    } else {
+        println!("Opening checkout for Pro ($5/month, unlimited)...");
+    }
+    println!("If browser doesn't open, visit (link expires in 10 minutes):");
+    println!("  {}", billing.url);
+    open_browser(&billing.url);
     Ok(())
 }
 
+/// Whether a browser can open on this machine (not over SSH, and with a
+/// display on Linux)
+fn has_local_browser() -> bool {
+    let env_set = |name: &str| std::env::var_os(name).is_some_and(|v| !v.is_empty());
+    if env_set("SSH_CONNECTION") || env_set("SSH_TTY") {
+        return false;
+    }
+    if cfg!(target_os = "linux") {
+        return env_set("DISPLAY") || env_set("WAYLAND_DISPLAY");
+    }
+    true
+}
+
+/// Best-effort attempt to open a URL in the default browser
+fn open_browser(url: &str) {
+    #[cfg(target_os = "macos")]
+    {
+        let _ = std::process::Command::new("open").arg(url).spawn();
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let _ = std::process::Command::new("xdg-open").arg(url).spawn();
+    }
+    #[cfg(target_os = "windows")]
+    {
+        let _ = std::process::Command::new("cmd")
+            .args(["/c", "start", url])
+            .spawn();
+    }
+}
+
 /// Get status from API with given auth
-fn get_status_with_auth(auth: &StoredAuth) -> Result<UserStatus, String> {
+fn get_status_with_auth(auth: &StoredAuth) -> Result<UserStatus, SlashcmdError> {
     let agent = ureq::AgentBuilder::new()
         .timeout_connect(Duration::from_secs(5))
         .timeout_read(Duration::from_secs(10))
@@ -259,10 +503,71 @@ fn get_status_with_auth(auth: &StoredAuth) -> Result<UserStatus, String> {
         .get(&format!("{}/status", API_URL))
         .set("Authorization", &format!("Bearer {}", auth.token))
         .call()
-        .map_err(|e| format!("Failed to get status: {}", e))?;
+        .map_err(SlashcmdError::from_ureq)?;
+
+    resp.into_json().map_err(parse_error)
+}
+
+fn parse_error(e: io::Error) -> SlashcmdError {
+    SlashcmdError::Parse(e.to_string())
+}
+
+fn load_status_cache() -> Option<CachedStatus> {
+    let content = fs::read_to_string(status_cache_file()).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Recently cached status without touching the network. The cache is
+/// written by `slashcmd status` and kept current by `update_cached_usage`,
+/// so checking it never adds a request.
+pub fn cached_status() -> Option<UserStatus> {
+    load_auth()?;
+    let cached = load_status_cache()?;
+    let age = crate::logs::now().saturating_sub(cached.fetched_at);
+    tracing::debug!(age_secs = age, "status cache");
+    (age < STATUS_CACHE_MAX_AGE_SECS).then_some(cached.status)
+}
+
+/// Update the cached status with the usage a `/command` response reported
+pub fn update_cached_usage(usage: i32, limit: i32, tier: &str) {
+    if load_auth().is_none() {
+        return;
+    }
+    let mut status = load_status_cache().map(|c| c.status).unwrap_or(UserStatus {
+        user: None,
+        username: None,
+        tier: String::new(),
+        usage: 0,
+        limit: 0,
+        remaining: 0,
+        org: None,
+    });
+    status.tier = tier.to_string();
+    status.usage = usage;
+    status.limit = limit;
+    status.remaining = if limit < 0 { -1 } else { limit - usage };
+    save_status_cache(&status);
+}
+
+fn save_status_cache(status: &UserStatus) {
+    let cached = CachedStatus {
+        fetched_at: crate::logs::now(),
+        status: status.clone(),
+    };
+    let Ok(json) = serde_json::to_string(&cached) else {
+        return;
+    };
+    // Write then rename so an interrupted refresh never leaves half a file
+    let path = status_cache_file();
+    let tmp = path.with_extension("tmp");
+    if fs::create_dir_all(config_dir()).is_ok() && fs::write(&tmp, json).is_ok() {
+        let _ = fs::rename(&tmp, &path);
+    }
+}
 
-    resp.into_json()
-        .map_err(|e| format!("Invalid response: {}", e))
+/// Check the stored token against the server: None when not logged in
+pub fn verify_token() -> Option<Result<UserStatus, SlashcmdError>> {
+    load_auth().map(|auth| get_status_with_auth(&auth))
 }
 
 /// Get the stored token if available
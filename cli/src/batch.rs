@@ -0,0 +1,173 @@
+//! `slashcmd batch`: many queries, no interaction
+//!
+//! Reads one query per line (blank lines and `#` comments skipped) and
+//! prints a query→command record for each, in input order, as JSON lines or
+//! CSV. A few requests run at once; each client already waits out 429s, so
+//! a burst that trips the rate limit slows down instead of failing. Commands
+//! are never run, only rated, which makes the output a starting point for
+//! runbooks or the team snippet library.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::audit::{self, AuditEvent};
+use crate::history::{csv_field, Format};
+use crate::ipc::ExplainOptions;
+use crate::mcp::Backend;
+use crate::policy::SafetyRules;
+use crate::secrets;
+
+/// Requests in flight when `--jobs` isn't given
+pub const DEFAULT_JOBS: usize = 4;
+
+/// How a batch runs and what it prints
+pub struct BatchOptions {
+    /// Ask for an explanation of each command too
+    pub explain: bool,
+    /// Requests in flight at once
+    pub jobs: usize,
+    pub format: Format,
+}
+
+#[derive(Serialize)]
+struct Record {
+    query: String,
+    /// The query as sent, secrets redacted; audited once written
+    #[serde(skip)]
+    sent: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    /// safe, caution, confirm, danger, denied or blocked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    safety: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explanation: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Record {
+    fn csv_row(&self) -> String {
+        [
+            self.query.as_str(),
+            self.command.as_deref().unwrap_or(""),
+            self.safety.unwrap_or(""),
+            self.explanation.as_deref().unwrap_or(""),
+            self.error.as_deref().unwrap_or(""),
+        ]
+        .map(csv_field)
+        .join(",")
+    }
+}
+
+/// The queries in a batch file
+pub fn read_queries<R: BufRead>(input: R) -> Result<Vec<String>, String> {
+    let mut queries = Vec::new();
+    for line in input.lines() {
+        let line = line.map_err(|e| format!("Failed to read queries: {}", e))?;
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            queries.push(line.to_string());
+        }
+    }
+    Ok(queries)
+}
+
+/// Generate a command for every query, `jobs` at a time, writing records to
+/// `out` in input order as they become available. Returns how many failed.
+pub fn run<W: Write>(
+    backend: &Backend,
+    queries: &[String],
+    options: &ExplainOptions,
+    rules: &SafetyRules,
+    batch: &BatchOptions,
+    out: &mut W,
+) -> Result<usize, String> {
+    let format = batch.format;
+    let explain = batch.explain;
+    let write_err = |e: std::io::Error| format!("Failed to write: {}", e);
+    if format == Format::Csv {
+        writeln!(out, "query,command,safety,explanation,error").map_err(write_err)?;
+    }
+
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    let mut failed = 0;
+    thread::scope(|scope| {
+        for _ in 0..batch.jobs.clamp(1, queries.len().max(1)) {
+            let tx = tx.clone();
+            let next = &next;
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(query) = queries.get(i) else {
+                    break;
+                };
+                if tx.send((i, translate(backend, query, options, rules, explain))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        // Hold early finishers until everything before them is written
+        let mut pending = BTreeMap::new();
+        let mut written = 0;
+        for (i, record) in rx {
+            pending.insert(i, record);
+            while let Some(record) = pending.remove(&written) {
+                // Audited here, one at a time, to keep the hash chain linear
+                match &record.command {
+                    Some(command) => {
+                        let _ = audit::record(AuditEvent::Generated, &record.sent, command);
+                    }
+                    None => failed += 1,
+                }
+                let line = match format {
+                    Format::Jsonl => serde_json::to_string(&record).map_err(|e| e.to_string())?,
+                    Format::Csv => record.csv_row(),
+                };
+                writeln!(out, "{}", line).map_err(write_err)?;
+                out.flush().map_err(write_err)?;
+                written += 1;
+            }
+        }
+        Ok(failed)
+    })
+}
+
+fn translate(backend: &Backend, query: &str, options: &ExplainOptions, rules: &SafetyRules, explain: bool) -> Record {
+    let sent = secrets::scrub(query.to_string(), "query");
+    match backend.generate(&sent, options, explain) {
+        Ok((result, explanation)) => Record {
+            query: query.to_string(),
+            sent,
+            safety: Some(rules.check(&result.command).label(Some(result.safe))),
+            command: Some(result.command),
+            explanation,
+            error: None,
+        },
+        Err(e) => Record {
+            query: query.to_string(),
+            sent,
+            command: None,
+            safety: None,
+            explanation: None,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_queries_skips_blanks_and_comments() {
+        let input = "# disk\nfind large files\n\n  show free space  \n#done\n";
+        assert_eq!(read_queries(input.as_bytes()).unwrap(), vec!["find large files", "show free space"]);
+    }
+}
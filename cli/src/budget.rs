@@ -0,0 +1,197 @@
+//! Local monthly usage budget
+//!
+//! The `[budget]` section of config.toml caps requests and/or tokens per
+//! calendar month (UTC). Usage is counted locally in `~/.cmd/usage.json`;
+//! tokens are only known in `--local` mode, where the APIs report them.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use crate::logs;
+
+/// Warn once this share of the budget is used, unless configured
+const DEFAULT_WARN_PERCENT: u8 = 80;
+
+/// The `[budget]` section of config.toml
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct BudgetConfig {
+    /// Maximum queries per month
+    pub monthly_requests: Option<u64>,
+    /// Maximum API tokens per month (local mode)
+    pub monthly_tokens: Option<u64>,
+    /// Percentage of either limit at which to start warning
+    pub warn_percent: Option<u8>,
+}
+
+/// This month's consumption
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct Usage {
+    /// "YYYY-MM"; a new month starts from zero
+    pub month: String,
+    pub requests: u64,
+    pub tokens: u64,
+}
+
+/// Where usage stands against the budget
+#[derive(Debug, PartialEq)]
+pub enum BudgetStatus {
+    Ok,
+    Warning(String),
+    Exceeded(String),
+}
+
+fn usage_file() -> PathBuf {
+    logs::logs_dir()
+        .parent()
+        .map(|dir| dir.join("usage.json"))
+        .unwrap_or_else(|| PathBuf::from("usage.json"))
+}
+
+/// Load this month's usage (zero if the file is from an earlier month)
+pub fn load_usage() -> Usage {
+    parse_usage(&fs::read_to_string(usage_file()).unwrap_or_default())
+}
+
+fn parse_usage(content: &str) -> Usage {
+    let month = current_month();
+    serde_json::from_str::<Usage>(content)
+        .ok()
+        .filter(|usage| usage.month == month)
+        .unwrap_or(Usage {
+            month,
+            ..Default::default()
+        })
+}
+
+/// Read, change and write back usage.json under an exclusive lock, so runs
+/// side by side (a batch and the MCP server, say) don't lose each other's counts
+fn update<T>(apply: impl FnOnce(&mut Usage) -> T) -> T {
+    let file = logs::ensure_logs_dir().ok().and_then(|_| {
+        fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(usage_file())
+            .ok()
+    });
+    let Some(mut file) = file else {
+        return apply(&mut load_usage());
+    };
+    // Released when `file` is closed
+    unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) };
+
+    let mut content = String::new();
+    let _ = file.read_to_string(&mut content);
+    let mut usage = parse_usage(&content);
+    let result = apply(&mut usage);
+    if let Ok(json) = serde_json::to_string_pretty(&usage) {
+        let _ = file
+            .set_len(0)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| file.write_all(json.as_bytes()));
+    }
+    result
+}
+
+/// Check the budget for `pending` queries and, unless that's over it, count
+/// them. Err is the over-budget message; Ok has a warning to show, if any.
+/// With `force` the queries go ahead over the budget, with the message as
+/// the warning.
+pub fn enforce(config: &BudgetConfig, pending: u64, force: bool) -> Result<Option<String>, String> {
+    update(|usage| match check(config, usage, pending) {
+        BudgetStatus::Exceeded(message) if !force => Err(message),
+        status => {
+            usage.requests += pending;
+            match status {
+                BudgetStatus::Exceeded(message) | BudgetStatus::Warning(message) => Ok(Some(message)),
+                BudgetStatus::Ok => Ok(None),
+            }
+        }
+    })
+}
+
+/// Count API tokens against the budget
+pub fn record_tokens(tokens: u64) {
+    update(|usage| usage.tokens += tokens);
+}
+
+/// Compare usage with the budget. `pending` is the number of queries about
+/// to be made.
+pub fn check(config: &BudgetConfig, usage: &Usage, pending: u64) -> BudgetStatus {
+    let warn_percent = config.warn_percent.unwrap_or(DEFAULT_WARN_PERCENT) as u64;
+    // (what, used including the pending queries, used so far, limit)
+    let limits = [
+        ("requests", usage.requests + pending, usage.requests, config.monthly_requests),
+        ("tokens", usage.tokens, usage.tokens, config.monthly_tokens),
+    ];
+
+    let mut status = BudgetStatus::Ok;
+    for (what, used, so_far, limit) in limits {
+        let Some(limit) = limit else { continue };
+        if used > limit {
+            return BudgetStatus::Exceeded(format!(
+                "Monthly budget of {} {} used up ({} so far)",
+                limit, what, so_far
+            ));
+        }
+        if used * 100 >= limit * warn_percent {
+            status = BudgetStatus::Warning(format!("{} of {} monthly {} used", used, limit, what));
+        }
+    }
+    status
+}
+
+/// "YYYY-MM" for the current UTC date
+fn current_month() -> String {
+    let (year, month) = year_month(logs::now() / 86400);
+    format!("{:04}-{:02}", year, month)
+}
+
+/// Year and month for days since 1970-01-01
+fn year_month(days: u64) -> (i64, i64) {
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_year_month() {
+        assert_eq!(year_month(0), (1970, 1));
+        assert_eq!(year_month(19782), (2024, 2)); // 2024-02-29
+        assert_eq!(year_month(19783), (2024, 3));
+    }
+
+    #[test]
+    fn test_check_budget() {
+        let config = BudgetConfig {
+            monthly_requests: Some(100),
+            ..Default::default()
+        };
+        let usage = |requests| Usage {
+            month: "2024-03".to_string(),
+            requests,
+            tokens: 0,
+        };
+
+        assert_eq!(check(&config, &usage(10), 1), BudgetStatus::Ok);
+        assert!(matches!(check(&config, &usage(79), 1), BudgetStatus::Warning(_)));
+        assert!(matches!(check(&config, &usage(100), 0), BudgetStatus::Warning(_)));
+        assert!(matches!(check(&config, &usage(100), 1), BudgetStatus::Exceeded(_)));
+        assert_eq!(check(&BudgetConfig::default(), &usage(1000), 1), BudgetStatus::Ok);
+    }
+}
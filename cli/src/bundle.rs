@@ -0,0 +1,268 @@
+//! `slashcmd debug-bundle`: diagnostics to attach to a bug report
+//!
+//! One uncompressed tar with a summary (versions, platform, which keys are
+//! set), config.toml and the machine policy with credentials masked, the
+//! tail of the daemon's log, the last log entries and per-provider timing.
+//! Secrets the `secrets` patterns recognize are redacted everywhere, and
+//! with `--redact-queries` so are the queries. Nothing is uploaded; users
+//! look it over and attach it themselves.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::ipc;
+use crate::logs::{self, LogEntry};
+use crate::policy;
+use crate::secrets;
+use crate::telemetry;
+
+/// Lines kept from the end of the daemon log
+const DAEMON_LOG_LINES: usize = 200;
+
+/// Config keys whose values are never included
+const SECRET_KEYS: &[&str] = &[
+    "key",
+    "token",
+    "secret",
+    "password",
+    "auth",
+    "credential",
+    "webhook",
+    "header",
+];
+
+pub struct BundleOptions {
+    /// How many of the newest log entries to include
+    pub entries: usize,
+    pub redact_queries: bool,
+}
+
+/// Collect the diagnostics and write them to `path`
+pub fn create(path: &Path, options: &BundleOptions) -> Result<(), String> {
+    let now = logs::now();
+    let dir = format!("slashcmd-debug-{}", now);
+    let entries = logs::recent_entries(options.entries);
+
+    let mut files: Vec<(String, String)> = vec![("summary.txt".to_string(), summary())];
+    if let Ok(content) = fs::read_to_string(config::config_file()) {
+        files.push(("config.toml".to_string(), mask_secrets(&content)));
+    }
+    if let Ok(content) = fs::read_to_string(policy::POLICY_FILE) {
+        files.push(("policy.toml".to_string(), mask_secrets(&content)));
+    }
+    if let Ok(content) = fs::read_to_string(ipc::daemon_log_file()) {
+        let lines: Vec<&str> = content.lines().collect();
+        let tail = lines[lines.len().saturating_sub(DAEMON_LOG_LINES)..].join("\n");
+        files.push(("daemon.log".to_string(), secrets::redact(&tail).0));
+    }
+    if let Some(health) = daemon_health() {
+        files.push(("daemon-health.json".to_string(), health));
+    }
+    files.push(("logs.jsonl".to_string(), log_lines(&entries, options.redact_queries)?));
+    files.push(("timing.txt".to_string(), timing(&entries)));
+    if let Ok(content) = fs::read_to_string(telemetry::queue_file()) {
+        files.push(("telemetry.jsonl".to_string(), content));
+    }
+
+    let files: Vec<(String, Vec<u8>)> = files
+        .into_iter()
+        .map(|(name, content)| (format!("{}/{}", dir, name), content.into_bytes()))
+        .collect();
+    fs::write(path, tar(&files, now)).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Default file name, in the current directory
+pub fn default_path() -> PathBuf {
+    PathBuf::from(format!("slashcmd-debug-{}.tar", logs::now()))
+}
+
+fn summary() -> String {
+    let features: Vec<&str> = [
+        ("edge", cfg!(feature = "edge")),
+        ("groq", cfg!(feature = "groq")),
+        ("gemini", cfg!(feature = "gemini")),
+        ("daemon", cfg!(feature = "daemon")),
+        ("tui", cfg!(feature = "tui")),
+    ]
+    .into_iter()
+    .filter_map(|(name, on)| on.then_some(name))
+    .collect();
+    let is_set = |name: &str| {
+        if std::env::var(name).is_ok_and(|v| !v.is_empty()) {
+            "set"
+        } else {
+            "unset"
+        }
+    };
+    let env = |name: &str| std::env::var(name).unwrap_or_default();
+
+    let mut summary = format!(
+        "slashcmd {}\nos: {} {}\nfeatures: {}\nshell: {}\nterm: {}\nGROQ_API_KEY: {}\nGEMINI_API_KEY: {}\n",
+        ipc::VERSION,
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        features.join(", "),
+        env("SHELL"),
+        env("TERM"),
+        is_set("GROQ_API_KEY"),
+        is_set("GEMINI_API_KEY"),
+    );
+    let mut settings: Vec<String> = std::env::vars()
+        .filter(|(name, _)| name.starts_with("SLASHCMD_"))
+        .map(|(name, value)| {
+            if is_secret_key(&name) {
+                format!("{}=***", name)
+            } else {
+                format!("{}={}", name, value)
+            }
+        })
+        .collect();
+    settings.sort();
+    for setting in settings {
+        summary.push_str(&setting);
+        summary.push('\n');
+    }
+    summary
+}
+
+fn daemon_health() -> Option<String> {
+    let mut stream = ipc::IpcClient::try_connect()?;
+    ipc::IpcClient::send_request(&mut stream, &ipc::IpcRequest::Health).ok()
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_lowercase();
+    SECRET_KEYS.iter().any(|secret| key.contains(secret))
+}
+
+/// TOML with the values of credential-like keys, and of every key in a
+/// `headers` table, replaced by "***"
+fn mask_secrets(toml: &str) -> String {
+    let mut in_headers = false;
+    let mut masked = String::new();
+    for line in toml.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('[') {
+            in_headers = is_secret_key(trimmed);
+            masked.push_str(line);
+        } else if let Some((key, _)) = line.split_once('=').filter(|_| !trimmed.starts_with('#')) {
+            if in_headers || is_secret_key(key) {
+                masked.push_str(&format!("{}= \"***\"", key));
+            } else {
+                masked.push_str(&secrets::redact(line).0);
+            }
+        } else {
+            masked.push_str(&secrets::redact(line).0);
+        }
+        masked.push('\n');
+    }
+    masked
+}
+
+/// The entries as JSON lines, oldest first
+fn log_lines(entries: &[LogEntry], redact_queries: bool) -> Result<String, String> {
+    let mut lines = String::new();
+    for entry in entries.iter().rev() {
+        let mut entry = entry.clone();
+        entry.query = if redact_queries {
+            "[redacted]".to_string()
+        } else {
+            secrets::redact(&entry.query).0
+        };
+        entry.command = secrets::redact(&entry.command).0;
+        entry.explanation = entry.explanation.map(|e| secrets::redact(&e).0);
+        lines.push_str(&serde_json::to_string(&entry).map_err(|e| format!("Failed to encode log entry: {}", e))?);
+        lines.push('\n');
+    }
+    Ok(lines)
+}
+
+/// Count, median and slowest generation per provider
+fn timing(entries: &[LogEntry]) -> String {
+    let mut by_path: Vec<(String, Vec<u64>)> = Vec::new();
+    for provenance in entries.iter().filter_map(|e| e.provenance.as_ref()) {
+        let path = if provenance.daemon {
+            format!("{} (daemon)", provenance.provider)
+        } else {
+            provenance.provider.clone()
+        };
+        match by_path.iter_mut().find(|(p, _)| *p == path) {
+            Some((_, latencies)) => latencies.push(provenance.latency_ms),
+            None => by_path.push((path, vec![provenance.latency_ms])),
+        }
+    }
+    if by_path.is_empty() {
+        return "No timed generations in these entries.\n".to_string();
+    }
+    by_path
+        .into_iter()
+        .map(|(path, mut latencies)| {
+            latencies.sort_unstable();
+            format!(
+                "{}: {} requests, median {} ms, slowest {} ms\n",
+                path,
+                latencies.len(),
+                latencies[latencies.len() / 2],
+                latencies[latencies.len() - 1]
+            )
+        })
+        .collect()
+}
+
+/// A ustar archive of regular files
+fn tar(files: &[(String, Vec<u8>)], mtime: u64) -> Vec<u8> {
+    let mut archive = Vec::new();
+    for (name, content) in files {
+        let mut header = [0u8; 512];
+        let mut field = |offset: usize, value: &[u8]| header[offset..offset + value.len()].copy_from_slice(value);
+        field(0, &name.as_bytes()[..name.len().min(100)]);
+        field(100, b"0000644\0");
+        field(108, b"0000000\0");
+        field(116, b"0000000\0");
+        field(124, format!("{:011o}\0", content.len()).as_bytes());
+        field(136, format!("{:011o}\0", mtime).as_bytes());
+        field(148, b"        ");
+        field(156, b"0");
+        field(257, b"ustar\0");
+        field(263, b"00");
+        let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(content);
+        archive.resize(archive.len().next_multiple_of(512), 0);
+    }
+    // End of archive: two empty blocks
+    archive.resize(archive.len() + 1024, 0);
+    archive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_and_tar() {
+        let config = "lang = \"pl\"\n[gateway]\nurl = \"https://llm.corp\"\nauth_value = \"Bearer abc\"\n\
+                      [gateway.headers]\nX-Team = \"ops\"\n[approval]\nwebhook = \"https://hooks.example/T1/x\"\n";
+        let masked = mask_secrets(config);
+        assert!(masked.contains("lang = \"pl\""));
+        assert!(masked.contains("url = \"https://llm.corp\""));
+        assert!(masked.contains("auth_value = \"***\""));
+        assert!(masked.contains("X-Team = \"***\""));
+        assert!(masked.contains("webhook = \"***\""));
+        assert!(!masked.contains("abc") && !masked.contains("ops") && !masked.contains("T1"));
+
+        let archive = tar(&[("b/summary.txt".to_string(), b"hello".to_vec())], 1_700_000_000);
+        assert_eq!(archive.len(), 512 * 4);
+        assert_eq!(&archive[..13], b"b/summary.txt");
+        assert_eq!(&archive[257..263], b"ustar\0");
+        assert_eq!(&archive[512..517], b"hello");
+        // The stored checksum is the header's byte sum with the field as spaces
+        let mut header = archive[..512].to_vec();
+        let stored = u32::from_str_radix(std::str::from_utf8(&header[148..154]).unwrap(), 8).unwrap();
+        header[148..156].copy_from_slice(b"        ");
+        assert_eq!(stored, header.iter().map(|&b| b as u32).sum::<u32>());
+    }
+}
@@ -1,150 +1,485 @@
-use std::process::Command;
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::sync::{mpsc, OnceLock};
+#[cfg(feature = "edge")]
+use std::thread;
+use std::time::Instant;
 
+use crate::audit::{self, AuditEvent};
+use crate::clipboard;
+use crate::context;
+use crate::coreutils;
+#[cfg(feature = "edge")]
 use crate::edge::EdgeClient;
-use crate::gemini::GeminiClient;
-use crate::groq::GroqClient;
-use crate::highlight::{dim, highlight_explanation};
-use crate::ipc::{ExplainStyle, IpcClient, IpcRequest};
+use crate::error::SlashcmdError;
+#[cfg(feature = "gemini")]
+use crate::explain_cache;
+#[cfg(feature = "gemini")]
+use crate::gemini::{self, GeminiClient};
+#[cfg(feature = "groq")]
+use crate::groq::{self, GroqClient};
+use crate::diagram;
+use crate::highlight::{banner, dim, highlight_command, level, ExplanationLines, Level};
+use crate::ipc::{ExplainOptions, ExplainStyle};
+#[cfg(feature = "groq")]
+use crate::ipc::{self, Generation};
+#[cfg(any(feature = "groq", feature = "gemini"))]
+use crate::ipc::{IpcClient, IpcRequest};
+use crate::layout;
 use crate::logs;
-use crate::prompt::CommandResult;
+#[cfg(feature = "groq")]
+use crate::logs::Provenance;
+use crate::policy::SafetyRules;
+use crate::prompt::{self, CommandResult};
+use crate::sync;
+use crate::syntax;
+use crate::telemetry::{self, Provider};
+use crate::templates;
+
+/// What CLI mode prints
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat {
+    /// Command followed by the highlighted explanation
+    #[default]
+    Text,
+    /// Mermaid flowchart source for the diagram style
+    Mermaid,
+    /// Log id and command on separate lines, no explanation (`slashcmd init`)
+    Shell,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "mermaid" | "mmd" => Ok(OutputFormat::Mermaid),
+            _ => Err(format!("Unknown output format: {}. Use: text, mermaid", s)),
+        }
+    }
+}
+
+/// Where --print-only writes its result instead of stdout (`use_result_fd`)
+static RESULT_FD: OnceLock<File> = OnceLock::new();
+
+/// Write --print-only results to file descriptor `fd`, which the caller
+/// opened (`3>&1`), leaving stdout to whatever else prints there
+pub fn use_result_fd(fd: u32) -> Result<(), SlashcmdError> {
+    let file = open_fd(fd).map_err(|e| SlashcmdError::Config(format!("--result-fd {}: {}", fd, e)))?;
+    let _ = RESULT_FD.set(file);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn open_fd(fd: u32) -> std::io::Result<File> {
+    // Reopening /dev/fd/N fails cleanly when N isn't open
+    std::fs::OpenOptions::new().append(true).open(format!("/dev/fd/{}", fd))
+}
+
+#[cfg(not(unix))]
+fn open_fd(_fd: u32) -> std::io::Result<File> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "file descriptors are only passed on Unix"))
+}
+
+/// The --print-only result: the log id (empty when nothing was logged) on
+/// the first line and the command after it, on the result fd or stdout
+pub fn print_result(id: &str, command: &str) -> Result<(), SlashcmdError> {
+    let result = format!("{}\n{}\n", id, command);
+    match RESULT_FD.get() {
+        Some(mut file) => file
+            .write_all(result.as_bytes())
+            .map_err(|e| SlashcmdError::Other(format!("Failed to write the result: {}", e))),
+        None => {
+            print!("{}", result);
+            Ok(())
+        }
+    }
+}
 
 /// Command source for CLI mode
 pub enum CliSource {
+    #[cfg(feature = "groq")]
     Direct { groq_api_key: String },
+    #[cfg(feature = "edge")]
     Edge { token: Option<String> },
 }
 
-/// Run CLI mode - for non-interactive/piped usage
+/// Run CLI mode - for non-interactive/piped usage. With `safety_badge`,
+/// output without an explanation (-q, --print-only) is followed by
+/// `# slashcmd: <LEVEL>` on stderr.
+#[cfg(feature = "groq")]
 pub fn run_cli(
     query: String,
     groq_api_key: String,
     gemini_api_key: Option<String>,
-    style: ExplainStyle,
+    options: ExplainOptions,
     quick: bool,
-) -> Result<(), String> {
-    run_cli_impl(query, CliSource::Direct { groq_api_key }, gemini_api_key, style, quick)
-}
-
-/// Run CLI mode with edge proxy (test JWT)
-pub fn run_cli_edge(
-    query: String,
-    gemini_api_key: Option<String>,
-    style: ExplainStyle,
-    quick: bool,
-) -> Result<(), String> {
-    run_cli_impl(query, CliSource::Edge { token: None }, gemini_api_key, style, quick)
+    output: OutputFormat,
+    safety_badge: Option<&SafetyRules>,
+) -> Result<(), SlashcmdError> {
+    let source = CliSource::Direct { groq_api_key };
+    run_cli_impl(query, source, gemini_api_key, options, quick, output, safety_badge)
 }
 
 /// Run CLI mode with edge proxy (authenticated)
+#[cfg(feature = "edge")]
 pub fn run_cli_edge_auth(
     query: String,
     token: String,
-    style: ExplainStyle,
+    options: ExplainOptions,
     quick: bool,
-) -> Result<(), String> {
-    run_cli_impl(query, CliSource::Edge { token: Some(token) }, None, style, quick)
+    output: OutputFormat,
+    safety_badge: Option<&SafetyRules>,
+) -> Result<(), SlashcmdError> {
+    let source = CliSource::Edge { token: Some(token) };
+    run_cli_impl(query, source, None, options, quick, output, safety_badge)
 }
 
 fn run_cli_impl(
     query: String,
     source: CliSource,
     gemini_api_key: Option<String>,
-    style: ExplainStyle,
+    options: ExplainOptions,
     quick: bool,
-) -> Result<(), String> {
-    // Get the command
-    let command = match &source {
-        CliSource::Direct { groq_api_key } => get_command(&query, groq_api_key)?,
+    output: OutputFormat,
+    safety_badge: Option<&SafetyRules>,
+) -> Result<(), SlashcmdError> {
+    // The diagram is drawn from the explanation, which -q skips
+    if quick && output == OutputFormat::Mermaid {
+        return Err(SlashcmdError::Config(
+            "--output mermaid needs the explanation, so it can't be used with -q".to_string(),
+        ));
+    }
+
+    // Get the command (the edge stream carries the explanation too, read in
+    // the background while the command is printed). A built-in template
+    // answers common requests without the model.
+    let request = prompt::with_preferences(&query);
+    let template = templates::find(prompt::strip_context(&query));
+    let (result, edge_explanation): (CommandResult, Option<mpsc::Receiver<Result<String, String>>>) = match &source {
+        #[cfg(feature = "groq")]
+        CliSource::Direct { groq_api_key } => match template {
+            Some(found) => (found, None),
+            None => (get_command(&request, groq_api_key, &options.generation)?, None),
+        },
+        #[cfg(feature = "edge")]
         CliSource::Edge { token } => {
             let edge = match token {
                 Some(t) => EdgeClient::new(t.clone()),
                 None => EdgeClient::with_test_jwt(),
             };
-            edge.query(&query)?.command
+            let (exp_tx, exp_rx) = mpsc::channel();
+            match template {
+                // The worker still explains it, unless that's not wanted
+                Some(found) if quick || output == OutputFormat::Shell => (found, None),
+                Some(found) => {
+                    let (to_explain, options) = (found.command.clone(), options.clone());
+                    thread::spawn(move || {
+                        let _ = exp_tx.send(edge.explain(&to_explain, &options).map_err(|e| e.to_string()));
+                    });
+                    (found, Some(exp_rx))
+                }
+                None => {
+                    let started = Instant::now();
+                    let (cmd_tx, cmd_rx) = mpsc::channel();
+                    let options = options.clone();
+                    thread::spawn(move || {
+                        // Failures before the command arrives end the wait
+                        let err_tx = cmd_tx.clone();
+                        if let Err(e) = edge.query_streaming(&request, &options, cmd_tx, exp_tx) {
+                            let _ = err_tx.send(Err(e));
+                        }
+                    });
+                    let response = cmd_rx
+                        .recv()
+                        .unwrap_or_else(|_| Err(SlashcmdError::Parse("no command received".to_string())));
+                    telemetry::record(Provider::Edge, started.elapsed(), response.is_ok());
+                    (response?, Some(exp_rx))
+                }
+            }
         }
     };
+    // Questions aren't asked for outside the TUI, but the model may ask anyway
+    if let Some(question) = result.question {
+        return Err(SlashcmdError::Other(format!(
+            "Ambiguous request: {} ({}). Add the answer to the query.",
+            question,
+            result.options.join(" / ")
+        )));
+    }
+    let command = layout::normalize(&result.command);
+    clipboard::generated(&command);
+    // Without an explanation, nothing else tells a shell integration how
+    // risky the command is
+    let badge = safety_badge
+        .filter(|_| quick || output == OutputFormat::Shell)
+        .map(|rules| format!("# slashcmd: {}", rules.check(&command).label(Some(result.safe)).to_uppercase()));
 
-    // Print command
-    println!("{}", command);
+    // The shell integration reports the exit code against this entry
+    if output == OutputFormat::Shell {
+        let entry = logs::create_entry(&query, &command, None, &options.style, result.provenance);
+        let _ = logs::save_log(&entry);
+        let _ = audit::record(AuditEvent::Generated, &query, &command);
+        print_result(&sync::entry_id(&entry), &command)?;
+        if let Some(badge) = badge {
+            eprintln!("{}", badge);
+        }
+        return Ok(());
+    }
+
+    // Mermaid output is pure diagram source; the command goes in a comment
+    if output == OutputFormat::Text {
+        // Print command (highlighted only when a human is looking at it)
+        if std::io::stdout().is_terminal() {
+            println!("{}", highlight_command(&command));
+        } else {
+            println!("{}", command);
+        }
+        if std::io::stderr().is_terminal() {
+            for text in context::kube_banner(&command).into_iter().chain(context::cloud_banner(&command)) {
+                eprintln!("{}", banner(&text));
+            }
+            let syntax = syntax::check(&command, prompt::strip_context(&query));
+            for problem in coreutils::lint(&command).into_iter().chain(syntax) {
+                eprintln!("{}", level(Level::Caution, &format!("⚠ {}", problem)));
+            }
+        }
+    }
 
     // If quick mode, we're done
     if quick {
+        if let Some(badge) = badge {
+            eprintln!("{}", badge);
+        }
         return Ok(());
     }
 
-    // Otherwise get and print explanation
-    if let Some(ref gemini_key) = gemini_api_key {
-        match get_explanation(&command, gemini_key, style) {
-            Ok(explanation) => {
-                println!();
-                println!("{}", highlight_explanation(&explanation, style));
+    // Otherwise get the explanation. Text output prints it a line at a time
+    // as it arrives, so pipes and CI logs don't sit waiting for all of it.
+    let mut printer = (output == OutputFormat::Text).then(|| LinePrinter::new(&options.style, std::io::stdout()));
+    #[cfg_attr(not(feature = "gemini"), allow(unused_mut))]
+    let mut on_chunk = |piece: &str| {
+        if let Some(printer) = printer.as_mut() {
+            printer.push(piece);
+        }
+    };
+    let explanation: Option<Result<String, String>> = match (edge_explanation, &gemini_api_key) {
+        // Nothing arrives if the stream ends without an explanation
+        (Some(exp_rx), _) => exp_rx.recv().ok().inspect(|exp| {
+            if let Ok(text) = exp {
+                on_chunk(text);
             }
-            Err(e) => {
-                eprintln!("\n{}", dim(&format!("(explanation unavailable: {})", e)));
+        }),
+        #[cfg(feature = "gemini")]
+        (None, Some(gemini_key)) => Some(get_explanation(&command, gemini_key, &options, on_chunk).map_err(|e| e.to_string())),
+        _ => None,
+    };
+    // Mermaid output is nothing but the diagram, so not drawing one is an error
+    let mut no_diagram = (output == OutputFormat::Mermaid).then(|| "no explanation to draw it from".to_string());
+    let explanation = match explanation {
+        Some(Ok(explanation)) => {
+            match output {
+                OutputFormat::Text => {
+                    if let Some(printer) = printer.as_mut() {
+                        printer.finish();
+                    }
+                }
+                OutputFormat::Mermaid => match diagram::mermaid_from_explanation(&command, &explanation) {
+                    Ok(source) => {
+                        println!("{}", source);
+                        no_diagram = None;
+                    }
+                    Err(e) => no_diagram = Some(e),
+                },
+                OutputFormat::Shell => unreachable!("returned above"),
             }
+            Some(explanation)
         }
-    }
+        Some(Err(e)) => {
+            eprintln!("\n{}", dim(&format!("(explanation unavailable: {})", e)));
+            None
+        }
+        None => None,
+    };
 
     // Save to log
-    let entry = logs::create_entry(&query, &command, None, style);
+    let entry = logs::create_entry(&query, &command, explanation, &options.style, result.provenance);
     let _ = logs::save_log(&entry);
+    let _ = audit::record(AuditEvent::Generated, &query, &command);
 
     // Spawn daemon in background for future requests (only for direct mode)
+    #[cfg(feature = "daemon")]
     if matches!(&source, CliSource::Direct { .. }) {
-        spawn_daemon_background();
+        ipc::spawn_daemon();
     }
 
-    Ok(())
+    match no_diagram {
+        Some(reason) => Err(SlashcmdError::Other(format!("No Mermaid diagram for `{}`: {}", command, reason))),
+        None => Ok(()),
+    }
+}
+
+/// Prints a streaming explanation a line at a time, highlighted like the
+/// finished text. Blank lines wait for text after them, so trailing ones are
+/// dropped.
+struct LinePrinter<W: Write> {
+    lines: ExplanationLines,
+    out: W,
+    /// Text after the last newline
+    partial: String,
+    blank_lines: usize,
+    started: bool,
+}
+
+impl<W: Write> LinePrinter<W> {
+    fn new(style: &ExplainStyle, out: W) -> Self {
+        LinePrinter {
+            lines: ExplanationLines::new(style),
+            out,
+            partial: String::new(),
+            blank_lines: 0,
+            started: false,
+        }
+    }
+
+    /// Add streamed text, printing every line it completes
+    fn push(&mut self, piece: &str) {
+        self.partial.push_str(piece);
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            self.print_line(line.trim_end_matches(['\n', '\r']));
+        }
+    }
+
+    /// Print the last line, which has no newline
+    fn finish(&mut self) {
+        let rest = std::mem::take(&mut self.partial);
+        if !rest.is_empty() {
+            self.print_line(&rest);
+        }
+    }
+
+    fn print_line(&mut self, line: &str) {
+        let Some(text) = self.lines.line(line) else {
+            return;
+        };
+        if text.trim().is_empty() {
+            self.blank_lines += 1;
+            return;
+        }
+        // A blank line separates the explanation from the command
+        if !self.started {
+            self.started = true;
+            self.blank_lines = 1;
+        }
+        let gap = "\n".repeat(std::mem::take(&mut self.blank_lines));
+        let _ = writeln!(self.out, "{}{}", gap, text.trim_end());
+        let _ = self.out.flush();
+    }
 }
 
 /// Get the CLI command from natural language
-fn get_command(query: &str, groq_api_key: &str) -> Result<String, String> {
+#[cfg(feature = "groq")]
+fn get_command(query: &str, groq_api_key: &str, generation: &Generation) -> Result<CommandResult, SlashcmdError> {
+    let started = Instant::now();
+
     // Try daemon first (fast path)
     if let Some(mut stream) = IpcClient::try_connect() {
         let request = IpcRequest::Command {
             query: query.to_string(),
+            client: Some(ipc::client_id()),
+            generation: *generation,
         };
-        return IpcClient::send_request(&mut stream, &request);
+        let result = IpcClient::send_request(&mut stream, &request);
+        telemetry::record(Provider::Daemon, started.elapsed(), result.is_ok());
+        let provenance = Provenance {
+            model: Some(groq::GROQ_MODEL.to_string()),
+            daemon: true,
+            ..Provenance::new("local", "groq", started.elapsed())
+        };
+        // Don't auto-trust the daemon's safety call; assume it isn't safe
+        return Ok(CommandResult {
+            safe: false,
+            provenance: Some(provenance),
+            ..prompt::parse_response(&result?)?
+        });
     }
 
     // Daemon not running - make direct HTTP request
     let groq = GroqClient::new(groq_api_key.to_string());
-    let result = groq.query(query)?;
+    let result = groq.query(query, generation);
+    telemetry::record(Provider::Groq, started.elapsed(), result.is_ok());
+    let result = result?;
 
     // Spawn daemon in background for future requests
-    spawn_daemon_background();
+    #[cfg(feature = "daemon")]
+    ipc::spawn_daemon();
 
-    Ok(result.command)
+    Ok(result)
 }
 
 /// Get explanation for the command
+#[cfg(feature = "gemini")]
 fn get_explanation(
     command: &str,
     gemini_api_key: &str,
-    style: ExplainStyle,
-) -> Result<String, String> {
-    // Try daemon first
+    options: &ExplainOptions,
+    mut on_chunk: impl FnMut(&str),
+) -> Result<String, SlashcmdError> {
+    if let Some(explanation) = explain_cache::get(command, options, gemini::MODEL) {
+        on_chunk(&explanation);
+        return Ok(explanation);
+    }
+
+    // Try daemon first (it caches what it explains)
     if let Some(mut stream) = IpcClient::try_connect() {
         let request = IpcRequest::Explain {
             command: command.to_string(),
-            style,
+            style: options.style.clone(),
+            verbosity: options.verbosity,
+            lang: options.lang.clone(),
+            generation: options.generation,
+            stream: true,
         };
-        return IpcClient::send_request(&mut stream, &request);
+        return IpcClient::send_request_streaming(&mut stream, &request, on_chunk);
     }
 
     // Daemon not running - make direct HTTP request
     let gemini = GeminiClient::new(gemini_api_key.to_string());
-    gemini.explain(command, style)
+    let explanation = gemini.explain_streaming(command, options, on_chunk)?;
+    explain_cache::put(command, options, gemini::MODEL, &explanation);
+    Ok(explanation)
 }
 
-/// Spawn the daemon as a detached background process
-fn spawn_daemon_background() {
-    if let Ok(exe) = std::env::current_exe() {
-        let _ = Command::new(&exe)
-            .arg("--daemon")
-            .stdin(std::process::Stdio::null())
-            .stdout(std::process::Stdio::null())
-            .stderr(std::process::Stdio::null())
-            .spawn();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlight::highlight_explanation;
+
+    #[test]
+    fn test_line_printer() {
+        let explanation = "[SAFE] Lists files\n\n```typescript\nfs.readdirSync(\".\")\n```\n\n";
+        let mut printer = LinePrinter::new(&ExplainStyle::Typescript, Vec::new());
+        // Chunks split mid-line, as streams do
+        for piece in ["[SAFE] Lis", "ts files\n", "\n```type", "script\nfs.readdirSync(\".\")\n``", "`\n\n"] {
+            printer.push(piece);
+        }
+        printer.finish();
+        let printed = String::from_utf8(printer.out).unwrap();
+        let expected = highlight_explanation(explanation, &ExplainStyle::Typescript);
+        assert_eq!(printed, format!("\n{}\n", expected));
+
+        let mut printer = LinePrinter::new(&ExplainStyle::Human, Vec::new());
+        printer.push("no newline at the end");
+        assert!(printer.out.is_empty());
+        printer.finish();
+        assert_eq!(String::from_utf8(printer.out).unwrap(), "\nno newline at the end\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_result_fd_must_be_open() {
+        assert!(use_result_fd(4_000_000).is_err());
+        assert!(RESULT_FD.get().is_none());
     }
 }
@@ -0,0 +1,110 @@
+//! Copy text to the system clipboard with whatever tool the OS has
+//!
+//! Without one (a bare server, SSH, tmux) the text goes to the terminal as
+//! an OSC 52 sequence, which most terminals and tmux put on the clipboard.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use crate::policy::{SafetyRules, Verdict};
+
+/// Clipboard tools to try, in order: macOS, Wayland, X11, Windows (and WSL)
+const TOOLS: &[(&str, &[&str])] = &[
+    ("pbcopy", &[]),
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+    ("xsel", &["--clipboard", "--input"]),
+    ("clip.exe", &[]),
+];
+
+/// Set by `copy_on_generate`
+static ON_GENERATE: OnceLock<SafetyRules> = OnceLock::new();
+
+/// The clipboard tool `copy` would try first, if any is installed
+pub fn tool() -> Option<&'static str> {
+    TOOLS
+        .iter()
+        .map(|(tool, _)| *tool)
+        .find(|tool| crate::exec::find_in_path(tool).is_some())
+}
+
+/// Copy `text` with the first clipboard tool that runs, or through the
+/// terminal when there is none
+pub fn copy(text: &str) -> Result<(), String> {
+    for (tool, args) in TOOLS {
+        let Ok(mut child) = Command::new(tool).args(*args).stdin(Stdio::piped()).spawn() else {
+            continue;
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(text.as_bytes());
+        }
+        if child.wait().is_ok_and(|status| status.success()) {
+            return Ok(());
+        }
+    }
+    copy_osc52(text).map_err(|_| "No clipboard tool found (pbcopy, wl-copy, xclip, xsel or clip.exe)".to_string())
+}
+
+#[cfg(unix)]
+fn copy_osc52(text: &str) -> std::io::Result<()> {
+    let mut tty = std::fs::OpenOptions::new().write(true).open("/dev/tty")?;
+    write!(tty, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+    tty.flush()
+}
+
+#[cfg(not(unix))]
+fn copy_osc52(_text: &str) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Put every command generated from now on on the clipboard, except those
+/// the policy blocks (`copy_on_generate` / --copy)
+pub fn copy_on_generate(rules: SafetyRules) {
+    let _ = ON_GENERATE.set(rules);
+}
+
+/// Called with each generated command, before it's confirmed or run
+pub fn generated(command: &str) {
+    let Some(rules) = ON_GENERATE.get() else {
+        return;
+    };
+    if rules.check(command) == Verdict::Blocked {
+        return;
+    }
+    // Quietly: the confirmation screen may already be drawn
+    if let Err(e) = copy(command) {
+        tracing::debug!(error = %e, "copy on generate");
+    }
+}
+
+/// Standard base64 with padding
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"ls -la | grep \xff"), "bHMgLWxhIHwgZ3JlcCD/");
+    }
+}
@@ -0,0 +1,149 @@
+//! User configuration for slashcmd
+//!
+//! Loaded from `~/.config/slashcmd/config.toml`. Every setting is optional;
+//! command-line flags take precedence over the file.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::approval::ApprovalConfig;
+use crate::budget::BudgetConfig;
+use crate::highlight::Palette;
+use crate::hooks::HooksConfig;
+use crate::ipc::{CustomStyle, ExplainStyle, Generation};
+use crate::policy::SafetyConfig;
+use crate::prompt::FlagStyle;
+use crate::secrets::SecretHandling;
+use crate::shell::Shell;
+
+/// Settings read from config.toml
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct Config {
+    /// Language for explanations, e.g. "es", "pl", "ja"
+    pub lang: Option<String>,
+    /// Extra explanation styles, one `[[styles]]` table each
+    pub styles: Vec<CustomStyle>,
+    /// `[generation]`: temperature, max_tokens (commands) and
+    /// max_output_tokens (explanations); flags override them
+    pub generation: Generation,
+    /// "color_blind" or "monochrome": safety levels marked with symbols,
+    /// weight and inverse video instead of red, yellow and green alone
+    pub accessibility: Palette,
+    /// Print a linear transcript instead of redrawing the screen (for
+    /// screen readers and braille displays); same as --plain
+    pub plain: bool,
+    /// Show the model recent queries→commands from history (opt-in)
+    pub personalize: bool,
+    /// Offer matching commands from the shell history (zsh, bash, fish)
+    /// before asking the model (opt-in)
+    pub history_matches: bool,
+    /// Ask for "long" (`--recursive`) or "short" (`-r`) options; --long-flags
+    /// and --short-flags override it
+    pub flags: FlagStyle,
+    /// Generate POSIX sh with portable flags only, for scripts that must
+    /// run on BSD, busybox and GNU alike (same as --portable)
+    pub portable: bool,
+    /// Shell commands are generated and run for: "sh", "powershell", "cmd"
+    /// or "nu"; --shell overrides it. Unset follows $SHELL for nu and pwsh.
+    pub shell: Option<Shell>,
+    /// Local regex rules that override the model's safety assessment
+    pub safety: SafetyConfig,
+    /// What to do with API keys and tokens found in queries and output
+    /// before sending them: "redact" (default), "confirm" or "off"
+    pub secrets: SecretHandling,
+    /// Put every generated command on the clipboard, run or not (same as --copy)
+    pub copy_on_generate: bool,
+    /// Follow -q and --print-only output with `# slashcmd: <LEVEL>` on
+    /// stderr (same as --safety-badge)
+    pub safety_badge: bool,
+    /// Keep a hash-chained audit log of generated and executed commands
+    pub audit: bool,
+    /// Monthly request/token limits tracked locally
+    pub budget: BudgetConfig,
+    /// Programs run before (and able to cancel) and after every command
+    pub hooks: HooksConfig,
+    /// Webhook that must approve commands the safety rules don't mark SAFE
+    pub approval: ApprovalConfig,
+    /// tmux pane (e.g. "main:0.1") to type confirmed commands into instead of running them
+    pub tmux_pane: Option<String>,
+    /// Put confirmed commands at the shell prompt (kitty, WezTerm, iTerm2) instead of running them
+    pub prefill: bool,
+    /// Self-hosted gateway the edge client talks to instead of the public worker
+    pub gateway: GatewayConfig,
+    /// "auto" (default) retries a request locally with GROQ_API_KEY when the
+    /// edge proxy can't be reached or fails; "never" reports the error
+    pub failover: Failover,
+    /// Prompt templates to use, `<prompt>.<version>.tmpl` ("builtin" ignores templates).
+    /// SLASHCMD_PROMPT_VERSION overrides it.
+    pub prompt_version: Option<String>,
+    /// Check for a new release once a day and mention it (opt-in)
+    pub update_check: bool,
+    /// Estimated tokens a query and its context (@files, --then output,
+    /// cluster and shell details) may take; larger context is cut to fit
+    pub max_context_tokens: Option<usize>,
+}
+
+/// What edge mode does when the proxy is down
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Failover {
+    /// Fall back to --local for the request, if GROQ_API_KEY is set
+    #[default]
+    Auto,
+    /// Fail with the edge error
+    Never,
+}
+
+/// The `[gateway]` section: an internal LLM gateway in front of (or instead
+/// of) the slashcmd worker, for edge mode
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct GatewayConfig {
+    /// Base URL replacing the public worker, e.g. "https://llm.corp.example/slashcmd"
+    pub url: Option<String>,
+    /// Header carrying the credential (default "Authorization")
+    pub auth_header: Option<String>,
+    /// Credential sent as-is. Without it the slashcmd login is sent as a bearer token.
+    pub auth_value: Option<String>,
+    /// Extra headers added to every request
+    pub headers: BTreeMap<String, String>,
+    /// PEM client certificate (chain) for gateways that require mTLS
+    pub client_cert: Option<PathBuf>,
+    /// PEM private key for `client_cert`
+    pub client_key: Option<PathBuf>,
+}
+
+/// Get the config directory for slashcmd
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("slashcmd")
+}
+
+/// Get the config file path
+pub fn config_file() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+/// Load the config file. A missing file is not an error.
+pub fn load() -> Result<Config, String> {
+    let path = config_file();
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    let config: Config = toml::from_str(&content).map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+    // Built-in names win everywhere, so such a style could never be used
+    if let Some(style) = config.styles.iter().find(|s| s.name.parse::<ExplainStyle>().is_ok()) {
+        return Err(format!(
+            "Invalid config {}: custom style '{}' has the name of a built-in style",
+            path.display(),
+            style.name
+        ));
+    }
+    Ok(config)
+}
@@ -0,0 +1,300 @@
+//! What a command would act on
+//!
+//! Queries about Kubernetes get the current kube context and namespace added
+//! to the prompt, and commands running kubectl or helm show the context in a
+//! banner before they run: running against the wrong cluster is the classic
+//! disaster. Cloud queries
+//! likewise get the active AWS profile, GCP project and Azure subscription,
+//! and commands using a cloud CLI show which account they'd hit. Cloud
+//! settings are read from the environment and config files, not the (slow)
+//! CLIs. Under WSL the model is told it can reach Windows too: `.exe`
+//! programs, drives under /mnt and `wslpath` for handing paths across.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Words that make a query about Kubernetes
+const KUBE_WORDS: &[&str] = &[
+    "kubectl", "k8s", "kube", "kubernetes", "helm", "pod", "pods", "deployment", "deployments",
+    "namespace", "namespaces", "statefulset", "statefulsets", "daemonset", "daemonsets",
+    "replicaset", "replicasets", "ingress", "configmap", "configmaps", "cronjob", "cronjobs",
+];
+
+/// Words that make a query about cloud resources
+const CLOUD_WORDS: &[&str] = &[
+    "aws", "s3", "ec2", "bucket", "buckets", "lambda", "rds", "iam", "ecr", "eks", "cloudformation",
+    "gcloud", "gcp", "gsutil", "gcs", "gke", "bigquery", "azure", "az", "aks", "cloud",
+];
+
+/// The cluster and namespace kubectl talks to
+#[derive(Debug, PartialEq)]
+pub struct KubeContext {
+    pub context: String,
+    pub namespace: String,
+}
+
+impl KubeContext {
+    /// Read the current context from kubectl. None without kubectl or a context.
+    pub fn current() -> Option<Self> {
+        let context = kubectl(&["config", "current-context"])?;
+        let namespace = kubectl(&["config", "view", "--minify", "--output", "jsonpath={..namespace}"])
+            .unwrap_or_else(|| "default".to_string());
+        Some(Self { context, namespace })
+    }
+
+    /// Context for the model
+    pub fn prompt(&self) -> String {
+        format!(
+            "The current kube context is `{}` with namespace `{}`. kubectl uses them unless the \
+             request names another cluster or namespace.",
+            self.context, self.namespace
+        )
+    }
+
+    /// Shown above the command
+    pub fn banner(&self) -> String {
+        format!("⎈ kube context: {}  namespace: {}", self.context, self.namespace)
+    }
+}
+
+/// Whether a command runs kubectl or helm
+pub fn runs_kube_cli(command: &str) -> bool {
+    command
+        .split(|c: char| c.is_whitespace() || "|;&()`\"'".contains(c))
+        .any(|word| matches!(word.rsplit('/').next(), Some("kubectl" | "helm")))
+}
+
+/// Shown above a command that runs kubectl or helm
+pub fn kube_banner(command: &str) -> Option<String> {
+    if !runs_kube_cli(command) {
+        return None;
+    }
+    KubeContext::current().map(|kube| kube.banner())
+}
+
+/// Whether a query is about Kubernetes
+pub fn mentions_kubernetes(query: &str) -> bool {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| KUBE_WORDS.contains(&word.to_lowercase().as_str()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cloud {
+    Aws,
+    Gcp,
+    Azure,
+}
+
+/// The account a cloud CLI acts on
+#[derive(Debug, PartialEq)]
+pub struct CloudProfile {
+    pub cloud: Cloud,
+    /// e.g. "profile prod (eu-west-1)"
+    pub description: String,
+}
+
+impl Cloud {
+    fn name(self) -> &'static str {
+        match self {
+            Cloud::Aws => "AWS",
+            Cloud::Gcp => "GCP",
+            Cloud::Azure => "Azure",
+        }
+    }
+}
+
+/// Whether a query is about cloud resources
+pub fn mentions_cloud(query: &str) -> bool {
+    words(query).any(|word| CLOUD_WORDS.contains(&word.to_lowercase().as_str()))
+}
+
+/// The cloud whose CLI a command runs
+pub fn cloud_cli(command: &str) -> Option<Cloud> {
+    words(command).find_map(|word| match word {
+        "aws" => Some(Cloud::Aws),
+        "gcloud" | "gsutil" | "bq" => Some(Cloud::Gcp),
+        "az" => Some(Cloud::Azure),
+        _ => None,
+    })
+}
+
+/// Active profiles of every cloud that has one configured
+pub fn cloud_profiles() -> Vec<CloudProfile> {
+    let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    let home = dirs::home_dir().unwrap_or_default();
+    let gcloud_dir = var("CLOUDSDK_CONFIG")
+        .map(Into::into)
+        .unwrap_or_else(|| dirs::config_dir().unwrap_or_default().join("gcloud"));
+
+    let aws = var("AWS_PROFILE")
+        .or_else(|| var("AWS_DEFAULT_PROFILE"))
+        .or_else(|| home.join(".aws").exists().then(|| "default".to_string()))
+        .map(|profile| match var("AWS_REGION").or_else(|| var("AWS_DEFAULT_REGION")) {
+            Some(region) => format!("profile {} ({})", profile, region),
+            None => format!("profile {}", profile),
+        });
+    let gcp = var("CLOUDSDK_CORE_PROJECT")
+        .or_else(|| gcloud_project(&gcloud_dir))
+        .map(|project| format!("project {}", project));
+    let azure = var("AZURE_SUBSCRIPTION_ID")
+        .or_else(|| {
+            let profile = fs::read_to_string(home.join(".azure").join("azureProfile.json")).ok()?;
+            azure_subscription(&profile)
+        })
+        .map(|subscription| format!("subscription {}", subscription));
+
+    [(Cloud::Aws, aws), (Cloud::Gcp, gcp), (Cloud::Azure, azure)]
+        .into_iter()
+        .filter_map(|(cloud, description)| description.map(|description| CloudProfile { cloud, description }))
+        .collect()
+}
+
+/// Context for the model
+pub fn cloud_prompt(profiles: &[CloudProfile]) -> String {
+    let accounts: Vec<String> = profiles
+        .iter()
+        .map(|p| format!("{} {}", p.cloud.name(), p.description))
+        .collect();
+    format!(
+        "Active cloud accounts: {}. Commands act on these unless the request names another.",
+        accounts.join("; ")
+    )
+}
+
+/// Shown above a command that uses a cloud CLI
+pub fn cloud_banner(command: &str) -> Option<String> {
+    let cloud = cloud_cli(command)?;
+    let description = cloud_profiles()
+        .into_iter()
+        .find(|p| p.cloud == cloud)
+        .map(|p| p.description)
+        .unwrap_or_else(|| "no profile configured".to_string());
+    Some(format!("☁ {} {}", cloud.name(), description))
+}
+
+/// The WSL distribution this runs in, or None outside WSL. WSL sets
+/// WSL_DISTRO_NAME; its kernel release names Microsoft in case it's unset.
+pub fn wsl_distro() -> Option<String> {
+    if let Some(name) = std::env::var("WSL_DISTRO_NAME").ok().filter(|v| !v.is_empty()) {
+        return Some(name);
+    }
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease").ok()?;
+    is_wsl_kernel(&release).then(|| "WSL".to_string())
+}
+
+/// `5.15.153.1-microsoft-standard-WSL2` (WSL 2) or `4.4.0-19041-Microsoft` (WSL 1)
+fn is_wsl_kernel(release: &str) -> bool {
+    release.to_lowercase().contains("microsoft")
+}
+
+/// Context for the model
+pub fn wsl_prompt(distro: &str) -> String {
+    format!(
+        "This is Linux ({}) under WSL on Windows. Windows programs run from here by their .exe name \
+         (explorer.exe ., notepad.exe, clip.exe, powershell.exe -c, cmd.exe /c), Windows drives are \
+         mounted at /mnt/c and so on, and a Linux path given to a Windows program must be converted \
+         with wslpath -w (wslpath -u for the other way).",
+        distro
+    )
+}
+
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty())
+}
+
+/// `project` from the active gcloud configuration
+fn gcloud_project(gcloud_dir: &Path) -> Option<String> {
+    let active = fs::read_to_string(gcloud_dir.join("active_config")).unwrap_or_else(|_| "default".to_string());
+    let config = fs::read_to_string(
+        gcloud_dir
+            .join("configurations")
+            .join(format!("config_{}", active.trim())),
+    )
+    .ok()?;
+    ini_value(&config, "core", "project")
+}
+
+/// A `key = value` from an INI section
+fn ini_value(content: &str, section: &str, key: &str) -> Option<String> {
+    let mut in_section = false;
+    for line in content.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_section = name.trim() == section;
+        } else if let Some((k, v)) = line.split_once('=') {
+            if in_section && k.trim() == key && !v.trim().is_empty() {
+                return Some(v.trim().to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Name of the default subscription in azureProfile.json
+fn azure_subscription(profile: &str) -> Option<String> {
+    // az writes the file with a byte order mark
+    let profile: serde_json::Value = serde_json::from_str(profile.trim_start_matches('\u{feff}')).ok()?;
+    profile["subscriptions"]
+        .as_array()?
+        .iter()
+        .find(|s| s["isDefault"].as_bool() == Some(true))
+        .and_then(|s| s["name"].as_str())
+        .map(str::to_string)
+}
+
+fn kubectl(args: &[&str]) -> Option<String> {
+    let output = Command::new("kubectl")
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !value.is_empty()).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mentions_kubernetes() {
+        assert!(mentions_kubernetes("restart the api deployment"));
+        assert!(mentions_kubernetes("list Pods in crashloop"));
+        assert!(mentions_kubernetes("k8s nodes by memory"));
+        assert!(!mentions_kubernetes("find large files"));
+        assert!(!mentions_kubernetes("show kubelet-free podcasts"));
+
+        assert!(runs_kube_cli("kubectl delete pod -l app=api --field-selector=status.phase=Failed"));
+        assert!(runs_kube_cli("helm uninstall old-release"));
+        assert!(!runs_kube_cli("cat kubectl.log"));
+    }
+
+    #[test]
+    fn test_cloud_detection() {
+        assert!(mentions_cloud("delete the old buckets"));
+        assert!(!mentions_cloud("delete old log files"));
+        assert_eq!(cloud_cli("aws s3 rb s3://old --force"), Some(Cloud::Aws));
+        assert_eq!(cloud_cli("gsutil -m rm -r gs://x"), Some(Cloud::Gcp));
+        assert_eq!(cloud_cli("xargs -n1 az group delete --name"), Some(Cloud::Azure));
+        assert_eq!(cloud_cli("rm -rf awsome"), None);
+    }
+
+    #[test]
+    fn test_cloud_config_parsing() {
+        let gcloud = "[core]\naccount = me@x.com\nproject = shop-prod\n\n[compute]\nregion = eu\n";
+        assert_eq!(ini_value(gcloud, "core", "project").as_deref(), Some("shop-prod"));
+        assert_eq!(ini_value(gcloud, "core", "region"), None);
+
+        let azure = "\u{feff}{\"subscriptions\": [{\"name\": \"Dev\", \"isDefault\": false}, {\"name\": \"Prod\", \"isDefault\": true}]}";
+        assert_eq!(azure_subscription(azure).as_deref(), Some("Prod"));
+    }
+
+    #[test]
+    fn test_is_wsl_kernel() {
+        assert!(is_wsl_kernel("5.15.153.1-microsoft-standard-WSL2\n"));
+        assert!(is_wsl_kernel("4.4.0-19041-Microsoft"));
+        assert!(!is_wsl_kernel("6.8.0-45-generic"));
+    }
+}
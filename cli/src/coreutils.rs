@@ -0,0 +1,143 @@
+//! BSD, GNU or BusyBox core utilities
+//!
+//! `sed -i`, `date -d` and `stat -c` mean different things (or nothing) on
+//! macOS and on Linux, and a command written for the other flavor fails at
+//! runtime, sometimes halfway through. The local flavor is told to the model
+//! and generated commands are checked for the classic cross-flavor mistakes
+//! before they run. `sed --version` is the probe: GNU sed answers, BusyBox
+//! says it's "not GNU sed", and BSD sed rejects the option.
+
+use regex::Regex;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flavor {
+    Gnu,
+    Bsd,
+    Busybox,
+}
+
+impl Flavor {
+    /// Context for the model
+    pub fn prompt(&self) -> &'static str {
+        match self {
+            Flavor::Gnu => {
+                "The core utilities here are GNU: sed -i takes no suffix argument, date -d parses dates, \
+                 stat -c formats, tac reverses lines."
+            }
+            Flavor::Bsd => {
+                "The core utilities here are BSD (as on macOS): sed -i needs a suffix argument (sed -i ''), \
+                 date uses -v and -j -f instead of -d, stat uses -f instead of -c, find has no -printf, \
+                 grep has no -P, tail -r instead of tac."
+            }
+            Flavor::Busybox => {
+                "The core utilities here are BusyBox: stick to basic POSIX options; find has no -printf \
+                 and grep has no -P."
+            }
+        }
+    }
+}
+
+/// Flavor found by `detect`, used by `lint`
+static FLAVOR: OnceLock<Flavor> = OnceLock::new();
+
+/// Probe the local `sed` once and remember the flavor for `lint`. None when
+/// there's no sed to ask.
+pub fn detect() -> Option<Flavor> {
+    if let Some(flavor) = FLAVOR.get() {
+        return Some(*flavor);
+    }
+    let output = Command::new("sed")
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let version = output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned());
+    let flavor = from_version(version.as_deref());
+    let _ = FLAVOR.set(flavor);
+    Some(flavor)
+}
+
+/// The flavor behind `sed --version` output; None if it failed
+fn from_version(version: Option<&str>) -> Flavor {
+    match version {
+        Some(v) if v.contains("BusyBox") || v.contains("not GNU") => Flavor::Busybox,
+        Some(v) if v.contains("GNU") => Flavor::Gnu,
+        _ => Flavor::Bsd,
+    }
+}
+
+// Options of the same command: anything up to the next pipe or separator
+const SAME: &str = r"[^|;&\n]*?\s";
+
+fn rules() -> &'static [(Regex, &'static [Flavor], &'static str)] {
+    static RULES: OnceLock<Vec<(Regex, &'static [Flavor], &'static str)>> = OnceLock::new();
+    RULES.get_or_init(|| {
+        let bsd: &'static [Flavor] = &[Flavor::Bsd];
+        let gnu: &'static [Flavor] = &[Flavor::Gnu, Flavor::Busybox];
+        let not_gnu: &'static [Flavor] = &[Flavor::Bsd, Flavor::Busybox];
+        [
+            (format!(r#"\bsed\b{SAME}-i\s+(?:-e\b|['"]?[sy][/|#,:])"#), bsd, "BSD sed -i needs a suffix argument: sed -i '' ..."),
+            (format!(r#"\bsed\b{SAME}-i\s+(?:''|"")(?:\s|$)"#), gnu, "GNU sed -i takes no suffix; '' would be read as the script"),
+            (format!(r"\bdate\b{SAME}(?:-d\b|--date\b)"), bsd, "BSD date has no -d; use -v or -j -f"),
+            (format!(r"\bdate\b{SAME}-v\s*[+-]?\d"), gnu, "GNU date has no -v; use -d"),
+            (format!(r"\bstat\b{SAME}(?:-c\b|--format\b)"), bsd, "BSD stat has no -c; use -f"),
+            (format!(r#"\bstat\b{SAME}-f\s*['"]?%"#), gnu, "GNU stat -f shows file system status; use -c for a format"),
+            (format!(r"\bgrep\b{SAME}-[a-zA-Z]*P"), not_gnu, "grep -P is GNU only; use grep -E"),
+            (format!(r"\bfind\b{SAME}-printf\b"), not_gnu, "find -printf is GNU only; use -exec stat"),
+            (format!(r"\bdu\b{SAME}--max-depth\b"), bsd, "BSD du has no --max-depth; use -d"),
+            (r"(?:^|[|;&(]\s*)tac\b".to_string(), bsd, "tac isn't on BSD; use tail -r"),
+            (format!(r"\btail\b{SAME}-r\b"), gnu, "tail -r is BSD only; use tac"),
+        ]
+        .into_iter()
+        .map(|(pattern, flavors, message)| (Regex::new(&pattern).expect("built-in lint pattern"), flavors, message))
+        .collect()
+    })
+}
+
+/// What in `command` won't work with the detected utilities. Empty when
+/// `detect` wasn't called (remote targets) or found nothing.
+pub fn lint(command: &str) -> Vec<&'static str> {
+    FLAVOR.get().map(|flavor| lint_for(command, *flavor)).unwrap_or_default()
+}
+
+fn lint_for(command: &str, flavor: Flavor) -> Vec<&'static str> {
+    rules()
+        .iter()
+        .filter(|(re, flavors, _)| flavors.contains(&flavor) && re.is_match(command))
+        .map(|(_, _, message)| *message)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_version() {
+        assert_eq!(from_version(Some("sed (GNU sed) 4.9\nPackaged by Debian")), Flavor::Gnu);
+        assert_eq!(from_version(Some("This is not GNU sed version 4.0")), Flavor::Busybox);
+        assert_eq!(from_version(None), Flavor::Bsd);
+    }
+
+    #[test]
+    fn test_lint_for() {
+        assert_eq!(lint_for("sed -i 's/foo/bar/' a.txt", Flavor::Bsd).len(), 1);
+        assert!(lint_for("sed -i '' 's/foo/bar/' a.txt", Flavor::Bsd).is_empty());
+        assert_eq!(lint_for("sed -i '' 's/foo/bar/' a.txt", Flavor::Gnu).len(), 1);
+        assert!(lint_for("sed -i 's/foo/bar/' a.txt", Flavor::Gnu).is_empty());
+        assert!(lint_for("sed -i.bak 's/foo/bar/' a.txt", Flavor::Bsd).is_empty());
+
+        assert_eq!(lint_for("date -d yesterday +%F", Flavor::Bsd).len(), 1);
+        assert_eq!(lint_for("date -v-1d +%F", Flavor::Gnu).len(), 1);
+        assert_eq!(lint_for("find . -printf '%s %p\\n' | sort -n", Flavor::Bsd).len(), 1);
+        assert_eq!(lint_for("git log | tac", Flavor::Bsd).len(), 1);
+        assert_eq!(lint_for("grep -oP '\\d+' log", Flavor::Busybox).len(), 1);
+
+        // Options of a different command in the pipeline don't count
+        assert!(lint_for("date | grep -d skip x", Flavor::Bsd).is_empty());
+        assert!(lint_for("ls -la", Flavor::Bsd).is_empty());
+    }
+}
@@ -1,14 +1,21 @@
-use std::io::{BufRead, BufReader, Write};
-use std::os::unix::net::UnixStream;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
+#[cfg(feature = "edge")]
 use crate::edge::EdgeClient;
-use crate::gemini::GeminiClient;
+#[cfg(feature = "gemini")]
+use crate::explain_cache;
+#[cfg(feature = "gemini")]
+use crate::gemini::{self, GeminiClient};
 use crate::groq::GroqClient;
-use crate::ipc::{IpcRequest, IpcResponse, IpcServer, SOCKET_PATH};
+use crate::ipc::{
+    Connection, DaemonHealth, ExplainOptions, IpcClient, IpcRequest, IpcResponse, IpcServer, ProviderHealth, SOCKET_PATH,
+    VERSION,
+};
+use crate::prompt::{self, Example};
 
 /// Daemon idle timeout in seconds (5 minutes)
 const DAEMON_IDLE_TIMEOUT_SECS: u64 = 300;
@@ -16,13 +23,110 @@ const DAEMON_IDLE_TIMEOUT_SECS: u64 = 300;
 /// Keep-alive interval in seconds (refresh TLS connection before it times out)
 const KEEP_ALIVE_INTERVAL_SECS: u64 = 30;
 
+/// Requests per client remembered for follow-ups
+const CONVERSATION_TURNS: usize = 3;
+
+/// A conversation older than this is forgotten (10 minutes)
+const CONVERSATION_TTL_SECS: u64 = 600;
+
+/// Counters behind `IpcRequest::Health`, shared with the warmup threads
+struct Metrics {
+    started: Instant,
+    requests: u64,
+    busy: Duration,
+    cache_hits: u64,
+    cache_lookups: u64,
+    /// Provider → when it was last warmed up, and the error if that failed
+    warmups: BTreeMap<&'static str, (Instant, Option<String>)>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            requests: 0,
+            busy: Duration::ZERO,
+            cache_hits: 0,
+            cache_lookups: 0,
+            warmups: BTreeMap::new(),
+        }
+    }
+
+    fn warmed(&mut self, provider: &'static str, result: &Result<(), String>) {
+        self.warmups.insert(provider, (Instant::now(), result.clone().err()));
+    }
+
+    fn health(&self, now: Instant) -> DaemonHealth {
+        DaemonHealth {
+            uptime_secs: now.duration_since(self.started).as_secs(),
+            requests: self.requests,
+            avg_latency_ms: (self.requests > 0).then(|| (self.busy.as_millis() / self.requests as u128) as u64),
+            cache_hits: self.cache_hits,
+            cache_lookups: self.cache_lookups,
+            providers: self
+                .warmups
+                .iter()
+                .map(|(name, (at, error))| ProviderHealth {
+                    name: name.to_string(),
+                    warmed_secs_ago: now.duration_since(*at).as_secs(),
+                    error: error.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Recent requests and commands per client, so a query can refer back to
+/// the previous one ("now only the ones older than a week")
+#[derive(Default)]
+struct Conversations {
+    clients: HashMap<String, (Instant, VecDeque<Example>)>,
+}
+
+impl Conversations {
+    /// The client's recent turns, oldest first, dropping stale conversations
+    fn recent(&mut self, client: &str, now: Instant) -> Vec<Example> {
+        let ttl = Duration::from_secs(CONVERSATION_TTL_SECS);
+        self.clients.retain(|_, (last, _)| now.duration_since(*last) < ttl);
+        self.clients
+            .get(client)
+            .map(|(_, turns)| turns.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn record(&mut self, client: &str, query: &str, command: &str, now: Instant) {
+        let (last, turns) = self.clients.entry(client.to_string()).or_insert_with(|| (now, VecDeque::new()));
+        *last = now;
+        if turns.len() == CONVERSATION_TURNS {
+            turns.pop_front();
+        }
+        turns.push_back(Example {
+            query: prompt::strip_context(query).to_string(),
+            command: command.to_string(),
+        });
+    }
+}
+
 /// Lazy-initialized Gemini client (warmed up on first explain request)
+#[cfg(feature = "gemini")]
 struct LazyGemini {
     client: Option<GeminiClient>,
     api_key: Option<String>,
     warmed_up: bool,
 }
 
+/// Built without Gemini: explain requests fail
+#[cfg(not(feature = "gemini"))]
+struct LazyGemini;
+
+#[cfg(not(feature = "gemini"))]
+impl LazyGemini {
+    fn new(_api_key: Option<String>) -> Self {
+        LazyGemini
+    }
+}
+
+#[cfg(feature = "gemini")]
 impl LazyGemini {
     fn new(api_key: Option<String>) -> Self {
         Self {
@@ -32,7 +136,7 @@ impl LazyGemini {
         }
     }
 
-    fn get_or_init(&mut self) -> Result<&GeminiClient, String> {
+    fn get_or_init(&mut self, metrics: &Mutex<Metrics>) -> Result<&GeminiClient, String> {
         if self.client.is_none() {
             let api_key = self.api_key.clone().ok_or_else(|| {
                 "GEMINI_API_KEY not set. Set it to enable command explanations.".to_string()
@@ -45,7 +149,9 @@ impl LazyGemini {
         // Warmup on first use
         if !self.warmed_up {
             eprintln!("Warming up Gemini TLS connection...");
-            if let Err(e) = client.warmup() {
+            let result = client.warmup().map_err(|e| e.to_string());
+            metrics.lock().unwrap().warmed("gemini", &result);
+            if let Err(e) = result {
                 eprintln!("Gemini warmup warning: {}", e);
             } else {
                 eprintln!("Gemini connection ready");
@@ -67,10 +173,14 @@ pub fn run_daemon(groq_api_key: String, gemini_api_key: Option<String>) -> Resul
     let start = Instant::now();
     let last_activity = Arc::new(AtomicU64::new(0));
     let shutdown = Arc::new(AtomicBool::new(false));
+    let mut conversations = Conversations::default();
+    let metrics = Arc::new(Mutex::new(Metrics::new()));
 
     // Warmup Groq TLS connection immediately (free /models call)
     eprintln!("Warming up Groq TLS connection...");
-    if let Err(e) = groq.warmup() {
+    let result = groq.warmup();
+    metrics.lock().unwrap().warmed("groq", &result);
+    if let Err(e) = result {
         eprintln!("Warning: Groq warmup failed: {}", e);
     } else {
         eprintln!("Groq connection ready");
@@ -79,6 +189,7 @@ pub fn run_daemon(groq_api_key: String, gemini_api_key: Option<String>) -> Resul
     // Spawn keep-alive thread for Groq (every 30 seconds)
     let groq_keepalive = Arc::clone(&groq);
     let shutdown_keepalive = Arc::clone(&shutdown);
+    let metrics_keepalive = Arc::clone(&metrics);
     thread::spawn(move || {
         loop {
             thread::sleep(Duration::from_secs(KEEP_ALIVE_INTERVAL_SECS));
@@ -87,18 +198,26 @@ pub fn run_daemon(groq_api_key: String, gemini_api_key: Option<String>) -> Resul
                 break;
             }
 
-            if let Err(e) = groq_keepalive.warmup() {
+            let result = groq_keepalive.warmup();
+            metrics_keepalive.lock().unwrap().warmed("groq", &result);
+            if let Err(e) = result {
                 eprintln!("Groq keep-alive failed: {}", e);
             }
         }
     });
 
     // Spawn keep-alive thread for Edge proxy (keeps Worker + Groq connections warm)
+    #[cfg(feature = "edge")]
     let shutdown_edge = Arc::clone(&shutdown);
+    #[cfg(feature = "edge")]
+    let metrics_edge = Arc::clone(&metrics);
+    #[cfg(feature = "edge")]
     thread::spawn(move || {
         let edge = EdgeClient::with_test_jwt();
         // Initial warmup
-        if let Err(e) = edge.warmup() {
+        let result = edge.warmup();
+        metrics_edge.lock().unwrap().warmed("edge", &result);
+        if let Err(e) = result {
             eprintln!("Edge warmup failed: {}", e);
         } else {
             eprintln!("Edge proxy connection ready");
@@ -111,7 +230,9 @@ pub fn run_daemon(groq_api_key: String, gemini_api_key: Option<String>) -> Resul
                 break;
             }
 
-            if let Err(e) = edge.warmup() {
+            let result = edge.warmup();
+            metrics_edge.lock().unwrap().warmed("edge", &result);
+            if let Err(e) = result {
                 eprintln!("Edge keep-alive failed: {}", e);
             }
         }
@@ -133,13 +254,19 @@ pub fn run_daemon(groq_api_key: String, gemini_api_key: Option<String>) -> Resul
         }
 
         // Poll for connections (non-blocking)
-        if let Some(mut stream) = server.accept() {
+        if let Some(stream) = server.accept() {
             // Update activity timestamp
             last_activity.store(start.elapsed().as_secs(), Ordering::Relaxed);
 
             // Handle request and send response
-            let response = handle_request(&mut stream, &groq, &gemini);
-            send_response(&mut stream, &response);
+            let mut connection = Connection::new(stream);
+            let response = handle_request(&mut connection, &groq, &gemini, &mut conversations, &metrics, &shutdown);
+            connection.send(&response);
+
+            if shutdown.load(Ordering::Relaxed) {
+                eprintln!("Shutdown requested, exiting");
+                break;
+            }
         }
 
         // Small sleep to avoid busy-waiting (10ms = 100 polls/sec)
@@ -150,74 +277,240 @@ pub fn run_daemon(groq_api_key: String, gemini_api_key: Option<String>) -> Resul
 }
 
 fn handle_request(
-    stream: &mut UnixStream,
+    connection: &mut Connection,
     groq: &GroqClient,
     gemini: &Arc<Mutex<LazyGemini>>,
+    conversations: &mut Conversations,
+    metrics: &Mutex<Metrics>,
+    shutdown: &AtomicBool,
 ) -> IpcResponse {
-    let mut reader = BufReader::new(&*stream);
-    let mut line = String::new();
-
-    if reader.read_line(&mut line).is_err() {
-        return IpcResponse {
-            success: false,
-            result: None,
-            error: Some("Failed to read request".to_string()),
-        };
-    }
+    // Answer the version handshake, then read the request that follows it
+    let request = loop {
+        match connection.read_request() {
+            Ok(IpcRequest::Hello { .. }) => connection.send(&IpcResponse {
+                success: true,
+                result: Some(VERSION.to_string()),
+                error: None,
+                partial: false,
+                failure: None,
+            }),
+            Ok(request) => break request,
+            Err(e) => return IpcResponse::failed(e.into()),
+        }
+    };
 
-    let request: IpcRequest = match serde_json::from_str(&line) {
-        Ok(r) => r,
-        Err(e) => {
+    let started = Instant::now();
+    let response = match request {
+        IpcRequest::Hello { .. } => unreachable!("answered above"),
+        IpcRequest::Shutdown => {
+            shutdown.store(true, Ordering::Relaxed);
             return IpcResponse {
-                success: false,
+                success: true,
                 result: None,
-                error: Some(format!("Invalid request: {}", e)),
-            }
+                error: None,
+                partial: false,
+                failure: None,
+            };
         }
-    };
-
-    match request {
-        IpcRequest::Command { query } => match groq.query(&query) {
-            Ok(cmd_result) => IpcResponse {
+        IpcRequest::Health => {
+            let health = metrics.lock().unwrap().health(started);
+            return IpcResponse {
                 success: true,
-                result: Some(cmd_result.command), // For now, daemon returns just command
+                result: serde_json::to_string(&health).ok(),
                 error: None,
-            },
-            Err(e) => IpcResponse {
-                success: false,
-                result: None,
-                error: Some(e),
-            },
-        },
-        IpcRequest::Explain { command, style } => {
-            let mut gemini_guard = gemini.lock().unwrap();
-            match gemini_guard.get_or_init() {
-                Ok(client) => match client.explain(&command, style) {
-                    Ok(result) => IpcResponse {
+                partial: false,
+                failure: None,
+            };
+        }
+        IpcRequest::Command { query, client, generation } => {
+            let now = Instant::now();
+            let turns = client.as_deref().map(|c| conversations.recent(c, now)).unwrap_or_default();
+            match groq.query(&prompt::with_conversation(&query, &turns), &generation) {
+                Ok(cmd_result) => {
+                    if let Some(client) = client.as_ref().filter(|_| cmd_result.question.is_none()) {
+                        conversations.record(client, &query, &cmd_result.command, now);
+                    }
+                    // The whole result as JSON, so a clarifying question gets through
+                    IpcResponse {
                         success: true,
-                        result: Some(result),
+                        result: serde_json::to_string(&cmd_result).ok(),
                         error: None,
-                    },
-                    Err(e) => IpcResponse {
-                        success: false,
-                        result: None,
-                        error: Some(e),
-                    },
-                },
-                Err(e) => IpcResponse {
-                    success: false,
-                    result: None,
-                    error: Some(e),
-                },
+                        partial: false,
+                        failure: None,
+                    }
+                }
+                Err(e) => IpcResponse::failed(e),
             }
         }
+        IpcRequest::Explain { command, style, verbosity, lang, generation, stream: streamed } => {
+            let mut send_chunk = |text: &str| {
+                connection.send(&IpcResponse {
+                    success: true,
+                    result: Some(text.to_string()),
+                    error: None,
+                    partial: true,
+                    failure: None,
+                })
+            };
+            let on_chunk = streamed.then_some(&mut send_chunk as &mut dyn FnMut(&str));
+            explain(gemini, metrics, &command, &ExplainOptions { style, verbosity, lang, generation }, on_chunk)
+        }
+    };
+
+    let mut metrics = metrics.lock().unwrap();
+    metrics.requests += 1;
+    metrics.busy += started.elapsed();
+    response
+}
+
+#[cfg(feature = "gemini")]
+/// Explain from the cache or Gemini. With `on_chunk`, Gemini's reply is
+/// passed along piece by piece as well; a cache hit is just the final frame.
+fn explain(
+    gemini: &Mutex<LazyGemini>,
+    metrics: &Mutex<Metrics>,
+    command: &str,
+    options: &ExplainOptions,
+    on_chunk: Option<&mut dyn FnMut(&str)>,
+) -> IpcResponse {
+    let cached = explain_cache::get(command, options, gemini::MODEL);
+    {
+        let mut metrics = metrics.lock().unwrap();
+        metrics.cache_lookups += 1;
+        metrics.cache_hits += cached.is_some() as u64;
+    }
+    if let Some(explanation) = cached {
+        return IpcResponse {
+            success: true,
+            result: Some(explanation),
+            error: None,
+            partial: false,
+            failure: None,
+        };
+    }
+    let mut gemini_guard = gemini.lock().unwrap();
+    match gemini_guard.get_or_init(metrics) {
+        Ok(client) => match on_chunk
+            .map(|f| client.explain_streaming(command, options, f))
+            .unwrap_or_else(|| client.explain(command, options))
+        {
+            Ok(result) => {
+                explain_cache::put(command, options, gemini::MODEL, &result);
+                IpcResponse {
+                    success: true,
+                    result: Some(result),
+                    error: None,
+                    partial: false,
+                    failure: None,
+                }
+            }
+            Err(e) => IpcResponse::failed(e),
+        },
+        Err(e) => IpcResponse::failed(e.into()),
+    }
+}
+
+#[cfg(not(feature = "gemini"))]
+fn explain(
+    _gemini: &Mutex<LazyGemini>,
+    _metrics: &Mutex<Metrics>,
+    _command: &str,
+    _options: &ExplainOptions,
+    _on_chunk: Option<&mut dyn FnMut(&str)>,
+) -> IpcResponse {
+    IpcResponse::failed(crate::error::SlashcmdError::Config(
+        "This slashcmd was built without the `gemini` feature".to_string(),
+    ))
+}
+
+/// `slashcmd daemon status`: ask the running daemon for its health.
+/// Returns the exit code: 1 when no daemon is running.
+pub fn status(json: bool) -> Result<i32, String> {
+    let Some(mut stream) = IpcClient::try_connect() else {
+        if json {
+            println!("{}", serde_json::json!({ "running": false }));
+        } else {
+            eprintln!("Daemon not running. It starts after the first --local request.");
+        }
+        return Ok(1);
+    };
+    let reply = IpcClient::send_request(&mut stream, &IpcRequest::Health).map_err(|e| e.to_string())?;
+    let health: DaemonHealth =
+        serde_json::from_str(&reply).map_err(|e| format!("Invalid health reply: {}", e))?;
+
+    if json {
+        let mut value = serde_json::to_value(&health).map_err(|e| format!("Failed to encode health: {}", e))?;
+        value["running"] = true.into();
+        println!("{}", value);
+        return Ok(0);
+    }
+
+    println!("Uptime:   {}", format_secs(health.uptime_secs));
+    match health.avg_latency_ms {
+        Some(avg) => println!("Requests: {} (avg {} ms)", health.requests, avg),
+        None => println!("Requests: 0"),
+    }
+    if let Some(rate) = (health.cache_hits * 100).checked_div(health.cache_lookups) {
+        println!(
+            "Cache:    {}% of explanations ({}/{})",
+            rate, health.cache_hits, health.cache_lookups
+        );
+    }
+    for provider in &health.providers {
+        let ago = format_secs(provider.warmed_secs_ago);
+        match &provider.error {
+            None => println!("{:<9} warmed {} ago", format!("{}:", provider.name), ago),
+            Some(e) => println!("{:<9} warmup failed {} ago: {}", format!("{}:", provider.name), ago, e),
+        }
     }
+    Ok(0)
 }
 
-fn send_response(stream: &mut UnixStream, response: &IpcResponse) {
-    let mut json = serde_json::to_string(response)
-        .unwrap_or_else(|_| r#"{"success":false,"error":"Serialize error"}"#.to_string());
-    json.push('\n');
-    let _ = stream.write_all(json.as_bytes());
-    let _ = stream.flush();
+/// "45s", "3m 12s", "2h 5m"
+fn format_secs(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversations() {
+        let mut conversations = Conversations::default();
+        let start = Instant::now();
+        for i in 0..4 {
+            conversations.record("1", &format!("query {}\n\nContext: x", i), &format!("cmd {}", i), start);
+        }
+        let turns = conversations.recent("1", start);
+        assert_eq!(turns.len(), CONVERSATION_TURNS);
+        assert_eq!(turns[0].query, "query 1");
+        assert_eq!(turns[2].command, "cmd 3");
+        assert!(conversations.recent("2", start).is_empty());
+
+        let later = start + Duration::from_secs(CONVERSATION_TTL_SECS);
+        assert!(conversations.recent("1", later).is_empty());
+    }
+
+    #[test]
+    fn test_health() {
+        let mut metrics = Metrics::new();
+        let now = metrics.started;
+        assert_eq!(metrics.health(now).avg_latency_ms, None);
+
+        metrics.requests = 4;
+        metrics.busy = Duration::from_millis(1000);
+        metrics.warmed("groq", &Ok(()));
+        metrics.warmed("edge", &Err("offline".to_string()));
+        let health = metrics.health(now + Duration::from_secs(90));
+        assert_eq!(health.uptime_secs, 90);
+        assert_eq!(health.avg_latency_ms, Some(250));
+        assert_eq!(health.providers[0].name, "edge");
+        assert_eq!(health.providers[0].error.as_deref(), Some("offline"));
+        assert_eq!(format_secs(192), "3m 12s");
+    }
 }
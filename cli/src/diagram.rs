@@ -0,0 +1,278 @@
+//! Data-flow diagram explanation renderer
+//!
+//! The model returns one pipeline stage per line, each followed by
+//! ` # what it outputs`. Stages are drawn as boxes connected by arrows
+//! (Unicode box drawing) or emitted as Mermaid flowchart source.
+
+use crate::annotate::split_comment;
+
+/// Widest stage text before it wraps inside its box
+const MAX_BOX_TEXT: usize = 50;
+
+/// How one stage hands over to the next
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Connector {
+    Pipe,
+    IfOk,
+    IfFailed,
+    Then,
+}
+
+impl Connector {
+    fn from_operator(op: &str) -> Self {
+        match op {
+            "&&" => Connector::IfOk,
+            "||" => Connector::IfFailed,
+            ";" => Connector::Then,
+            _ => Connector::Pipe,
+        }
+    }
+
+    /// Label shown on the arrow for non-pipe connectors
+    fn label(&self) -> Option<&'static str> {
+        match self {
+            Connector::Pipe => None,
+            Connector::IfOk => Some("if it succeeds"),
+            Connector::IfFailed => Some("if it fails"),
+            Connector::Then => Some("then"),
+        }
+    }
+}
+
+/// One box in the diagram
+struct Stage {
+    code: String,
+    output: Option<String>,
+    /// Connector to the next stage
+    next: Connector,
+}
+
+/// Render the model's stage list as a box-and-arrow diagram
+pub fn render(code: &str) -> String {
+    let stages = parse_stages(code);
+    if stages.is_empty() {
+        return code.trim_end().to_string();
+    }
+
+    let wrapped: Vec<Vec<String>> = stages.iter().map(|s| wrap(&s.code, MAX_BOX_TEXT)).collect();
+    let inner = wrapped
+        .iter()
+        .flat_map(|lines| lines.iter().map(|l| l.chars().count()))
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let center = inner / 2 + 1;
+
+    let mut result = String::new();
+    for (i, (stage, lines)) in stages.iter().zip(&wrapped).enumerate() {
+        let is_last = i == stages.len() - 1;
+
+        result.push_str(&format!("┌{}┐\n", "─".repeat(inner)));
+        for (j, line) in lines.iter().enumerate() {
+            let pad = inner - 2 - line.chars().count();
+            result.push_str(&format!("│ {}{} │", line, " ".repeat(pad)));
+            // Output label beside the first row of the box
+            if j == 0 {
+                if let Some(output) = &stage.output {
+                    result.push_str(&format!(" ─▶ {}", output));
+                }
+            }
+            result.push('\n');
+        }
+
+        if is_last {
+            result.push_str(&format!("└{}┘\n", "─".repeat(inner)));
+        } else {
+            result.push_str(&format!(
+                "└{}┬{}┘\n",
+                "─".repeat(center - 1),
+                "─".repeat(inner - center)
+            ));
+            let arrow_indent = " ".repeat(center);
+            match stage.next.label() {
+                Some(label) => result.push_str(&format!("{}│ {}\n", arrow_indent, label)),
+                None => result.push_str(&format!("{}│\n", arrow_indent)),
+            }
+            result.push_str(&format!("{}▼\n", arrow_indent));
+        }
+    }
+
+    result.trim_end().to_string()
+}
+
+/// Render the model's stage list as Mermaid flowchart source
+pub fn to_mermaid(code: &str) -> String {
+    let stages = parse_stages(code);
+    let mut result = String::from("flowchart TD\n");
+
+    for (i, stage) in stages.iter().enumerate() {
+        result.push_str(&format!("    s{}[\"{}\"]\n", i, mermaid_escape(&stage.code)));
+    }
+    for (i, stage) in stages.iter().enumerate() {
+        let target = if i + 1 < stages.len() {
+            format!("s{}", i + 1)
+        } else {
+            "out([stdout])".to_string()
+        };
+        let label = match (stage.next.label(), &stage.output) {
+            (Some(connector), _) if i + 1 < stages.len() => Some(connector.to_string()),
+            (_, Some(output)) => Some(output.clone()),
+            _ => None,
+        };
+        match label {
+            Some(label) => result.push_str(&format!(
+                "    s{} -->|\"{}\"| {}\n",
+                i,
+                mermaid_escape(&label),
+                target
+            )),
+            None => result.push_str(&format!("    s{} --> {}\n", i, target)),
+        }
+    }
+
+    result.trim_end().to_string()
+}
+
+/// Build Mermaid source from a full explanation (safety line + code block).
+/// Everything outside the code block becomes `%%` comments so the result
+/// can be written straight to a `.mmd` file. Err when there are no stages.
+pub fn mermaid_from_explanation(command: &str, explanation: &str) -> Result<String, String> {
+    let mut header = format!("%% {}\n", command);
+    let mut code = String::new();
+    let mut in_code_block = false;
+
+    for line in explanation.lines() {
+        if line.starts_with("```") {
+            in_code_block = !in_code_block;
+        } else if in_code_block {
+            code.push_str(line);
+            code.push('\n');
+        } else if !line.trim().is_empty() {
+            header.push_str(&format!("%% {}\n", line.replace("**", "")));
+        }
+    }
+
+    if parse_stages(&code).is_empty() {
+        return Err("the explanation has no stages to draw".to_string());
+    }
+    Ok(format!("{}{}", header, to_mermaid(&code)))
+}
+
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;").replace('|', "#124;")
+}
+
+fn parse_stages(code: &str) -> Vec<Stage> {
+    let mut stages: Vec<Stage> = Vec::new();
+
+    for line in code.lines() {
+        let (segment, output) = split_comment(line);
+        let output = output.map(str::to_string);
+        let mut segment = segment.trim();
+
+        // A leading operator tells us how the previous stage connects
+        for op in ["&&", "||", "|", ";"] {
+            if let Some(rest) = segment.strip_prefix(op) {
+                if let Some(prev) = stages.last_mut() {
+                    prev.next = Connector::from_operator(op);
+                }
+                segment = rest.trim_start();
+                break;
+            }
+        }
+
+        // A trailing operator tells us how this stage connects to the next
+        let mut next = Connector::Pipe;
+        for op in ["&&", "||", "|", ";"] {
+            if let Some(rest) = segment.strip_suffix(op) {
+                next = Connector::from_operator(op);
+                segment = rest.trim_end();
+                break;
+            }
+        }
+
+        if segment.is_empty() {
+            continue;
+        }
+        stages.push(Stage {
+            code: segment.to_string(),
+            output,
+            next,
+        });
+    }
+
+    stages
+}
+
+/// Word-wrap `text` to at most `width` characters per line
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        let word_len = word.chars().count();
+        let current_len = current.chars().count();
+        if !current.is_empty() && current_len + 1 + word_len > width {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAGES: &str = "find . -name \"*.log\" # paths of .log files\n\
+                          xargs wc -l # line count per file\n\
+                          sort -n # counts, smallest first";
+
+    #[test]
+    fn test_render_boxes_and_arrows() {
+        let diagram = render(STAGES);
+        assert_eq!(diagram.matches('┌').count(), 3);
+        assert_eq!(diagram.matches('▼').count(), 2);
+        assert!(diagram.contains("│ xargs wc -l"));
+        assert!(diagram.contains("─▶ paths of .log files"));
+        // All box rows have the same width
+        let widths: Vec<usize> = diagram
+            .lines()
+            .filter(|l| l.starts_with('┌') || l.starts_with('└'))
+            .map(|l| l.chars().count())
+            .collect();
+        assert!(widths.windows(2).all(|w| w[0] == w[1]));
+    }
+
+    #[test]
+    fn test_render_connector_labels() {
+        let diagram = render("make build && # binary\n./run # output");
+        assert!(diagram.contains("│ if it succeeds"));
+    }
+
+    #[test]
+    fn test_mermaid_source() {
+        let mermaid = to_mermaid(STAGES);
+        assert!(mermaid.starts_with("flowchart TD"));
+        assert!(mermaid.contains("s0[\"find . -name #quot;*.log#quot;\"]"));
+        assert!(mermaid.contains("s0 -->|\"paths of .log files\"| s1"));
+        assert!(mermaid.contains("s2 -->|\"counts, smallest first\"| out([stdout])"));
+
+        let explanation = format!("**Safe**\n```\n{}\n```", STAGES);
+        assert!(mermaid_from_explanation("find", &explanation).unwrap().starts_with("%% find\n%% Safe\nflowchart TD"));
+        assert!(mermaid_from_explanation("find", "Lists the log files.").is_err());
+    }
+
+    #[test]
+    fn test_quoted_hash_stays_in_stage() {
+        let mermaid = to_mermaid("grep \"a # b\" app.log # matching lines");
+        assert!(mermaid.contains("s0[\"grep #quot;a # b#quot; app.log\"]"));
+        assert!(mermaid.contains("s0 -->|\"matching lines\"| out([stdout])"));
+    }
+}
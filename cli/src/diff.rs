@@ -0,0 +1,117 @@
+//! Word-level diff between two commands
+//!
+//! Shown when a refinement changes the command, so the difference is
+//! obvious before confirming. Words are compared whole: `-la` → `-lah` is
+//! one removal and one addition, which reads better than a character diff.
+
+const RESET: &str = "\x1b[0m";
+const REMOVED: &str = "\x1b[9;31m"; // Red strikethrough
+const ADDED: &str = "\x1b[1;32m"; // Bold green
+const DIM: &str = "\x1b[2m";
+
+#[derive(Debug, PartialEq)]
+pub enum Change<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Word diff from `old` to `new` (longest common subsequence of words)
+pub fn word_diff<'a>(old: &'a str, new: &'a str) -> Vec<Change<'a>> {
+    let old: Vec<&str> = old.split_whitespace().collect();
+    let new: Vec<&str> = new.split_whitespace().collect();
+
+    // lcs[i][j] = common words in old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut changes = Vec::new();
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            changes.push(Change::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            changes.push(Change::Removed(old[i]));
+            i += 1;
+        } else {
+            changes.push(Change::Added(new[j]));
+            j += 1;
+        }
+    }
+    changes.extend(old[i..].iter().map(|w| Change::Removed(w)));
+    changes.extend(new[j..].iter().map(|w| Change::Added(w)));
+    changes
+}
+
+/// One line with removed words struck out in red and added ones in green.
+/// None if the commands only differ in spacing.
+pub fn render(old: &str, new: &str) -> Option<String> {
+    let changes = word_diff(old, new);
+    if changes.iter().all(|c| matches!(c, Change::Same(_))) {
+        return None;
+    }
+    let words: Vec<String> = changes
+        .iter()
+        .map(|change| match change {
+            Change::Same(w) => format!("{}{}{}", DIM, w, RESET),
+            Change::Removed(w) => format!("{}{}{}", REMOVED, w, RESET),
+            Change::Added(w) => format!("{}{}{}", ADDED, w, RESET),
+        })
+        .collect();
+    Some(words.join(" "))
+}
+
+/// The change in words, for screen readers: "removed +7; added +30".
+/// None if the commands only differ in spacing.
+pub fn describe(old: &str, new: &str) -> Option<String> {
+    let changes = word_diff(old, new);
+    let removed: Vec<&str> = changes.iter().filter_map(|c| match c {
+        Change::Removed(w) => Some(*w),
+        _ => None,
+    }).collect();
+    let added: Vec<&str> = changes.iter().filter_map(|c| match c {
+        Change::Added(w) => Some(*w),
+        _ => None,
+    }).collect();
+    let parts: Vec<String> = [("removed", removed), ("added", added)]
+        .into_iter()
+        .filter(|(_, words)| !words.is_empty())
+        .map(|(what, words)| format!("{} {}", what, words.join(" ")))
+        .collect();
+    (!parts.is_empty()).then(|| parts.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_diff() {
+        assert_eq!(
+            word_diff("find . -mtime +7 -name '*.log'", "find /var/log -mtime +30 -name '*.log'"),
+            vec![
+                Change::Same("find"),
+                Change::Removed("."),
+                Change::Added("/var/log"),
+                Change::Same("-mtime"),
+                Change::Removed("+7"),
+                Change::Added("+30"),
+                Change::Same("-name"),
+                Change::Same("'*.log'"),
+            ]
+        );
+        assert_eq!(word_diff("ls", "ls | wc -l")[1..], [Change::Added("|"), Change::Added("wc"), Change::Added("-l")]);
+        assert!(render("ls  -la", "ls -la").is_none());
+        assert_eq!(describe("ls -la", "ls -lah | head").as_deref(), Some("removed -la; added -lah | head"));
+    }
+}
@@ -0,0 +1,216 @@
+//! `slashcmd doctor`: find out why slashcmd isn't working
+//!
+//! Runs every check even after one fails, prints one line per check and,
+//! under anything that isn't fine, the fix. Only failures (things that stop
+//! slashcmd from working) make the exit code 1; warnings cover features
+//! that are optional or degrade gracefully. With `--local` the edge checks
+//! are skipped and a missing GROQ_API_KEY is a failure instead.
+
+use std::io::{ErrorKind, IsTerminal};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::clipboard;
+use crate::config::{self, Config};
+use crate::highlight::{dim, level, Level};
+use crate::ipc::SOCKET_PATH;
+use crate::policy::{Policy, SafetyRules};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, status: Status::Ok, detail: detail.into(), fix: None }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self { name, status: Status::Warn, detail: detail.into(), fix: Some(fix.into()) }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self { name, status: Status::Fail, detail: detail.into(), fix: Some(fix.into()) }
+    }
+}
+
+/// Run every check for the mode slashcmd runs in and print the results.
+/// Returns the exit code.
+pub fn run(config: &Config, policy: &Policy, local: bool) -> i32 {
+    let mut checks = vec![check_config(config, policy)];
+    #[cfg(feature = "groq")]
+    {
+        let mut groq = check_env_key("GROQ_API_KEY", "needed for --local", "https://console.groq.com/keys");
+        if local && groq.status == Status::Warn {
+            groq.status = Status::Fail;
+        }
+        checks.push(groq);
+    }
+    #[cfg(feature = "gemini")]
+    checks.push(check_env_key("GEMINI_API_KEY", "explains --local commands", "https://aistudio.google.com/apikey"));
+    #[cfg(feature = "edge")]
+    if !local {
+        checks.push(check_login());
+        checks.push(check_edge());
+    }
+    checks.push(check_socket(Path::new(SOCKET_PATH)));
+    checks.push(check_terminal());
+    checks.push(check_clipboard());
+
+    for check in &checks {
+        let mark = match check.status {
+            Status::Ok => level(Level::Safe, "✓"),
+            Status::Warn => level(Level::Caution, "!"),
+            Status::Fail => level(Level::Danger, "✗"),
+        };
+        println!("{} {:<14} {}", mark, check.name, check.detail);
+        if let Some(fix) = &check.fix {
+            println!("  {}", dim(&format!("fix: {}", fix)));
+        }
+    }
+
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    if failed > 0 {
+        println!("\n{} problem(s) found", failed);
+        1
+    } else {
+        0
+    }
+}
+
+fn check_config(config: &Config, policy: &Policy) -> Check {
+    let path = config::config_file();
+    if let Err(e) = config::load() {
+        return Check::fail("config", e, format!("fix or remove {}", path.display()));
+    }
+    if let Err(e) = SafetyRules::new(&config.safety, policy) {
+        return Check::fail("config", e, format!("fix the [safety] patterns in {}", path.display()));
+    }
+    if path.exists() {
+        Check::ok("config", path.display().to_string())
+    } else {
+        Check::ok("config", "defaults (no config.toml)")
+    }
+}
+
+fn check_env_key(name: &'static str, purpose: &str, url: &str) -> Check {
+    match std::env::var(name) {
+        Ok(key) if !key.trim().is_empty() => Check::ok(name, "set"),
+        _ => Check::warn(name, format!("not set ({})", purpose), format!("export {}=... (get one at {})", name, url)),
+    }
+}
+
+#[cfg(feature = "edge")]
+fn check_login() -> Check {
+    if crate::edge::gateway_authenticates() {
+        return Check::ok("login", "not needed, [gateway] sends its own credential");
+    }
+    match crate::auth::verify_token() {
+        None => Check::fail("login", "not logged in", "slashcmd login (or use --local)"),
+        Some(Ok(status)) => {
+            let user = status.username.unwrap_or_else(|| "logged in".to_string());
+            if status.tier != "pro" && status.remaining <= 0 {
+                Check::fail("login", format!("{}, free requests used up", user), "slashcmd upgrade")
+            } else {
+                Check::ok("login", format!("{} ({})", user, status.tier))
+            }
+        }
+        Some(Err(crate::error::SlashcmdError::Auth(_))) => {
+            Check::fail("login", "token rejected", "slashcmd logout && slashcmd login")
+        }
+        Some(Err(e)) => Check::warn("login", format!("couldn't verify: {}", e), "check the edge check below"),
+    }
+}
+
+#[cfg(feature = "edge")]
+fn check_edge() -> Check {
+    let started = Instant::now();
+    match crate::edge::EdgeClient::new(String::new()).warmup() {
+        Ok(()) => Check::ok("edge", format!("reachable ({} ms)", started.elapsed().as_millis())),
+        Err(e) => Check::fail("edge", e, "check your network or proxy, or use --local"),
+    }
+}
+
+/// The daemon socket: absent is fine, a live daemon must answer, and a file
+/// we can't use blocks every future daemon
+fn check_socket(path: &Path) -> Check {
+    if !path.exists() {
+        return Check::ok("daemon", "not running (starts after the first --local request)");
+    }
+    let started = Instant::now();
+    match UnixStream::connect(path) {
+        Ok(_) => {
+            #[cfg(feature = "daemon")]
+            if crate::ipc::IpcClient::try_connect().is_none() {
+                return Check::warn("daemon", "running but didn't answer", "slashcmd restarts it on the next --local request");
+            }
+            Check::ok("daemon", format!("running, answered in {} ms", started.elapsed().as_millis()))
+        }
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => Check::fail(
+            "daemon",
+            format!("{} belongs to another user", path.display()),
+            format!("sudo rm {}", path.display()),
+        ),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => Check::warn(
+            "daemon",
+            format!("stale socket {}", path.display()),
+            format!("rm {}", path.display()),
+        ),
+        Err(e) => Check::fail("daemon", format!("{}: {}", path.display(), e), format!("rm {}", path.display())),
+    }
+}
+
+fn check_terminal() -> Check {
+    let term = std::env::var("TERM").unwrap_or_default();
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return Check::warn("terminal", "not a TTY, commands are printed without confirmation", "run slashcmd directly in a terminal");
+    }
+    if term.is_empty() || term == "dumb" {
+        return Check::warn("terminal", format!("TERM={:?}, no colors or cursor control", term), "export TERM=xterm-256color");
+    }
+    let colors = if std::env::var_os("NO_COLOR").is_some() { ", NO_COLOR set" } else { "" };
+    Check::ok("terminal", format!("{}{}", term, colors))
+}
+
+fn check_clipboard() -> Check {
+    match clipboard::tool() {
+        Some(tool) => Check::ok("clipboard", tool),
+        None => Check::warn(
+            "clipboard",
+            "no tool found, copying relies on the terminal supporting OSC 52",
+            "install wl-clipboard (Wayland), xclip or xsel (X11)",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_check_socket() {
+        let dir = std::env::temp_dir().join(format!("slashcmd-doctor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cmd.sock");
+        assert_eq!(check_socket(&path).status, Status::Ok);
+
+        // A socket file nobody listens on
+        drop(UnixListener::bind(&path).unwrap());
+        let check = check_socket(&path);
+        assert_eq!(check.status, Status::Warn);
+        assert_eq!(check.fix.as_deref(), Some(format!("rm {}", path.display()).as_str()));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
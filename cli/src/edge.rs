@@ -1,17 +1,155 @@
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use serde::{Deserialize, Serialize};
 use std::io::{BufRead, BufReader};
-use std::time::Duration;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, trace};
 use ureq::{Agent, AgentBuilder};
 
-use crate::prompt::CommandResult;
+use crate::auth;
+use crate::config::GatewayConfig;
+use crate::error::SlashcmdError;
+#[cfg(feature = "tui")]
+use crate::followup::FollowUp;
+use crate::ipc::{ExplainOptions, ExplainStyle, Generation};
+use crate::logs::Provenance;
+use crate::models::Model;
+use crate::prompt::{personal_examples, CommandResult, Example};
+use crate::ratelimit;
+use crate::snippets::Snippets;
+use crate::sync::SyncItem;
 
 const WORKER_URL: &str = "https://groq-warm-proxy.gozdak.workers.dev";
 const HTTP_TIMEOUT_SECS: u64 = 30;
 
+static GATEWAY: OnceLock<GatewayConfig> = OnceLock::new();
+static CLIENT_TLS: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+
+/// Send every edge request through this gateway instead of the public worker,
+/// loading its client certificate if it has one
+pub fn use_gateway(gateway: GatewayConfig) -> Result<(), String> {
+    match (&gateway.client_cert, &gateway.client_key) {
+        (Some(cert), Some(key)) => {
+            let _ = CLIENT_TLS.set(client_tls(cert, key)?);
+        }
+        (None, None) => {}
+        _ => return Err("[gateway] needs both client_cert and client_key".to_string()),
+    }
+    let _ = GATEWAY.set(gateway);
+    Ok(())
+}
+
+/// TLS settings presenting a client certificate, otherwise as ureq's own
+/// (bundled webpki roots, ring)
+fn client_tls(cert: &Path, key: &Path) -> Result<Arc<rustls::ClientConfig>, String> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read client certificate {}: {}", cert.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate in {}", cert.display()));
+    }
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| format!("Failed to read client key {}: {}", key.display(), e))?;
+
+    let roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_root_certificates(roots)
+        .with_client_auth_cert(certs, key)
+        .map_err(|e| format!("Invalid client certificate or key: {}", e))?;
+    Ok(Arc::new(config))
+}
+
+/// Whether the gateway authenticates requests itself, so no slashcmd login is needed
+pub fn gateway_authenticates() -> bool {
+    GATEWAY.get().is_some_and(|g| g.auth_value.is_some())
+}
+
 #[derive(Serialize)]
 struct CommandRequest {
     query: String,
     style: String,
+    verbosity: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<String>,
+    /// Instruction for a custom style the worker doesn't know
+    #[serde(skip_serializing_if = "Option::is_none")]
+    style_prompt: Option<String>,
+    /// Personalization examples from local history (opt-in)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    examples: Vec<Example>,
+    /// Sampling overrides; the worker keeps its defaults for unset ones
+    #[serde(flatten)]
+    generation: Generation,
+}
+
+impl CommandRequest {
+    fn new(query: &str, options: &ExplainOptions) -> Self {
+        Self {
+            query: query.to_string(),
+            style: options.style.as_str().to_string(),
+            verbosity: options.verbosity.as_str().to_string(),
+            lang: options.lang.clone(),
+            style_prompt: match &options.style {
+                ExplainStyle::Custom(custom) => Some(custom.prompt.clone()),
+                _ => None,
+            },
+            examples: personal_examples(),
+            generation: options.generation,
+        }
+    }
+}
+
+/// Body of `/explain`: an explanation for a command we already have
+#[derive(Serialize)]
+struct ExplainRequest<'a> {
+    command: &'a str,
+    style: &'a str,
+    verbosity: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    style_prompt: Option<&'a str>,
+    #[serde(flatten)]
+    generation: Generation,
+}
+
+/// A command and its explanation published with `slashcmd share`
+#[derive(Serialize)]
+pub struct ShareRequest {
+    pub query: String,
+    pub command: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ShareResponse {
+    url: String,
+}
+
+/// The team snippet library and its version, for optimistic concurrency
+#[derive(Deserialize)]
+pub struct TeamSnippets {
+    pub snippets: Snippets,
+    pub version: u64,
+}
+
+#[derive(Serialize)]
+struct PushSnippets<'a> {
+    snippets: &'a Snippets,
+    base_version: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SyncItems {
+    items: Vec<SyncItem>,
 }
 
 #[derive(Deserialize)]
@@ -19,6 +157,31 @@ struct ExplanationData {
     text: String,
 }
 
+/// The `usage` event: the account's quota after this request
+#[derive(Deserialize)]
+struct UsageData {
+    usage: i32,
+    limit: i32,
+    tier: String,
+}
+
+/// Keep the cached status current from the usage the worker reports with
+/// every command. A gateway's accounting isn't the user's edge quota.
+fn record_usage(data: &str) {
+    if GATEWAY.get().is_some_and(|g| g.url.is_some()) {
+        return;
+    }
+    if let Ok(usage) = serde_json::from_str::<UsageData>(data) {
+        auth::update_cached_usage(usage.usage, usage.limit, &usage.tier);
+    }
+}
+
+/// How the command the worker (or gateway) just sent was generated
+fn provenance(started: Instant) -> Option<Provenance> {
+    let provider = if GATEWAY.get().is_some_and(|g| g.url.is_some()) { "gateway" } else { "edge" };
+    Some(Provenance::new("edge", provider, started.elapsed()))
+}
+
 /// SSE response containing command and explanation
 pub struct EdgeResponse {
     pub command: CommandResult,
@@ -29,17 +192,70 @@ pub struct EdgeResponse {
 pub struct EdgeClient {
     agent: Agent,
     jwt: String,
+    rate_limit_tx: Option<Sender<Duration>>,
 }
 
 impl EdgeClient {
     /// Create a new edge client with a JWT token
     pub fn new(jwt: String) -> Self {
-        let agent = AgentBuilder::new()
+        let mut builder = AgentBuilder::new()
             .timeout_connect(Duration::from_secs(5))
-            .timeout_read(Duration::from_secs(HTTP_TIMEOUT_SECS))
-            .build();
+            .timeout_read(Duration::from_secs(HTTP_TIMEOUT_SECS));
+        if let Some(tls) = CLIENT_TLS.get() {
+            builder = builder.tls_config(Arc::clone(tls));
+        }
 
-        Self { agent, jwt }
+        Self {
+            agent: builder.build(),
+            jwt,
+            rate_limit_tx: None,
+        }
+    }
+
+    /// Report rate-limit waits (before each retry) to the UI
+    pub fn with_rate_limit_notice(mut self, tx: Sender<Duration>) -> Self {
+        self.rate_limit_tx = Some(tx);
+        self
+    }
+
+    /// A request to the worker, or the configured gateway, with auth set
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        self.request_via(GATEWAY.get(), method, path)
+    }
+
+    fn request_via(&self, gateway: Option<&GatewayConfig>, method: &str, path: &str) -> ureq::Request {
+        let base = gateway
+            .and_then(|g| g.url.as_deref())
+            .map(|url| url.trim_end_matches('/'))
+            .unwrap_or(WORKER_URL);
+        let mut request = self.agent.request(method, &format!("{}{}", base, path));
+        let Some(gateway) = gateway else {
+            return request.set("Authorization", &format!("Bearer {}", self.jwt));
+        };
+        for (name, value) in &gateway.headers {
+            request = request.set(name, value);
+        }
+        let header = gateway.auth_header.as_deref().unwrap_or("Authorization");
+        match &gateway.auth_value {
+            Some(value) => request.set(header, value),
+            None => request.set(header, &format!("Bearer {}", self.jwt)),
+        }
+    }
+
+    /// POST /command asking for the SSE stream, retrying rate limits
+    fn post_command(&self, request: &CommandRequest) -> Result<ureq::Response, SlashcmdError> {
+        let started = Instant::now();
+        let response = ratelimit::send_json_with_retry(
+            || {
+                self.request("POST", "/command")
+                    .set("Content-Type", "application/json")
+                    .set("Accept", "text/event-stream")
+            },
+            request,
+            self.rate_limit_tx.as_ref(),
+        );
+        debug!(elapsed_ms = started.elapsed().as_millis() as u64, ok = response.is_ok(), "edge /command headers");
+        response
     }
 
     /// Create client with a test JWT (for development)
@@ -48,27 +264,16 @@ impl EdgeClient {
         Self::new(jwt)
     }
 
-    /// Query via edge proxy - returns command only (legacy compatibility)
-    pub fn query(&self, user_query: &str) -> Result<CommandResult, String> {
-        let response = self.query_with_explanation(user_query, "typescript")?;
-        Ok(response.command)
-    }
-
     /// Query via edge proxy with SSE - returns command and explanation
-    pub fn query_with_explanation(&self, user_query: &str, style: &str) -> Result<EdgeResponse, String> {
-        let request = CommandRequest {
-            query: user_query.to_string(),
-            style: style.to_string(),
-        };
+    pub fn query_with_explanation(
+        &self,
+        user_query: &str,
+        options: &ExplainOptions,
+    ) -> Result<EdgeResponse, SlashcmdError> {
+        let request = CommandRequest::new(user_query, options);
 
-        let response = self
-            .agent
-            .post(&format!("{}/command", WORKER_URL))
-            .set("Authorization", &format!("Bearer {}", self.jwt))
-            .set("Content-Type", "application/json")
-            .set("Accept", "text/event-stream")
-            .send_json(&request)
-            .map_err(|e| format!("Edge proxy error: {}", e))?;
+        let started = Instant::now();
+        let response = self.post_command(&request)?;
 
         // Parse SSE response
         let reader = BufReader::new(response.into_reader());
@@ -77,32 +282,35 @@ impl EdgeClient {
         let mut current_event = String::new();
 
         for line in reader.lines() {
-            let line = line.map_err(|e| format!("Read error: {}", e))?;
-
-            if line.starts_with("event: ") {
-                current_event = line[7..].to_string();
-            } else if line.starts_with("data: ") {
-                let data = &line[6..];
+            let line = line.map_err(|e| SlashcmdError::Network(e.to_string()))?;
 
+            if let Some(event) = line.strip_prefix("event: ") {
+                current_event = event.to_string();
+            } else if let Some(data) = line.strip_prefix("data: ") {
+                trace!(event = %current_event, bytes = data.len(), "sse event");
                 match current_event.as_str() {
                     "command" => {
-                        command = serde_json::from_str(data).ok();
+                        command = serde_json::from_str(data).ok().map(|command| CommandResult {
+                            provenance: provenance(started),
+                            ..command
+                        });
                     }
                     "explanation" => {
                         if let Ok(exp_data) = serde_json::from_str::<ExplanationData>(data) {
                             explanation = Some(exp_data.text);
                         }
                     }
+                    "usage" => record_usage(data),
                     "done" => break,
                     "error" => {
-                        return Err(format!("Server error: {}", data));
+                        return Err(SlashcmdError::Server(data.to_string()));
                     }
                     _ => {}
                 }
             }
         }
 
-        let command = command.ok_or_else(|| "No command received".to_string())?;
+        let command = command.ok_or_else(|| SlashcmdError::Parse("no command received".to_string()))?;
 
         Ok(EdgeResponse {
             command,
@@ -114,40 +322,34 @@ impl EdgeClient {
     pub fn query_streaming(
         &self,
         user_query: &str,
-        style: &str,
-        cmd_tx: std::sync::mpsc::Sender<Result<CommandResult, String>>,
+        options: &ExplainOptions,
+        cmd_tx: std::sync::mpsc::Sender<Result<CommandResult, SlashcmdError>>,
         exp_tx: std::sync::mpsc::Sender<Result<String, String>>,
-    ) -> Result<(), String> {
-        let request = CommandRequest {
-            query: user_query.to_string(),
-            style: style.to_string(),
-        };
+    ) -> Result<(), SlashcmdError> {
+        let request = CommandRequest::new(user_query, options);
 
-        let response = self
-            .agent
-            .post(&format!("{}/command", WORKER_URL))
-            .set("Authorization", &format!("Bearer {}", self.jwt))
-            .set("Content-Type", "application/json")
-            .set("Accept", "text/event-stream")
-            .send_json(&request)
-            .map_err(|e| format!("Edge proxy error: {}", e))?;
+        let started = Instant::now();
+        let response = self.post_command(&request)?;
 
         // Parse SSE response and send events through channels as they arrive
         let reader = BufReader::new(response.into_reader());
         let mut current_event = String::new();
 
         for line in reader.lines() {
-            let line = line.map_err(|e| format!("Read error: {}", e))?;
-
-            if line.starts_with("event: ") {
-                current_event = line[7..].to_string();
-            } else if line.starts_with("data: ") {
-                let data = &line[6..];
+            let line = line.map_err(|e| SlashcmdError::Network(e.to_string()))?;
 
+            if let Some(event) = line.strip_prefix("event: ") {
+                current_event = event.to_string();
+            } else if let Some(data) = line.strip_prefix("data: ") {
+                trace!(event = %current_event, bytes = data.len(), "sse event");
                 match current_event.as_str() {
                     "command" => {
-                        let result: Result<CommandResult, String> = serde_json::from_str(data)
-                            .map_err(|e| format!("Parse error: {}", e));
+                        let result = serde_json::from_str(data)
+                            .map(|command| CommandResult {
+                                provenance: provenance(started),
+                                ..command
+                            })
+                            .map_err(|e| SlashcmdError::Parse(e.to_string()));
                         let _ = cmd_tx.send(result);
                     }
                     "explanation" => {
@@ -155,9 +357,10 @@ impl EdgeClient {
                             let _ = exp_tx.send(Ok(exp_data.text));
                         }
                     }
+                    "usage" => record_usage(data),
                     "done" => break,
                     "error" => {
-                        let _ = cmd_tx.send(Err(format!("Server error: {}", data)));
+                        let _ = cmd_tx.send(Err(SlashcmdError::Server(data.to_string())));
                         break;
                     }
                     _ => {}
@@ -168,12 +371,198 @@ impl EdgeClient {
         Ok(())
     }
 
+    /// Ask a follow-up question about a command's output
+    #[cfg(feature = "tui")]
+    pub fn follow_up(&self, request: &FollowUp) -> Result<String, SlashcmdError> {
+        let response = ratelimit::send_json_with_retry(
+            || {
+                self.request("POST", "/followup")
+                    .set("Content-Type", "application/json")
+            },
+            request,
+            self.rate_limit_tx.as_ref(),
+        )?;
+
+        let data: ExplanationData = response.into_json().map_err(parse_error)?;
+        Ok(data.text)
+    }
+
+    /// Explain a command without generating one
+    pub fn explain(&self, command: &str, options: &ExplainOptions) -> Result<String, SlashcmdError> {
+        let request = ExplainRequest {
+            command,
+            style: options.style.as_str(),
+            verbosity: options.verbosity.as_str(),
+            lang: options.lang.as_deref(),
+            style_prompt: match &options.style {
+                ExplainStyle::Custom(custom) => Some(&custom.prompt),
+                _ => None,
+            },
+            generation: options.generation,
+        };
+        let response = ratelimit::send_json_with_retry(
+            || {
+                self.request("POST", "/explain")
+                    .set("Content-Type", "application/json")
+            },
+            &request,
+            self.rate_limit_tx.as_ref(),
+        )?;
+
+        let data: ExplanationData = response.into_json().map_err(parse_error)?;
+        Ok(data.text)
+    }
+
+    /// Publish a command and explanation, returning its short URL
+    pub fn share(&self, request: &ShareRequest) -> Result<String, SlashcmdError> {
+        let response = self
+            .request("POST", "/share")
+            .set("Content-Type", "application/json")
+            .send_json(request)
+            .map_err(SlashcmdError::from_ureq)?;
+
+        let data: ShareResponse = response.into_json().map_err(parse_error)?;
+        Ok(data.url)
+    }
+
+    /// Fetch the team snippet library (Pro)
+    pub fn team_snippets(&self) -> Result<TeamSnippets, SlashcmdError> {
+        self.request("GET", "/snippets")
+            .call()
+            .map_err(snippet_error)?
+            .into_json()
+            .map_err(parse_error)
+    }
+
+    /// Replace the team library, if it is still at `base_version`.
+    /// Returns the new version, or None if someone pushed in between.
+    pub fn push_team_snippets(&self, snippets: &Snippets, base_version: u64) -> Result<Option<u64>, SlashcmdError> {
+        #[derive(Deserialize)]
+        struct Pushed {
+            version: u64,
+        }
+
+        let result = self
+            .request("PUT", "/snippets")
+            .set("Content-Type", "application/json")
+            .send_json(PushSnippets {
+                snippets,
+                base_version,
+            });
+        match result {
+            Ok(response) => {
+                let pushed: Pushed = response.into_json().map_err(parse_error)?;
+                Ok(Some(pushed.version))
+            }
+            Err(ureq::Error::Status(409, _)) => Ok(None),
+            Err(e) => Err(snippet_error(e)),
+        }
+    }
+
+    /// Fetch every encrypted history item stored for this user
+    pub fn sync_items(&self) -> Result<Vec<SyncItem>, SlashcmdError> {
+        let data: SyncItems = self
+            .request("GET", "/sync")
+            .call()
+            .map_err(SlashcmdError::from_ureq)?
+            .into_json()
+            .map_err(parse_error)?;
+        Ok(data.items)
+    }
+
+    /// Upload encrypted history items (the server ignores ids it already has)
+    pub fn push_sync_items(&self, items: &[SyncItem]) -> Result<(), SlashcmdError> {
+        self.request("POST", "/sync")
+            .set("Content-Type", "application/json")
+            .send_json(SyncItems {
+                items: items.to_vec(),
+            })
+            .map_err(SlashcmdError::from_ureq)?;
+        Ok(())
+    }
+
+    /// The models the worker runs, with their roles
+    pub fn models(&self) -> Result<Vec<Model>, SlashcmdError> {
+        #[derive(Deserialize)]
+        struct ModelList {
+            models: Vec<Model>,
+        }
+
+        let list: ModelList = match self.request("GET", "/models").call() {
+            Ok(response) => response.into_json().map_err(parse_error)?,
+            Err(ureq::Error::Status(404, _)) => {
+                return Err(SlashcmdError::Server(
+                    "The edge worker doesn't list its models. Use --local to list Groq and Gemini models.".to_string(),
+                ))
+            }
+            Err(e) => return Err(SlashcmdError::from_ureq(e)),
+        };
+        Ok(list
+            .models
+            .into_iter()
+            .map(|model| Model {
+                provider: "edge".to_string(),
+                ..model
+            })
+            .collect())
+    }
+
     /// Ping the edge proxy to keep connection warm
     pub fn warmup(&self) -> Result<(), String> {
-        self.agent
-            .get(&format!("{}/ping", WORKER_URL))
+        self.request("GET", "/ping")
             .call()
             .map_err(|e| format!("Edge warmup error: {}", e))?;
         Ok(())
     }
 }
+
+fn snippet_error(e: ureq::Error) -> SlashcmdError {
+    match e {
+        ureq::Error::Status(403, _) => SlashcmdError::Plan("Team snippets need a Pro plan.".to_string()),
+        e => SlashcmdError::from_ureq(e),
+    }
+}
+
+fn parse_error(e: std::io::Error) -> SlashcmdError {
+    SlashcmdError::Parse(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gateway_headers() {
+        let client = EdgeClient::new("jwt".to_string());
+        let request = client.request_via(None, "POST", "/command");
+        assert_eq!(request.url(), format!("{}/command", WORKER_URL));
+        assert_eq!(request.header("Authorization"), Some("Bearer jwt"));
+
+        let gateway = GatewayConfig {
+            url: Some("https://llm.corp.example/slashcmd/".to_string()),
+            auth_header: Some("X-Api-Key".to_string()),
+            auth_value: Some("secret".to_string()),
+            headers: [("X-Team".to_string(), "infra".to_string())].into(),
+            ..Default::default()
+        };
+        let request = client.request_via(Some(&gateway), "GET", "/ping");
+        assert_eq!(request.url(), "https://llm.corp.example/slashcmd/ping");
+        assert_eq!(request.header("X-Api-Key"), Some("secret"));
+        assert_eq!(request.header("X-Team"), Some("infra"));
+        assert_eq!(request.header("Authorization"), None);
+    }
+
+    #[test]
+    fn test_client_tls_reports_bad_files() {
+        let dir = std::env::temp_dir().join(format!("slashcmd-mtls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = dir.join("client.pem");
+        std::fs::write(&cert, "not a certificate").unwrap();
+
+        let err = client_tls(&cert, &dir.join("client.key")).unwrap_err();
+        assert!(err.starts_with("No certificate in"), "{}", err);
+        let err = client_tls(&dir.join("missing.pem"), &cert).unwrap_err();
+        assert!(err.contains("missing.pem"), "{}", err);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
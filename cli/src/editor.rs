@@ -0,0 +1,84 @@
+//! One-shot mode for editor integrations (`--editor`)
+//!
+//! Reads the query from stdin (unless given as arguments) and prints exactly
+//! one JSON object on stdout: `{"command", "safety", "explanation"}`, or
+//! `{"error", "code", "hint"}` with the error's exit code. It never opens the
+//! terminal, never spawns the daemon and never writes ANSI escapes, so an
+//! extension can parse the output as-is.
+
+use serde::Serialize;
+use std::io::Read;
+
+use crate::audit::{self, AuditEvent};
+use crate::error::SlashcmdError;
+use crate::ipc::ExplainOptions;
+use crate::logs;
+use crate::mcp::Backend;
+use crate::policy::SafetyRules;
+
+#[derive(Serialize)]
+struct Output<'a> {
+    command: &'a str,
+    /// safe, caution, confirm, danger or blocked
+    safety: &'a str,
+    explanation: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct ErrorOutput<'a> {
+    error: String,
+    code: &'a str,
+    hint: Option<&'a str>,
+}
+
+/// Read the whole query from stdin
+pub fn read_query() -> Result<String, SlashcmdError> {
+    let mut query = String::new();
+    std::io::stdin()
+        .read_to_string(&mut query)
+        .map_err(|e| SlashcmdError::Other(format!("Failed to read stdin: {}", e)))?;
+    let query = query.trim();
+    if query.is_empty() {
+        return Err(SlashcmdError::Config("No query on stdin".to_string()));
+    }
+    Ok(query.to_string())
+}
+
+/// Generate, log and print the command. Returns the exit code.
+pub fn run(backend: &Backend, query: &str, options: &ExplainOptions, rules: &SafetyRules, explain: bool) -> i32 {
+    match backend.generate(query, options, explain) {
+        Ok((result, explanation)) => {
+            let entry = logs::create_entry(
+                query,
+                &result.command,
+                explanation.clone(),
+                &options.style,
+                result.provenance.clone(),
+            );
+            let _ = logs::save_log(&entry);
+            let _ = audit::record(AuditEvent::Generated, query, &result.command);
+
+            print(&Output {
+                command: &result.command,
+                safety: rules.check(&result.command).label(Some(result.safe)),
+                explanation: explanation.as_deref(),
+            });
+            0
+        }
+        Err(e) => fail(&e),
+    }
+}
+
+/// Print an error as JSON and return its exit code
+pub fn fail(error: &SlashcmdError) -> i32 {
+    print(&ErrorOutput {
+        error: error.to_string(),
+        code: error.code(),
+        hint: error.hint(),
+    });
+    error.exit_code()
+}
+
+fn print<T: Serialize>(value: &T) {
+    println!("{}", serde_json::to_string(value).unwrap_or_default());
+}
@@ -0,0 +1,188 @@
+//! Crate-wide error type
+//!
+//! Network-facing code returns `SlashcmdError` so callers (and `main`, which
+//! turns it into an exit code and a hint) can tell an expired login from a
+//! dropped connection or an unexpected response. Modules that only ever fail
+//! one way still return `Result<_, String>`; those convert to `Other`.
+//! Exit code 2 is left to clap, which uses it for usage errors.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Exit code when there is no stored login or it was rejected
+pub const EXIT_NOT_LOGGED_IN: i32 = 3;
+/// Exit code when the free tier is used up or the feature needs Pro
+pub const EXIT_QUOTA_EXHAUSTED: i32 = 4;
+/// Exit code when still rate limited after retrying
+pub const EXIT_RATE_LIMITED: i32 = 5;
+/// Exit code when the server could not be reached
+pub const EXIT_NETWORK: i32 = 6;
+/// Exit code when the server answered with an error
+pub const EXIT_SERVER: i32 = 7;
+/// Exit code when a response could not be understood
+pub const EXIT_PARSE: i32 = 8;
+/// Exit code for invalid configuration or flags
+pub const EXIT_CONFIG: i32 = 9;
+
+/// Also sent by the daemon, so a failure there keeps its kind (and exit code)
+#[derive(Debug, Error, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum SlashcmdError {
+    #[error("Not logged in")]
+    NotLoggedIn,
+    /// The token or API key was rejected
+    #[error("{0}")]
+    Auth(String),
+    /// The free tier is used up, or the feature needs Pro
+    #[error("{0}")]
+    Plan(String),
+    #[error("{0}")]
+    RateLimited(String),
+    #[error("Network error: {0}")]
+    Network(String),
+    /// An HTTP error status or an error event from the server
+    #[error("Server error: {0}")]
+    Server(String),
+    #[error("Unexpected response: {0}")]
+    Parse(String),
+    #[error("{0}")]
+    Config(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl SlashcmdError {
+    /// Stable name for scripts and debug output
+    pub fn code(&self) -> &'static str {
+        match self {
+            SlashcmdError::NotLoggedIn => "not_logged_in",
+            SlashcmdError::Auth(_) => "auth",
+            SlashcmdError::Plan(_) => "plan",
+            SlashcmdError::RateLimited(_) => "rate_limited",
+            SlashcmdError::Network(_) => "network",
+            SlashcmdError::Server(_) => "server",
+            SlashcmdError::Parse(_) => "parse",
+            SlashcmdError::Config(_) => "config",
+            SlashcmdError::Other(_) => "other",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SlashcmdError::NotLoggedIn | SlashcmdError::Auth(_) => EXIT_NOT_LOGGED_IN,
+            SlashcmdError::Plan(_) => EXIT_QUOTA_EXHAUSTED,
+            SlashcmdError::RateLimited(_) => EXIT_RATE_LIMITED,
+            SlashcmdError::Network(_) => EXIT_NETWORK,
+            SlashcmdError::Server(_) => EXIT_SERVER,
+            SlashcmdError::Parse(_) => EXIT_PARSE,
+            SlashcmdError::Config(_) => EXIT_CONFIG,
+            SlashcmdError::Other(_) => 1,
+        }
+    }
+
+    /// What the user can do about it
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            SlashcmdError::NotLoggedIn => {
+                Some("Run 'slashcmd login', or use --local with GROQ_API_KEY for direct API access.")
+            }
+            SlashcmdError::Auth(_) => Some("Run 'slashcmd login' again (with --local, check GROQ_API_KEY)."),
+            SlashcmdError::Plan(_) => Some("Run 'slashcmd upgrade' for more requests."),
+            SlashcmdError::RateLimited(_) => Some("Wait a moment and try again."),
+            SlashcmdError::Network(_) => Some("Check your internet connection and proxy settings."),
+            SlashcmdError::Parse(_) => Some("Try again; if it keeps happening, run with -v and report the output."),
+            SlashcmdError::Server(_) | SlashcmdError::Config(_) | SlashcmdError::Other(_) => None,
+        }
+    }
+
+    /// The service couldn't be reached or failed on its side, as opposed to
+    /// refusing the request (login, plan, rate limit)
+    pub fn is_outage(&self) -> bool {
+        matches!(self, SlashcmdError::Network(_) | SlashcmdError::Server(_))
+    }
+
+    /// Classify a failed HTTP request
+    pub fn from_ureq(error: ureq::Error) -> Self {
+        match error {
+            ureq::Error::Status(status, response) => {
+                let message = error_message(response);
+                match status {
+                    401 | 403 => SlashcmdError::Auth(message),
+                    402 => SlashcmdError::Plan(message),
+                    429 => SlashcmdError::RateLimited(message),
+                    _ => SlashcmdError::Server(message),
+                }
+            }
+            ureq::Error::Transport(transport) => SlashcmdError::Network(transport.to_string()),
+        }
+    }
+}
+
+impl From<String> for SlashcmdError {
+    fn from(message: String) -> Self {
+        SlashcmdError::Other(message)
+    }
+}
+
+impl From<&str> for SlashcmdError {
+    fn from(message: &str) -> Self {
+        SlashcmdError::Other(message.to_string())
+    }
+}
+
+/// The server's `error` message if the body has one, else the status line
+pub fn error_message(response: ureq::Response) -> String {
+    let status = response.status();
+    let status_text = response.status_text().to_string();
+    let body: serde_json::Value = response.into_json().unwrap_or_default();
+    let message = body["error"]
+        .as_str()
+        .or_else(|| body["error"]["message"].as_str())
+        .map(str::to_string);
+    match message {
+        Some(message) if status == 429 => format!("Rate limited: {}", message),
+        Some(message) => message,
+        None => format!("HTTP {} {}", status, status_text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            SlashcmdError::NotLoggedIn,
+            SlashcmdError::Plan(String::new()),
+            SlashcmdError::RateLimited(String::new()),
+            SlashcmdError::Network(String::new()),
+            SlashcmdError::Server(String::new()),
+            SlashcmdError::Parse(String::new()),
+            SlashcmdError::Config(String::new()),
+            SlashcmdError::Other(String::new()),
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(SlashcmdError::exit_code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert_eq!(SlashcmdError::Auth(String::new()).exit_code(), EXIT_NOT_LOGGED_IN);
+        assert!(SlashcmdError::Server(String::new()).is_outage());
+        assert!(!SlashcmdError::RateLimited(String::new()).is_outage());
+    }
+
+    #[test]
+    fn test_string_roundtrip() {
+        let error: SlashcmdError = "bad key".into();
+        assert_eq!(error.code(), "other");
+        assert_eq!(SlashcmdError::Network("timed out".into()).to_string(), "Network error: timed out");
+
+        // The daemon sends errors as JSON; the kind has to survive the trip
+        let json = serde_json::to_string(&SlashcmdError::Plan("Daily limit".into())).unwrap();
+        assert_eq!(json, r#"{"kind":"plan","message":"Daily limit"}"#);
+        let error: SlashcmdError = serde_json::from_str(&json).unwrap();
+        assert_eq!(error.exit_code(), EXIT_QUOTA_EXHAUSTED);
+        let error: SlashcmdError = serde_json::from_str(r#"{"kind":"not_logged_in"}"#).unwrap();
+        assert_eq!(error.exit_code(), EXIT_NOT_LOGGED_IN);
+    }
+}
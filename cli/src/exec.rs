@@ -0,0 +1,383 @@
+//! Running confirmed commands
+//!
+//! Commands run through `sh -c` (or the `--shell` interpreter, or on a
+//! remote target, see `target`),
+//! optionally inside a sandbox that makes the
+//! filesystem read-only except for the current directory and temp space:
+//! `sandbox-exec` on macOS, `bwrap` or `firejail` on Linux. With a timeout
+//! the command runs in its own process group (in the terminal's foreground)
+//! and the whole group is killed once the limit passes: pipelines, `$(...)`
+//! and anything under the sandbox wrapper. With capture on, output is
+//! still shown live but also kept (the last MAX_CAPTURE_BYTES) for follow-ups.
+//! Alternatively a command can be typed into a tmux pane, where it runs in
+//! the user's own shell.
+
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::safety;
+use crate::shell::{self, Shell};
+use crate::target::Target;
+
+/// Exit code reported for a killed command, matching coreutils `timeout`
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// How much captured output to keep (the tail, where errors usually are)
+const MAX_CAPTURE_BYTES: usize = 16 * 1024;
+
+/// How to run a confirmed command
+#[derive(Debug, Clone, Default)]
+pub struct ExecOptions {
+    /// Limit writes to the current directory and temp space
+    pub sandbox: bool,
+    /// Kill the command after this long
+    pub timeout: Option<Duration>,
+    /// Keep stdout/stderr (while still printing them)
+    pub capture: bool,
+    /// Where the command runs
+    pub target: Target,
+}
+
+/// How a command finished
+#[derive(Debug, Clone)]
+pub struct ExecOutcome {
+    pub exit_code: i32,
+    pub timed_out: bool,
+    /// Interleaved stdout/stderr tail, when capturing
+    pub output: Option<String>,
+}
+
+/// Run a command and wait for it (or for the timeout)
+pub fn execute(command: &str, options: &ExecOptions) -> Result<ExecOutcome, String> {
+    let mut invocation = if options.sandbox {
+        if options.target != Target::Local {
+            return Err("--sandbox only works for local commands".to_string());
+        }
+        if safety::elevates(command) {
+            return Err("sudo can't gain privileges inside --sandbox".to_string());
+        }
+        if shell::target() != Shell::Sh {
+            return Err("--sandbox only runs sh commands".to_string());
+        }
+        sandboxed(command)?
+    } else {
+        // A remote sudo needs a terminal for its password prompt, capture or not
+        let tty = (!options.capture || safety::elevates(command)) && io::stdin().is_terminal();
+        options.target.invocation(command, tty)
+    };
+
+    if options.capture {
+        invocation.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    #[cfg(unix)]
+    let foreground = options.timeout.is_some() && own_group(&mut invocation);
+    let mut child = invocation.spawn().map_err(|e| format!("Failed to execute: {}", e))?;
+
+    let captured = Arc::new(Mutex::new(Vec::new()));
+    let mut pumps = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        pumps.push(pump(stdout, io::stdout(), Arc::clone(&captured)));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        pumps.push(pump(stderr, io::stderr(), Arc::clone(&captured)));
+    }
+
+    let started = Instant::now();
+    let waited = wait(&mut child, options.timeout);
+    #[cfg(unix)]
+    if foreground {
+        take_terminal_back();
+    }
+    let (exit_code, timed_out) = waited?;
+    tracing::debug!(
+        exit_code,
+        timed_out,
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        sandbox = options.sandbox,
+        "command finished"
+    );
+    for handle in pumps {
+        let _ = handle.join();
+    }
+
+    let output = options.capture.then(|| {
+        let bytes = captured.lock().map(|b| b.clone()).unwrap_or_default();
+        String::from_utf8_lossy(&bytes).into_owned()
+    });
+    Ok(ExecOutcome {
+        exit_code,
+        timed_out,
+        output,
+    })
+}
+
+/// Wait for the child, killing it at the timeout. Returns (exit code, timed out).
+fn wait(child: &mut Child, timeout: Option<Duration>) -> Result<(i32, bool), String> {
+    let Some(timeout) = timeout else {
+        let status = child.wait().map_err(|e| format!("Failed to wait: {}", e))?;
+        return Ok((exit_code(status), false));
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait().map_err(|e| format!("Failed to wait: {}", e))? {
+            return Ok((exit_code(status), false));
+        }
+        if Instant::now() >= deadline {
+            // The whole group: the shell and everything it started
+            #[cfg(unix)]
+            unsafe {
+                libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok((TIMEOUT_EXIT_CODE, true));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// The exit code, or 128 + the signal for a command killed by one, as sh reports it
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Start the command in a process group of its own. Returns whether the
+/// group also gets the terminal, which it needs to read input and to
+/// receive Ctrl+C; that's only done when slashcmd is in the foreground.
+#[cfg(unix)]
+fn own_group(invocation: &mut Command) -> bool {
+    use std::os::unix::process::CommandExt;
+
+    invocation.process_group(0);
+    let foreground = io::stdin().is_terminal() && unsafe { libc::tcgetpgrp(0) == libc::getpgrp() };
+    if foreground {
+        // Set in the child too, before exec, so it never reads from a
+        // terminal it doesn't own yet
+        unsafe {
+            invocation.pre_exec(|| {
+                libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+                libc::tcsetpgrp(0, libc::getpgrp());
+                libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+                Ok(())
+            });
+        }
+    }
+    foreground
+}
+
+/// Make slashcmd's group the terminal's foreground again
+#[cfg(unix)]
+fn take_terminal_back() {
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(0, libc::getpgrp());
+        libc::signal(libc::SIGTTOU, previous);
+    }
+}
+
+/// Copy a child's pipe to our own stream, keeping the tail in `captured`
+fn pump<R, W>(mut from: R, mut to: W, captured: Arc<Mutex<Vec<u8>>>) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = from.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let _ = to.write_all(&buf[..n]);
+            let _ = to.flush();
+            if let Ok(mut captured) = captured.lock() {
+                captured.extend_from_slice(&buf[..n]);
+                let excess = captured.len().saturating_sub(MAX_CAPTURE_BYTES);
+                captured.drain(..excess);
+            }
+        }
+    })
+}
+
+/// Type a command into a tmux pane and press Enter there. The command runs
+/// in that pane's shell, so its exit code is not known here.
+pub fn send_to_tmux(pane: &str, command: &str) -> Result<(), String> {
+    for args in tmux_args(pane, command) {
+        let status = Command::new("tmux")
+            .args(&args)
+            .status()
+            .map_err(|e| format!("Failed to run tmux: {}", e))?;
+        if !status.success() {
+            return Err(format!("tmux could not send to pane '{}'", pane));
+        }
+    }
+    Ok(())
+}
+
+/// `send-keys` invocations: the command literally (so words like "Enter"
+/// in it aren't read as key names), then the Enter key
+fn tmux_args(pane: &str, command: &str) -> [Vec<String>; 2] {
+    let send = |keys: &[&str]| {
+        ["send-keys", "-t", pane]
+            .iter()
+            .chain(keys)
+            .map(|s| s.to_string())
+            .collect()
+    };
+    [send(&["-l", command]), send(&["Enter"])]
+}
+
+/// Build the sandboxed invocation. Never falls back to running unsandboxed.
+fn sandboxed(command: &str) -> Result<Command, String> {
+    let cwd = std::env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+
+    if cfg!(target_os = "macos") {
+        let mut sandbox = Command::new("sandbox-exec");
+        sandbox
+            .arg("-p")
+            .arg(seatbelt_profile(&cwd))
+            .arg("sh")
+            .arg("-c")
+            .arg(command);
+        return Ok(sandbox);
+    }
+
+    if find_in_path("bwrap").is_some() {
+        let mut sandbox = Command::new("bwrap");
+        sandbox.args(bwrap_args(&cwd)).arg("sh").arg("-c").arg(command);
+        return Ok(sandbox);
+    }
+
+    if find_in_path("firejail").is_some() {
+        let mut sandbox = Command::new("firejail");
+        sandbox
+            .arg("--quiet")
+            .arg("--read-only=/")
+            .arg(format!("--read-write={}", cwd.display()))
+            .arg("--private-tmp")
+            .arg("--")
+            .arg("sh")
+            .arg("-c")
+            .arg(command);
+        return Ok(sandbox);
+    }
+
+    Err("--sandbox needs bwrap (bubblewrap) or firejail installed".to_string())
+}
+
+/// macOS Seatbelt profile: everything allowed except writes outside cwd and temp
+fn seatbelt_profile(cwd: &Path) -> String {
+    let quote = |p: &str| p.replace('\\', "\\\\").replace('"', "\\\"");
+    format!(
+        r#"(version 1)
+(allow default)
+(deny file-write*)
+(allow file-write*
+    (subpath "{}")
+    (subpath "/private/tmp")
+    (subpath "/private/var/folders")
+    (literal "/dev/null")
+    (literal "/dev/tty"))"#,
+        quote(&cwd.to_string_lossy())
+    )
+}
+
+/// bubblewrap arguments: read-only root, writable cwd, private /tmp
+fn bwrap_args(cwd: &Path) -> Vec<String> {
+    let cwd = cwd.to_string_lossy().to_string();
+    [
+        "--ro-bind", "/", "/",
+        "--dev", "/dev",
+        "--proc", "/proc",
+        "--tmpfs", "/tmp",
+        "--bind", &cwd, &cwd,
+        "--chdir", &cwd,
+        "--die-with-parent",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+/// Where `program` is on PATH, if it is
+pub fn find_in_path(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeout_kills_command() {
+        let options = ExecOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let outcome = execute("sleep 5", &options).unwrap();
+        assert!(outcome.timed_out);
+        assert_eq!(outcome.exit_code, TIMEOUT_EXIT_CODE);
+        assert!(started.elapsed() < Duration::from_secs(3));
+
+        let outcome = execute("exit 3", &options).unwrap();
+        assert!(!outcome.timed_out);
+        assert_eq!(outcome.exit_code, 3);
+
+        // Grandchildren go too: here the `sh` under xargs
+        let marker = std::env::temp_dir().join(format!("slashcmd-exec-{}", std::process::id()));
+        let command = format!("echo {} | xargs -I{{}} sh -c 'sleep 1; touch {{}}'", marker.display());
+        assert!(execute(&command, &options).unwrap().timed_out);
+        thread::sleep(Duration::from_millis(1500));
+        assert!(!marker.exists());
+
+        // Killed by a signal, not a success
+        #[cfg(unix)]
+        assert_eq!(execute("kill -SEGV $$", &options).unwrap().exit_code, 139);
+    }
+
+    #[test]
+    fn test_capture_keeps_both_streams() {
+        let options = ExecOptions {
+            capture: true,
+            ..Default::default()
+        };
+        let outcome = execute("echo out; echo err >&2; exit 1", &options).unwrap();
+        let output = outcome.output.unwrap();
+        assert!(output.contains("out\n"));
+        assert!(output.contains("err\n"));
+        assert_eq!(outcome.exit_code, 1);
+    }
+
+    #[test]
+    fn test_sandbox_profiles_allow_cwd_writes() {
+        let cwd = Path::new("/work/my \"project\"");
+        assert!(seatbelt_profile(cwd).contains(r#"(subpath "/work/my \"project\"")"#));
+
+        let args = bwrap_args(Path::new("/work/app"));
+        let bind = args.iter().position(|a| a == "--bind").unwrap();
+        assert_eq!(&args[bind + 1..bind + 3], ["/work/app", "/work/app"]);
+        assert_eq!(&args[..3], ["--ro-bind", "/", "/"]);
+    }
+
+    #[test]
+    fn test_tmux_sends_command_literally() {
+        let [typed, enter] = tmux_args("work:1.0", "echo Enter -l");
+        assert_eq!(typed, ["send-keys", "-t", "work:1.0", "-l", "echo Enter -l"]);
+        assert_eq!(enter, ["send-keys", "-t", "work:1.0", "Enter"]);
+    }
+}
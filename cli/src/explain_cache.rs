@@ -0,0 +1,116 @@
+//! On-disk cache of explanations
+//!
+//! Refining a query often lands on the same command again, and explaining it
+//! a second time costs a request and a few seconds for an identical answer.
+//! Explanations are stored one file per (command, style, verbosity, language,
+//! model, prompt version), named by their hash, and the oldest are pruned
+//! past `MAX_ENTRIES`.
+
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::config_dir;
+use crate::ipc::{ExplainOptions, Generation};
+use crate::prompt;
+
+/// Entries kept before the least recently written are removed
+const MAX_ENTRIES: usize = 500;
+
+fn cache_dir() -> PathBuf {
+    config_dir().join("explain-cache")
+}
+
+/// Everything that changes the explanation, hashed
+fn key(command: &str, options: &ExplainOptions, model: &str) -> String {
+    // Custom styles serialize with their prompt, so editing one invalidates it
+    let style = serde_json::to_string(&options.style).unwrap_or_default();
+    // Only hashed when set, so entries made with the defaults stay valid
+    let generation = if options.generation == Generation::default() {
+        String::new()
+    } else {
+        serde_json::to_string(&options.generation).unwrap_or_default()
+    };
+    let mut hasher = Sha256::new();
+    for field in [
+        command,
+        &style,
+        options.verbosity.as_str(),
+        options.lang.as_deref().unwrap_or(""),
+        model,
+        &prompt::prompt_version(),
+        &generation,
+    ] {
+        hasher.update(field.as_bytes());
+        // Separator so ("ab", "c") and ("a", "bc") differ
+        hasher.update([0]);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// A cached explanation of `command`
+pub fn get(command: &str, options: &ExplainOptions, model: &str) -> Option<String> {
+    let explanation = fs::read_to_string(cache_dir().join(key(command, options, model))).ok()?;
+    tracing::debug!(command, "explanation cache hit");
+    Some(explanation)
+}
+
+/// Remember an explanation. Failures are ignored; the cache is best effort.
+pub fn put(command: &str, options: &ExplainOptions, model: &str, explanation: &str) {
+    let dir = cache_dir();
+    if fs::create_dir_all(&dir).is_err() {
+        return;
+    }
+    // Write then rename so a reader never sees half an explanation
+    let path = dir.join(key(command, options, model));
+    let tmp = path.with_extension("tmp");
+    if fs::write(&tmp, explanation).is_ok() {
+        let _ = fs::rename(&tmp, &path);
+    }
+    prune(&dir);
+}
+
+fn prune(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut files: Vec<_> = entries
+        .flatten()
+        .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+        .collect();
+    if files.len() <= MAX_ENTRIES {
+        return;
+    }
+    files.sort();
+    for (_, path) in &files[..files.len() - MAX_ENTRIES] {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::{ExplainStyle, Verbosity};
+
+    #[test]
+    fn test_key_covers_options_and_model() {
+        let options = ExplainOptions::default();
+        let base = key("ls -la", &options, "m1");
+        assert_eq!(base, key("ls -la", &options, "m1"));
+        assert_ne!(base, key("ls -l", &options, "m1"));
+        assert_ne!(base, key("ls -la", &options, "m2"));
+        let other_style = ExplainOptions { style: ExplainStyle::Diagram, ..Default::default() };
+        assert_ne!(base, key("ls -la", &other_style, "m1"));
+        let terse = ExplainOptions { verbosity: Verbosity::Terse, ..Default::default() };
+        assert_ne!(base, key("ls -la", &terse, "m1"));
+        let spanish = ExplainOptions { lang: Some("es".to_string()), ..Default::default() };
+        assert_ne!(base, key("ls -la", &spanish, "m1"));
+        let generation = Generation { temperature: Some(0.0), ..Default::default() };
+        let cold = ExplainOptions { generation, ..Default::default() };
+        assert_ne!(base, key("ls -la", &cold, "m1"));
+    }
+}
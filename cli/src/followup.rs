@@ -0,0 +1,158 @@
+//! Follow-up questions about a command's output
+//!
+//! With `--capture`, the executed command's output is kept and the user gets
+//! a one-key follow-up: summarize the output, or ask why the command failed.
+//! The output goes back to the model (Gemini directly, or the edge proxy).
+
+use crossterm::event::{self, Event, KeyCode};
+use crossterm::terminal;
+use serde::Serialize;
+use std::io::IsTerminal;
+
+#[cfg(feature = "edge")]
+use crate::edge::EdgeClient;
+use crate::exec::ExecOutcome;
+#[cfg(feature = "gemini")]
+use crate::gemini::GeminiClient;
+use crate::highlight::{dim, format_safety};
+use crate::secrets;
+
+/// Output budget for follow-up answers
+const FOLLOW_UP_MAX_TOKENS: u32 = 600;
+
+/// Where follow-up questions are answered
+pub enum FollowUpSource {
+    #[cfg(feature = "gemini")]
+    Gemini { api_key: String },
+    #[cfg(feature = "edge")]
+    Edge { token: String },
+}
+
+/// What the user wants to know about the output
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Question {
+    Summarize,
+    WhyFailed,
+}
+
+/// A follow-up request, also the body sent to the edge `/followup` endpoint
+#[derive(Serialize)]
+pub struct FollowUp {
+    pub command: String,
+    pub output: String,
+    pub exit_code: i32,
+    pub question: Question,
+}
+
+/// Offer the follow-up keys after a captured run and print the answer
+pub fn offer(source: &FollowUpSource, command: &str, outcome: &ExecOutcome) {
+    let Some(output) = &outcome.output else {
+        return;
+    };
+    if !std::io::stdin().is_terminal() {
+        return;
+    }
+
+    let failed = outcome.exit_code != 0;
+    let hint = if failed {
+        "[w] why did it fail  [s] summarize output  [any other key] done"
+    } else {
+        "[s] summarize output  [w] why did it fail  [any other key] done"
+    };
+    eprint!("\n{} ", dim(hint));
+
+    let question = match read_key() {
+        Some('s') => Question::Summarize,
+        Some('w') => Question::WhyFailed,
+        _ => {
+            eprintln!();
+            return;
+        }
+    };
+    eprintln!("\n{}", dim("Thinking..."));
+
+    let request = FollowUp {
+        command: command.to_string(),
+        output: secrets::scrub(output.clone(), "command output"),
+        exit_code: outcome.exit_code,
+        question,
+    };
+    match ask(source, &request) {
+        Ok(answer) => {
+            for line in answer.lines() {
+                println!("{}", format_safety(line));
+            }
+        }
+        Err(e) => eprintln!("{}", dim(&format!("(follow-up unavailable: {})", e))),
+    }
+}
+
+/// Answer a follow-up question
+#[cfg_attr(not(any(feature = "edge", feature = "gemini")), allow(unused_variables))]
+pub fn ask(source: &FollowUpSource, request: &FollowUp) -> Result<String, String> {
+    match *source {
+        #[cfg(feature = "gemini")]
+        FollowUpSource::Gemini { ref api_key } => GeminiClient::new(api_key.clone())
+            .generate(build_prompt(request), FOLLOW_UP_MAX_TOKENS)
+            .map_err(|e| e.to_string()),
+        #[cfg(feature = "edge")]
+        FollowUpSource::Edge { ref token } => EdgeClient::new(token.clone())
+            .follow_up(request)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Prompt for a follow-up question about captured output
+pub fn build_prompt(request: &FollowUp) -> String {
+    let task = match request.question {
+        Question::Summarize => {
+            "Summarize this output in 2-4 short sentences: what it shows and anything notable \
+             (errors, warnings, unusually large or small values)."
+        }
+        Question::WhyFailed => {
+            "Explain why this command failed in 1-3 short sentences, then suggest a fixed \
+             command in a ```bash code block if one would help."
+        }
+    };
+
+    format!(
+        r#"A developer ran this shell command.
+
+Command: `{command}`
+Exit code: {exit_code}
+
+Output (may be truncated to the last part):
+```
+{output}
+```
+
+{task}
+Be concise. No markdown headers."#,
+        command = request.command,
+        exit_code = request.exit_code,
+        output = request.output.trim_end(),
+        task = task
+    )
+}
+
+/// Read a single key press without waiting for Enter
+fn read_key() -> Option<char> {
+    terminal::enable_raw_mode().ok()?;
+    let key = loop {
+        match event::read() {
+            Ok(Event::Key(key)) => break key.code,
+            Ok(_) => continue,
+            Err(_) => {
+                terminal::disable_raw_mode().ok();
+                return None;
+            }
+        }
+    };
+    terminal::disable_raw_mode().ok();
+
+    match key {
+        KeyCode::Char(c) => Some(c.to_ascii_lowercase()),
+        _ => None,
+    }
+}
@@ -1,11 +1,20 @@
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::io::{BufRead, BufReader};
+use std::time::{Duration, Instant};
+use tracing::debug;
 use ureq::{Agent, AgentBuilder};
 
-use crate::ipc::ExplainStyle;
+use crate::budget;
+use crate::error::SlashcmdError;
+use crate::ipc::{ExplainOptions, ExplainStyle, Verbosity};
+use crate::models::{Model, Role};
+use crate::prompt::{load_template, os_name, render_template};
 
-const GEMINI_API_URL: &str = "https://generativelanguage.googleapis.com/v1beta/models/gemini-3-flash-preview:generateContent";
+const GEMINI_MODELS_URL: &str = "https://generativelanguage.googleapis.com/v1beta/models";
+/// Model used for explanations, part of the explanation cache key
+pub const MODEL: &str = "gemini-3-flash-preview";
 const HTTP_TIMEOUT_SECS: u64 = 30;
+const TEMPERATURE: f32 = 0.3;
 
 #[derive(Serialize)]
 struct GeminiRequest {
@@ -34,6 +43,30 @@ struct GenerationConfig {
 #[derive(Deserialize)]
 struct GeminiResponse {
     candidates: Option<Vec<Candidate>>,
+    #[serde(rename = "usageMetadata")]
+    usage_metadata: Option<UsageMetadata>,
+}
+
+impl GeminiResponse {
+    fn text(self) -> String {
+        self.candidates
+            .and_then(|c| c.into_iter().next())
+            .map(|c| {
+                c.content
+                    .parts
+                    .into_iter()
+                    .map(|p| p.text)
+                    .collect::<Vec<_>>()
+                    .join("")
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize)]
+struct UsageMetadata {
+    #[serde(rename = "totalTokenCount", default)]
+    total_token_count: u64,
 }
 
 #[derive(Deserialize)]
@@ -68,64 +101,207 @@ impl GeminiClient {
     }
 
     /// Explain a command with safety assessment
-    pub fn explain(&self, command: &str, style: ExplainStyle) -> Result<String, String> {
-        let prompt = build_explain_prompt(command, style);
+    pub fn explain(&self, command: &str, options: &ExplainOptions) -> Result<String, SlashcmdError> {
+        self.send(explain_request(build_explain_prompt(command, options), options))
+    }
+
+    /// Like `explain`, but hands each piece of text to `on_chunk` as it arrives
+    pub fn explain_streaming(
+        &self,
+        command: &str,
+        options: &ExplainOptions,
+        mut on_chunk: impl FnMut(&str),
+    ) -> Result<String, SlashcmdError> {
+        let request = explain_request(build_explain_prompt(command, options), options);
+        let url = format!(
+            "{}/{}:streamGenerateContent?alt=sse&key={}",
+            GEMINI_MODELS_URL, MODEL, self.api_key
+        );
 
-        let request = GeminiRequest {
-            contents: vec![Content {
-                parts: vec![Part { text: prompt }],
-            }],
-            generation_config: GenerationConfig {
-                temperature: 0.3,
-                max_output_tokens: 500,
-            },
-        };
+        let started = Instant::now();
+        let response = self
+            .agent
+            .post(&url)
+            .set("Content-Type", "application/json")
+            .send_json(&request)
+            .map_err(SlashcmdError::from_ureq)?;
 
-        let url = format!("{}?key={}", GEMINI_API_URL, self.api_key);
+        // Each SSE event is a full GeminiResponse holding the next piece
+        let mut text = String::new();
+        let mut tokens = None;
+        for line in BufReader::new(response.into_reader()).lines() {
+            let line = line.map_err(|e| SlashcmdError::Network(format!("Gemini stream: {}", e)))?;
+            let Some(data) = line.strip_prefix("data: ") else {
+                continue;
+            };
+            let event: GeminiResponse =
+                serde_json::from_str(data).map_err(|e| SlashcmdError::Parse(format!("Gemini: {}", e)))?;
+            // Usage is cumulative, the last event has the total
+            tokens = event.usage_metadata.as_ref().map(|u| u.total_token_count).or(tokens);
+            let piece = event.text();
+            if !piece.is_empty() {
+                on_chunk(&piece);
+                text.push_str(&piece);
+            }
+        }
+        debug!(elapsed_ms = started.elapsed().as_millis() as u64, ?tokens, "gemini stream");
+        if let Some(tokens) = tokens {
+            budget::record_tokens(tokens);
+        }
 
+        Ok(text.trim().to_string())
+    }
+
+    /// Send a prompt and return the model's text reply
+    pub fn generate(&self, prompt: String, max_output_tokens: u32) -> Result<String, SlashcmdError> {
+        self.send(gemini_request(prompt, TEMPERATURE, max_output_tokens))
+    }
+
+    fn send(&self, request: GeminiRequest) -> Result<String, SlashcmdError> {
+        let url = format!("{}/{}:generateContent?key={}", GEMINI_MODELS_URL, MODEL, self.api_key);
+
+        let started = Instant::now();
         let response = self
             .agent
             .post(&url)
             .set("Content-Type", "application/json")
             .send_json(&request)
-            .map_err(|e| format!("Gemini HTTP error: {}", e))?;
+            .map_err(SlashcmdError::from_ureq)?;
 
         let gemini_response: GeminiResponse = response
             .into_json()
-            .map_err(|e| format!("Gemini JSON parse error: {}", e))?;
+            .map_err(|e| SlashcmdError::Parse(format!("Gemini: {}", e)))?;
+        let tokens = gemini_response.usage_metadata.as_ref().map(|u| u.total_token_count);
+        debug!(elapsed_ms = started.elapsed().as_millis() as u64, ?tokens, "gemini generate");
+        if let Some(tokens) = tokens {
+            budget::record_tokens(tokens);
+        }
 
-        let text = gemini_response
-            .candidates
-            .and_then(|c| c.into_iter().next())
-            .map(|c| {
-                c.content
-                    .parts
-                    .into_iter()
-                    .map(|p| p.text)
-                    .collect::<Vec<_>>()
-                    .join("")
-            })
-            .unwrap_or_default();
+        Ok(gemini_response.text().trim().to_string())
+    }
 
-        Ok(text.trim().to_string())
+    /// Models this key can use, marking the one explanations come from
+    pub fn models(&self) -> Result<Vec<Model>, SlashcmdError> {
+        #[derive(Deserialize)]
+        struct ModelList {
+            #[serde(default)]
+            models: Vec<ModelEntry>,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ModelEntry {
+            name: String,
+            #[serde(default)]
+            supported_generation_methods: Vec<String>,
+        }
+
+        let url = format!("{}?pageSize=1000&key={}", GEMINI_MODELS_URL, self.api_key);
+        let list: ModelList = self
+            .agent
+            .get(&url)
+            .call()
+            .map_err(SlashcmdError::from_ureq)?
+            .into_json()
+            .map_err(|e| SlashcmdError::Parse(format!("Gemini: {}", e)))?;
+        Ok(list
+            .models
+            .into_iter()
+            .map(|entry| {
+                let id = entry.name.trim_start_matches("models/").to_string();
+                let explains = entry.supported_generation_methods.iter().any(|m| m == "generateContent");
+                Model {
+                    provider: "gemini".to_string(),
+                    roles: if explains { vec![Role::Explanation] } else { vec![] },
+                    in_use: id == MODEL,
+                    id,
+                }
+            })
+            .collect())
     }
 
     /// Warmup TLS connection
-    pub fn warmup(&self) -> Result<(), String> {
+    pub fn warmup(&self) -> Result<(), SlashcmdError> {
         // Simple request to establish connection
-        let url = format!(
-            "https://generativelanguage.googleapis.com/v1beta/models?key={}",
-            self.api_key
-        );
+        let url = format!("{}?key={}", GEMINI_MODELS_URL, self.api_key);
         self.agent
             .get(&url)
             .call()
-            .map_err(|e| format!("Gemini warmup error: {}", e))?;
+            .map_err(SlashcmdError::from_ureq)?;
         Ok(())
     }
 }
 
-fn build_explain_prompt(command: &str, style: ExplainStyle) -> String {
+fn gemini_request(prompt: String, temperature: f32, max_output_tokens: u32) -> GeminiRequest {
+    GeminiRequest {
+        contents: vec![Content {
+            parts: vec![Part { text: prompt }],
+        }],
+        generation_config: GenerationConfig {
+            temperature,
+            max_output_tokens,
+        },
+    }
+}
+
+/// An explanation request, with `[generation]` overriding the defaults
+fn explain_request(prompt: String, options: &ExplainOptions) -> GeminiRequest {
+    let generation = &options.generation;
+    gemini_request(
+        prompt,
+        generation.temperature.unwrap_or(TEMPERATURE),
+        generation.max_output_tokens.unwrap_or_else(|| max_output_tokens(options.verbosity)),
+    )
+}
+
+/// Output budget per verbosity level
+fn max_output_tokens(verbosity: Verbosity) -> u32 {
+    match verbosity {
+        Verbosity::Terse => 200,
+        Verbosity::Normal => 500,
+        Verbosity::Eli5 => 1200,
+    }
+}
+
+/// English name for a language code, so the model isn't left guessing
+fn language_name(code: &str) -> String {
+    let name = match code.to_lowercase().as_str() {
+        "es" => "Spanish",
+        "pl" => "Polish",
+        "ja" => "Japanese",
+        "de" => "German",
+        "fr" => "French",
+        "pt" => "Portuguese",
+        "it" => "Italian",
+        "nl" => "Dutch",
+        "sv" => "Swedish",
+        "uk" => "Ukrainian",
+        "ru" => "Russian",
+        "tr" => "Turkish",
+        "zh" => "Chinese",
+        "ko" => "Korean",
+        "hi" => "Hindi",
+        _ => return code.to_string(),
+    };
+    name.to_string()
+}
+
+/// Build the explanation prompt. A user template at
+/// `~/.config/slashcmd/prompts/explain.tmpl` replaces the built-in one.
+fn build_explain_prompt(command: &str, options: &ExplainOptions) -> String {
+    let (style, verbosity) = (&options.style, options.verbosity);
+    if let Some(template) = load_template("explain") {
+        return render_template(
+            &template,
+            &[
+                ("command", command),
+                ("os", os_name()),
+                ("style", style.as_str()),
+                ("verbosity", verbosity.as_str()),
+                ("lang", options.lang.as_deref().unwrap_or("en")),
+            ],
+        );
+    }
+
     let style_instruction = match style {
         ExplainStyle::Typescript => r#"Explain it as TypeScript-like pseudo-code. Use familiar programming constructs like:
 - `for (const file of files)` for loops
@@ -141,14 +317,89 @@ fn build_explain_prompt(command: &str, style: ExplainStyle) -> String {
 - `files.each do |file|` for loops
 - `if condition` / `end` blocks
 - Use snake_case variable names"#,
+        ExplainStyle::Rust => r#"Explain it as Rust-like pseudo-code. Use familiar programming constructs like:
+- `for file in files` for loops
+- `if condition { }` / `match` for conditionals
+- iterator chains like `.filter()` / `.map()` for pipes
+- Use snake_case variable names"#,
+        ExplainStyle::Go => r#"Explain it as Go-like pseudo-code. Use familiar programming constructs like:
+- `for _, file := range files` for loops
+- `if err != nil { }` for conditionals
+- Comments with `//`
+- Use camelCase variable names"#,
         ExplainStyle::Human => r#"Explain it in plain English, step by step.
 - Use simple, clear language
 - Number each step
 - Avoid jargon where possible"#,
+        ExplainStyle::Annotated => r#"Explain it like explainshell: split the command into segments, one per line.
+- First the program with its positional arguments, then each flag together with its value
+- Follow each segment with ` # ` and a short explanation (max 8 words)
+- Keep operators (|, &&, ||, ;) at the end of the segment before them
+- Copy every segment exactly; do not change, reorder, or drop anything"#,
+        ExplainStyle::Diagram => r#"Explain how data flows through it, one pipeline stage per line.
+- Copy each stage exactly as it appears in the command
+- Keep operators (|, &&, ||, ;) at the end of the stage before them
+- Follow each stage with ` # ` and what it outputs (max 8 words)"#,
+        ExplainStyle::Custom(custom) => &custom.prompt,
+    };
+
+    // Annotated output is the command itself rather than pseudo-code
+    let format_section = match style {
+        ExplainStyle::Annotated => r#"Format (one segment per line, in the original order):
+[SAFETY_LEVEL] One brief sentence.
+```
+find . # search from the current directory
+-type f # regular files only
+-size +100M | # larger than 100 MB
+sort -hr # largest first
+```"#,
+        ExplainStyle::Diagram => r#"Format (one stage per line, in the original order):
+[SAFETY_LEVEL] One brief sentence.
+```
+find . -name "*.log" | # paths of .log files
+xargs wc -l | # line count per file
+sort -n # counts, smallest first
+```"#,
+        _ => match verbosity {
+            Verbosity::Terse => r#"Format (keep pseudo-code to 1-3 lines):
+[SAFETY_LEVEL] One brief sentence.
+```
+pseudo-code
+```"#,
+            Verbosity::Normal => r#"Format (keep pseudo-code to 3-6 lines):
+[SAFETY_LEVEL] One brief sentence.
+```
+pseudo-code
+```"#,
+            Verbosity::Eli5 => r#"Format (pseudo-code up to 15 lines, with a comment on each step):
+[SAFETY_LEVEL] Two or three sentences a beginner can follow.
+```
+pseudo-code
+```"#,
+        },
+    };
+
+    let audience = match verbosity {
+        Verbosity::Terse => "an expert who wants the shortest possible explanation",
+        Verbosity::Normal => "an experienced developer",
+        Verbosity::Eli5 => {
+            "a beginner who is new to the shell: define every flag, operator and concept \
+             in plain words, and don't assume prior knowledge"
+        }
+    };
+
+    // Safety tags stay in English so the CLI can still detect them
+    let language_instruction = match options.lang.as_deref() {
+        Some(code) if !code.eq_ignore_ascii_case("en") => format!(
+            "\nWrite all prose (sentences, steps and comments) in {}. \
+             Keep the safety tag ([SAFE], [CAUTION] or [DANGER]) in English, exactly as shown.\n",
+            language_name(code)
+        ),
+        _ => String::new(),
     };
 
     format!(
-        r#"Analyze this shell command for an experienced developer.
+        r#"Analyze this shell command for {audience}.
 
 SAFETY LEVEL (be practical, not paranoid):
 
@@ -178,15 +429,14 @@ IMPORTANT: Assume the developer knows what they asked for.
 - Only use CAUTION for actual side effects or explicit secret file access
 
 {style_instruction}
-
+{language_instruction}
 Command: `{command}`
 
-Format (keep pseudo-code to 3-6 lines):
-[SAFETY_LEVEL] One brief sentence.
-```
-pseudo-code
-```"#,
+{format_section}"#,
+        audience = audience,
         style_instruction = style_instruction,
-        command = command
+        language_instruction = language_instruction,
+        command = command,
+        format_section = format_section
     )
 }
@@ -1,12 +1,20 @@
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use tracing::debug;
 use ureq::{Agent, AgentBuilder};
 
-use crate::prompt::{build_prompt, parse_response, CommandResult};
+use crate::budget;
+use crate::logs::Provenance;
+use crate::error::SlashcmdError;
+use crate::ipc::Generation;
+use crate::models::{self, Model, Role};
+use crate::ratelimit;
+use crate::prompt::{build_prompt, parse_response, personal_examples, CommandResult};
 
 const GROQ_API_URL: &str = "https://api.groq.com/openai/v1/chat/completions";
 const GROQ_MODELS_URL: &str = "https://api.groq.com/openai/v1/models";
-const GROQ_MODEL: &str = "moonshotai/kimi-k2-instruct-0905";
+pub const GROQ_MODEL: &str = "moonshotai/kimi-k2-instruct-0905";
 const HTTP_TIMEOUT_SECS: u64 = 30;
 const MAX_TOKENS: u32 = 500;
 const TEMPERATURE: f32 = 0.3;
@@ -29,6 +37,16 @@ struct Message {
 #[derive(Deserialize)]
 struct ChatResponse {
     choices: Vec<Choice>,
+    usage: Option<TokenUsage>,
+}
+
+#[derive(Deserialize)]
+struct TokenUsage {
+    #[serde(default)]
+    prompt_tokens: Option<u64>,
+    #[serde(default)]
+    completion_tokens: Option<u64>,
+    total_tokens: u64,
 }
 
 #[derive(Deserialize)]
@@ -45,6 +63,7 @@ struct ResponseMessage {
 pub struct GroqClient {
     agent: Agent,
     api_key: String,
+    rate_limit_tx: Option<Sender<Duration>>,
 }
 
 impl GroqClient {
@@ -55,33 +74,58 @@ impl GroqClient {
             .timeout_read(Duration::from_secs(HTTP_TIMEOUT_SECS))
             .build();
 
-        Self { agent, api_key }
+        Self {
+            agent,
+            api_key,
+            rate_limit_tx: None,
+        }
+    }
+
+    /// Report rate-limit waits (before each retry) to the UI
+    pub fn with_rate_limit_notice(mut self, tx: Sender<Duration>) -> Self {
+        self.rate_limit_tx = Some(tx);
+        self
     }
 
     /// Query Groq API with a natural language request, returns command + safety
-    pub fn query(&self, user_query: &str) -> Result<CommandResult, String> {
+    pub fn query(&self, user_query: &str, generation: &Generation) -> Result<CommandResult, SlashcmdError> {
         let request = ChatRequest {
             messages: vec![Message {
                 role: "user".to_string(),
-                content: build_prompt(user_query),
+                content: build_prompt(user_query, &personal_examples()),
             }],
             model: GROQ_MODEL.to_string(),
             stream: false,
-            max_tokens: MAX_TOKENS,
-            temperature: TEMPERATURE,
+            max_tokens: generation.max_tokens.unwrap_or(MAX_TOKENS),
+            temperature: generation.temperature.unwrap_or(TEMPERATURE),
         };
 
-        let response = self
-            .agent
-            .post(GROQ_API_URL)
-            .set("Authorization", &format!("Bearer {}", self.api_key))
-            .set("Content-Type", "application/json")
-            .send_json(&request)
-            .map_err(|e| format!("HTTP error: {}", e))?;
+        let started = Instant::now();
+        let response = ratelimit::send_json_with_retry(
+            || {
+                self.agent
+                    .post(GROQ_API_URL)
+                    .set("Authorization", &format!("Bearer {}", self.api_key))
+                    .set("Content-Type", "application/json")
+            },
+            &request,
+            self.rate_limit_tx.as_ref(),
+        )?;
 
         let chat_response: ChatResponse = response
             .into_json()
-            .map_err(|e| format!("JSON parse error: {}", e))?;
+            .map_err(|e| SlashcmdError::Parse(e.to_string()))?;
+        let tokens = chat_response.usage.as_ref().map(|u| u.total_tokens);
+        debug!(elapsed_ms = started.elapsed().as_millis() as u64, ?tokens, "groq command");
+        if let Some(tokens) = tokens {
+            budget::record_tokens(tokens);
+        }
+        let provenance = Provenance {
+            model: Some(GROQ_MODEL.to_string()),
+            prompt_tokens: chat_response.usage.as_ref().and_then(|u| u.prompt_tokens),
+            completion_tokens: chat_response.usage.as_ref().and_then(|u| u.completion_tokens),
+            ..Provenance::new("local", "groq", started.elapsed())
+        };
 
         let content = chat_response
             .choices
@@ -89,7 +133,40 @@ impl GroqClient {
             .map(|c| c.message.content.clone())
             .unwrap_or_default();
 
-        parse_response(&content)
+        let mut result = parse_response(&content).map_err(SlashcmdError::Parse)?;
+        result.provenance = Some(provenance);
+        Ok(result)
+    }
+
+    /// Models this key can use, marking the one commands are generated with
+    pub fn models(&self) -> Result<Vec<Model>, SlashcmdError> {
+        #[derive(Deserialize)]
+        struct ModelList {
+            data: Vec<ModelEntry>,
+        }
+        #[derive(Deserialize)]
+        struct ModelEntry {
+            id: String,
+        }
+
+        let list: ModelList = self
+            .agent
+            .get(GROQ_MODELS_URL)
+            .set("Authorization", &format!("Bearer {}", self.api_key))
+            .call()
+            .map_err(SlashcmdError::from_ureq)?
+            .into_json()
+            .map_err(|e| SlashcmdError::Parse(e.to_string()))?;
+        Ok(list
+            .data
+            .into_iter()
+            .map(|entry| Model {
+                provider: "groq".to_string(),
+                roles: if models::groq_can_generate(&entry.id) { vec![Role::Command] } else { vec![] },
+                in_use: entry.id == GROQ_MODEL,
+                id: entry.id,
+            })
+            .collect())
     }
 
     /// Warm up the TLS connection by calling the free /models endpoint.
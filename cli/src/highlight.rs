@@ -1,5 +1,10 @@
-/// Simple ANSI syntax highlighting for pseudo-code
-/// Keeps binary small - no heavy dependencies like syntect
+//! Simple ANSI syntax highlighting for pseudo-code and shell commands
+//! Keeps binary small - no heavy dependencies like syntect
+//!
+//! Safety levels are red, yellow and green by default. The color-blind and
+//! monochrome palettes (`accessibility` in config.toml) add a symbol to each
+//! label and tell the levels apart by weight and inverse video too, so no
+//! level depends on seeing a hue.
 
 // ANSI color codes
 const RESET: &str = "\x1b[0m";
@@ -10,6 +15,13 @@ const FUNCTION: &str = "\x1b[38;5;81m";    // Cyan for functions
 const NUMBER: &str = "\x1b[38;5;208m";     // Orange for numbers
 const TYPE: &str = "\x1b[38;5;81m";        // Cyan for types
 const DIM: &str = "\x1b[2m";               // Dim for less important
+const COMMAND: &str = "\x1b[1;38;5;81m";  // Bold cyan for command names
+const FLAG: &str = "\x1b[38;5;180m";       // Tan for flags
+const REDIRECT: &str = "\x1b[38;5;221m";   // Yellow for redirections
+const SUDO: &str = "\x1b[1;31m";           // Bold red for privilege escalation
+const BANNER: &str = "\x1b[1;30;43m";      // Black on yellow for "you are acting on X"
+const BOLD: &str = "\x1b[1m";             // Weight instead of color (monochrome)
+const INVERSE: &str = "\x1b[1;7m";         // Inverse video for DANGER (monochrome)
 
 /// TypeScript keywords
 const TS_KEYWORDS: &[&str] = &[
@@ -32,188 +44,1105 @@ const RB_KEYWORDS: &[&str] = &[
     "true", "false", "nil", "require", "include", "attr_accessor",
 ];
 
-use crate::ipc::ExplainStyle;
+/// Rust keywords
+const RS_KEYWORDS: &[&str] = &[
+    "fn", "let", "mut", "if", "else", "match", "for", "in", "while", "loop",
+    "return", "use", "struct", "enum", "impl", "trait", "pub", "mod", "const",
+    "static", "as", "ref", "move", "async", "await", "break", "continue",
+    "where", "dyn", "self", "Self", "true", "false", "Some", "None", "Ok", "Err",
+];
+
+/// Go keywords
+const GO_KEYWORDS: &[&str] = &[
+    "func", "var", "const", "if", "else", "for", "range", "return", "go",
+    "defer", "chan", "select", "switch", "case", "default", "type", "struct",
+    "interface", "map", "package", "import", "break", "continue", "nil",
+    "true", "false",
+];
+
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+use crate::annotate;
+use crate::diagram;
+use crate::ipc::{CustomStyle, ExplainStyle};
+use crate::shell::{self, Shell};
+
+/// Lexical rules for one pseudo-code language
+struct Language<'a> {
+    keywords: &'a [&'a str],
+    line_comment: &'a str,
+    block_comment: Option<(&'static str, &'static str)>,
+    /// Python `"""..."""` / `'''...'''` strings (may span lines)
+    triple_quotes: bool,
+    /// TypeScript `` `...${expr}...` `` template literals (may span lines)
+    template_literals: bool,
+    /// Ruby `"...#{expr}..."` interpolation in double-quoted strings
+    hash_interpolation: bool,
+    /// Prefixes that may precede a string literal (Python `f"..."`, `r'...'`)
+    string_prefixes: &'static [&'static str],
+    /// Go `` `...` `` raw strings (may span lines, no escapes)
+    raw_backtick_strings: bool,
+    /// Rust `'a` lifetimes: a single quote only starts a char literal (`'x'`)
+    lifetimes: bool,
+}
+
+const TYPESCRIPT: Language<'static> = Language {
+    keywords: TS_KEYWORDS,
+    line_comment: "//",
+    block_comment: Some(("/*", "*/")),
+    triple_quotes: false,
+    template_literals: true,
+    hash_interpolation: false,
+    string_prefixes: &[],
+    raw_backtick_strings: false,
+    lifetimes: false,
+};
+
+const PYTHON: Language<'static> = Language {
+    keywords: PY_KEYWORDS,
+    line_comment: "#",
+    block_comment: None,
+    triple_quotes: true,
+    template_literals: false,
+    hash_interpolation: false,
+    string_prefixes: &["r", "b", "f", "u", "rb", "br", "fr", "rf"],
+    raw_backtick_strings: false,
+    lifetimes: false,
+};
+
+const RUBY: Language<'static> = Language {
+    keywords: RB_KEYWORDS,
+    line_comment: "#",
+    block_comment: None,
+    triple_quotes: false,
+    template_literals: false,
+    hash_interpolation: true,
+    string_prefixes: &[],
+    raw_backtick_strings: false,
+    lifetimes: false,
+};
+
+const RUST: Language<'static> = Language {
+    keywords: RS_KEYWORDS,
+    line_comment: "//",
+    block_comment: Some(("/*", "*/")),
+    triple_quotes: false,
+    template_literals: false,
+    hash_interpolation: false,
+    string_prefixes: &["b"],
+    raw_backtick_strings: false,
+    lifetimes: true,
+};
+
+const GO: Language<'static> = Language {
+    keywords: GO_KEYWORDS,
+    line_comment: "//",
+    block_comment: Some(("/*", "*/")),
+    triple_quotes: false,
+    template_literals: false,
+    hash_interpolation: false,
+    string_prefixes: &[],
+    raw_backtick_strings: true,
+    lifetimes: false,
+};
+
+/// Token categories produced by the pseudo-code tokenizer
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TokenKind {
+    Plain,
+    Keyword,
+    String,
+    Comment,
+    Number,
+    Function,
+    /// `${` / `#{` / `}` around an interpolated expression
+    Interpolation,
+}
 
 /// Highlight code based on style
-pub fn highlight(code: &str, style: ExplainStyle) -> String {
+pub fn highlight(code: &str, style: &ExplainStyle) -> String {
     match style {
-        ExplainStyle::Typescript => highlight_typescript(code),
-        ExplainStyle::Python => highlight_python(code),
-        ExplainStyle::Ruby => highlight_ruby(code),
+        ExplainStyle::Typescript => highlight_code(code, &TYPESCRIPT),
+        ExplainStyle::Python => highlight_code(code, &PYTHON),
+        ExplainStyle::Ruby => highlight_code(code, &RUBY),
+        ExplainStyle::Rust => highlight_code(code, &RUST),
+        ExplainStyle::Go => highlight_code(code, &GO),
         ExplainStyle::Human => code.to_string(), // No highlighting for human
+        ExplainStyle::Annotated => annotate::render(code)
+            .lines()
+            .map(highlight_command)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        ExplainStyle::Diagram => diagram::render(code),
+        ExplainStyle::Custom(custom) => highlight_custom(code, custom),
     }
 }
 
-fn highlight_typescript(code: &str) -> String {
+/// Highlight a config-defined style: its own keywords, C-like literals
+fn highlight_custom(code: &str, custom: &CustomStyle) -> String {
+    let keywords: Vec<&str> = custom.keywords.iter().map(String::as_str).collect();
+    let lang = Language {
+        keywords: &keywords,
+        line_comment: custom.line_comment.as_deref().unwrap_or("#"),
+        block_comment: None,
+        triple_quotes: false,
+        template_literals: false,
+        hash_interpolation: false,
+        string_prefixes: &[],
+        raw_backtick_strings: false,
+        lifetimes: false,
+    };
+    highlight_code(code, &lang)
+}
+
+fn highlight_code(code: &str, lang: &Language) -> String {
     let mut result = String::new();
 
-    for line in code.lines() {
-        let highlighted = highlight_line(line, TS_KEYWORDS, "//");
-        result.push_str(&highlighted);
-        result.push('\n');
+    for (kind, text) in tokenize(code, lang) {
+        let color = match kind {
+            TokenKind::Plain => {
+                result.push_str(&text);
+                continue;
+            }
+            TokenKind::Keyword | TokenKind::Interpolation => KEYWORD,
+            TokenKind::String => STRING,
+            TokenKind::Comment => COMMENT,
+            TokenKind::Number => NUMBER,
+            TokenKind::Function => FUNCTION,
+        };
+        // Color each line separately so a multi-line token never bleeds
+        // into lines that get truncated away or redrawn
+        let painted: Vec<String> = text
+            .split('\n')
+            .map(|part| if part.is_empty() { String::new() } else { paint(color, part) })
+            .collect();
+        result.push_str(&painted.join("\n"));
     }
 
     result.trim_end().to_string()
 }
 
-fn highlight_python(code: &str) -> String {
-    let mut result = String::new();
+/// Split code into colored tokens. Works on the whole block so strings
+/// and comments can span lines.
+fn tokenize(code: &str, lang: &Language) -> Vec<(TokenKind, String)> {
+    let chars: Vec<char> = code.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
 
-    for line in code.lines() {
-        let highlighted = highlight_line(line, PY_KEYWORDS, "#");
-        result.push_str(&highlighted);
-        result.push('\n');
-    }
+    while i < chars.len() {
+        let c = chars[i];
 
-    result.trim_end().to_string()
-}
+        if starts_with(&chars, i, lang.line_comment) {
+            let end = chars[i..]
+                .iter()
+                .position(|&ch| ch == '\n')
+                .map_or(chars.len(), |p| i + p);
+            push_token(&mut tokens, TokenKind::Comment, &chars[i..end]);
+            i = end;
+        } else if let Some((open, close)) =
+            lang.block_comment.filter(|(open, _)| starts_with(&chars, i, open))
+        {
+            let body_start = i + open.chars().count();
+            let end = (body_start..chars.len())
+                .find(|&j| starts_with(&chars, j, close))
+                .map_or(chars.len(), |j| j + close.chars().count());
+            push_token(&mut tokens, TokenKind::Comment, &chars[i..end]);
+            i = end;
+        } else if lang.triple_quotes
+            && (starts_with(&chars, i, "\"\"\"") || starts_with(&chars, i, "'''"))
+        {
+            i = lex_triple_quoted(&chars, i, &mut tokens);
+        } else if c == '`' && lang.template_literals {
+            i = lex_string(&chars, i, lang, "${", true, &mut tokens);
+        } else if c == '`' && lang.raw_backtick_strings {
+            let end = chars[i + 1..]
+                .iter()
+                .position(|&ch| ch == '`')
+                .map_or(chars.len(), |p| i + p + 2);
+            push_token(&mut tokens, TokenKind::String, &chars[i..end]);
+            i = end;
+        } else if c == '\'' && lang.lifetimes && !is_char_literal(&chars, i) {
+            // Lifetime or loop label ('a, 'outer)
+            push_token(&mut tokens, TokenKind::Plain, &chars[i..i + 1]);
+            i += 1;
+        } else if c == '"' && lang.hash_interpolation {
+            i = lex_string(&chars, i, lang, "#{", false, &mut tokens);
+        } else if c == '"' || c == '\'' {
+            i = lex_string(&chars, i, lang, "", false, &mut tokens);
+        } else if c.is_ascii_digit()
+            || (c == '.'
+                && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit())
+                && !chars
+                    .get(i.wrapping_sub(1))
+                    .is_some_and(|p| is_ident_char(*p) || *p == '.'))
+        {
+            let end = number_end(&chars, i);
+            push_token(&mut tokens, TokenKind::Number, &chars[i..end]);
+            i = end;
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let end = (i..chars.len())
+                .find(|&j| !is_ident_char(chars[j]))
+                .unwrap_or(chars.len());
+            let word: String = chars[i..end].iter().collect();
 
-fn highlight_ruby(code: &str) -> String {
-    let mut result = String::new();
+            let next = chars.get(end).copied();
+            if matches!(next, Some('"') | Some('\''))
+                && lang.string_prefixes.contains(&word.to_lowercase().as_str())
+            {
+                // Prefixed string literal: r"...", f'...'
+                push_token(&mut tokens, TokenKind::String, &chars[i..end]);
+                i = if lang.triple_quotes
+                    && (starts_with(&chars, end, "\"\"\"") || starts_with(&chars, end, "'''"))
+                {
+                    lex_triple_quoted(&chars, end, &mut tokens)
+                } else {
+                    lex_string(&chars, end, lang, "", false, &mut tokens)
+                };
+                continue;
+            }
 
-    for line in code.lines() {
-        let highlighted = highlight_line(line, RB_KEYWORDS, "#");
-        result.push_str(&highlighted);
-        result.push('\n');
+            let is_macro = next == Some('!') && chars.get(end + 1) == Some(&'(');
+            let kind = if lang.keywords.contains(&word.as_str()) {
+                TokenKind::Keyword
+            } else if next == Some('(') || is_macro {
+                TokenKind::Function
+            } else {
+                TokenKind::Plain
+            };
+            push_token(&mut tokens, kind, &chars[i..end]);
+            i = end;
+        } else {
+            push_token(&mut tokens, TokenKind::Plain, &chars[i..i + 1]);
+            i += 1;
+        }
     }
 
-    result.trim_end().to_string()
+    tokens
 }
 
-fn highlight_line(line: &str, keywords: &[&str], comment_prefix: &str) -> String {
-    // Handle full-line comments
-    let trimmed = line.trim_start();
-    if trimmed.starts_with(comment_prefix) {
-        return format!("{}{}{}", COMMENT, line, RESET);
+/// `'x'` or `'\n'`, as opposed to a Rust lifetime `'a`
+fn is_char_literal(chars: &[char], start: usize) -> bool {
+    match chars.get(start + 1) {
+        Some('\\') => chars[start + 2..].iter().take(10).any(|&c| c == '\''),
+        Some(_) => chars.get(start + 2) == Some(&'\''),
+        None => false,
     }
+}
 
-    let mut result = String::new();
-    let mut chars = line.chars().peekable();
-    let mut current_word = String::new();
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
 
-    while let Some(c) = chars.next() {
-        if c.is_alphanumeric() || c == '_' {
-            current_word.push(c);
+fn starts_with(chars: &[char], at: usize, pattern: &str) -> bool {
+    !pattern.is_empty()
+        && pattern.chars().enumerate().all(|(k, pc)| chars.get(at + k) == Some(&pc))
+}
+
+/// Append a token, merging it with the previous one when the kind matches
+fn push_token(tokens: &mut Vec<(TokenKind, String)>, kind: TokenKind, text: &[char]) {
+    match tokens.last_mut() {
+        Some((last_kind, last_text)) if *last_kind == kind => last_text.extend(text),
+        _ => tokens.push((kind, text.iter().collect())),
+    }
+}
+
+/// End of a numeric literal: integers, floats, exponents, hex, `1_000`
+fn number_end(chars: &[char], start: usize) -> usize {
+    let mut i = start;
+    let mut seen_dot = false;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_ascii_alphanumeric() || c == '_' {
+            // Exponent sign: 1e-3, 2.5E+10
+            let is_exponent = (c == 'e' || c == 'E')
+                && !starts_with(chars, start, "0x")
+                && matches!(chars.get(i + 1), Some('+') | Some('-'))
+                && chars.get(i + 2).is_some_and(|d| d.is_ascii_digit());
+            i += if is_exponent { 2 } else { 1 };
+        } else if c == '.' && !seen_dot && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit()) {
+            // A dot only belongs to the number when a digit follows (not `1..10` or `1.toString`)
+            seen_dot = true;
+            i += 1;
         } else {
-            // Flush current word
-            if !current_word.is_empty() {
-                result.push_str(&colorize_word(&current_word, keywords));
-                current_word.clear();
-            }
-
-            // Handle strings
-            if c == '"' || c == '\'' {
-                result.push_str(STRING);
-                result.push(c);
-                let quote = c;
-                while let Some(sc) = chars.next() {
-                    result.push(sc);
-                    if sc == quote {
-                        break;
+            break;
+        }
+    }
+    i
+}
+
+/// Lex a quoted string starting at `start` (the opening quote). Handles
+/// backslash escapes and, when `interpolation` is non-empty, embedded
+/// expressions which are tokenized as code. Strings that aren't
+/// `multiline` stop at the end of the line so an unterminated quote
+/// can't color the rest of the block.
+fn lex_string(
+    chars: &[char],
+    start: usize,
+    lang: &Language,
+    interpolation: &str,
+    multiline: bool,
+    tokens: &mut Vec<(TokenKind, String)>,
+) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    let mut segment_start = start;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\\' {
+            i = (i + 2).min(chars.len());
+        } else if c == quote {
+            i += 1;
+            break;
+        } else if c == '\n' && !multiline {
+            break;
+        } else if starts_with(chars, i, interpolation) {
+            push_token(tokens, TokenKind::String, &chars[segment_start..i]);
+            let open_len = interpolation.chars().count();
+            push_token(tokens, TokenKind::Interpolation, &chars[i..i + open_len]);
+
+            // Find the matching close brace
+            let expr_start = i + open_len;
+            let mut depth = 1;
+            let mut j = expr_start;
+            while j < chars.len() {
+                match chars[j] {
+                    '{' => depth += 1,
+                    '}' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
                     }
+                    _ => {}
                 }
-                result.push_str(RESET);
-            }
-            // Handle inline comments
-            else if c == '/' && chars.peek() == Some(&'/') {
-                result.push_str(COMMENT);
-                result.push(c);
-                for remaining in chars.by_ref() {
-                    result.push(remaining);
-                }
-                result.push_str(RESET);
+                j += 1;
             }
-            else if c == '#' && comment_prefix == "#" {
-                result.push_str(COMMENT);
-                result.push(c);
-                for remaining in chars.by_ref() {
-                    result.push(remaining);
-                }
-                result.push_str(RESET);
+
+            let expr: String = chars[expr_start..j].iter().collect();
+            for (kind, text) in tokenize(&expr, lang) {
+                let text: Vec<char> = text.chars().collect();
+                push_token(tokens, kind, &text);
             }
-            else {
-                result.push(c);
+            if j < chars.len() {
+                push_token(tokens, TokenKind::Interpolation, &chars[j..j + 1]);
+                j += 1;
             }
+            i = j;
+            segment_start = j;
+        } else {
+            i += 1;
         }
     }
 
-    // Flush remaining word
-    if !current_word.is_empty() {
-        result.push_str(&colorize_word(&current_word, keywords));
+    push_token(tokens, TokenKind::String, &chars[segment_start..i]);
+    i
+}
+
+/// Lex a Python triple-quoted string starting at `start`
+fn lex_triple_quoted(
+    chars: &[char],
+    start: usize,
+    tokens: &mut Vec<(TokenKind, String)>,
+) -> usize {
+    let delimiter: String = chars[start..start + 3].iter().collect();
+    let mut i = start + 3;
+    while i < chars.len() {
+        if chars[i] == '\\' {
+            i += 2;
+        } else if starts_with(chars, i, &delimiter) {
+            i += 3;
+            break;
+        } else {
+            i += 1;
+        }
     }
+    let end = i.min(chars.len());
+    push_token(tokens, TokenKind::String, &chars[start..end]);
+    end
+}
 
-    result
+/// How safety levels and status lines are told apart
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Palette {
+    /// Red, yellow and green
+    #[default]
+    Standard,
+    /// Blue, amber and inverse vermillion with symbols; no red against green
+    ColorBlind,
+    /// No colors: symbols, bold and inverse video
+    Monochrome,
 }
 
-fn colorize_word(word: &str, keywords: &[&str]) -> String {
-    // Keywords
-    if keywords.contains(&word) {
-        return format!("{}{}{}", KEYWORD, word, RESET);
-    }
+static PALETTE: OnceLock<Palette> = OnceLock::new();
 
-    // Numbers
-    if word.chars().all(|c| c.is_ascii_digit()) {
-        return format!("{}{}{}", NUMBER, word, RESET);
+/// Use `palette` for everything this process prints
+pub fn set_palette(palette: Palette) {
+    let _ = PALETTE.set(palette);
+}
+
+pub fn palette() -> Palette {
+    PALETTE.get().copied().unwrap_or_default()
+}
+
+/// A safety level, for labels and the status lines that go with them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Safe,
+    Caution,
+    Danger,
+}
+
+impl Level {
+    fn ansi(self, palette: Palette) -> &'static str {
+        match (palette, self) {
+            (Palette::Standard, Level::Safe) => "\x1b[32m",
+            (Palette::Standard, Level::Caution) => "\x1b[33m",
+            (Palette::Standard, Level::Danger) => "\x1b[31m",
+            (Palette::ColorBlind, Level::Safe) => "\x1b[1;38;5;33m",
+            (Palette::ColorBlind, Level::Caution) => "\x1b[1;38;5;220m",
+            (Palette::ColorBlind, Level::Danger) => "\x1b[1;7;38;5;202m",
+            (Palette::Monochrome, Level::Safe) => "",
+            (Palette::Monochrome, Level::Caution) => BOLD,
+            (Palette::Monochrome, Level::Danger) => INVERSE,
+        }
     }
 
-    // Function calls (word followed by paren - handled by context)
-    // For simplicity, color camelCase/snake_case that look like functions
-    if word.contains('(') || word.ends_with("()") {
-        return format!("{}{}{}", FUNCTION, word, RESET);
+    /// Shown before `[SAFE]`, `[CAUTION]` and `[DANGER]` outside the standard palette
+    fn symbol(self) -> &'static str {
+        match self {
+            Level::Safe => "✓ ",
+            Level::Caution => "▲ ",
+            Level::Danger => "✖ ",
+        }
     }
+}
 
-    word.to_string()
+/// `text` styled for `level` in the current palette
+pub fn level(level: Level, text: &str) -> String {
+    styled(level, text, palette())
 }
 
-/// Format safety level with color
+fn styled(level: Level, text: &str, palette: Palette) -> String {
+    match level.ansi(palette) {
+        "" => text.to_string(),
+        ansi => format!("{}{}{}", ansi, text, RESET),
+    }
+}
+
+/// Style a leading safety label in the current palette
 pub fn format_safety(text: &str) -> String {
-    if text.starts_with("[SAFE]") {
-        text.replacen("[SAFE]", "\x1b[32m[SAFE]\x1b[0m", 1)
-    } else if text.starts_with("[CAUTION]") {
-        text.replacen("[CAUTION]", "\x1b[33m[CAUTION]\x1b[0m", 1)
-    } else if text.starts_with("[DANGER]") {
-        text.replacen("[DANGER]", "\x1b[31m[DANGER]\x1b[0m", 1)
-    } else {
-        text.to_string()
+    format_safety_in(text, palette())
+}
+
+fn format_safety_in(text: &str, palette: Palette) -> String {
+    let labels = [("[SAFE]", Level::Safe), ("[CAUTION]", Level::Caution), ("[DANGER]", Level::Danger)];
+    for (label, level) in labels {
+        if text.starts_with(label) {
+            let symbol = if palette == Palette::Standard { "" } else { level.symbol() };
+            return text.replacen(label, &styled(level, &format!("{}{}", symbol, label), palette), 1);
+        }
     }
+    text.to_string()
 }
 
 /// Highlight the full explanation (safety line + code block)
-pub fn highlight_explanation(explanation: &str, style: ExplainStyle) -> String {
+pub fn highlight_explanation(explanation: &str, style: &ExplainStyle) -> String {
+    let mut lines = ExplanationLines::new(style);
     let mut result = String::new();
-    let mut in_code_block = false;
-    let mut code_buffer = String::new();
-
     for line in explanation.lines() {
+        if let Some(text) = lines.line(line) {
+            result.push_str(&text);
+            result.push('\n');
+        }
+    }
+    result.trim_end().to_string()
+}
+
+/// `highlight_explanation` a line at a time, for text still streaming in
+pub struct ExplanationLines {
+    style: ExplainStyle,
+    in_code_block: bool,
+    code_buffer: String,
+}
+
+impl ExplanationLines {
+    pub fn new(style: &ExplainStyle) -> Self {
+        ExplanationLines {
+            style: style.clone(),
+            in_code_block: false,
+            code_buffer: String::new(),
+        }
+    }
+
+    /// What to print for the next line, if anything yet. A code block comes
+    /// out whole, highlighted, when its closing ``` arrives.
+    pub fn line(&mut self, line: &str) -> Option<String> {
         if line.starts_with("```") {
-            if in_code_block {
+            let mut highlighted = None;
+            if self.in_code_block {
                 // End of code block - highlight and add
-                let highlighted = highlight(&code_buffer, style);
-                result.push_str(&highlighted);
-                result.push('\n');
-                code_buffer.clear();
+                highlighted = Some(highlight(&self.code_buffer, &self.style));
+                self.code_buffer.clear();
             }
-            in_code_block = !in_code_block;
+            self.in_code_block = !self.in_code_block;
             // Skip the ``` lines themselves
-        } else if in_code_block {
-            code_buffer.push_str(line);
-            code_buffer.push('\n');
+            highlighted
+        } else if self.in_code_block {
+            self.code_buffer.push_str(line);
+            self.code_buffer.push('\n');
+            None
         } else {
             // Regular text - format safety if present
-            result.push_str(&format_safety(line));
-            result.push('\n');
+            Some(format_safety(line))
         }
     }
-
-    result.trim_end().to_string()
 }
 
-/// Dim text for secondary information
+/// Dim text for secondary information (full contrast outside the standard palette)
 pub fn dim(text: &str) -> String {
-    format!("{}{}{}", DIM, text, RESET)
+    match palette() {
+        Palette::Standard => format!("{}{}{}", DIM, text, RESET),
+        _ => text.to_string(),
+    }
 }
 
-/// Bold cyan for commands
-pub fn command_style(text: &str) -> String {
-    format!("\x1b[1;36m{}\x1b[0m", text)
+/// Banner for what a command will act on (cluster, cloud account)
+pub fn banner(text: &str) -> String {
+    let style = if palette() == Palette::Monochrome { INVERSE } else { BANNER };
+    format!("{} {} {}", style, text, RESET)
+}
+
+/// Words that run the word after them as a command (`sudo rm`, `xargs rm`)
+const SHELL_WRAPPERS: &[&str] = &[
+    "env", "exec", "command", "nohup", "nice", "time", "timeout", "watch", "xargs",
+];
+
+/// Shell keywords that are followed by a command
+const SHELL_KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "for", "in", "do", "done", "while",
+    "until", "case", "esac", "!",
+];
+
+/// PowerShell keywords (case-insensitive, like PowerShell itself)
+const POWERSHELL_KEYWORDS: &[&str] = &[
+    "if", "elseif", "else", "foreach", "for", "in", "while", "do", "until",
+    "switch", "function", "filter", "param", "begin", "process", "end",
+    "return", "break", "continue", "exit", "try", "catch", "finally", "throw", "trap",
+];
+
+/// PowerShell operators, which look like parameters (`-eq`, `-match`)
+const POWERSHELL_OPERATORS: &[&str] = &[
+    "-eq", "-ne", "-gt", "-ge", "-lt", "-le", "-like", "-notlike", "-match",
+    "-notmatch", "-contains", "-notcontains", "-in", "-notin", "-replace",
+    "-split", "-join", "-and", "-or", "-xor", "-not", "-is", "-isnot", "-as", "-f",
+];
+
+/// Nushell keywords (`where`, `each` and friends are commands)
+const NU_KEYWORDS: &[&str] = &[
+    "def", "let", "mut", "const", "if", "else", "for", "in", "loop", "while",
+    "match", "try", "catch", "do", "return", "break", "continue", "alias",
+    "use", "module", "export", "extern", "source", "overlay", "not", "and", "or",
+];
+
+/// cmd.exe keywords
+const CMD_KEYWORDS: &[&str] = &[
+    "if", "else", "for", "in", "do", "not", "exist", "defined", "goto", "call",
+];
+
+/// Highlight a command for the target shell (`shell::target`): command
+/// names, flags, strings, pipes and redirections. `sudo`/`doas` are shown
+/// in red so they stand out.
+pub fn highlight_command(command: &str) -> String {
+    highlight_command_in(command, shell::target())
+}
+
+fn highlight_command_in(command: &str, shell: Shell) -> String {
+    let keywords = match shell {
+        Shell::Sh => SHELL_KEYWORDS,
+        Shell::Powershell => POWERSHELL_KEYWORDS,
+        Shell::Cmd => CMD_KEYWORDS,
+        Shell::Nu => NU_KEYWORDS,
+    };
+    let blocks = matches!(shell, Shell::Powershell | Shell::Nu);
+    let mut result = String::new();
+    let mut chars = command.chars().peekable();
+    // True when the next word is in command position
+    let mut at_command = true;
+    // True right after a redirection (next word is a file, not a command)
+    let mut after_redirect = false;
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            result.push(c);
+            chars.next();
+        } else if c == '#' {
+            let rest: String = chars.by_ref().collect();
+            result.push_str(&paint(COMMENT, &rest));
+        } else if c == '|' || c == ';' || c == '(' || c == ')' || c == '`' {
+            let op = take_operator(&mut chars);
+            result.push_str(&paint(KEYWORD, &op));
+            at_command = c != ')';
+        } else if c == '&' {
+            chars.next();
+            if chars.peek() == Some(&'>') {
+                let op = format!("&{}", take_redirect(&mut chars));
+                result.push_str(&paint(REDIRECT, &op));
+                after_redirect = true;
+            } else {
+                let mut op = String::from("&");
+                if chars.peek() == Some(&'&') {
+                    op.push('&');
+                    chars.next();
+                }
+                result.push_str(&paint(KEYWORD, &op));
+                at_command = true;
+            }
+        } else if shell == Shell::Nu && "<>=!".contains(c) {
+            // Comparisons, not redirections: `where size > 10mb`
+            let mut op = String::new();
+            while let Some(&c) = chars.peek().filter(|c| "<>=!~".contains(**c)) {
+                op.push(c);
+                chars.next();
+            }
+            result.push_str(&paint(KEYWORD, &op));
+        } else if c == '<' || c == '>' {
+            let op = take_redirect(&mut chars);
+            result.push_str(&paint(REDIRECT, &op));
+            after_redirect = true;
+        } else if c == '$' && {
+            let mut ahead = chars.clone();
+            ahead.next();
+            ahead.peek() == Some(&'(')
+        } {
+            chars.next();
+            chars.next();
+            result.push_str(&paint(KEYWORD, "$("));
+            at_command = true;
+        } else if blocks && (c == '{' || c == '}') {
+            // Script blocks and closures: `ForEach-Object { $_.Name }`, `each {|f| ... }`
+            chars.next();
+            result.push_str(&paint(KEYWORD, &c.to_string()));
+            at_command = c == '{';
+            let params = c == '{' && shell == Shell::Nu && {
+                let mut ahead = chars.clone();
+                while ahead.next_if(|c| *c == ' ').is_some() {}
+                ahead.peek() == Some(&'|')
+            };
+            if params {
+                while let Some(space) = chars.next_if(|c| *c == ' ') {
+                    result.push(space);
+                }
+                let mut names: String = chars.next().into_iter().collect();
+                for c in chars.by_ref() {
+                    names.push(c);
+                    if c == '|' {
+                        break;
+                    }
+                }
+                result.push_str(&paint(TYPE, &names));
+            }
+        } else {
+            let word = take_word(&mut chars);
+
+            // File descriptor prefix of a redirection, e.g. `2>` or `2>&1`
+            if word.chars().all(|ch| ch.is_ascii_digit())
+                && matches!(chars.peek(), Some('<') | Some('>'))
+            {
+                let op = format!("{}{}", word, take_redirect(&mut chars));
+                result.push_str(&paint(REDIRECT, &op));
+                after_redirect = true;
+                continue;
+            }
+
+            if after_redirect {
+                result.push_str(&highlight_shell_word(&word));
+                after_redirect = false;
+            } else if at_command {
+                if word == "sudo" || word == "doas" {
+                    result.push_str(&paint(SUDO, &word));
+                } else if is_keyword(keywords, &word, shell) {
+                    result.push_str(&paint(KEYWORD, &word));
+                } else if word.starts_with('-') {
+                    // Options of a wrapper, e.g. `sudo -E cmd`
+                    result.push_str(&paint(FLAG, &word));
+                } else if is_assignment(&word) {
+                    result.push_str(&paint(TYPE, &word));
+                } else if blocks && word.starts_with('$') {
+                    // An expression, not a command: `{ $_.Length -gt 1MB }`
+                    result.push_str(&paint(TYPE, &word));
+                    at_command = false;
+                } else {
+                    result.push_str(&paint(COMMAND, &word));
+                    at_command = shell == Shell::Sh && SHELL_WRAPPERS.contains(&word.as_str());
+                }
+            } else if shell == Shell::Powershell && is_keyword(POWERSHELL_OPERATORS, &word, shell) {
+                result.push_str(&paint(KEYWORD, &word));
+            } else if shell == Shell::Cmd && word.starts_with('/') {
+                // cmd switches: `dir /s /b`
+                result.push_str(&paint(FLAG, &word));
+            } else if shell == Shell::Cmd && word.len() > 2 && word.starts_with('%') && word.ends_with('%') {
+                result.push_str(&paint(TYPE, &word));
+            } else {
+                result.push_str(&highlight_shell_word(&word));
+            }
+        }
+    }
+
+    result
+}
+
+/// sh keywords are case-sensitive, PowerShell and cmd ones aren't
+fn is_keyword(keywords: &[&str], word: &str, shell: Shell) -> bool {
+    match shell {
+        Shell::Sh | Shell::Nu => keywords.contains(&word),
+        Shell::Powershell | Shell::Cmd => keywords.iter().any(|k| k.eq_ignore_ascii_case(word)),
+    }
+}
+
+fn paint(color: &str, text: &str) -> String {
+    if palette() == Palette::Monochrome {
+        // Command names and sudo keep their weight, the rest is plain
+        return match color {
+            COMMAND | SUDO => format!("{}{}{}", BOLD, text, RESET),
+            _ => text.to_string(),
+        };
+    }
+    format!("{}{}{}", color, text, RESET)
+}
+
+/// Take a run of control operator characters (`|`, `||`, `;`, `;;`, ...)
+fn take_operator(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let first = chars.next().unwrap_or_default();
+    let mut op = String::from(first);
+    if first == '|' && matches!(chars.peek(), Some('|') | Some('&'))
+        || first == ';' && chars.peek() == Some(&';')
+    {
+        op.extend(chars.next());
+    }
+    op
+}
+
+/// Take a redirection operator (`>`, `>>`, `<<<`, `>&2`, `>&-`, ...)
+fn take_redirect(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut op = String::new();
+    while let Some(&c) = chars.peek() {
+        if c == '<' || c == '>' {
+            op.push(c);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    if chars.peek() == Some(&'&') {
+        op.push('&');
+        chars.next();
+        while let Some(&c) = chars.peek() {
+            if c.is_ascii_digit() || c == '-' {
+                op.push(c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+    }
+    op
+}
+
+/// Take a single shell word, keeping quoted sections (`--name="a b"`) intact
+fn take_word(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut word = String::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || "|&;()<>`".contains(c) {
+            break;
+        }
+        chars.next();
+        word.push(c);
+        if c == '\\' {
+            word.extend(chars.next());
+        } else if c == '\'' || c == '"' {
+            while let Some(qc) = chars.next() {
+                word.push(qc);
+                if qc == '\\' && c == '"' {
+                    word.extend(chars.next());
+                } else if qc == c {
+                    break;
+                }
+            }
+        }
+    }
+    word
+}
+
+/// `NAME=value` prefix assignment (`FOO=1 cmd`)
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+/// Color an argument word: flags, variables, numbers, quoted strings
+fn highlight_shell_word(word: &str) -> String {
+    if word.starts_with('-') && word.len() > 1 {
+        // Highlight `--flag=value` with the value colored separately
+        return match word.split_once('=') {
+            Some((flag, value)) => {
+                format!("{}={}", paint(FLAG, flag), highlight_shell_word(value))
+            }
+            None => paint(FLAG, word),
+        };
+    }
+    if word.starts_with('$') {
+        return paint(TYPE, word);
+    }
+    if word.chars().all(|c| c.is_ascii_digit()) {
+        return paint(NUMBER, word);
+    }
+
+    // Plain word, possibly with embedded quoted sections
+    let mut result = String::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        if c == '\'' || c == '"' {
+            let mut quoted = String::from(c);
+            while let Some(qc) = chars.next() {
+                quoted.push(qc);
+                if qc == '\\' && c == '"' {
+                    quoted.extend(chars.next());
+                } else if qc == c {
+                    break;
+                }
+            }
+            result.push_str(&paint(STRING, &quoted));
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_safety_palettes() {
+        let line = "[DANGER] Deletes everything";
+        assert_eq!(format_safety_in(line, Palette::Standard), "\x1b[31m[DANGER]\x1b[0m Deletes everything");
+        assert_eq!(format_safety_in(line, Palette::Monochrome), "\x1b[1;7m✖ [DANGER]\x1b[0m Deletes everything");
+        assert_eq!(format_safety_in("[SAFE] Lists files", Palette::Monochrome), "✓ [SAFE] Lists files");
+        assert!(format_safety_in("[CAUTION] Writes", Palette::ColorBlind).contains("▲ [CAUTION]"));
+        assert_eq!(format_safety_in("No label", Palette::ColorBlind), "No label");
+    }
+
+    fn strip_ansi(s: &str) -> String {
+        let mut out = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                for ec in chars.by_ref() {
+                    if ec == 'm' {
+                        break;
+                    }
+                }
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    fn kinds(code: &str, lang: &Language) -> Vec<(TokenKind, String)> {
+        tokenize(code, lang)
+            .into_iter()
+            .filter(|(kind, _)| *kind != TokenKind::Plain)
+            .collect()
+    }
+
+    #[test]
+    fn test_escaped_quotes_stay_in_string() {
+        let tokens = kinds(r#"const s = "say \"hi\"" + x"#, &TYPESCRIPT);
+        assert_eq!(tokens[1], (TokenKind::String, r#""say \"hi\"""#.to_string()));
+        assert_eq!(tokens.len(), 2);
+    }
+
+    #[test]
+    fn test_comment_markers_inside_strings() {
+        let tokens = kinds(r#"fetch("http://example.com") // get it"#, &TYPESCRIPT);
+        assert_eq!(tokens[0], (TokenKind::Function, "fetch".to_string()));
+        assert_eq!(tokens[1], (TokenKind::String, r#""http://example.com""#.to_string()));
+        assert_eq!(tokens[2], (TokenKind::Comment, "// get it".to_string()));
+    }
+
+    #[test]
+    fn test_unterminated_string_stops_at_line_end() {
+        let tokens = kinds("x = 'oops\nif y:", &PYTHON);
+        assert_eq!(tokens[0], (TokenKind::String, "'oops".to_string()));
+        assert_eq!(tokens[1], (TokenKind::Keyword, "if".to_string()));
+    }
+
+    #[test]
+    fn test_python_triple_quoted_multiline() {
+        let tokens = kinds("doc = \"\"\"line one\n# not a comment\n\"\"\"\nreturn doc", &PYTHON);
+        assert_eq!(
+            tokens[0],
+            (TokenKind::String, "\"\"\"line one\n# not a comment\n\"\"\"".to_string())
+        );
+        assert_eq!(tokens[1], (TokenKind::Keyword, "return".to_string()));
+    }
+
+    #[test]
+    fn test_python_prefixed_strings() {
+        let tokens = kinds(r#"print(f"{name}", r'\d+')"#, &PYTHON);
+        assert_eq!(tokens[1], (TokenKind::String, r#"f"{name}""#.to_string()));
+        assert_eq!(tokens[2], (TokenKind::String, r"r'\d+'".to_string()));
+    }
+
+    #[test]
+    fn test_numbers_and_floats() {
+        let tokens = kinds("x = 3.14 + 1e-3 + 0xFF + 1_000 + .5", &TYPESCRIPT);
+        let numbers: Vec<&str> = tokens.iter().map(|(_, t)| t.as_str()).collect();
+        assert_eq!(numbers, vec!["3.14", "1e-3", "0xFF", "1_000", ".5"]);
+        assert!(tokens.iter().all(|(kind, _)| *kind == TokenKind::Number));
+    }
+
+    #[test]
+    fn test_ruby_range_is_not_float() {
+        let tokens = kinds("(1..10).each do |i|", &RUBY);
+        assert_eq!(tokens[0], (TokenKind::Number, "1".to_string()));
+        assert_eq!(tokens[1], (TokenKind::Number, "10".to_string()));
+    }
+
+    #[test]
+    fn test_template_literal_interpolation() {
+        let tokens = kinds("log(`size: ${getSize(file)}\ndone`)", &TYPESCRIPT);
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenKind::Function, "log".to_string()),
+                (TokenKind::String, "`size: ".to_string()),
+                (TokenKind::Interpolation, "${".to_string()),
+                (TokenKind::Function, "getSize".to_string()),
+                (TokenKind::Interpolation, "}".to_string()),
+                (TokenKind::String, "\ndone`".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ruby_hash_interpolation_is_not_comment() {
+        let tokens = kinds(r##"puts "#{count} files" # total"##, &RUBY);
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenKind::String, "\"".to_string()),
+                (TokenKind::Interpolation, "#{".to_string()),
+                (TokenKind::Interpolation, "}".to_string()),
+                (TokenKind::String, " files\"".to_string()),
+                (TokenKind::Comment, "# total".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rust_lifetimes_and_macros() {
+        let tokens = kinds("fn first<'a>(s: &'a str) { println!(\"{}\", 'x'); }", &RUST);
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenKind::Keyword, "fn".to_string()),
+                (TokenKind::Function, "println".to_string()),
+                (TokenKind::String, "\"{}\"".to_string()),
+                (TokenKind::String, "'x'".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_go_raw_strings() {
+        let tokens = kinds("re := `\\d+\n`; go run()", &GO);
+        assert_eq!(tokens[0], (TokenKind::String, "`\\d+\n`".to_string()));
+        assert_eq!(tokens[1], (TokenKind::Keyword, "go".to_string()));
+    }
+
+    #[test]
+    fn test_multiline_tokens_reset_every_line() {
+        let out = highlight("/* a\nb */ x", &ExplainStyle::Typescript);
+        for line in out.lines() {
+            // The last escape sequence on every line must be a reset
+            assert!(line.rfind('\x1b').is_none_or(|p| line[p..].starts_with(RESET)));
+        }
+    }
+
+    #[test]
+    fn test_custom_style_keywords() {
+        let style = ExplainStyle::Custom(CustomStyle {
+            name: "sql".to_string(),
+            prompt: "Explain it as SQL".to_string(),
+            keywords: vec!["SELECT".to_string(), "FROM".to_string()],
+            line_comment: Some("--".to_string()),
+        });
+        let out = highlight("SELECT name FROM files -- big ones", &style);
+        assert!(out.contains(&paint(KEYWORD, "SELECT")));
+        assert!(out.contains(&paint(KEYWORD, "FROM")));
+        assert!(out.contains(&paint(COMMENT, "-- big ones")));
+    }
+
+    #[test]
+    fn test_highlight_command_preserves_text() {
+        let cmd = r#"find . -name "*.log" -mtime +7 2>/dev/null | xargs -0 rm -f && echo 'done' > out.txt"#;
+        assert_eq!(strip_ansi(&highlight_command(cmd)), cmd);
+    }
+
+    #[test]
+    fn test_highlight_command_sudo_red() {
+        let out = highlight_command("ls | sudo tee /etc/hosts");
+        assert!(out.contains(&paint(SUDO, "sudo")));
+        assert!(out.contains(&paint(COMMAND, "tee")));
+        assert!(out.contains(&paint(KEYWORD, "|")));
+    }
+
+    #[test]
+    fn test_highlight_command_flags_and_redirects() {
+        let out = highlight_command("grep -rn --color=auto foo src 2>&1");
+        assert!(out.contains(&paint(COMMAND, "grep")));
+        assert!(out.contains(&paint(FLAG, "-rn")));
+        assert!(out.contains(&paint(FLAG, "--color")));
+        assert!(out.contains(&paint(REDIRECT, "2>&1")));
+    }
+
+    #[test]
+    fn test_highlight_command_other_shells() {
+        let cmd = r#"Get-ChildItem -Recurse | Where-Object { $_.Length -gt 1MB } | ForEach-Object { $_.Name }"#;
+        let out = highlight_command_in(cmd, Shell::Powershell);
+        assert_eq!(strip_ansi(&out), cmd);
+        assert!(out.contains(&paint(COMMAND, "Where-Object")));
+        assert!(out.contains(&paint(COMMAND, "ForEach-Object")));
+        assert!(out.contains(&paint(KEYWORD, "-gt")));
+        assert!(out.contains(&paint(FLAG, "-Recurse")));
+        assert!(out.contains(&paint(TYPE, "$_.Length")));
+        assert!(highlight_command_in("Foreach ($f in $files) { Remove-Item $f }", Shell::Powershell)
+            .contains(&paint(KEYWORD, "Foreach")));
+
+        let out = highlight_command_in("if exist %TEMP%\\old del /q %TEMP%\\old", Shell::Cmd);
+        assert!(out.contains(&paint(KEYWORD, "if")));
+        assert!(out.contains(&paint(FLAG, "/q")));
+
+        let cmd = "ls | where size > 10mb | each {|f| $f.name } | sort-by modified --reverse";
+        let out = highlight_command_in(cmd, Shell::Nu);
+        assert_eq!(strip_ansi(&out), cmd);
+        assert!(out.contains(&paint(COMMAND, "where")));
+        assert!(out.contains(&paint(KEYWORD, ">")));
+        assert!(out.contains(&paint(TYPE, "|f|")));
+        assert!(out.contains(&paint(TYPE, "$f.name")));
+        assert!(out.contains(&paint(COMMAND, "sort-by")));
+        assert!(highlight_command_in("let big = (ls)", Shell::Nu).contains(&paint(KEYWORD, "let")));
+    }
 }
@@ -0,0 +1,344 @@
+//! History export and import
+//!
+//! `slashcmd history export` writes the command logs as JSON lines (one
+//! `LogEntry` per line) or CSV; `slashcmd history import` reads either back,
+//! skipping entries that are already present. `slashcmd history explain`
+//! explains a logged command again, in another style.
+
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+
+use crate::logs::{self, LogEntry, Provenance};
+use crate::sync::entry_id;
+
+const CSV_HEADER: [&str; 16] = [
+    "timestamp",
+    "query",
+    "command",
+    "explanation",
+    "style",
+    "executed",
+    "exit_code",
+    "timed_out",
+    "prompt_version",
+    "provenance_mode",
+    "provenance_provider",
+    "provenance_model",
+    "provenance_daemon",
+    "provenance_prompt_tokens",
+    "provenance_completion_tokens",
+    "provenance_latency_ms",
+];
+
+/// Export/import file format
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Jsonl,
+    Csv,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jsonl" | "json" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("Unknown format '{}': expected jsonl or csv", s)),
+        }
+    }
+}
+
+/// Write entries (oldest first) with a timestamp at or after `since`
+pub fn export<W: Write>(out: &mut W, format: Format, since: u64) -> Result<usize, String> {
+    let mut entries = logs::recent_entries(usize::MAX);
+    entries.retain(|entry| entry.timestamp >= since);
+    entries.reverse();
+
+    let write_err = |e: std::io::Error| format!("Failed to write: {}", e);
+    if format == Format::Csv {
+        writeln!(out, "{}", CSV_HEADER.join(",")).map_err(write_err)?;
+    }
+    for entry in &entries {
+        let line = match format {
+            Format::Jsonl => serde_json::to_string(entry).map_err(|e| format!("Failed to encode: {}", e))?,
+            Format::Csv => to_csv_row(entry),
+        };
+        writeln!(out, "{}", line).map_err(write_err)?;
+    }
+    Ok(entries.len())
+}
+
+/// Read entries and save the ones not already in the logs. Returns
+/// (imported, skipped as duplicates).
+pub fn import<R: BufRead>(input: R, format: Format) -> Result<(usize, usize), String> {
+    let entries = match format {
+        Format::Jsonl => parse_jsonl(input)?,
+        Format::Csv => parse_csv(input)?,
+    };
+
+    let mut known: HashSet<String> = logs::recent_entries(usize::MAX).iter().map(entry_id).collect();
+    let (mut imported, mut skipped) = (0, 0);
+    for entry in entries {
+        if !known.insert(entry_id(&entry)) {
+            skipped += 1;
+            continue;
+        }
+        logs::save_log(&entry).map_err(|e| format!("Failed to save log: {}", e))?;
+        imported += 1;
+    }
+    Ok((imported, skipped))
+}
+
+/// The entry `id` names among `entries` (newest first): its id as the shell
+/// integration prints it, a unique prefix of at least 4 characters, or a
+/// number up to 999 for the Nth most recent (1 = last)
+pub fn find(id: &str, entries: Vec<LogEntry>) -> Result<LogEntry, String> {
+    let id = id.trim().to_lowercase();
+    if id.len() <= 3 {
+        let n: usize = id.parse().map_err(|_| format!("Id '{}' is too short: use at least 4 characters", id))?;
+        let count = entries.len();
+        return n
+            .checked_sub(1)
+            .and_then(|i| entries.into_iter().nth(i))
+            .ok_or_else(|| format!("There are only {} logged commands", count));
+    }
+    let mut found = entries.into_iter().filter(|entry| entry_id(entry).starts_with(&id));
+    match (found.next(), found.next()) {
+        (Some(entry), None) => Ok(entry),
+        (Some(_), Some(_)) => Err(format!("Id '{}' matches more than one command; give more of it", id)),
+        (None, _) => Err(format!("No logged command with id '{}'", id)),
+    }
+}
+
+/// Parse `--since`: a date (YYYY-MM-DD, UTC) or a unix timestamp
+pub fn parse_since(value: &str) -> Result<u64, String> {
+    if let Ok(timestamp) = value.parse::<u64>() {
+        return Ok(timestamp);
+    }
+    let invalid = || format!("Invalid --since '{}': use YYYY-MM-DD or a unix timestamp", value);
+    let parts: Vec<&str> = value.split('-').collect();
+    let [year, month, day] = parts.as_slice() else {
+        return Err(invalid());
+    };
+    let (year, month, day): (i64, i64, i64) = (
+        year.parse().map_err(|_| invalid())?,
+        month.parse().map_err(|_| invalid())?,
+        day.parse().map_err(|_| invalid())?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+        return Err(invalid());
+    }
+    Ok(days_from_civil(year, month, day) as u64 * 86400)
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn parse_jsonl<R: BufRead>(input: R) -> Result<Vec<LogEntry>, String> {
+    let mut entries = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(serde_json::from_str(&line).map_err(|e| format!("Line {}: {}", i + 1, e))?);
+    }
+    Ok(entries)
+}
+
+fn to_csv_row(entry: &LogEntry) -> String {
+    let provenance = entry.provenance.as_ref();
+    let tokens = |count: Option<u64>| count.map(|c| c.to_string()).unwrap_or_default();
+    [
+        entry.timestamp.to_string(),
+        entry.query.clone(),
+        entry.command.clone(),
+        entry.explanation.clone().unwrap_or_default(),
+        entry.style.clone(),
+        entry.executed.to_string(),
+        entry.exit_code.map(|c| c.to_string()).unwrap_or_default(),
+        entry.timed_out.to_string(),
+        entry.prompt_version.clone().unwrap_or_default(),
+        provenance.map(|p| p.mode.clone()).unwrap_or_default(),
+        provenance.map(|p| p.provider.clone()).unwrap_or_default(),
+        provenance.and_then(|p| p.model.clone()).unwrap_or_default(),
+        provenance.map(|p| p.daemon.to_string()).unwrap_or_default(),
+        tokens(provenance.and_then(|p| p.prompt_tokens)),
+        tokens(provenance.and_then(|p| p.completion_tokens)),
+        provenance.map(|p| p.latency_ms.to_string()).unwrap_or_default(),
+    ]
+    .iter()
+    .map(|field| csv_field(field))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+/// Quote a field if it contains a comma, quote or newline
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn parse_csv<R: BufRead>(mut input: R) -> Result<Vec<LogEntry>, String> {
+    let mut content = String::new();
+    input
+        .read_to_string(&mut content)
+        .map_err(|e| format!("Failed to read: {}", e))?;
+
+    let mut rows = csv_records(&content).into_iter();
+    let header = rows.next().unwrap_or_default();
+    let column = |name: &str| header.iter().position(|h| h == name);
+    let required = |name: &str| column(name).ok_or_else(|| format!("CSV is missing the '{}' column", name));
+    let (timestamp, query, command) = (required("timestamp")?, required("query")?, required("command")?);
+    let (explanation, style, executed, exit_code, timed_out, prompt_version) = (
+        column("explanation"),
+        column("style"),
+        column("executed"),
+        column("exit_code"),
+        column("timed_out"),
+        column("prompt_version"),
+    );
+    // The provenance_* columns, in CSV_HEADER order
+    let provenance_columns: [Option<usize>; 7] = std::array::from_fn(|i| column(CSV_HEADER[9 + i]));
+
+    rows.enumerate()
+        .filter(|(_, row)| row.iter().any(|field| !field.is_empty()))
+        .map(|(i, row)| {
+            let get = |index: Option<usize>| index.and_then(|i| row.get(i)).cloned().unwrap_or_default();
+            let [mode, provider, model, daemon, prompt_tokens, completion_tokens, latency_ms] =
+                provenance_columns.map(get);
+            // Entries from before provenance was recorded have none
+            let provenance = (!mode.is_empty()).then(|| Provenance {
+                mode,
+                provider,
+                model: Some(model).filter(|m| !m.is_empty()),
+                daemon: daemon == "true",
+                prompt_tokens: prompt_tokens.parse().ok(),
+                completion_tokens: completion_tokens.parse().ok(),
+                latency_ms: latency_ms.parse().unwrap_or_default(),
+            });
+            Ok(LogEntry {
+                timestamp: get(Some(timestamp))
+                    .parse()
+                    .map_err(|_| format!("Row {}: invalid timestamp", i + 2))?,
+                query: get(Some(query)),
+                command: get(Some(command)),
+                explanation: Some(get(explanation)).filter(|e| !e.is_empty()),
+                style: get(style),
+                executed: get(executed) == "true",
+                exit_code: get(exit_code).parse().ok(),
+                timed_out: get(timed_out) == "true",
+                prompt_version: Some(get(prompt_version)).filter(|v| !v.is_empty()),
+                provenance,
+            })
+        })
+        .collect()
+}
+
+/// Split CSV text into records, honoring quoted fields with embedded
+/// commas, quotes and newlines
+fn csv_records(content: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if field.is_empty() => in_quotes = true,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (c, _) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::ExplainStyle;
+
+    #[test]
+    fn test_csv_roundtrip() {
+        let mut provenance = Provenance::new("local", "groq", std::time::Duration::from_millis(412));
+        provenance.model = Some("llama-3.3-70b-versatile".to_string());
+        provenance.prompt_tokens = Some(1200);
+        let mut entry = logs::create_entry(
+            "find \"big\" files",
+            "du -ah . | sort -rh | head -5",
+            Some("line one,\nline two".to_string()),
+            &ExplainStyle::Human,
+            Some(provenance),
+        );
+        entry.exit_code = Some(0);
+        entry.executed = true;
+
+        let csv = format!("{}\n{}\n", CSV_HEADER.join(","), to_csv_row(&entry));
+        let parsed = parse_csv(csv.as_bytes()).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].query, entry.query);
+        assert_eq!(parsed[0].explanation, entry.explanation);
+        assert_eq!(parsed[0].exit_code, Some(0));
+        assert!(parsed[0].executed);
+        assert_eq!(parsed[0].provenance, entry.provenance);
+        assert_eq!(entry_id(&parsed[0]), entry_id(&entry));
+
+        // Files exported before provenance columns existed still import
+        let old = "timestamp,query,command\n1700000000,list files,ls\n";
+        assert_eq!(parse_csv(old.as_bytes()).unwrap()[0].provenance, None);
+    }
+
+    #[test]
+    fn test_find() {
+        let entries: Vec<LogEntry> = ["ls -la", "git status", "df -h"]
+            .iter()
+            .map(|command| logs::create_entry("query", command, None, &ExplainStyle::Human, None))
+            .collect();
+        let id = entry_id(&entries[1]);
+        let find = |id: &str| find(id, entries.clone());
+
+        assert_eq!(find(&id).unwrap().command, "git status");
+        assert_eq!(find(&id[..6].to_uppercase()).unwrap().command, "git status");
+        assert_eq!(find("1").unwrap().command, "ls -la");
+        assert_eq!(find("3").unwrap().command, "df -h");
+        assert!(find("4").is_err());
+        assert!(find("0").is_err());
+        assert!(find("ab").is_err());
+        assert!(find("ffffffff").is_err());
+    }
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(parse_since("1970-01-02"), Ok(86400));
+        assert_eq!(parse_since("2024-03-01"), Ok(1709251200));
+        assert_eq!(parse_since("1700000000"), Ok(1700000000));
+        assert!(parse_since("last week").is_err());
+        assert!(parse_since("2024-13-01").is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! User hooks around command execution
+//!
+//! The `[hooks]` section of config.toml names two programs. `pre_exec` runs
+//! before every confirmed command, with the command as its argument, and
+//! vetoes it by exiting non-zero. `post_exec` runs afterwards with the
+//! command and its exit code. Both also get `SLASHCMD_COMMAND` and
+//! `SLASHCMD_QUERY` (plus `SLASHCMD_EXIT_CODE` after) in their environment
+//! and share the terminal, so they can print, notify or ask.
+
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Hooks for this process, set once at startup
+static HOOKS: OnceLock<HooksConfig> = OnceLock::new();
+
+/// The `[hooks]` section of config.toml
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Program run before a command; a non-zero exit cancels it
+    pub pre_exec: Option<String>,
+    /// Program run after a command with its exit code
+    pub post_exec: Option<String>,
+}
+
+/// Use `hooks` for the rest of this process
+pub fn enable(hooks: HooksConfig) {
+    let _ = HOOKS.set(hooks);
+}
+
+/// Ask the pre-exec hook about `command`. Err says why it must not run:
+/// the hook vetoed it, or couldn't be started.
+pub fn pre_exec(query: &str, command: &str) -> Result<(), String> {
+    match HOOKS.get() {
+        Some(hooks) => run_pre_exec(hooks, query, command),
+        None => Ok(()),
+    }
+}
+
+/// Tell the post-exec hook how `command` went
+pub fn post_exec(query: &str, command: &str, exit_code: i32) -> Result<(), String> {
+    let Some(hook) = HOOKS.get().and_then(|h| h.post_exec.as_deref()) else {
+        return Ok(());
+    };
+    let status = hook_command(hook, query, command)
+        .arg(exit_code.to_string())
+        .env("SLASHCMD_EXIT_CODE", exit_code.to_string())
+        .status()
+        .map_err(|e| format!("Failed to run post-exec hook {}: {}", hook, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("Post-exec hook {} failed ({})", hook, status))
+    }
+}
+
+fn run_pre_exec(hooks: &HooksConfig, query: &str, command: &str) -> Result<(), String> {
+    let Some(hook) = hooks.pre_exec.as_deref() else {
+        return Ok(());
+    };
+    let status = hook_command(hook, query, command)
+        .status()
+        .map_err(|e| format!("Failed to run pre-exec hook {}: {}", hook, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("Cancelled by pre-exec hook {} ({})", hook, status))
+    }
+}
+
+/// `hook` with the command as its first argument and in the environment
+fn hook_command(hook: &str, query: &str, command: &str) -> Command {
+    let mut cmd = Command::new(expand_home(hook));
+    cmd.arg(command)
+        .env("SLASHCMD_COMMAND", command)
+        .env("SLASHCMD_QUERY", query);
+    cmd
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pre_exec_veto() {
+        let hooks = |pre_exec: &str| HooksConfig {
+            pre_exec: Some(pre_exec.to_string()),
+            post_exec: None,
+        };
+        assert!(run_pre_exec(&HooksConfig::default(), "list files", "ls").is_ok());
+        assert!(run_pre_exec(&hooks("true"), "list files", "ls").is_ok());
+        let vetoed = run_pre_exec(&hooks("false"), "delete logs", "rm -rf logs").unwrap_err();
+        assert!(vetoed.starts_with("Cancelled by pre-exec hook false"), "{}", vetoed);
+        assert!(run_pre_exec(&hooks("/nonexistent/hook"), "list files", "ls").is_err());
+
+        assert_eq!(expand_home("/etc/hook"), PathBuf::from("/etc/hook"));
+        assert!(!expand_home("~/bin/hook").starts_with("~"));
+    }
+}
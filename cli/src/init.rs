@@ -0,0 +1,91 @@
+//! `slashcmd init <shell>`: shell integration to source from the rc file
+//!
+//! Defines `/cmd` (`cmd` in fish, which doesn't allow `/` in names). The
+//! generated command is put up for editing, and when it runs unedited its
+//! exit code goes back to the log entry via `slashcmd _report <id> <code>`,
+//! so history shows whether generated commands actually worked.
+//!
+//! `--print-only` prints the log id on the first line and the command after
+//! it. With `--result-fd N` that goes to file descriptor N and nothing to
+//! stdout; warnings, prompts and errors are always on stderr. The scripts
+//! pass `--result-fd 3 3>&1 1>&2`, so they capture exactly the result and
+//! anything else printed reaches the terminal.
+
+use crate::snippets::Shell;
+
+/// zsh: the command lands at the prompt (`print -z`) and the hooks notice
+/// when exactly that command is run
+const ZSH: &str = r#"# slashcmd shell integration: eval "$(slashcmd init zsh)"
+/cmd() {
+  local out
+  out=$(command slashcmd --print-only --result-fd 3 "$@" 3>&1 1>&2) || return
+  _slashcmd_id=${out%%$'\n'*}
+  _slashcmd_cmd=${out#*$'\n'}
+  print -z -- "$_slashcmd_cmd"
+}
+_slashcmd_preexec() {
+  [[ -n $_slashcmd_id && $1 == "$_slashcmd_cmd" ]] && _slashcmd_ran=$_slashcmd_id
+  _slashcmd_id=
+}
+_slashcmd_precmd() {
+  local code=$?
+  if [[ -n $_slashcmd_ran ]]; then
+    command slashcmd _report "$_slashcmd_ran" "$code" &!
+    _slashcmd_ran=
+  fi
+}
+autoload -Uz add-zsh-hook
+add-zsh-hook preexec _slashcmd_preexec
+add-zsh-hook precmd _slashcmd_precmd
+"#;
+
+/// bash can't preload the prompt, so the command is edited with readline
+/// and run from the function
+const BASH: &str = r#"# slashcmd shell integration: eval "$(slashcmd init bash)"
+/cmd() {
+  local out id cmd line code
+  out=$(command slashcmd --print-only --result-fd 3 "$@" 3>&1 1>&2) || return
+  id=${out%%$'\n'*}
+  cmd=${out#*$'\n'}
+  read -e -r -i "$cmd" -p '$ ' line || return
+  history -s -- "$line"
+  eval "$line"
+  code=$?
+  [[ $line == "$cmd" ]] && (command slashcmd _report "$id" "$code" &)
+  return $code
+}
+"#;
+
+/// fish (3.4.1+): like zsh, the command lands in the commandline buffer
+/// for the next prompt and the event hooks notice when exactly it is run
+const FISH: &str = r#"# slashcmd shell integration: slashcmd init fish | source
+function cmd --description 'Natural language to shell commands'
+    set -l out (command slashcmd --print-only --result-fd 3 $argv 3>&1 1>&2); or return
+    set -g _slashcmd_id $out[1]
+    set -g _slashcmd_cmd (string join \n -- $out[2..-1])
+    commandline --replace -- $_slashcmd_cmd
+end
+function _slashcmd_preexec --on-event fish_preexec
+    if set -q _slashcmd_id; and test "$argv[1]" = "$_slashcmd_cmd"
+        set -g _slashcmd_ran $_slashcmd_id
+    end
+    set -e _slashcmd_id
+end
+function _slashcmd_postexec --on-event fish_postexec
+    set -l code $status
+    if set -q _slashcmd_ran
+        command slashcmd _report $_slashcmd_ran $code &
+        disown
+        set -e _slashcmd_ran
+    end
+end
+"#;
+
+/// The integration script for `shell`
+pub fn script(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Zsh => ZSH,
+        Shell::Bash => BASH,
+        Shell::Fish => FISH,
+    }
+}
@@ -1,18 +1,132 @@
 use serde::{Deserialize, Serialize};
 use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::MetadataExt;
 use std::os::unix::net::{UnixListener, UnixStream};
 
+use crate::error::SlashcmdError;
+
 pub const SOCKET_PATH: &str = "/tmp/cmd.sock";
 
+/// Sent in the `Hello` handshake so a newer CLI can replace an older daemon
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Largest frame we read, so a corrupt length can't allocate gigabytes
+const MAX_FRAME_BYTES: usize = 64 * 1024 * 1024;
+
+/// How a message is delimited on the socket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Framing {
+    /// Newline-terminated JSON, what daemons before length prefixes speak.
+    /// Still used for `Hello` and `Shutdown`, which may reach such a daemon.
+    Lines,
+    /// 4-byte big-endian length, then that many bytes of JSON
+    Prefixed,
+}
+
+/// Write one message in the given framing
+pub fn write_frame(mut writer: impl Write, json: &[u8], framing: Framing) -> std::io::Result<()> {
+    match framing {
+        Framing::Lines => {
+            writer.write_all(json)?;
+            writer.write_all(b"\n")?;
+        }
+        Framing::Prefixed => {
+            let len = u32::try_from(json.len())
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "frame too large"))?;
+            writer.write_all(&len.to_be_bytes())?;
+            writer.write_all(json)?;
+        }
+    }
+    writer.flush()
+}
+
+/// Read one message in whichever framing the peer used. JSON always starts
+/// with `{`, and no length under `MAX_FRAME_BYTES` has that as its first byte.
+pub fn read_frame(reader: &mut impl BufRead) -> std::io::Result<(Vec<u8>, Framing)> {
+    let first = match reader.fill_buf()? {
+        [] => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+        buf => buf[0],
+    };
+
+    if first == b'{' {
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line)?;
+        return Ok((line, Framing::Lines));
+    }
+
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_BYTES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is over the limit", len),
+        ));
+    }
+    let mut json = vec![0; len];
+    reader.read_exact(&mut json)?;
+    Ok((json, Framing::Prefixed))
+}
+
 /// Explanation style for command breakdown
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum ExplainStyle {
     #[default]
     Typescript,
     Python,
     Ruby,
+    Rust,
+    Go,
     Human,
+    Annotated,
+    Diagram,
+    /// User-defined style from config.toml, sent whole so the daemon
+    /// doesn't need to read the config
+    Custom(CustomStyle),
+}
+
+/// A style registered in config.toml under `[[styles]]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomStyle {
+    /// Name used with --style and as an inline keyword
+    pub name: String,
+    /// Instruction given to the model in place of a built-in style's
+    pub prompt: String,
+    /// Words to highlight as keywords in the code block
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Line comment marker for highlighting (default `#`)
+    #[serde(default)]
+    pub line_comment: Option<String>,
+}
+
+impl ExplainStyle {
+    /// Name used on the wire (edge requests) and in logs
+    pub fn as_str(&self) -> &str {
+        match self {
+            ExplainStyle::Typescript => "typescript",
+            ExplainStyle::Python => "python",
+            ExplainStyle::Ruby => "ruby",
+            ExplainStyle::Rust => "rust",
+            ExplainStyle::Go => "go",
+            ExplainStyle::Human => "human",
+            ExplainStyle::Annotated => "annotated",
+            ExplainStyle::Diagram => "diagram",
+            ExplainStyle::Custom(custom) => &custom.name,
+        }
+    }
+
+    /// Parse a style name, falling back to the user's custom styles
+    pub fn resolve(name: &str, custom: &[CustomStyle]) -> Result<Self, String> {
+        name.parse().or_else(|e| {
+            custom
+                .iter()
+                .find(|c| c.name.eq_ignore_ascii_case(name))
+                .map(|c| ExplainStyle::Custom(c.clone()))
+                .ok_or(e)
+        })
+    }
 }
 
 impl std::str::FromStr for ExplainStyle {
@@ -22,23 +136,169 @@ impl std::str::FromStr for ExplainStyle {
             "typescript" | "ts" => Ok(ExplainStyle::Typescript),
             "python" | "py" => Ok(ExplainStyle::Python),
             "ruby" | "rb" => Ok(ExplainStyle::Ruby),
+            "rust" | "rs" => Ok(ExplainStyle::Rust),
+            "go" | "golang" => Ok(ExplainStyle::Go),
             "human" | "plain" => Ok(ExplainStyle::Human),
-            _ => Err(format!("Unknown style: {}. Use: typescript, python, ruby, human", s)),
+            "annotated" | "annotate" | "explainshell" => Ok(ExplainStyle::Annotated),
+            "diagram" | "flow" => Ok(ExplainStyle::Diagram),
+            _ => Err(format!(
+                "Unknown style: {}. Use: typescript, python, ruby, rust, go, human, annotated, diagram",
+                s
+            )),
         }
     }
 }
 
+/// How much detail the explanation goes into, independent of style
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    /// One-liner for experts
+    Terse,
+    #[default]
+    Normal,
+    /// Full beginner walkthrough
+    Eli5,
+}
+
+impl Verbosity {
+    /// Name used on the wire (edge requests)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verbosity::Terse => "terse",
+            Verbosity::Normal => "normal",
+            Verbosity::Eli5 => "eli5",
+        }
+    }
+}
+
+impl std::str::FromStr for Verbosity {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "terse" | "short" => Ok(Verbosity::Terse),
+            "normal" => Ok(Verbosity::Normal),
+            "eli5" | "beginner" => Ok(Verbosity::Eli5),
+            _ => Err(format!("Unknown verbosity: {}. Use: terse, normal, eli5", s)),
+        }
+    }
+}
+
+/// Sampling settings from `[generation]` or flags. None keeps the built-in
+/// default of whichever model answers.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+#[serde(default)]
+pub struct Generation {
+    /// 0 is most repeatable, 2 most varied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Token limit for the command reply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// Token limit for explanations, instead of the one for the verbosity
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+}
+
+impl Generation {
+    /// Settings from `other` where set, ours otherwise
+    pub fn or(self, other: Generation) -> Generation {
+        Generation {
+            temperature: other.temperature.or(self.temperature),
+            max_tokens: other.max_tokens.or(self.max_tokens),
+            max_output_tokens: other.max_output_tokens.or(self.max_output_tokens),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(t) = self.temperature {
+            if !(0.0..=2.0).contains(&t) {
+                return Err(format!("temperature must be between 0 and 2, got {}", t));
+            }
+        }
+        if self.max_tokens == Some(0) || self.max_output_tokens == Some(0) {
+            return Err("max_tokens and max_output_tokens must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Everything that shapes an explanation besides the command itself
+#[derive(Debug, Clone, Default)]
+pub struct ExplainOptions {
+    pub style: ExplainStyle,
+    pub verbosity: Verbosity,
+    /// Language code for the prose parts ("es", "pl", "ja"); None = English
+    pub lang: Option<String>,
+    /// Sampling for the explanation and, where options travel with the
+    /// query, the command
+    pub generation: Generation,
+}
+
 /// Request types for IPC
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum IpcRequest {
     /// Get CLI command from natural language
     #[serde(rename = "command")]
-    Command { query: String },
+    Command {
+        query: String,
+        /// Conversation key (see `client_id`); None = no memory
+        #[serde(default)]
+        client: Option<String>,
+        #[serde(default)]
+        generation: Generation,
+    },
 
     /// Explain a command with safety assessment
     #[serde(rename = "explain")]
-    Explain { command: String, style: ExplainStyle },
+    Explain {
+        command: String,
+        style: ExplainStyle,
+        #[serde(default)]
+        verbosity: Verbosity,
+        #[serde(default)]
+        lang: Option<String>,
+        #[serde(default)]
+        generation: Generation,
+        /// Send the explanation in partial frames as it's generated
+        #[serde(default)]
+        stream: bool,
+    },
+
+    /// Uptime, counters and provider warmups (`slashcmd daemon status`)
+    #[serde(rename = "health")]
+    Health,
+
+    /// First line on every connection. The daemon answers with its own
+    /// version and keeps the connection open for the actual request.
+    #[serde(rename = "hello")]
+    Hello { version: String },
+
+    /// Exit now instead of at the idle timeout
+    #[serde(rename = "shutdown")]
+    Shutdown,
+}
+
+/// Reply to `Health`, as JSON in `IpcResponse::result`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DaemonHealth {
+    pub uptime_secs: u64,
+    /// Command and explain requests answered
+    pub requests: u64,
+    pub avg_latency_ms: Option<u64>,
+    pub cache_hits: u64,
+    pub cache_lookups: u64,
+    pub providers: Vec<ProviderHealth>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProviderHealth {
+    pub name: String,
+    /// Since the last warmup or keep-alive
+    pub warmed_secs_ago: u64,
+    /// Why the last warmup failed, None if it worked
+    pub error: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -46,6 +306,31 @@ pub struct IpcResponse {
     pub success: bool,
     pub result: Option<String>,
     pub error: Option<String>,
+    /// `result` is the next piece of a streamed reply; more frames follow
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+    /// `error` with its kind, so the client exits the way it would have
+    /// without the daemon. Older daemons only send `error`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<SlashcmdError>,
+}
+
+impl IpcResponse {
+    pub fn failed(error: SlashcmdError) -> Self {
+        IpcResponse {
+            success: false,
+            result: None,
+            error: Some(error.to_string()),
+            partial: false,
+            failure: Some(error),
+        }
+    }
+}
+
+/// The shell we were started from. The daemon keeps one conversation per
+/// client, so follow-ups in one terminal don't see another's requests.
+pub fn client_id() -> String {
+    std::os::unix::process::parent_id().to_string()
 }
 
 /// Client-side IPC operations
@@ -53,43 +338,160 @@ pub struct IpcClient;
 
 impl IpcClient {
     /// Try to connect to the daemon socket. Returns None if daemon isn't running.
+    /// A daemon from an older slashcmd is restarted and also gives None, so
+    /// this request goes direct and the next one reaches the fresh daemon.
     pub fn try_connect() -> Option<UnixStream> {
-        UnixStream::connect(SOCKET_PATH).ok()
+        let stream = UnixStream::connect(SOCKET_PATH).ok();
+        tracing::debug!(connected = stream.is_some(), "daemon socket");
+        let mut stream = stream?;
+
+        let hello = IpcRequest::Hello {
+            version: VERSION.to_string(),
+        };
+        match Self::exchange(&mut stream, &hello, Framing::Lines, |_| {}) {
+            Ok(version) if version == VERSION => Some(stream),
+            // Newer than us: an old binary is still on PATH somewhere, leave it be
+            Ok(version) if is_older(VERSION, &version) => None,
+            // Older, or too old to know `Hello` at all
+            reply => {
+                tracing::debug!(?reply, ours = VERSION, "restarting outdated daemon");
+                Self::restart_daemon();
+                None
+            }
+        }
+    }
+
+    /// Ask the running daemon to exit and start one from this binary. Daemons
+    /// older than `Shutdown` ignore it, but the new one takes over the socket.
+    fn restart_daemon() {
+        if let Ok(mut stream) = UnixStream::connect(SOCKET_PATH) {
+            let _ = Self::exchange(&mut stream, &IpcRequest::Shutdown, Framing::Lines, |_| {});
+        }
+        #[cfg(feature = "daemon")]
+        spawn_daemon();
     }
 
     /// Send a request to the daemon and wait for response
-    pub fn send_request(stream: &mut UnixStream, request: &IpcRequest) -> Result<String, String> {
-        let mut json =
-            serde_json::to_string(request).map_err(|e| format!("Serialize error: {}", e))?;
-        json.push('\n');
-
-        stream
-            .write_all(json.as_bytes())
-            .map_err(|e| format!("Write error: {}", e))?;
-        stream
-            .flush()
-            .map_err(|e| format!("Flush error: {}", e))?;
+    pub fn send_request(stream: &mut UnixStream, request: &IpcRequest) -> Result<String, SlashcmdError> {
+        Self::send_request_streaming(stream, request, |_| {})
+    }
+
+    /// Send a request whose reply may come as partial frames before the final
+    /// one. `on_chunk` gets each partial frame's text; the final frame's is returned.
+    pub fn send_request_streaming(
+        stream: &mut UnixStream,
+        request: &IpcRequest,
+        on_chunk: impl FnMut(&str),
+    ) -> Result<String, SlashcmdError> {
+        Self::exchange(stream, request, Framing::Prefixed, on_chunk)
+    }
+
+    fn exchange(
+        stream: &mut UnixStream,
+        request: &IpcRequest,
+        framing: Framing,
+        mut on_chunk: impl FnMut(&str),
+    ) -> Result<String, SlashcmdError> {
+        let started = std::time::Instant::now();
+        let json = serde_json::to_vec(request).map_err(|e| format!("Serialize error: {}", e))?;
+        write_frame(&mut *stream, &json, framing).map_err(|e| format!("Write error: {}", e))?;
 
         let mut reader = BufReader::new(stream);
-        let mut response_line = String::new();
-        reader
-            .read_line(&mut response_line)
-            .map_err(|e| format!("Read error: {}", e))?;
+        let mut response_bytes = 0;
+        let response = loop {
+            let (frame, _) = read_frame(&mut reader).map_err(|e| format!("Read error: {}", e))?;
+            response_bytes += frame.len();
 
-        let response: IpcResponse = serde_json::from_str(&response_line)
-            .map_err(|e| format!("Parse error: {}", e))?;
+            let response: IpcResponse =
+                serde_json::from_slice(&frame).map_err(|e| SlashcmdError::Parse(e.to_string()))?;
+            if !response.partial {
+                break response;
+            }
+            on_chunk(response.result.as_deref().unwrap_or_default());
+        };
+        tracing::debug!(
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            success = response.success,
+            "ipc round-trip"
+        );
+        tracing::trace!(request_bytes = json.len(), response_bytes, "ipc payload");
 
         if response.success {
             Ok(response.result.unwrap_or_default())
+        } else if let Some(failure) = response.failure {
+            Err(failure)
         } else {
-            Err(response.error.unwrap_or_else(|| "Unknown error".to_string()))
+            Err(response.error.unwrap_or_else(|| "Unknown error".to_string()).into())
         }
     }
 }
 
+/// Whether dotted version `a` is older than `b` ("0.9.1" < "0.10.0")
+pub fn is_older(a: &str, b: &str) -> bool {
+    let parse = |v: &str| -> Vec<u64> { v.split('.').map(|p| p.parse().unwrap_or(0)).collect() };
+    parse(a) < parse(b)
+}
+
+/// Where the daemon's stderr goes, started afresh by each spawn
+pub fn daemon_log_file() -> std::path::PathBuf {
+    crate::logs::logs_dir()
+        .parent()
+        .map(|dir| dir.join("daemon.log"))
+        .unwrap_or_else(|| std::path::PathBuf::from("daemon.log"))
+}
+
+/// Spawn the daemon as a detached background process
+#[cfg(feature = "daemon")]
+pub fn spawn_daemon() {
+    if let Ok(exe) = std::env::current_exe() {
+        let log = daemon_log_file();
+        let stderr = log
+            .parent()
+            .and_then(|dir| std::fs::create_dir_all(dir).ok())
+            .and_then(|_| std::fs::File::create(&log).ok())
+            .map_or_else(std::process::Stdio::null, std::process::Stdio::from);
+        let _ = std::process::Command::new(&exe)
+            .args(["--local", "--daemon"])
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(stderr)
+            .spawn();
+    }
+}
+
+/// One accepted client on the daemon side. Replies go out in the framing
+/// its last request came in, so older clients still understand them.
+pub struct Connection {
+    reader: BufReader<UnixStream>,
+    framing: Framing,
+}
+
+impl Connection {
+    pub fn new(stream: UnixStream) -> Self {
+        Self {
+            reader: BufReader::new(stream),
+            framing: Framing::Prefixed,
+        }
+    }
+
+    pub fn read_request(&mut self) -> Result<IpcRequest, String> {
+        let (frame, framing) = read_frame(&mut self.reader).map_err(|e| format!("Failed to read request: {}", e))?;
+        self.framing = framing;
+        serde_json::from_slice(&frame).map_err(|e| format!("Invalid request: {}", e))
+    }
+
+    pub fn send(&mut self, response: &IpcResponse) {
+        let json = serde_json::to_vec(response)
+            .unwrap_or_else(|_| br#"{"success":false,"error":"Serialize error"}"#.to_vec());
+        let _ = write_frame(self.reader.get_mut(), &json, self.framing);
+    }
+}
+
 /// Server-side IPC operations
 pub struct IpcServer {
     listener: UnixListener,
+    /// Inode of our socket file, so we don't delete a successor's on exit
+    inode: Option<u64>,
 }
 
 impl IpcServer {
@@ -106,7 +508,8 @@ impl IpcServer {
             .set_nonblocking(true)
             .map_err(|e| format!("Failed to set non-blocking: {}", e))?;
 
-        Ok(Self { listener })
+        let inode = std::fs::metadata(SOCKET_PATH).ok().map(|m| m.ino());
+        Ok(Self { listener, inode })
     }
 
     /// Try to accept a connection. Returns None if no connection is pending.
@@ -121,7 +524,136 @@ impl IpcServer {
 
 impl Drop for IpcServer {
     fn drop(&mut self) {
-        // Clean up socket file on shutdown
-        let _ = std::fs::remove_file(SOCKET_PATH);
+        // Clean up socket file on shutdown, unless a newer daemon replaced it
+        let current = std::fs::metadata(SOCKET_PATH).ok().map(|m| m.ino());
+        if current.is_some() && current == self.inode {
+            let _ = std::fs::remove_file(SOCKET_PATH);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_older() {
+        assert!(is_older("0.9.1", "0.10.0"));
+        assert!(is_older("1.2", "1.2.1"));
+        assert!(!is_older("1.2.1", "1.2.1"));
+        assert!(!is_older("2.0.0", "1.9.9"));
+    }
+
+    #[test]
+    fn test_generation_flags_override_config() {
+        let config = Generation { temperature: Some(0.1), max_tokens: Some(300), max_output_tokens: None };
+        let flags = Generation { temperature: Some(0.9), ..Default::default() };
+        let merged = config.or(flags);
+        assert_eq!(merged, Generation { temperature: Some(0.9), max_tokens: Some(300), max_output_tokens: None });
+        assert!(merged.validate().is_ok());
+        assert!(Generation { temperature: Some(2.5), ..Default::default() }.validate().is_err());
+        assert!(Generation { max_output_tokens: Some(0), ..Default::default() }.validate().is_err());
+
+        // Older clients send no generation at all
+        let request: IpcRequest = serde_json::from_str(r#"{"type":"command","query":"ls"}"#).unwrap();
+        assert!(matches!(request, IpcRequest::Command { generation, .. } if generation == Generation::default()));
+    }
+
+    #[test]
+    fn test_streamed_reply() {
+        let (mut client, daemon) = UnixStream::pair().unwrap();
+        let replier = std::thread::spawn(move || {
+            let mut connection = Connection::new(daemon);
+            assert!(matches!(connection.read_request(), Ok(IpcRequest::Health)));
+            for (text, partial) in [("Lists ", true), ("files", true), ("Lists files", false)] {
+                connection.send(&IpcResponse {
+                    success: true,
+                    result: Some(text.to_string()),
+                    error: None,
+                    partial,
+                    failure: None,
+                });
+            }
+        });
+
+        let mut chunks = Vec::new();
+        let reply = IpcClient::send_request_streaming(&mut client, &IpcRequest::Health, |c| chunks.push(c.to_string()));
+        replier.join().unwrap();
+        assert_eq!(chunks, ["Lists ", "files"]);
+        assert_eq!(reply.unwrap(), "Lists files");
+    }
+
+    #[test]
+    fn test_large_payload() {
+        let (mut client, daemon) = UnixStream::pair().unwrap();
+        // Bigger than any socket buffer, with newlines and non-ASCII in it
+        let explanation = "step one\nkrok drugi ✓\n".repeat(200_000);
+        let expected = explanation.clone();
+        let replier = std::thread::spawn(move || {
+            let mut connection = Connection::new(daemon);
+            let request = connection.read_request().unwrap();
+            assert!(matches!(request, IpcRequest::Explain { ref command, .. } if command.len() == 1_000_000));
+            connection.send(&IpcResponse {
+                success: true,
+                result: Some(explanation),
+                error: None,
+                partial: false,
+                failure: None,
+            });
+        });
+
+        let request = IpcRequest::Explain {
+            command: "x".repeat(1_000_000),
+            style: ExplainStyle::Human,
+            verbosity: Verbosity::Normal,
+            lang: None,
+            generation: Generation::default(),
+            stream: false,
+        };
+        let reply = IpcClient::send_request(&mut client, &request);
+        replier.join().unwrap();
+        assert_eq!(reply.unwrap(), expected);
+    }
+
+    #[test]
+    fn test_failure_keeps_kind() {
+        let (mut client, daemon) = UnixStream::pair().unwrap();
+        let replier = std::thread::spawn(move || {
+            let mut connection = Connection::new(daemon);
+            assert!(matches!(connection.read_request(), Ok(IpcRequest::Health)));
+            connection.send(&IpcResponse::failed(SlashcmdError::Auth("Invalid API key".to_string())));
+        });
+
+        let err = IpcClient::send_request(&mut client, &IpcRequest::Health).unwrap_err();
+        replier.join().unwrap();
+        assert!(matches!(err, SlashcmdError::Auth(ref message) if message == "Invalid API key"));
+        assert_eq!(err.exit_code(), crate::error::EXIT_NOT_LOGGED_IN);
+    }
+
+    #[test]
+    fn test_legacy_lines() {
+        // An older client writes a JSON line and expects one back
+        let (mut client, daemon) = UnixStream::pair().unwrap();
+        client.write_all(b"{\"type\":\"health\"}\n").unwrap();
+        let mut connection = Connection::new(daemon);
+        assert!(matches!(connection.read_request(), Ok(IpcRequest::Health)));
+        connection.send(&IpcResponse {
+            success: true,
+            result: Some("ok".to_string()),
+            error: None,
+            partial: false,
+            failure: None,
+        });
+
+        let mut line = String::new();
+        BufReader::new(&client).read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"success\":true,\"result\":\"ok\",\"error\":null}\n");
+    }
+
+    #[test]
+    fn test_oversized_frame() {
+        let mut bytes = &((MAX_FRAME_BYTES + 1) as u32).to_be_bytes()[..];
+        let err = read_frame(&mut bytes).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
     }
 }
@@ -0,0 +1,176 @@
+//! How a generated command is laid out before it's shown
+//!
+//! Models pad commands with stray spaces and escape spaces in paths with
+//! backslashes (`~/My\ Documents`). `normalize` collapses the padding and
+//! single-quotes such paths. In the TUI, `split` then puts each stage of a
+//! long pipeline on its own line, ending the line before with ` \`. The
+//! shell reads the result as the same command, and it is what runs, so the
+//! user confirms exactly the bytes that execute. Only sh commands are laid
+//! out; other shells continue lines differently.
+
+use crate::shell::{self, Shell};
+
+/// Widest a pipeline gets before it's split, whatever the terminal
+pub const MAX_WIDTH: usize = 100;
+
+/// What starts each continued line of a split command
+const CONTINUATION: &str = " \\\n  ";
+
+/// `command` with whitespace between words collapsed and backslash-escaped
+/// spaces in plain paths quoted instead. Multi-line commands, heredocs and
+/// other shells are left alone.
+pub fn normalize(command: &str) -> String {
+    if shell::target() != Shell::Sh || command.trim().contains('\n') || command.contains("<<") {
+        return command.to_string();
+    }
+    words(command).iter().map(|word| quote_escaped_spaces(word)).collect::<Vec<_>>().join(" ")
+}
+
+/// `command` split before each top-level `|`, `&&` and `||` when it's
+/// wider than `width`. Unchanged when it fits or has nowhere to split.
+pub fn split(command: &str, width: usize) -> String {
+    if shell::target() != Shell::Sh || command.chars().count() <= width || command.contains('\n') {
+        return command.to_string();
+    }
+    let mut laid_out = String::new();
+    let mut start = 0;
+    for at in operators(command) {
+        // The space before the operator becomes the continuation
+        if at > 0 && command.as_bytes()[at - 1] == b' ' {
+            laid_out.push_str(&command[start..at - 1]);
+            laid_out.push_str(CONTINUATION);
+            start = at;
+        }
+    }
+    laid_out.push_str(&command[start..]);
+    laid_out
+}
+
+/// `command` with line continuations removed: what the shell reads, for
+/// rules that match a whole line
+pub fn join(command: &str) -> String {
+    let mut joined = String::with_capacity(command.len());
+    let mut rest = command;
+    while let Some(at) = rest.find("\\\n") {
+        joined.push_str(&rest[..at]);
+        rest = rest[at + 2..].trim_start_matches([' ', '\t']);
+    }
+    joined.push_str(rest);
+    joined
+}
+
+/// Words separated by unquoted whitespace, quotes and escapes kept as written
+fn words(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                word.push(c);
+                if let Some(next) = chars.next() {
+                    word.push(next);
+                }
+                continue;
+            }
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, _) if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// `My\ Documents/a\ b.txt` as `'My Documents/a b.txt'`, keeping a leading
+/// `~/` outside the quotes so it still expands. Words with anything the
+/// quotes would change (globs, variables, other escapes) are left alone.
+fn quote_escaped_spaces(word: &str) -> String {
+    let (home, path) = match word.strip_prefix("~/") {
+        Some(path) => ("~/", path),
+        None => ("", word),
+    };
+    if !path.contains("\\ ") {
+        return word.to_string();
+    }
+    let unescaped = path.replace("\\ ", " ");
+    let plain = |c: char| c.is_alphanumeric() || " _-./,:+@%".contains(c);
+    if !unescaped.chars().all(plain) {
+        return word.to_string();
+    }
+    format!("{}'{}'", home, unescaped)
+}
+
+/// Byte offsets of the top-level `|`, `&&` and `||` in `command`: outside
+/// quotes, groups and substitutions
+pub fn operators(command: &str) -> Vec<usize> {
+    let bytes = command.as_bytes();
+    let mut found = Vec::new();
+    let mut quote = None;
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        match (quote, b) {
+            (Some(q), _) if b == q => quote = None,
+            (Some(b'"'), b'\\') | (None, b'\\') => i += 1,
+            (Some(_), _) => {}
+            (None, b'\'' | b'"') => quote = Some(b),
+            (None, b'(' | b'{') => depth += 1,
+            (None, b')' | b'}') => depth = depth.saturating_sub(1),
+            (None, b'|' | b'&') if depth == 0 => {
+                let next = bytes.get(i + 1).copied();
+                let redirect = i > 0 && matches!(bytes[i - 1], b'>' | b'<');
+                if next == Some(b) || (b == b'|' && !redirect) {
+                    found.push(i);
+                }
+                // Skip the second half of `&&`, `||` and `|&`
+                if matches!(next, Some(b'|' | b'&')) {
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("  ls   -la  "), "ls -la");
+        assert_eq!(normalize("grep  'a   b' \"c  d\"  |  wc -l"), "grep 'a   b' \"c  d\" | wc -l");
+        assert_eq!(normalize("cd ~/My\\ Documents/old\\ files"), "cd ~/'My Documents/old files'");
+        assert_eq!(normalize("rm *.tmp\\ copy"), "rm *.tmp\\ copy");
+        assert_eq!(normalize("echo a\\  b"), "echo 'a ' b");
+        assert_eq!(normalize("cat <<EOF\n  x\nEOF"), "cat <<EOF\n  x\nEOF");
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        let command = "find . -name '*.log' -mtime +7 | xargs grep -l 'a | b' && echo done || echo $(ls | wc -l)";
+        assert_eq!(split(command, 200), command);
+        let laid_out = split(command, 40);
+        assert_eq!(
+            laid_out,
+            "find . -name '*.log' -mtime +7 \\\n  | xargs grep -l 'a | b' \\\n  && echo done \\\n  || echo $(ls | wc -l)"
+        );
+        assert_eq!(join(&laid_out), command);
+        assert_eq!(split("make 2>&1 | tee build.log >| out.txt", 10), "make 2>&1 \\\n  | tee build.log >| out.txt");
+    }
+}
@@ -2,12 +2,17 @@ use serde::{Deserialize, Serialize};
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::ipc::ExplainStyle;
+use crate::prompt;
+use crate::sync;
+
+/// How far back `record_report` looks for the entry
+const REPORT_SEARCH_LIMIT: usize = 200;
 
 /// Log entry for a command execution
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct LogEntry {
     pub timestamp: u64,
     pub query: String,
@@ -16,6 +21,51 @@ pub struct LogEntry {
     pub style: String,
     pub executed: bool,
     pub exit_code: Option<i32>,
+    /// Killed by --timeout
+    #[serde(default)]
+    pub timed_out: bool,
+    /// Prompts that produced the command (`prompt::prompt_version`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_version: Option<String>,
+    /// Where the command came from; None for entries from before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
+/// Everything needed to reconstruct (or report) how a command was generated
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Provenance {
+    /// "edge" (the proxy or a gateway), "local" (your own API key) or
+    /// "offline" (a built-in template)
+    pub mode: String,
+    /// "edge", "gateway", "groq" or "template"
+    pub provider: String,
+    /// Unknown in edge mode, where the worker picks it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Generated by the background daemon rather than this process
+    #[serde(default)]
+    pub daemon: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<u64>,
+    /// From sending the request to having the command
+    pub latency_ms: u64,
+}
+
+impl Provenance {
+    pub fn new(mode: &str, provider: &str, latency: Duration) -> Self {
+        Self {
+            mode: mode.to_string(),
+            provider: provider.to_string(),
+            model: None,
+            daemon: false,
+            prompt_tokens: None,
+            completion_tokens: None,
+            latency_ms: latency.as_millis() as u64,
+        }
+    }
 }
 
 /// Get the logs directory path
@@ -63,21 +113,27 @@ pub fn now() -> u64 {
         .unwrap_or(0)
 }
 
-/// Create a log entry
+/// Create a log entry. The query is kept without its context blocks: an
+/// attached file or a command's output was agreed to for one request, not
+/// for every later use of the logs (examples, sharing, snippets, sync).
 pub fn create_entry(
     query: &str,
     command: &str,
     explanation: Option<String>,
-    style: ExplainStyle,
+    style: &ExplainStyle,
+    provenance: Option<Provenance>,
 ) -> LogEntry {
     LogEntry {
         timestamp: now(),
-        query: query.to_string(),
+        query: prompt::strip_context(query).to_string(),
         command: command.to_string(),
         explanation,
-        style: format!("{:?}", style).to_lowercase(),
+        style: style.as_str().to_string(),
         executed: false,
         exit_code: None,
+        timed_out: false,
+        prompt_version: Some(prompt::prompt_version()),
+        provenance,
     }
 }
 
@@ -106,3 +162,47 @@ pub fn load_log(path: &PathBuf) -> std::io::Result<LogEntry> {
     let content = fs::read_to_string(path)?;
     serde_json::from_str(&content).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
 }
+
+/// Load the most recent log entries, newest first, skipping unreadable files
+pub fn recent_entries(limit: usize) -> Vec<LogEntry> {
+    list_logs(limit)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|path| load_log(path).ok())
+        .collect()
+}
+
+/// Mark the newest log entry for `command` as executed with its outcome
+pub fn record_execution(command: &str, exit_code: i32, timed_out: bool) -> std::io::Result<()> {
+    let Some(path) = list_logs(1)?.into_iter().next() else {
+        return Ok(());
+    };
+    let mut entry = load_log(&path)?;
+    if entry.command != command {
+        return Ok(());
+    }
+
+    entry.executed = true;
+    entry.exit_code = Some(exit_code);
+    entry.timed_out = timed_out;
+    let json = serde_json::to_string_pretty(&entry)?;
+    fs::write(&path, json)
+}
+
+/// Record the exit code the shell integration saw for the entry with this
+/// id (`sync::entry_id`). Returns false if no recent entry has it.
+pub fn record_report(id: &str, exit_code: i32) -> std::io::Result<bool> {
+    for path in list_logs(REPORT_SEARCH_LIMIT)? {
+        let Ok(mut entry) = load_log(&path) else {
+            continue;
+        };
+        if sync::entry_id(&entry) != id {
+            continue;
+        }
+        entry.executed = true;
+        entry.exit_code = Some(exit_code);
+        fs::write(&path, serde_json::to_string_pretty(&entry)?)?;
+        return Ok(true);
+    }
+    Ok(false)
+}
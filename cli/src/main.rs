@@ -1,19 +1,94 @@
+// Partial feature sets leave some shared helpers unused
+#![cfg_attr(
+    not(all(feature = "edge", feature = "groq", feature = "gemini", feature = "daemon", feature = "tui")),
+    allow(dead_code)
+)]
+
+mod annotate;
+mod approval;
+mod audit;
+mod attach;
+#[cfg(feature = "edge")]
 mod auth;
+mod batch;
+mod bundle;
+mod budget;
 mod cli;
+mod clipboard;
+mod config;
+mod context;
+mod coreutils;
+#[cfg(feature = "daemon")]
 mod daemon;
+mod diagram;
+mod diff;
+mod doctor;
+mod editor;
+#[cfg(feature = "edge")]
 mod edge;
+mod error;
+mod exec;
+#[cfg(feature = "gemini")]
+mod explain_cache;
+#[cfg(feature = "tui")]
+mod followup;
+#[cfg(feature = "gemini")]
 mod gemini;
+#[cfg(feature = "groq")]
 mod groq;
 mod highlight;
+mod history;
+mod hooks;
+mod init;
 mod ipc;
+mod layout;
 mod logs;
+mod mcp;
+mod models;
+mod plugins;
+mod policy;
+mod prefill;
+mod profile;
 mod prompt;
+mod ratelimit;
+mod safety;
+mod secrets;
+mod shell;
+mod shell_history;
+mod snippets;
+mod sync;
+mod syntax;
+mod target;
+mod telemetry;
+mod templates;
+mod tokens;
+#[cfg(feature = "tui")]
 mod tui;
+mod update;
+#[cfg(feature = "tui")]
+mod walkthrough;
+#[cfg(feature = "tui")]
+mod wrap;
+
+#[cfg(not(any(feature = "edge", feature = "groq")))]
+compile_error!("slashcmd needs a command provider: enable the `edge` or `groq` feature");
 
-use clap::{Parser, Subcommand};
-use ipc::ExplainStyle;
+use audit::AuditEvent;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
+use cli::OutputFormat;
+use config::Config;
+#[cfg(all(feature = "edge", feature = "groq"))]
+use config::Failover;
+use error::SlashcmdError;
+use exec::{ExecOptions, ExecOutcome};
+#[cfg(feature = "tui")]
+use followup::FollowUpSource;
+use ipc::{CustomStyle, ExplainOptions, ExplainStyle, Generation, Verbosity};
+use policy::{Policy, SafetyRules, Verdict};
 use std::io::IsTerminal;
-use std::process::Command;
+use std::time::Duration;
+use target::Target;
 
 #[derive(Parser)]
 #[command(name = "slashcmd")]
@@ -27,6 +102,14 @@ struct Args {
     #[arg(long, hide = true, global = true)]
     daemon: bool,
 
+    /// Print debug details to stderr (-vv for more: SSE events, IPC payloads)
+    #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Editor integration: query from stdin, one JSON object on stdout, no TTY or colors
+    #[arg(long, global = true)]
+    editor: bool,
+
     /// Skip the explanation (just show the command)
     #[arg(short = 'q', long, global = true)]
     quick: bool,
@@ -35,14 +118,116 @@ struct Args {
     #[arg(short = 'n', long, global = true)]
     non_interactive: bool,
 
-    /// Print command only (for shell integration)
+    /// Print the log id and the command, nothing else (for `slashcmd init`)
     #[arg(long, hide = true, global = true)]
     print_only: bool,
 
-    /// Explanation style: typescript (default), python, ruby, human
+    /// With --print-only, write the result to this file descriptor instead of stdout
+    #[arg(long, value_name = "FD", hide = true, global = true, requires = "print_only")]
+    result_fd: Option<u32>,
+
+    /// Explanation style: typescript (default), python, ruby, rust, go, human, annotated, diagram,
+    /// or a custom style from config.toml
     #[arg(short, long, default_value = "typescript", global = true)]
     style: String,
 
+    /// Explanation detail: terse, normal (default), eli5
+    #[arg(long, default_value = "normal", global = true)]
+    verbosity: String,
+
+    /// Language for explanations (e.g. es, pl, ja); defaults to `lang` in config.toml
+    #[arg(long, global = true)]
+    lang: Option<String>,
+
+    /// Sampling temperature, 0 (repeatable) to 2 (varied); defaults to `[generation]` in config.toml
+    #[arg(long, value_name = "T", global = true)]
+    temperature: Option<f32>,
+
+    /// Token limit for the generated command
+    #[arg(long, value_name = "N", global = true)]
+    max_tokens: Option<u32>,
+
+    /// Token limit for the explanation (default depends on --verbosity)
+    #[arg(long, value_name = "N", global = true)]
+    max_output_tokens: Option<u32>,
+
+    /// Output format: text (default) or mermaid (diagram source, implies --style diagram);
+    /// for batch, jsonl (default) or csv
+    #[arg(long, value_name = "FORMAT", global = true)]
+    output: Option<String>,
+
+    /// Run the confirmed command with writes limited to the current directory
+    #[arg(long, global = true)]
+    sandbox: bool,
+
+    /// Follow-up query run after the command, with its output as context (repeatable)
+    #[arg(long, value_name = "QUERY", global = true, conflicts_with_all = ["quick", "non_interactive", "print_only", "editor"])]
+    then: Vec<String>,
+
+    /// Capture the executed command's output and offer an AI follow-up on it
+    #[arg(long, global = true)]
+    capture: bool,
+
+    /// Kill the executed command after this many seconds
+    #[arg(long, value_name = "SECS", global = true)]
+    timeout: Option<u64>,
+
+    /// Type the confirmed command into this tmux pane instead of running it
+    #[arg(long, value_name = "TARGET", global = true, conflicts_with_all = ["sandbox", "timeout"])]
+    tmux_pane: Option<String>,
+
+    /// Put the confirmed command at your shell prompt instead of running it (kitty, WezTerm, iTerm2)
+    #[arg(long, global = true, conflicts_with_all = ["sandbox", "timeout", "tmux_pane"])]
+    prefill: bool,
+
+    /// Generate for and run the command on this SSH host (user@server)
+    #[arg(long, value_name = "HOST", global = true, conflicts_with_all = ["sandbox", "timeout", "tmux_pane", "prefill"])]
+    host: Option<String>,
+
+    /// Generate for and run the command inside this Docker container
+    #[arg(long, value_name = "NAME", global = true, conflicts_with_all = ["host", "sandbox", "timeout", "tmux_pane", "prefill"])]
+    container: Option<String>,
+
+    /// Prefer long options (--recursive over -r) in generated commands
+    #[arg(long, global = true, conflicts_with = "short_flags")]
+    long_flags: bool,
+
+    /// Prefer short options (-r over --recursive) in generated commands
+    #[arg(long, global = true)]
+    short_flags: bool,
+
+    /// Linear output for screen readers: no spinner, cursor movement or redraws
+    #[arg(long, global = true)]
+    plain: bool,
+
+    /// Generate POSIX sh with portable flags only (runs on BSD, busybox and GNU)
+    #[arg(long, global = true)]
+    portable: bool,
+
+    /// Shell to generate and run commands for: sh, powershell, cmd, nu (default: from $SHELL)
+    #[arg(long, conflicts_with = "portable")]
+    shell: Option<String>,
+
+    /// Always ask the model, even for requests a built-in template answers
+    #[arg(long, global = true)]
+    no_templates: bool,
+
+    /// Always confirm before running, even read-only commands
+    #[arg(long, global = true)]
+    ask: bool,
+
+    /// Put every generated command on the clipboard, whether or not it runs
+    #[arg(long)]
+    copy: bool,
+
+    /// With -q or --print-only, print `# slashcmd: <LEVEL>` on stderr (SAFE, CAUTION, DANGER...)
+    #[arg(long, global = true)]
+    safety_badge: bool,
+
+    /// Run even if the monthly budget from config.toml is used up
+    #[arg(long, global = true)]
+    force: bool,
+
     /// Use local API keys instead of edge proxy (requires GROQ_API_KEY)
     #[arg(short, long, global = true)]
     local: bool,
@@ -55,26 +240,302 @@ struct Args {
 #[derive(Subcommand)]
 enum Commands {
     /// Login with GitHub via browser
-    Login,
+    #[cfg(feature = "edge")]
+    Login {
+        /// Join an organization's team plan (an admin has to invite you first)
+        #[arg(long, value_name = "SLUG")]
+        org: Option<String>,
+        /// Don't open a browser; authorize from another device with a code
+        #[arg(long)]
+        no_browser: bool,
+        /// Paste a token (hidden prompt) or pipe it on stdin, e.g. in CI
+        #[arg(long, conflicts_with_all = ["org", "no_browser"])]
+        token: bool,
+    },
     /// Logout and clear stored credentials
+    #[cfg(feature = "edge")]
     Logout,
-    /// Show usage and tier status
+    /// Show usage and tier status (exit 3: not logged in, 4: quota used up)
+    #[cfg(feature = "edge")]
+    Status {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Open checkout (or the billing portal for Pro) in the browser
+    #[cfg(feature = "edge")]
+    Upgrade,
+    /// Verify the audit log's hash chain
+    Audit,
+    /// Sync history with your other machines (end-to-end encrypted)
+    #[cfg(feature = "edge")]
+    Sync {
+        #[command(subcommand)]
+        action: Option<SyncCommand>,
+    },
+    /// Export or import command history
+    History {
+        #[command(subcommand)]
+        action: HistoryCommand,
+    },
+    /// Anonymous usage statistics (latency, provider, success; never queries)
+    Telemetry {
+        #[command(subcommand)]
+        action: TelemetryCommand,
+    },
+    /// Share the last command and explanation as a short link
+    #[cfg(feature = "edge")]
+    Share,
+    /// Print the most recently generated command
+    Last {
+        /// Run it, confirming first unless it may auto-execute (as a snippet)
+        #[arg(long)]
+        run: bool,
+        /// Copy it to the clipboard
+        #[arg(long, conflicts_with = "run")]
+        copy: bool,
+    },
+    /// Save the last generated command as a named snippet
+    Save {
+        /// Explicit, so `save file` is still a request
+        #[arg(long = "as", value_name = "NAME")]
+        name: String,
+    },
+    /// Run a saved snippet
+    Run { name: String },
+    /// Manage saved snippets
+    Snippets {
+        #[command(subcommand)]
+        action: SnippetsCommand,
+    },
+    /// Background daemon that keeps --local connections warm
+    #[cfg(feature = "daemon")]
+    Daemon {
+        #[command(subcommand)]
+        action: DaemonCommand,
+    },
+    /// Check API keys, login, network, daemon, terminal and clipboard
+    Doctor,
+    /// List the provider's models and what slashcmd can use them for
+    Models {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Learn your preferred tools (rg, eza, podman...) from your shell history
+    ImportHistory {
+        /// Read the history without asking first
+        #[arg(long)]
+        yes: bool,
+        /// Forget the learned preferences
+        #[arg(long, conflicts_with = "yes")]
+        clear: bool,
+    },
+    /// Print shell integration: eval "$(slashcmd init zsh)"
+    Init {
+        /// bash, zsh or fish
+        shell: String,
+    },
+    /// Record the exit code of a command run by the shell integration
+    #[command(name = "_report", hide = true)]
+    Report { id: String, exit_code: i32 },
+    /// Generate commands for a file of queries, one per line ("-" for stdin)
+    Batch {
+        file: std::path::PathBuf,
+        /// Requests in flight at once
+        #[arg(short, long, default_value_t = batch::DEFAULT_JOBS)]
+        jobs: usize,
+        /// Include an explanation of each command
+        #[arg(long)]
+        explain: bool,
+    },
+    /// Write diagnostics for a bug report to a tar file (secrets masked)
+    DebugBundle {
+        /// How many recent log entries to include
+        #[arg(long, default_value_t = 20)]
+        entries: usize,
+        /// Leave out what the logged queries said
+        #[arg(long)]
+        redact_queries: bool,
+        /// Where to write it (default: slashcmd-debug-<time>.tar here)
+        #[arg(short = 'o', long)]
+        file: Option<std::path::PathBuf>,
+    },
+    /// Replace this binary with the latest release
+    SelfUpdate {
+        /// Only say whether there is a newer release
+        #[arg(long)]
+        check: bool,
+    },
+    /// Serve slashcmd to editors and agents
+    Serve {
+        /// Model Context Protocol over stdio
+        #[arg(long)]
+        mcp: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum HistoryCommand {
+    /// Write history to stdout
+    Export {
+        /// jsonl or csv
+        #[arg(long, default_value = "jsonl")]
+        format: String,
+        /// Only entries from this date (YYYY-MM-DD) or unix timestamp on
+        #[arg(long)]
+        since: Option<String>,
+    },
+    /// Add entries from a file exported on another machine
+    Import {
+        file: std::path::PathBuf,
+        /// jsonl or csv (default: from the file extension)
+        #[arg(long)]
+        format: Option<String>,
+    },
+    /// Explain a logged command again, in the --style given
+    Explain {
+        /// Entry id (or 4+ characters of it), or N for the Nth most recent
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TelemetryCommand {
+    /// Opt in
+    On,
+    /// Opt out and delete queued events
+    Off,
+    /// Show whether telemetry is on
     Status,
 }
 
+#[cfg(feature = "edge")]
+#[derive(Subcommand)]
+enum SyncCommand {
+    /// Print the sync key, or set one copied from another machine
+    Key {
+        /// Paste the key (hidden prompt) or pipe it on stdin
+        #[arg(long)]
+        set: bool,
+    },
+}
+
+#[cfg(feature = "daemon")]
+#[derive(Subcommand)]
+enum DaemonCommand {
+    /// Uptime, latency, cache hit rate and provider warmups (exit 1: not running)
+    Status {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnippetsCommand {
+    /// List saved snippets
+    List,
+    /// Delete a saved snippet
+    Delete { name: String },
+    /// Add snippets from the team library (Pro)
+    #[cfg(feature = "edge")]
+    Pull,
+    /// Publish local snippets to the team library (Pro)
+    #[cfg(feature = "edge")]
+    Push {
+        /// Overwrite team snippets whose command differs from yours
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print snippets as shell aliases (fish abbreviations), e.g. `>> ~/.zshrc`
+    Export {
+        /// Target shell: bash, zsh or fish
+        #[arg(long, default_value = "zsh")]
+        shell: String,
+    },
+}
+
+/// Parse argv. A request that starts with a subcommand's name (`upgrade all
+/// brew packages`, `run the tests`) but doesn't fit its arguments is a query,
+/// and so is `run tests` when there's no snippet called "tests".
+fn parse_args() -> Args {
+    let argv: Vec<String> = std::env::args().collect();
+    let error = match Args::try_parse_from(&argv) {
+        Ok(args) => match &args.command {
+            Some(Commands::Run { name }) if snippets::load().is_ok_and(|all| !all.contains_key(name)) => {
+                return as_query(&argv).and_then(|query| Args::try_parse_from(query).ok()).unwrap_or(args);
+            }
+            _ => return args,
+        },
+        Err(error) => error,
+    };
+    let misfit = matches!(
+        error.kind(),
+        ErrorKind::UnknownArgument
+            | ErrorKind::InvalidSubcommand
+            | ErrorKind::InvalidValue
+            | ErrorKind::TooManyValues
+            | ErrorKind::WrongNumberOfValues
+    );
+    match as_query(&argv).filter(|_| misfit) {
+        Some(query) => Args::try_parse_from(&query).unwrap_or_else(|_| error.exit()),
+        None => error.exit(),
+    }
+}
+
+/// argv with the subcommand name and what follows it made query words
+fn as_query(argv: &[String]) -> Option<Vec<String>> {
+    let at = query_start(argv)?;
+    let mut query = argv.to_vec();
+    query.insert(at, "--".to_string());
+    Some(query)
+}
+
+/// Where argv's subcommand name is, if words (not flags) follow it
+fn query_start(argv: &[String]) -> Option<usize> {
+    let command = Args::command();
+    let names: Vec<&str> = command.get_subcommands().map(|c| c.get_name()).collect();
+    let at = argv.iter().skip(1).position(|arg| names.contains(&arg.as_str()))? + 1;
+    argv.get(at + 1).filter(|next| !next.starts_with('-')).map(|_| at)
+}
+
 fn main() {
-    let args = Args::parse();
+    let mut args = parse_args();
+    init_tracing(args.verbose, !args.editor);
+
+    // An admin policy that exists but can't be applied must stop us
+    let policy = policy::load_policy().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        eprintln!("slashcmd will not run until the policy can be applied.");
+        std::process::exit(1);
+    });
+
+    // Subcommands have no query for `--then` to follow up on
+    if args.command.is_some() && !args.then.is_empty() {
+        fail(SlashcmdError::Config("--then follows a query, not a subcommand".to_string()));
+    }
 
     // Handle subcommands first
     if let Some(cmd) = &args.command {
         match cmd {
-            Commands::Login => {
-                if let Err(e) = auth::login() {
-                    eprintln!("Error: {}", e);
-                    std::process::exit(1);
+            #[cfg(feature = "edge")]
+            Commands::Login {
+                org,
+                no_browser,
+                token,
+            } => {
+                let result = if *token {
+                    auth::login_with_token()
+                } else {
+                    auth::login(org.as_deref(), *no_browser)
+                };
+                if let Err(e) = result {
+                    fail(e);
                 }
                 return;
             }
+            #[cfg(feature = "edge")]
             Commands::Logout => {
                 if let Err(e) = auth::logout() {
                     eprintln!("Error: {}", e);
@@ -82,58 +543,378 @@ fn main() {
                 }
                 return;
             }
-            Commands::Status => {
-                if let Err(e) = auth::status() {
+            #[cfg(feature = "edge")]
+            Commands::Status { json } => match auth::status(*json) {
+                Ok(code) => std::process::exit(code),
+                Err(e) => fail(e),
+            },
+            #[cfg(feature = "daemon")]
+            Commands::Daemon {
+                action: DaemonCommand::Status { json },
+            } => match daemon::status(*json) {
+                Ok(code) => std::process::exit(code),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            },
+            #[cfg(feature = "edge")]
+            Commands::Upgrade => {
+                if let Err(e) = auth::upgrade() {
+                    fail(e);
+                }
+                return;
+            }
+            Commands::Audit => {
+                let path = policy.audit_file();
+                match audit::verify(&path) {
+                    Ok(count) => println!("{}: {} entries, chain intact", path.display(), count),
+                    Err(e) => {
+                        eprintln!("{}: {}", path.display(), e);
+                        std::process::exit(1);
+                    }
+                }
+                return;
+            }
+            #[cfg(feature = "edge")]
+            Commands::Sync { action } => {
+                if let Err(e) = sync_history(action.as_ref()) {
+                    fail(e);
+                }
+                return;
+            }
+            // Explaining needs the config's styles
+            Commands::History {
+                action: HistoryCommand::Explain { .. },
+            } => {}
+            Commands::History { action } => {
+                if let Err(e) = manage_history(action) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+                return;
+            }
+            Commands::Telemetry { action } => {
+                let result = match action {
+                    TelemetryCommand::On => telemetry::set_enabled(true),
+                    TelemetryCommand::Off => telemetry::set_enabled(false),
+                    TelemetryCommand::Status => Ok(()),
+                };
+                if let Err(e) = result {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+                if telemetry::enabled() {
+                    println!("Telemetry is on ({} events queued)", telemetry::queued());
+                } else {
+                    println!("Telemetry is off");
+                }
+                return;
+            }
+            #[cfg(feature = "edge")]
+            Commands::Share => {
+                match share_last() {
+                    Ok(url) => println!("{}", url),
+                    Err(e) => fail(e),
+                }
+                return;
+            }
+            Commands::DebugBundle { entries, redact_queries, file } => {
+                let path = file.clone().unwrap_or_else(bundle::default_path);
+                let options = bundle::BundleOptions {
+                    entries: *entries,
+                    redact_queries: *redact_queries,
+                };
+                if let Err(e) = bundle::create(&path, &options) {
                     eprintln!("Error: {}", e);
                     std::process::exit(1);
                 }
+                println!("{}", path.display());
+                eprintln!("{}", highlight::dim("Look it over before attaching it to an issue."));
+                return;
+            }
+            Commands::SelfUpdate { check } => {
+                if let Err(e) = self_update(*check) {
+                    fail(e);
+                }
                 return;
             }
+            Commands::ImportHistory { yes, clear } => {
+                if let Err(e) = import_history(*yes, *clear) {
+                    fail(e);
+                }
+                return;
+            }
+            Commands::Init { shell } => {
+                match shell.parse::<snippets::Shell>() {
+                    Ok(shell) => print!("{}", init::script(shell)),
+                    Err(e) => fail(SlashcmdError::Config(e)),
+                }
+                return;
+            }
+            Commands::Report { id, exit_code } => {
+                // Runs in the background after every command; stay quiet
+                let _ = logs::record_report(id, *exit_code);
+                return;
+            }
+            Commands::Save { name } => {
+                match snippets::save_last(name) {
+                    Ok(snippet) => println!("Saved '{}': {}", name, snippet.command),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                return;
+            }
+            Commands::Snippets { action } => {
+                if let Err(e) = manage_snippets(action) {
+                    fail(e);
+                }
+                return;
+            }
+            // Need config and policy, handled below
+            Commands::Run { .. }
+            | Commands::Last { .. }
+            | Commands::Batch { .. }
+            | Commands::Serve { .. }
+            | Commands::Doctor
+            | Commands::Models { .. } => {}
         }
     }
 
-    // Local mode uses direct API calls (requires GROQ_API_KEY)
-    if args.local {
-        run_local_mode(&args);
+    let mut config = config::load().unwrap_or_else(|e| {
+        eprintln!("Warning: {}", e);
+        Config::default()
+    });
+    highlight::set_palette(config.accessibility);
+    #[cfg(feature = "tui")]
+    tui::use_plain(args.plain || config.plain);
+    if let Some(fd) = args.result_fd {
+        cli::use_result_fd(fd).unwrap_or_else(|e| fail(e));
+    }
+    if args.ask {
+        config.safety.auto_execute = policy::AutoExecute::Never;
+    }
+
+    hooks::enable(config.hooks.clone());
+    let approval = policy.approval.clone().unwrap_or_else(|| config.approval.clone());
+    if approval.webhook.is_some() {
+        approval::enable(approval, safety_rules(&config, &policy));
+    }
+    if config.audit || policy.audit {
+        audit::enable(policy.audit_file());
+    }
+    if args.copy || config.copy_on_generate {
+        clipboard::copy_on_generate(safety_rules(&config, &policy));
+    }
+    secrets::set_handling(config.secrets);
+    if let Some(max_tokens) = config.max_context_tokens {
+        tokens::limit_context(max_tokens);
+    }
+    prompt::prefer_flags(if args.long_flags {
+        prompt::FlagStyle::Long
+    } else if args.short_flags {
+        prompt::FlagStyle::Short
+    } else {
+        config.flags
+    });
+    prompt::require_portable(args.portable || config.portable);
+    let remote = args.host.is_some() || args.container.is_some();
+    let chosen_shell = match args.shell.as_deref() {
+        Some(name) => Some(name.parse().unwrap_or_else(|e| fail(SlashcmdError::Config(e)))),
+        None => config.shell,
+    };
+    if remote && chosen_shell.is_some_and(|s| s != shell::Shell::Sh) {
+        fail(SlashcmdError::Config("--shell only applies to commands run on this machine".to_string()));
+    }
+    // Remote commands go through the remote sh; --portable asks for sh
+    let detected = || if remote || prompt::portable() { shell::Shell::Sh } else { shell::from_env() };
+    shell::set_target(chosen_shell.unwrap_or_else(detected));
+    templates::enable(!args.no_templates && !remote && !prompt::portable() && shell::target() == shell::Shell::Sh);
+    #[cfg(feature = "edge")]
+    if let Err(e) = edge::use_gateway(config.gateway.clone()) {
+        fail(SlashcmdError::Config(e));
+    }
+
+    // Logged with every entry. The edge worker builds its own prompts.
+    if !args.local && cfg!(feature = "edge") {
+        prompt::select_version("edge".to_string());
+    } else if let Some(version) = std::env::var("SLASHCMD_PROMPT_VERSION")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .or_else(|| config.prompt_version.clone())
+    {
+        prompt::select_version(version);
+    }
+
+    // --sandbox, --timeout and remote targets need the command to run here;
+    // a flag beats config.toml
+    let runs_here = !args.sandbox && args.timeout.is_none() && args.host.is_none() && args.container.is_none();
+    if runs_here && args.tmux_pane.is_none() && !args.prefill {
+        args.tmux_pane = config.tmux_pane.clone();
+        args.prefill = args.tmux_pane.is_none() && config.prefill;
+    }
+
+    if let Some(Commands::Doctor) = &args.command {
+        std::process::exit(doctor::run(&config, &policy, args.local || !cfg!(feature = "edge")));
+    }
+    if let Some(Commands::Models { json }) = &args.command {
+        match list_models(args.local || !cfg!(feature = "edge")) {
+            Ok(list) => models::print(list, *json),
+            Err(e) => fail(e),
+        }
         return;
     }
+    if let Some(Commands::Run { name }) = &args.command {
+        run_snippet(&args, &config, &policy, name);
+    }
+    if let Some(Commands::Last { run, copy }) = &args.command {
+        last(&args, &config, &policy, *run, *copy);
+    }
+    if let Some(Commands::Batch { file, jobs, explain }) = &args.command {
+        let batch = args.output.as_deref().unwrap_or("jsonl").parse().map(|format| batch::BatchOptions {
+            explain: *explain,
+            jobs: *jobs,
+            format,
+        });
+        match batch {
+            Ok(batch) => run_batch(&args, &config, &policy, file, &batch),
+            Err(e) => fail(SlashcmdError::Config(e)),
+        }
+    }
+    if let Some(Commands::History {
+        action: HistoryCommand::Explain { id },
+    }) = &args.command
+    {
+        explain_logged(&args, &config, &policy, id);
+    }
+    if let Some(Commands::Serve { mcp }) = &args.command {
+        serve(&args, &config, &policy, *mcp);
+    }
+    if args.editor {
+        run_editor_mode(&args, &config, &policy);
+    }
+
+    // `slashcmd foo ...` with a `slashcmd-foo` on PATH; no login needed
+    if let Some(plugin) = args.query.first().and_then(|name| plugins::find(name)) {
+        run_plugin(&args, &config, &policy, &plugin);
+    }
+
+    if !args.query.is_empty() {
+        enforce_budget(&args, &config, 1 + args.then.len() as u64).unwrap_or_else(|e| fail(e));
+        telemetry::flush_in_background();
+        if config.update_check && std::io::stderr().is_terminal() {
+            update::notify();
+        }
+    }
+
+    if args.local && policy.disable_local {
+        eprintln!("Error: --local is disabled by policy ({})", policy::POLICY_FILE);
+        std::process::exit(1);
+    }
+    if args.quick && policy.require_explanation {
+        eprintln!("Error: --quick is disabled by policy ({})", policy::POLICY_FILE);
+        std::process::exit(1);
+    }
+
+    // Local mode uses direct API calls (requires GROQ_API_KEY), and is
+    // the only mode in builds without the edge proxy
+    if args.local || !cfg!(feature = "edge") {
+        #[cfg(feature = "groq")]
+        run_local_mode(&args, &config, &policy);
+        #[cfg(not(feature = "groq"))]
+        fail(not_built("--local", "groq"));
+    } else {
+        // Default: Edge mode (uses proxy, requires login)
+        #[cfg(feature = "edge")]
+        run_edge_mode(&args, &config, &policy);
+    }
+}
 
-    // Default: Edge mode (uses proxy, requires login)
-    run_edge_mode(&args);
+/// A flag or mode whose code was left out of this build
+#[allow(dead_code)] // every feature is on in the default build
+fn not_built(what: &str, feature: &str) -> SlashcmdError {
+    SlashcmdError::Config(format!(
+        "{} is not available: slashcmd was built without the `{}` feature",
+        what, feature
+    ))
+}
+
+/// Print an error with its hint and exit with the error's code
+fn fail(error: SlashcmdError) -> ! {
+    tracing::debug!(code = error.code(), "exiting");
+    eprintln!("Error: {}", error);
+    if let Some(hint) = error.hint() {
+        eprintln!("{}", highlight::dim(&format!("Hint: {}", hint)));
+    }
+    std::process::exit(error.exit_code())
+}
+
+/// -v shows debug events (HTTP timings, daemon fallbacks, cache hits),
+/// -vv adds trace events (SSE events, IPC payload sizes)
+fn init_tracing(verbose: u8, ansi: bool) {
+    let level = match verbose {
+        0 => return,
+        1 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .with_ansi(ansi)
+        .init();
 }
 
 /// Parse style keyword from first or last word of query
 /// e.g., "human list files" → (ExplainStyle::Human, "list files")
 /// e.g., "list files ts" → (ExplainStyle::Typescript, "list files")
-fn parse_style_from_query(words: &[String], default: ExplainStyle) -> (String, ExplainStyle) {
+/// Custom style names from config.toml work as keywords too.
+fn parse_style_from_query(
+    words: &[String],
+    default: ExplainStyle,
+    custom: &[CustomStyle],
+) -> (String, ExplainStyle) {
     if words.is_empty() {
         return (String::new(), default);
     }
 
-    let style_keywords = [
+    let mut style_keywords = vec![
         ("human", ExplainStyle::Human),
         ("ruby", ExplainStyle::Ruby),
         ("ts", ExplainStyle::Typescript),
         ("typescript", ExplainStyle::Typescript),
         ("py", ExplainStyle::Python),
         ("python", ExplainStyle::Python),
+        ("rs", ExplainStyle::Rust),
+        ("rust", ExplainStyle::Rust),
+        // Not "go": queries often start with the go tool itself
+        ("golang", ExplainStyle::Go),
+        ("annotated", ExplainStyle::Annotated),
+        ("diagram", ExplainStyle::Diagram),
     ];
+    style_keywords.extend(
+        custom
+            .iter()
+            .map(|c| (c.name.as_str(), ExplainStyle::Custom(c.clone()))),
+    );
 
     // Check first word
     let first = words[0].to_lowercase();
     for (keyword, style) in &style_keywords {
-        if first == *keyword {
+        if first.eq_ignore_ascii_case(keyword) {
             let remaining = words[1..].join(" ");
-            return (remaining, *style);
+            return (remaining, style.clone());
         }
     }
 
     // Check last word
     let last = words[words.len() - 1].to_lowercase();
     for (keyword, style) in &style_keywords {
-        if last == *keyword {
+        if last.eq_ignore_ascii_case(keyword) {
             let remaining = words[..words.len() - 1].join(" ");
-            return (remaining, *style);
+            return (remaining, style.clone());
         }
     }
 
@@ -141,41 +922,905 @@ fn parse_style_from_query(words: &[String], default: ExplainStyle) -> (String, E
     (words.join(" "), default)
 }
 
+/// Tell the model about a remote host or container (probing it first)
+fn with_target_context(args: &Args, query: String) -> String {
+    let target = Target::new(args.host.as_deref(), args.container.as_deref());
+    if target == Target::Local {
+        return query;
+    }
+    eprintln!("{}", highlight::dim(&format!("Checking {}...", target.name())));
+    match target.describe() {
+        Some(context) => prompt::with_context(&query, &context),
+        None => query,
+    }
+}
+
+/// Add the current kube context to Kubernetes queries. The banner naming it
+/// comes at confirmation, for commands that run kubectl or helm. Skipped for
+/// remote targets, which have their own kubeconfig.
+fn with_kube_context(args: &Args, query: String) -> String {
+    if args.host.is_some() || args.container.is_some() || !context::mentions_kubernetes(&query) {
+        return query;
+    }
+    let Some(kube) = context::KubeContext::current() else {
+        return query;
+    };
+    prompt::with_context(&query, &kube.prompt())
+}
+
+/// Add the active AWS/GCP/Azure accounts to cloud queries
+fn with_cloud_context(args: &Args, query: String) -> String {
+    if args.host.is_some() || args.container.is_some() || !context::mentions_cloud(&query) {
+        return query;
+    }
+    let profiles = context::cloud_profiles();
+    if profiles.is_empty() {
+        return query;
+    }
+    prompt::with_context(&query, &context::cloud_prompt(&profiles))
+}
+
+/// Say whether the local sed, date and friends are BSD, GNU or BusyBox.
+/// Skipped for remote targets and --portable commands, which must work with
+/// any, and for PowerShell, cmd and nu, which don't use them.
+fn with_coreutils_context(args: &Args, query: String) -> String {
+    let remote = args.host.is_some() || args.container.is_some();
+    if remote || prompt::portable() || shell::target() != shell::Shell::Sh {
+        return query;
+    }
+    match coreutils::detect() {
+        Some(flavor) => prompt::with_context(&query, flavor.prompt()),
+        None => query,
+    }
+}
+
+/// Tell the model about Windows interop when running under WSL. Skipped for
+/// remote targets, which aren't.
+fn with_wsl_context(args: &Args, query: String) -> String {
+    if args.host.is_some() || args.container.is_some() {
+        return query;
+    }
+    match context::wsl_distro() {
+        Some(distro) => prompt::with_context(&query, &context::wsl_prompt(&distro)),
+        None => query,
+    }
+}
+
+/// Combine the resolved style with --verbosity, --lang and the sampling
+/// flags (falling back to config.toml)
+fn explain_options(args: &Args, config: &Config, style: ExplainStyle) -> Result<ExplainOptions, SlashcmdError> {
+    let verbosity: Verbosity = args.verbosity.parse().map_err(SlashcmdError::Config)?;
+
+    let lang = args.lang.clone().or_else(|| config.lang.clone());
+
+    let generation = config.generation.or(Generation {
+        temperature: args.temperature,
+        max_tokens: args.max_tokens,
+        max_output_tokens: args.max_output_tokens,
+    });
+    generation.validate().map_err(SlashcmdError::Config)?;
+
+    Ok(ExplainOptions {
+        style,
+        verbosity,
+        lang,
+        generation,
+    })
+}
+
+/// Count `pending` queries against the `[budget]`, warning near the limits
+/// and refusing past them (unless --force)
+fn enforce_budget(args: &Args, config: &Config, pending: u64) -> Result<(), SlashcmdError> {
+    let warning = budget::enforce(&config.budget, pending, args.force)
+        .map_err(|message| SlashcmdError::Other(format!("{}. Use --force to run anyway.", message)))?;
+    if let Some(message) = warning {
+        eprintln!("{}", highlight::dim(&format!("Budget: {}", message)));
+    }
+    Ok(())
+}
+
+/// Show the remaining free-tier quota before sending requests when it runs
+/// low, from the cached `/status` so no request is added to the hot path
+#[cfg(all(feature = "edge", feature = "tui"))]
+fn quota_preflight(pending: i32) {
+    let Some(status) = auth::cached_status() else {
+        return;
+    };
+    if status.tier == "pro" || status.remaining > auth::LOW_QUOTA {
+        return;
+    }
+    let message = if status.remaining < pending {
+        format!(
+            "Only {} free request(s) left but this needs {}. Upgrade: slashcmd upgrade",
+            status.remaining.max(0),
+            pending
+        )
+    } else {
+        format!("{} free request(s) left. Upgrade: slashcmd upgrade", status.remaining)
+    };
+    eprintln!("{}", highlight::dim(&message));
+}
+
+/// Compile the `[safety]` rules and policy. A broken rule is fatal rather than ignored.
+fn safety_rules(config: &Config, policy: &Policy) -> SafetyRules {
+    SafetyRules::new(&config.safety, policy).unwrap_or_else(|e| fail(SlashcmdError::Config(e)))
+}
+
 fn print_usage() {
     eprintln!("Usage: slashcmd [OPTIONS] <your natural language request>");
     eprintln!("       slashcmd <COMMAND>");
     eprintln!();
     eprintln!("Commands:");
-    eprintln!("  login    Login with GitHub via browser");
-    eprintln!("  logout   Logout and clear stored credentials");
-    eprintln!("  status   Show usage and tier status");
+    eprintln!("  login [--org SLUG]     Login with GitHub via browser, optionally to a team");
+    eprintln!("                         (--no-browser: authorize from another device)");
+    eprintln!("  login --token          Login with a pasted or piped token (CI)");
+    eprintln!("  logout                 Logout and clear stored credentials");
+    eprintln!("  status [--json]        Show usage and tier status (exit 3: logged out,");
+    eprintln!("                         4: quota used up)");
+    eprintln!("  upgrade                Open checkout or the billing portal");
+    eprintln!("  audit                  Verify the audit log's hash chain");
+    eprintln!("  daemon status [--json] Latency, cache and warmups of the --local daemon");
+    eprintln!("  history export|import  Move history as JSONL or CSV (--format, --since)");
+    eprintln!("  history explain <ID>   Explain a logged command again (--style; N = Nth newest)");
+    eprintln!("  sync [key [--set]]     Sync encrypted history across machines");
+    eprintln!("  telemetry on|off       Opt-in anonymous latency/failure stats (or: status)");
+    eprintln!("  share                  Share the last command and explanation as a link");
+    eprintln!("  last [--run|--copy]    Print the last generated command, or run/copy it");
+    eprintln!("  save --as <NAME>       Save the last command as a snippet");
+    eprintln!("  run <NAME>             Run a saved snippet");
+    eprintln!("  snippets list|delete   Manage saved snippets");
+    eprintln!("  snippets pull|push     Sync with your team's library (Pro)");
+    eprintln!("  snippets export        Print snippets as aliases or fish abbrs (--shell bash|zsh|fish)");
+    eprintln!("  doctor [--local]       Diagnose keys, login, network, daemon and terminal");
+    eprintln!("  models [--json]        List models and whether they generate or explain");
+    eprintln!("  import-history         Learn preferred tools (rg, eza...) from shell history");
+    eprintln!("  init <SHELL>           Shell integration that records exit codes (bash|zsh|fish)");
+    eprintln!("  batch <FILE>           Commands for a file of queries as JSONL or CSV");
+    eprintln!("                         (--output, --jobs, --explain; never runs them)");
+    eprintln!("  serve --mcp            MCP server on stdio for editors and agents");
+    eprintln!("  debug-bundle           Diagnostics for a bug report (--entries, --redact-queries)");
+    eprintln!("  self-update            Install the latest release (--check to only look)");
+    eprintln!("  <NAME> [ARGS]...       Hand the request to a `slashcmd-<NAME>` plugin on PATH");
     eprintln!();
     eprintln!("Options:");
     eprintln!("  -q, --quick           Skip explanation (just show command)");
+    eprintln!("  --editor              Query on stdin, JSON on stdout (editor integrations)");
+    eprintln!("  -v, --verbose         Debug output on stderr (-vv for more)");
     eprintln!("  -n, --non-interactive Don't wait for Enter, just print and exit");
-    eprintln!("  -s, --style <STYLE>   Explanation style: typescript, python, ruby, human");
+    eprintln!("  -s, --style <STYLE>   Explanation style: typescript, python, ruby, rust, go,");
+    eprintln!("                        human, annotated, diagram, or a [[styles]] entry");
+    eprintln!("                        from ~/.config/slashcmd/config.toml");
+    eprintln!("      --verbosity <LVL> Explanation detail: terse, normal, eli5");
+    eprintln!("      --lang <CODE>     Explanation language (es, pl, ja, ...)");
+    eprintln!("      --temperature <T> Sampling temperature, 0 (repeatable) to 2 (varied)");
+    eprintln!("      --max-tokens <N>  Token limit for the command");
+    eprintln!("      --max-output-tokens <N>  Token limit for the explanation");
+    eprintln!("      --output <FORMAT> Output format: text, mermaid (diagram source)");
+    eprintln!("      --sandbox         Run with writes limited to the current directory");
+    eprintln!("      --then <QUERY>    Follow-up query that sees the command's output");
+    eprintln!("      --capture         Keep the output and offer an AI follow-up on it");
+    eprintln!("      --timeout <SECS>  Kill the command if it runs longer");
+    eprintln!("      --long-flags      Prefer --recursive over -r (--short-flags: the reverse)");
+    eprintln!("      --plain           Linear output for screen readers, no redraws");
+    eprintln!("      --portable        POSIX sh and portable flags only (BSD, busybox, GNU)");
+    eprintln!("      --shell <SHELL>   Generate and run for sh, powershell, cmd or nu (default: $SHELL)");
+    eprintln!("      --no-templates    Ask the model even when a built-in template matches");
+    eprintln!("      --ask             Confirm before running, even read-only commands");
+    eprintln!("      --safety-badge    With -q or --print-only, `# slashcmd: <LEVEL>` on stderr");
+    eprintln!("      --copy            Put every generated command on the clipboard");
+    eprintln!("      --tmux-pane <T>   Type the command into a tmux pane instead of running it");
+    eprintln!("      --prefill         Put the command at your prompt (kitty, WezTerm, iTerm2)");
+    eprintln!("      --host <HOST>     Generate for and run on a remote host over SSH");
+    eprintln!("      --container <NAME> Generate for and run inside a Docker container");
+    eprintln!("      --force           Run even if the monthly [budget] is used up");
     eprintln!("  -l, --local           Use local API keys (requires GROQ_API_KEY)");
     eprintln!();
     eprintln!("Style keywords (first or last word):");
-    eprintln!("  human, ruby, ts, py   Override explanation style inline");
+    eprintln!("  human, ruby, ts, py, rs, golang, annotated, diagram   Override explanation style inline");
     eprintln!();
     eprintln!("Examples:");
     eprintln!("  slashcmd login                       # Authenticate with GitHub");
     eprintln!("  slashcmd find five largest files     # TypeScript-style explanation");
     eprintln!("  slashcmd human list docker containers# Plain English explanation");
     eprintln!("  slashcmd -q list files               # Just the command, no explanation");
+    eprintln!("  slashcmd list docker images --then \"delete the dangling ones\"");
+    eprintln!("  slashcmd convert @schema.sql to a postgres migration  # Sends the file, if you agree");
     eprintln!("  slashcmd status                      # Check usage (47/100 free tier)");
     eprintln!();
     eprintln!("Shell integration (add to .zshrc):");
-    eprintln!("  /cmd() {{ slashcmd \"$@\" }}");
+    eprintln!("  eval \"$(slashcmd init zsh)\"   # then: /cmd find large files");
+    eprintln!();
+    eprintln!("Exit codes:");
+    eprintln!("  1 error, 2 usage error, 3 not logged in, 4 quota used up, 5 rate limited,");
+    eprintln!("  6 network, 7 server error, 8 unexpected response, 9 invalid config; otherwise the command's");
     eprintln!();
     eprintln!("Pricing:");
     eprintln!("  Free: 100 commands (lifetime)");
-    eprintln!("  Pro:  $5/month unlimited - https://slashcmd.lgandecki.net/upgrade");
+    eprintln!("  Pro:  $5/month unlimited - slashcmd upgrade");
+}
+
+#[cfg(feature = "edge")]
+fn require_token() -> Result<String, SlashcmdError> {
+    // A gateway with its own credential doesn't need a slashcmd login
+    auth::get_token()
+        .or_else(|| edge::gateway_authenticates().then(String::new))
+        .ok_or(SlashcmdError::NotLoggedIn)
+}
+
+/// `slashcmd history export|import`
+fn manage_history(action: &HistoryCommand) -> Result<(), String> {
+    match action {
+        HistoryCommand::Export { format, since } => {
+            let since = since.as_deref().map(history::parse_since).transpose()?.unwrap_or(0);
+            let count = history::export(&mut std::io::stdout().lock(), format.parse()?, since)?;
+            eprintln!("{}", highlight::dim(&format!("Exported {} entries", count)));
+        }
+        HistoryCommand::Explain { .. } => unreachable!("handled after the config is loaded"),
+        HistoryCommand::Import { file, format } => {
+            let format = match format {
+                Some(format) => format.parse()?,
+                None if file.extension().is_some_and(|ext| ext == "csv") => history::Format::Csv,
+                None => history::Format::Jsonl,
+            };
+            let reader = std::fs::File::open(file)
+                .map(std::io::BufReader::new)
+                .map_err(|e| format!("Failed to open {}: {}", file.display(), e))?;
+            let (imported, skipped) = history::import(reader, format)?;
+            println!("Imported {} entries ({} already present)", imported, skipped);
+        }
+    }
+    Ok(())
+}
+
+/// `slashcmd history explain <id>`: a fresh explanation of a logged
+/// command in `--style`, without generating the command again
+fn explain_logged(args: &Args, config: &Config, policy: &Policy, id: &str) -> ! {
+    let entry = history::find(id, logs::recent_entries(usize::MAX)).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    let output = output_format(args);
+    let style = ExplainStyle::resolve(&args.style, &config.styles).unwrap_or_else(|e| fail(SlashcmdError::Config(e)));
+    let style = if output == OutputFormat::Mermaid { ExplainStyle::Diagram } else { style };
+    let options = explain_options(args, config, style).unwrap_or_else(|e| fail(e));
+    let explanation = backend(args, policy)
+        .and_then(|backend| backend.explain(&entry.command, &options))
+        .unwrap_or_else(|e| fail(e));
+
+    if output == OutputFormat::Mermaid {
+        let source = diagram::mermaid_from_explanation(&entry.command, &explanation).unwrap_or_else(|e| {
+            fail(SlashcmdError::Other(format!("No Mermaid diagram for `{}`: {}", entry.command, e)))
+        });
+        println!("{}", source);
+        std::process::exit(0)
+    }
+    eprintln!("{}", highlight::highlight_command(&entry.command));
+    eprintln!();
+    println!("{}", highlight::highlight_explanation(&explanation, &options.style));
+    std::process::exit(0)
+}
+
+/// `slashcmd sync [key]`
+#[cfg(feature = "edge")]
+fn sync_history(action: Option<&SyncCommand>) -> Result<(), SlashcmdError> {
+    match action {
+        Some(SyncCommand::Key { set: true }) => {
+            sync::set_key(&auth::read_secret("Paste sync key: ")?)?;
+            println!("Sync key saved");
+        }
+        Some(SyncCommand::Key { set: false }) => {
+            println!("{}", sync::load_or_create_key()?);
+            eprintln!("{}", highlight::dim("Run `slashcmd sync key --set` on your other machines. Keep it secret."));
+        }
+        None => {
+            let report = sync::sync(&edge::EdgeClient::new(require_token()?))?;
+            println!("Pushed {}, pulled {} history entries", report.pushed, report.pulled);
+        }
+    }
+    Ok(())
+}
+
+/// Publish the most recent command and its explanation via the edge service
+#[cfg(feature = "edge")]
+fn share_last() -> Result<String, SlashcmdError> {
+    let token = require_token()?;
+    let last = logs::recent_entries(1)
+        .into_iter()
+        .next()
+        .ok_or("No command to share yet. Generate one first.")?;
+
+    eprintln!("{}", highlight::dim(&format!("Sharing: {}", last.command)));
+    edge::EdgeClient::new(token).share(&edge::ShareRequest {
+        query: last.query,
+        command: last.command,
+        explanation: last.explanation,
+    })
+}
+
+/// `slashcmd import-history`: learn preferred tools from the shell history,
+/// after asking. Only tool names and counts are kept.
+fn import_history(yes: bool, clear: bool) -> Result<(), SlashcmdError> {
+    if clear {
+        let removed = profile::clear().map_err(SlashcmdError::Config)?;
+        println!("{}", if removed { "Forgot the learned tool preferences" } else { "No tool preferences to forget" });
+        return Ok(());
+    }
+
+    let files = shell_history::files();
+    if files.is_empty() {
+        return Err(SlashcmdError::Config(
+            "No shell history found (~/.zsh_history, ~/.bash_history, fish_history or $HISTFILE)".into(),
+        ));
+    }
+    let names: Vec<String> = files.iter().map(|f| f.display().to_string()).collect();
+    if !yes {
+        if !std::io::stdin().is_terminal() {
+            return Err(SlashcmdError::Config("Pass --yes to read your shell history without asking".into()));
+        }
+        eprint!(
+            "Read {} to learn which tools you use? Only tool names and counts are kept, in {}; \
+             nothing is sent. [y/N] ",
+            names.join(" and "),
+            profile::profile_file().display()
+        );
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).ok();
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            return Ok(());
+        }
+    }
+
+    let commands = shell_history::load();
+    let learned = profile::Profile::build(&commands);
+    profile::store(&learned).map_err(SlashcmdError::Config)?;
+    println!("Read {} commands.", learned.commands);
+    if learned.preferences.is_empty() {
+        println!("You use the standard tools; nothing to add to prompts.");
+    }
+    for p in &learned.preferences {
+        println!("  {} instead of {} {} ({} uses)", p.tool, p.instead_of, p.purpose, p.uses);
+    }
+    eprintln!("{}", highlight::dim("Forget them with: slashcmd import-history --clear"));
+    Ok(())
+}
+
+/// `slashcmd self-update [--check]`
+fn self_update(check: bool) -> Result<(), SlashcmdError> {
+    let latest = update::latest_version()?;
+    if !update::is_newer(&latest) {
+        println!("slashcmd {} is the latest version", ipc::VERSION);
+        return Ok(());
+    }
+    if check {
+        println!("slashcmd {} is available (you have {})", latest, ipc::VERSION);
+        return Ok(());
+    }
+    eprintln!("Downloading slashcmd {}...", latest);
+    let path = update::install(&latest)?;
+    println!("Updated {} to {}", path.display(), latest);
+    Ok(())
+}
+
+/// `slashcmd snippets ...`
+fn manage_snippets(action: &SnippetsCommand) -> Result<(), SlashcmdError> {
+    match action {
+        SnippetsCommand::List => {
+            let snippets = snippets::load()?;
+            if snippets.is_empty() {
+                eprintln!("No snippets yet. Save the last command with `slashcmd save --as <name>`.");
+            }
+            let width = snippets.keys().map(|name| name.len()).max().unwrap_or(0);
+            for (name, snippet) in &snippets {
+                println!("{:width$}  {}", name, snippet.command, width = width);
+            }
+            Ok(())
+        }
+        SnippetsCommand::Delete { name } => {
+            snippets::delete(name)?;
+            println!("Deleted '{}'", name);
+            Ok(())
+        }
+        #[cfg(feature = "edge")]
+        SnippetsCommand::Pull => {
+            let client = edge::EdgeClient::new(require_token()?);
+            let team = client.team_snippets()?;
+            let result = snippets::merge(&snippets::load()?, &team.snippets);
+            snippets::store(&result.merged)?;
+            println!("Pulled {} new snippet(s)", result.added.len());
+            for name in &result.rejected {
+                eprintln!("Skipped '{}': not a valid snippet name", name.escape_debug());
+            }
+            for name in &result.conflicts {
+                eprintln!(
+                    "Conflict: '{}' differs from the team's ({}); kept yours",
+                    name, team.snippets[name].command
+                );
+            }
+            Ok(())
+        }
+        #[cfg(feature = "edge")]
+        SnippetsCommand::Push { force } => {
+            let client = edge::EdgeClient::new(require_token()?);
+            let local = snippets::load()?;
+            // Retry if someone else pushes between our read and write
+            for _ in 0..3 {
+                let team = client.team_snippets()?;
+                let mut result = snippets::merge(&team.snippets, &local);
+                if !result.conflicts.is_empty() {
+                    if !*force {
+                        return Err(SlashcmdError::Other(format!(
+                            "These snippets differ from the team's: {}. Pull, rename them, or push --force.",
+                            result.conflicts.join(", ")
+                        )));
+                    }
+                    for name in &result.conflicts {
+                        result.merged.insert(name.clone(), local[name].clone());
+                    }
+                }
+                if client.push_team_snippets(&result.merged, team.version)?.is_some() {
+                    println!(
+                        "Pushed {} new and {} overwritten snippet(s)",
+                        result.added.len(),
+                        result.conflicts.len()
+                    );
+                    for name in &result.rejected {
+                        eprintln!("Skipped '{}': not a valid snippet name", name.escape_debug());
+                    }
+                    return Ok(());
+                }
+            }
+            Err("The team library keeps changing; try again".into())
+        }
+        SnippetsCommand::Export { shell } => {
+            let shell: snippets::Shell = shell.parse()?;
+            print!("{}", snippets::export(&snippets::load()?, shell));
+            Ok(())
+        }
+    }
+}
+
+/// Replay a saved snippet. Policy blocks and DANGER rules still apply,
+/// and anything not safe to auto-execute is confirmed first.
+fn run_snippet(args: &Args, config: &Config, policy: &Policy, name: &str) -> ! {
+    let snippet = snippets::get(name).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    replay(args, config, policy, &snippet.query, &snippet.command)
+}
+
+/// `slashcmd last`: print, run or copy the newest command from the logs.
+/// Running goes through `replay`, so it's checked and confirmed like a snippet.
+fn last(args: &Args, config: &Config, policy: &Policy, run: bool, copy: bool) -> ! {
+    let Some(entry) = logs::recent_entries(1).into_iter().next() else {
+        eprintln!("No command generated yet.");
+        std::process::exit(1);
+    };
+
+    if run {
+        replay(args, config, policy, &entry.query, &entry.command);
+    }
+    if copy {
+        if let Err(e) = clipboard::copy(&entry.command) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        eprintln!("{}", highlight::dim(&format!("Copied: {}", entry.command)));
+    } else {
+        println!("{}", entry.command);
+    }
+    std::process::exit(0)
+}
+
+/// Run a command generated earlier, unless the safety rules now refuse it.
+/// It's confirmed first unless they'd let it auto-execute.
+fn replay(args: &Args, config: &Config, policy: &Policy, query: &str, command: &str) -> ! {
+    let rules = safety_rules(config, policy);
+    let verdict = rules.check(command);
+    match verdict {
+        Verdict::Blocked => {
+            eprintln!("⛔ Blocked by policy: {}", command);
+            std::process::exit(1);
+        }
+        Verdict::Denied => {
+            eprintln!("Not replaying a denylisted command: {}", command);
+            std::process::exit(1);
+        }
+        Verdict::Danger => {
+            eprintln!("Not replaying a DANGER command: {}", command);
+            std::process::exit(1);
+        }
+        _ => {}
+    }
+    if rules.require_explanation() {
+        eprintln!("Not replaying: policy requires an explanation before a command runs.");
+        std::process::exit(1);
+    }
+
+    if rules.may_auto_execute(verdict, false) {
+        eprintln!("{}", highlight::dim(&format!("$ {}", command)));
+    } else {
+        if !std::io::stdin().is_terminal() || args.non_interactive {
+            eprintln!("Not replaying without confirmation: {}", command);
+            std::process::exit(1);
+        }
+        eprintln!("{}", highlight::highlight_command(command));
+        eprint!("Run it? [y/N] ");
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).ok();
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            std::process::exit(130);
+        }
+    }
+    let outcome = run_command(args, query, command, false);
+    std::process::exit(outcome.exit_code)
+}
+
+/// Ask an external plugin for a command, then show, check and confirm it
+/// like a generated one. Without a terminal the command is only printed.
+fn run_plugin(args: &Args, config: &Config, policy: &Policy, path: &std::path::Path) -> ! {
+    let query = args.query[1..].join(" ");
+    let response = plugins::run(path, &args.query[1..]).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    let Some(command) = response.command.filter(|c| !c.trim().is_empty()) else {
+        println!("{}", response.explanation.unwrap_or_default());
+        std::process::exit(0);
+    };
+
+    let interactive = std::io::stdin().is_terminal()
+        && std::io::stdout().is_terminal()
+        && !args.non_interactive
+        && !args.quick
+        && !args.print_only;
+    if args.print_only {
+        // Plugin commands aren't logged, so there's no id to report against
+        cli::print_result("", &command).unwrap_or_else(|e| fail(e));
+        std::process::exit(0);
+    }
+    if !interactive {
+        println!("{}", command);
+        std::process::exit(0);
+    }
+
+    eprintln!("{}", highlight::highlight_command(&command));
+    if let Some(explanation) = &response.explanation {
+        eprintln!("{}", highlight::dim(explanation));
+    }
+    let rules = safety_rules(config, policy);
+    let verdict = rules.check(&command);
+    match verdict {
+        Verdict::Blocked => {
+            eprintln!("⛔ Blocked by policy: {}", command);
+            std::process::exit(1);
+        }
+        Verdict::Denied => {
+            eprintln!("Not running a denylisted command: {}", command);
+            std::process::exit(1);
+        }
+        _ => {}
+    }
+    if rules.require_explanation() && response.explanation.is_none() {
+        eprintln!("Not running: policy requires an explanation and the plugin gave none.");
+        std::process::exit(1);
+    }
+
+    if !rules.may_auto_execute(verdict, response.safe) {
+        let word = if verdict == Verdict::Danger {
+            let warning = "DANGER: this command matches your safety rules";
+            eprintln!("{}", highlight::level(highlight::Level::Danger, warning));
+            let word = rules.confirm_word().unwrap_or("yes");
+            eprint!("Type \"{}\" to run it: ", word);
+            word
+        } else {
+            eprint!("Run it? [y/N] ");
+            "y"
+        };
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).ok();
+        if !answer.trim().eq_ignore_ascii_case(word) {
+            std::process::exit(130);
+        }
+    }
+    let outcome = run_command(args, &query, &command, false);
+    std::process::exit(outcome.exit_code)
+}
+
+/// Edge's model list, or Groq's plus Gemini's (with a key) when local
+#[cfg_attr(not(feature = "edge"), allow(unused_variables))]
+fn list_models(local: bool) -> Result<Vec<models::Model>, SlashcmdError> {
+    #[cfg(feature = "edge")]
+    if !local {
+        return edge::EdgeClient::new(require_token()?).models();
+    }
+    local_models()
+}
+
+#[cfg(not(feature = "groq"))]
+fn local_models() -> Result<Vec<models::Model>, SlashcmdError> {
+    Err(not_built("--local", "groq"))
+}
+
+#[cfg(feature = "groq")]
+fn local_models() -> Result<Vec<models::Model>, SlashcmdError> {
+    let groq_api_key = std::env::var("GROQ_API_KEY")
+        .ok()
+        .filter(|k| !k.is_empty())
+        .ok_or_else(|| SlashcmdError::Config("GROQ_API_KEY environment variable is not set".to_string()))?;
+    #[cfg_attr(not(feature = "gemini"), allow(unused_mut))]
+    let mut list = groq::GroqClient::new(groq_api_key).models()?;
+    #[cfg(feature = "gemini")]
+    if let Some(key) = std::env::var("GEMINI_API_KEY").ok().filter(|k| !k.is_empty()) {
+        list.extend(gemini::GeminiClient::new(key).models()?);
+    }
+    Ok(list)
+}
+
+/// `slashcmd batch`: records on stdout, exit 1 if any query failed
+fn run_batch(args: &Args, config: &Config, policy: &Policy, file: &std::path::Path, batch: &batch::BatchOptions) -> ! {
+    let queries = if file.as_os_str() == "-" {
+        batch::read_queries(std::io::stdin().lock())
+    } else {
+        std::fs::File::open(file)
+            .map_err(|e| format!("Failed to open {}: {}", file.display(), e))
+            .and_then(|f| batch::read_queries(std::io::BufReader::new(f)))
+    };
+    let queries = queries.unwrap_or_else(|e| fail(SlashcmdError::Other(e)));
+    if queries.is_empty() {
+        fail(SlashcmdError::Config(format!("No queries in {}", file.display())));
+    }
+
+    let backend = backend(args, policy).unwrap_or_else(|e| fail(e));
+    let style = ExplainStyle::resolve(&args.style, &config.styles).unwrap_or_else(|e| fail(SlashcmdError::Config(e)));
+    let options = explain_options(args, config, style).unwrap_or_else(|e| fail(e));
+    let rules = safety_rules(config, policy);
+
+    enforce_budget(args, config, queries.len() as u64).unwrap_or_else(|e| fail(e));
+
+    let mut out = std::io::stdout().lock();
+    match batch::run(&backend, &queries, &options, &rules, batch, &mut out) {
+        Ok(0) => std::process::exit(0),
+        Ok(failed) => {
+            eprintln!("{} of {} queries failed", failed, queries.len());
+            std::process::exit(1)
+        }
+        Err(e) => fail(SlashcmdError::Other(e)),
+    }
+}
+
+/// `slashcmd serve --mcp`: answer MCP requests on stdio until stdin closes
+fn serve(args: &Args, config: &Config, policy: &Policy, mcp: bool) -> ! {
+    if !mcp {
+        fail(SlashcmdError::Config("Nothing to serve. Use `slashcmd serve --mcp`.".to_string()));
+    }
+
+    let backend = backend(args, policy).unwrap_or_else(|e| fail(e));
+    let style = ExplainStyle::resolve(&args.style, &config.styles).unwrap_or_else(|e| fail(SlashcmdError::Config(e)));
+    let options = explain_options(args, config, style).unwrap_or_else(|e| fail(e));
+    let server = mcp::Server::new(backend, config, safety_rules(config, policy), options);
+    if let Err(e) = server.run() {
+        fail(e.into());
+    }
+    std::process::exit(0)
+}
+
+/// `--editor`: one JSON object on stdout, whatever happens
+fn run_editor_mode(args: &Args, config: &Config, policy: &Policy) -> ! {
+    let code = match editor_request(args, config, policy) {
+        Ok((backend, query, options, rules)) => editor::run(&backend, &query, &options, &rules, !args.quick),
+        Err(e) => editor::fail(&e),
+    };
+    std::process::exit(code)
+}
+
+/// Everything `--editor` needs, with errors returned rather than printed
+fn editor_request(
+    args: &Args,
+    config: &Config,
+    policy: &Policy,
+) -> Result<(mcp::Backend, String, ExplainOptions, SafetyRules), SlashcmdError> {
+    if args.quick && policy.require_explanation {
+        return Err(SlashcmdError::Config(format!("--quick is disabled by policy ({})", policy::POLICY_FILE)));
+    }
+    let words: Vec<String> = if args.query.is_empty() {
+        editor::read_query()?.split_whitespace().map(str::to_string).collect()
+    } else {
+        args.query.clone()
+    };
+    let default_style = ExplainStyle::resolve(&args.style, &config.styles).map_err(SlashcmdError::Config)?;
+    let (query, style) = parse_style_from_query(&words, default_style, &config.styles);
+    let query = secrets::scrub(query, "query");
+    let options = explain_options(args, config, style)?;
+    let rules = SafetyRules::new(&config.safety, policy).map_err(SlashcmdError::Config)?;
+    let backend = backend(args, policy)?;
+
+    enforce_budget(args, config, 1)?;
+    Ok((backend, query, options, rules))
+}
+
+/// Client for the non-interactive modes: the edge proxy, or Groq (and
+/// Gemini, if its key is set) directly with --local
+#[cfg_attr(not(feature = "edge"), allow(unused_variables))]
+fn backend(args: &Args, policy: &Policy) -> Result<mcp::Backend, SlashcmdError> {
+    #[cfg(feature = "edge")]
+    if !args.local {
+        return Ok(mcp::Backend::Edge(edge::EdgeClient::new(require_token()?)));
+    }
+    local_backend(policy)
+}
+
+#[cfg(not(feature = "groq"))]
+fn local_backend(_policy: &Policy) -> Result<mcp::Backend, SlashcmdError> {
+    Err(not_built("--local", "groq"))
+}
+
+#[cfg(feature = "groq")]
+fn local_backend(policy: &Policy) -> Result<mcp::Backend, SlashcmdError> {
+    if policy.disable_local {
+        return Err(SlashcmdError::Config(format!("--local is disabled by policy ({})", policy::POLICY_FILE)));
+    }
+    let groq_api_key = std::env::var("GROQ_API_KEY")
+        .ok()
+        .filter(|k| !k.is_empty())
+        .ok_or_else(|| SlashcmdError::Config("GROQ_API_KEY environment variable is not set".to_string()))?;
+    Ok(mcp::Backend::Local {
+        groq: groq::GroqClient::new(groq_api_key),
+        #[cfg(feature = "gemini")]
+        gemini: std::env::var("GEMINI_API_KEY")
+            .ok()
+            .filter(|k| !k.is_empty())
+            .map(gemini::GeminiClient::new),
+    })
+}
+
+/// Run the interactive flow for the query and every `--then` follow-up.
+/// Each confirmed command runs before the next query is generated, so the
+/// next query can see its output. Exits with the last command's status.
+#[cfg(feature = "tui")]
+fn run_tui_chain<F>(args: &Args, query: String, follow_up: Option<FollowUpSource>, mut run_tui: F) -> !
+where
+    F: FnMut(String, Option<&str>) -> Result<tui::TuiResult, SlashcmdError>,
+{
+    // Only the TUI can put a question to the user
+    prompt::allow_questions(true);
+    let steps: Vec<String> = std::iter::once(query).chain(args.then.iter().cloned()).collect();
+    let mut previous: Option<(String, String)> = None;
+
+    for (i, step) in steps.iter().enumerate() {
+        let is_last = i + 1 == steps.len();
+        // The previous step's output goes with every request for this step,
+        // refinements included, but stays out of the query that's logged
+        let output_context = previous.take().map(|(command, output)| {
+            prompt::previous_output(&command, &secrets::scrub(output, "command output"))
+        });
+
+        // Refinements regenerate with the rejected command as context
+        let mut step_query = step.clone();
+        let mut refined: Option<String> = None;
+        let command = loop {
+            let request = match &output_context {
+                Some(context) => prompt::with_context(&step_query, context),
+                None => step_query.clone(),
+            };
+            match run_tui(prompt::with_preferences(&request), refined.as_deref()) {
+                Ok(tui::TuiResult::Execute(command)) => break command,
+                Ok(tui::TuiResult::Cancel) => {
+                    // User cancelled
+                    std::process::exit(130); // Standard Ctrl+C exit code
+                }
+                Ok(tui::TuiResult::Refine { refinement, command }) => {
+                    let turn = prompt::Example {
+                        query: prompt::strip_context(&step_query).to_string(),
+                        command: command.clone(),
+                    };
+                    let refinement = secrets::scrub(refinement, "refinement");
+                    step_query = prompt::with_conversation(&refinement, &[turn]);
+                    refined = Some(command);
+                }
+                Ok(tui::TuiResult::Clarify { question, answer }) => {
+                    let answer = secrets::scrub(answer, "answer");
+                    step_query = prompt::with_answer(&step_query, &question, &answer);
+                }
+                Err(e) => fail(e),
+            }
+        };
+
+        // Capture for the next step, or for --capture follow-ups on the last
+        let capture = !is_last || (args.capture && follow_up.is_some());
+        let outcome = run_command(args, prompt::strip_context(&step_query), &command, capture);
+
+        if is_last || outcome.exit_code != 0 {
+            if let Some(source) = &follow_up {
+                followup::offer(source, &command, &outcome);
+            }
+            if !is_last {
+                eprintln!("Stopping: `{}` exited with {}.", command, outcome.exit_code);
+            }
+            std::process::exit(outcome.exit_code);
+        }
+        previous = Some((command, outcome.output.unwrap_or_default()));
+    }
+    std::process::exit(0)
+}
+
+/// Execute a confirmed command. Nothing runs if auditing is on but the
+/// audit entry can't be written.
+fn run_command(args: &Args, query: &str, command: &str, capture: bool) -> ExecOutcome {
+    if let Err(e) = hooks::pre_exec(query, command) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    match approval::require(query, command) {
+        Ok(approval::Decision::NotNeeded) => {}
+        Ok(approval::Decision::Approved(token)) => eprintln!("{}", highlight::dim(&format!("Approved ({})", token))),
+        Ok(approval::Decision::Overridden) => eprintln!("{}", highlight::dim("Running without approval (overridden)")),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("Not running the command without approval.");
+            std::process::exit(1);
+        }
+    }
+    if let Err(e) = audit::record(AuditEvent::Executed, query, command) {
+        eprintln!("Error: {}", e);
+        eprintln!("Not running the command without an audit record.");
+        std::process::exit(1);
+    }
+
+    if args.prefill {
+        if let Err(e) = prefill::prefill(command) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return ExecOutcome {
+            exit_code: 0,
+            timed_out: false,
+            output: None,
+        };
+    }
+    if let Some(pane) = &args.tmux_pane {
+        if let Err(e) = exec::send_to_tmux(pane, command) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        eprintln!("{}", highlight::dim(&format!("Sent to tmux pane {}", pane)));
+        return ExecOutcome {
+            exit_code: 0,
+            timed_out: false,
+            output: None,
+        };
+    }
+
+    if safety::elevates(command) {
+        eprintln!("{}", highlight::dim("Runs with elevated privileges; you may be asked for your password."));
+    }
+    let options = ExecOptions {
+        sandbox: args.sandbox,
+        timeout: args.timeout.map(Duration::from_secs),
+        capture,
+        target: Target::new(args.host.as_deref(), args.container.as_deref()),
+    };
+    match exec::execute(command, &options) {
+        Ok(outcome) => {
+            if outcome.timed_out {
+                eprintln!("Timed out after {}s, command killed.", args.timeout.unwrap_or(0));
+            }
+            let _ = logs::record_execution(command, outcome.exit_code, outcome.timed_out);
+            if let Err(e) = hooks::post_exec(query, command, outcome.exit_code) {
+                eprintln!("Warning: {}", e);
+            }
+            outcome
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
 /// Run in local mode - uses direct API calls (requires GROQ_API_KEY)
-fn run_local_mode(args: &Args) {
+#[cfg(feature = "groq")]
+fn run_local_mode(args: &Args, config: &Config, policy: &Policy) {
     // Get API keys from environment
     let groq_api_key = match std::env::var("GROQ_API_KEY") {
         Ok(key) if !key.is_empty() => key,
@@ -190,11 +1835,16 @@ fn run_local_mode(args: &Args) {
 
     if args.daemon {
         // Daemon mode - run background server
-        if let Err(e) = daemon::run_daemon(groq_api_key, gemini_api_key) {
-            eprintln!("Daemon error: {}", e);
-            std::process::exit(1);
+        #[cfg(feature = "daemon")]
+        {
+            if let Err(e) = daemon::run_daemon(groq_api_key, gemini_api_key) {
+                eprintln!("Daemon error: {}", e);
+                std::process::exit(1);
+            }
+            return;
         }
-        return;
+        #[cfg(not(feature = "daemon"))]
+        fail(not_built("--daemon", "daemon"));
     }
 
     // CLI mode - process user query
@@ -204,114 +1854,217 @@ fn run_local_mode(args: &Args) {
     }
 
     // Parse style from -s flag as default
-    let default_style: ExplainStyle = args.style.parse().unwrap_or_else(|e| {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
-    });
+    let default_style = ExplainStyle::resolve(&args.style, &config.styles)
+        .unwrap_or_else(|e| fail(SlashcmdError::Config(e)));
 
     // Check for style keywords in query (first or last word)
-    let (query, style) = parse_style_from_query(&args.query, default_style);
+    let (query, style) = parse_style_from_query(&args.query, default_style, &config.styles);
+    let query = attach::expand(query);
+    let query = with_target_context(args, query);
+    let query = with_kube_context(args, query);
+    let query = with_cloud_context(args, query);
+    let query = with_coreutils_context(args, query);
+    let query = with_wsl_context(args, query);
+    let query = secrets::scrub(query, "query");
 
-    // Determine mode: interactive TUI vs non-interactive
-    let is_tty = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
-    let use_tui = is_tty && !args.non_interactive && !args.quick && !args.print_only;
+    let output = output_format(args);
+    // Mermaid source only makes sense for the diagram style
+    let style = if output == OutputFormat::Mermaid { ExplainStyle::Diagram } else { style };
+
+    let options = explain_options(args, config, style).unwrap_or_else(|e| fail(e));
+    // Compiled even when nothing checks them, so a broken rule is always fatal
+    let rules = safety_rules(config, policy);
+    let safety_badge = (args.safety_badge || config.safety_badge).then_some(&rules);
+    check_then(args, output);
+
+    #[cfg(feature = "tui")]
+    if wants_tui(args, output) {
+        offer_history_matches(args, config, policy, &query);
 
-    if use_tui {
         // Interactive TUI mode
-        match tui::run_interactive(query, groq_api_key, gemini_api_key, style) {
-            Ok(tui::TuiResult::Execute(command)) => {
-                // Execute the command
-                let status = Command::new("sh")
-                    .arg("-c")
-                    .arg(&command)
-                    .status();
-
-                match status {
-                    Ok(s) => std::process::exit(s.code().unwrap_or(0)),
-                    Err(e) => {
-                        eprintln!("Failed to execute: {}", e);
-                        std::process::exit(1);
-                    }
-                }
-            }
-            Ok(tui::TuiResult::Cancel) => {
-                // User cancelled
-                std::process::exit(130); // Standard Ctrl+C exit code
-            }
-            Err(e) => {
-                eprintln!("Error: {}", e);
-                std::process::exit(1);
-            }
-        }
-    } else {
-        // Non-interactive mode (piped input, -q flag, or -n flag)
-        if let Err(e) = cli::run_cli(query, groq_api_key, gemini_api_key, style, args.quick) {
-            eprintln!("Error: {}", e);
-            std::process::exit(1);
-        }
+        #[cfg(feature = "gemini")]
+        let follow_up = gemini_api_key
+            .clone()
+            .map(|api_key| FollowUpSource::Gemini { api_key });
+        #[cfg(not(feature = "gemini"))]
+        let follow_up = None;
+        run_tui_chain(args, query, follow_up, |query, previous| {
+            tui::run_interactive(
+                query,
+                groq_api_key.clone(),
+                gemini_api_key.clone(),
+                options.clone(),
+                &rules,
+                previous,
+            )
+        });
+    }
+
+    // Non-interactive mode (piped input, -q flag, or -n flag)
+    if let Err(e) = cli::run_cli(
+        query,
+        groq_api_key,
+        gemini_api_key,
+        options,
+        args.quick,
+        output,
+        safety_badge,
+    ) {
+        fail(e);
+    }
+}
+
+/// With `history_matches` on, offer shell history commands that already do
+/// what the query asks. Picking one runs it (with the usual safety rules) and
+/// exits; otherwise this returns and the model is asked.
+#[cfg(feature = "tui")]
+fn offer_history_matches(args: &Args, config: &Config, policy: &Policy, query: &str) {
+    let remote = args.host.is_some() || args.container.is_some();
+    if !config.history_matches || remote || !args.then.is_empty() || shell::target() != shell::Shell::Sh {
+        return;
+    }
+    let query = prompt::strip_context(query);
+    let found = shell_history::matches(query, &shell_history::load(), 3);
+    if found.is_empty() {
+        return;
+    }
+
+    eprintln!("Found in your shell history:");
+    for (i, command) in found.iter().enumerate() {
+        eprintln!("  {}) {}", i + 1, highlight::highlight_command(command));
+    }
+    eprint!("Run one (1-{}), or Enter to ask the model: ", found.len());
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).ok();
+    if let Some(command) = answer.trim().parse::<usize>().ok().and_then(|n| found.get(n.wrapping_sub(1))) {
+        replay(args, config, policy, query, command);
+    }
+}
+
+/// --output for a query (--print-only means shell output)
+fn output_format(args: &Args) -> OutputFormat {
+    if args.print_only {
+        return OutputFormat::Shell;
+    }
+    args.output
+        .as_deref()
+        .unwrap_or("text")
+        .parse()
+        .unwrap_or_else(|e| fail(SlashcmdError::Config(e)))
+}
+
+/// Interactive confirmation unless piped, or asked for plain output
+#[cfg(feature = "tui")]
+fn wants_tui(args: &Args, output: OutputFormat) -> bool {
+    std::io::stdin().is_terminal()
+        && std::io::stdout().is_terminal()
+        && !args.non_interactive
+        && !args.quick
+        && !args.print_only
+        && output == OutputFormat::Text
+}
+
+/// Built without the TUI, everything is printed as with -n
+#[cfg(not(feature = "tui"))]
+fn wants_tui(_args: &Args, _output: OutputFormat) -> bool {
+    false
+}
+
+/// `--then` steps only run in the interactive flow; anywhere else they
+/// would be dropped without a word
+fn check_then(args: &Args, output: OutputFormat) {
+    if !args.then.is_empty() && !wants_tui(args, output) {
+        fail(SlashcmdError::Config(
+            "--then needs the interactive screen: a terminal, without --output or piped input".to_string(),
+        ));
     }
 }
 
 /// Run in edge mode - uses Cloudflare Worker proxy (requires login)
-fn run_edge_mode(args: &Args) {
+#[cfg(feature = "edge")]
+fn run_edge_mode(args: &Args, config: &Config, policy: &Policy) {
     if args.query.is_empty() {
         print_usage();
         std::process::exit(1);
     }
 
     // Check for auth token
-    let token = match auth::get_token() {
-        Some(t) => t,
-        None => {
-            eprintln!("Not logged in. Please run 'slashcmd login' first.");
-            eprintln!();
-            eprintln!("Or use --local flag with GROQ_API_KEY for direct API access.");
-            std::process::exit(1);
-        }
-    };
+    let token = require_token().unwrap_or_else(|e| fail(e));
 
     // Parse style
-    let default_style: ExplainStyle = args.style.parse().unwrap_or_else(|e| {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
-    });
+    let default_style = ExplainStyle::resolve(&args.style, &config.styles)
+        .unwrap_or_else(|e| fail(SlashcmdError::Config(e)));
 
-    let (query, style) = parse_style_from_query(&args.query, default_style);
+    let (query, style) = parse_style_from_query(&args.query, default_style, &config.styles);
+    let query = attach::expand(query);
+    let query = with_target_context(args, query);
+    let query = with_kube_context(args, query);
+    let query = with_cloud_context(args, query);
+    let query = with_coreutils_context(args, query);
+    let query = with_wsl_context(args, query);
+    let query = secrets::scrub(query, "query");
 
-    // Determine mode
-    let is_tty = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
-    let use_tui = is_tty && !args.non_interactive && !args.quick && !args.print_only;
+    let output = output_format(args);
+    // Mermaid source only makes sense for the diagram style
+    let style = if output == OutputFormat::Mermaid { ExplainStyle::Diagram } else { style };
+
+    let options = explain_options(args, config, style).unwrap_or_else(|e| fail(e));
+    // Compiled even when nothing checks them, so a broken rule is always fatal
+    let rules = safety_rules(config, policy);
+    let safety_badge = (args.safety_badge || config.safety_badge).then_some(&rules);
+    check_then(args, output);
+
+    #[cfg(feature = "tui")]
+    if wants_tui(args, output) {
+        offer_history_matches(args, config, policy, &query);
+        quota_preflight(1 + args.then.len() as i32);
 
-    if use_tui {
         // Interactive TUI mode with edge
-        match tui::run_interactive_edge_auth(query, token, style) {
-            Ok(tui::TuiResult::Execute(command)) => {
-                let status = Command::new("sh")
-                    .arg("-c")
-                    .arg(&command)
-                    .status();
-
-                match status {
-                    Ok(s) => std::process::exit(s.code().unwrap_or(0)),
-                    Err(e) => {
-                        eprintln!("Failed to execute: {}", e);
-                        std::process::exit(1);
-                    }
-                }
-            }
-            Ok(tui::TuiResult::Cancel) => {
-                std::process::exit(130);
-            }
-            Err(e) => {
-                eprintln!("Error: {}", e);
-                std::process::exit(1);
-            }
-        }
-    } else {
-        // Non-interactive mode with edge
-        if let Err(e) = cli::run_cli_edge_auth(query, token, style, args.quick) {
-            eprintln!("Error: {}", e);
-            std::process::exit(1);
-        }
+        let follow_up = Some(FollowUpSource::Edge { token: token.clone() });
+        run_tui_chain(args, query, follow_up, |query, previous| {
+            let result =
+                tui::run_interactive_edge_auth(query.clone(), token.clone(), options.clone(), &rules, previous);
+            #[cfg(feature = "groq")]
+            let result = result.or_else(|e| {
+                fail_over(e, config, policy, |groq_api_key, gemini_api_key| {
+                    tui::run_interactive(query, groq_api_key, gemini_api_key, options.clone(), &rules, previous)
+                })
+            });
+            result
+        });
+    }
+
+    // Non-interactive mode with edge
+    let result = cli::run_cli_edge_auth(query.clone(), token, options.clone(), args.quick, output, safety_badge);
+    #[cfg(feature = "groq")]
+    let result = result.or_else(|e| {
+        fail_over(e, config, policy, |groq_api_key, gemini_api_key| {
+            cli::run_cli(query, groq_api_key, gemini_api_key, options, args.quick, output, safety_badge)
+        })
+    });
+    if let Err(e) = result {
+        fail(e);
     }
 }
+
+/// After an edge outage, run the request again locally with GROQ_API_KEY
+/// (and GEMINI_API_KEY for the explanation), unless `failover = "never"`,
+/// the policy disables --local or there is no key. Otherwise the edge
+/// error stands.
+#[cfg(all(feature = "edge", feature = "groq"))]
+fn fail_over<T>(
+    error: SlashcmdError,
+    config: &Config,
+    policy: &Policy,
+    local: impl FnOnce(String, Option<String>) -> Result<T, SlashcmdError>,
+) -> Result<T, SlashcmdError> {
+    let groq_api_key = std::env::var("GROQ_API_KEY").ok().filter(|key| !key.is_empty());
+    let groq_api_key = match groq_api_key {
+        Some(key) if error.is_outage() && config.failover == Failover::Auto && !policy.disable_local => key,
+        _ => return Err(error),
+    };
+    tracing::debug!(error = %error, "edge failover");
+    eprintln!("{}", highlight::dim(&format!("Edge proxy unavailable ({}); using GROQ_API_KEY directly.", error)));
+    let gemini_api_key = std::env::var("GEMINI_API_KEY").ok().filter(|key| !key.is_empty());
+    local(groq_api_key, gemini_api_key)
+}
@@ -0,0 +1,403 @@
+//! MCP server mode
+//!
+//! `slashcmd serve --mcp` speaks the Model Context Protocol (JSON-RPC 2.0,
+//! one message per line) on stdin/stdout, so editors and agent frameworks
+//! can generate and explain commands without scraping terminal output.
+//! Generated commands carry the same safety classification as the TUI,
+//! including `[safety]` rules and the machine policy. Nothing is executed,
+//! and a command the policy blocks or the denylist refuses isn't returned:
+//! the calling agent would run it.
+
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+
+use crate::audit::{self, AuditEvent};
+use crate::budget;
+use crate::config::Config;
+#[cfg(feature = "edge")]
+use crate::edge::EdgeClient;
+use crate::error::SlashcmdError;
+#[cfg(all(feature = "groq", feature = "gemini"))]
+use crate::gemini::GeminiClient;
+#[cfg(feature = "groq")]
+use crate::groq::GroqClient;
+use crate::ipc::{ExplainOptions, ExplainStyle};
+use crate::logs::{self, LogEntry};
+use crate::policy::{SafetyRules, Verdict};
+use crate::prompt::{self, CommandResult};
+use crate::secrets;
+
+/// Protocol revisions we can speak, newest first
+const PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+const DEFAULT_SEARCH_LIMIT: usize = 10;
+
+/// Where commands and explanations come from
+pub enum Backend {
+    #[cfg(feature = "edge")]
+    Edge(EdgeClient),
+    #[cfg(feature = "groq")]
+    Local {
+        groq: GroqClient,
+        #[cfg(feature = "gemini")]
+        gemini: Option<GeminiClient>,
+    },
+}
+
+impl Backend {
+    /// A command for `query`, with its explanation if `explain` (and, in
+    /// local mode, a Gemini key)
+    pub fn generate(
+        &self,
+        query: &str,
+        options: &ExplainOptions,
+        explain: bool,
+    ) -> Result<(CommandResult, Option<String>), SlashcmdError> {
+        let query = &prompt::with_preferences(query);
+        match self {
+            #[cfg(feature = "edge")]
+            Backend::Edge(client) => {
+                let response = client.query_with_explanation(query, options)?;
+                Ok((response.command, response.explanation.filter(|_| explain)))
+            }
+            #[cfg(feature = "groq")]
+            Backend::Local { groq, .. } => {
+                let result = groq.query(query, &options.generation)?;
+                let explanation = if explain { self.local_explanation(&result.command, options)? } else { None };
+                Ok((result, explanation))
+            }
+        }
+    }
+
+    pub fn explain(&self, command: &str, options: &ExplainOptions) -> Result<String, SlashcmdError> {
+        match self {
+            #[cfg(feature = "edge")]
+            Backend::Edge(client) => client.explain(command, options),
+            #[cfg(feature = "groq")]
+            Backend::Local { .. } => self.local_explanation(command, options)?.ok_or_else(|| {
+                SlashcmdError::Config("Explanations need GEMINI_API_KEY in --local mode".to_string())
+            }),
+        }
+    }
+
+    /// Gemini's explanation in --local mode, None without a key
+    #[cfg(all(feature = "groq", feature = "gemini"))]
+    fn local_explanation(&self, command: &str, options: &ExplainOptions) -> Result<Option<String>, SlashcmdError> {
+        match self {
+            Backend::Local { gemini: Some(gemini), .. } => Ok(Some(gemini.explain(command, options)?)),
+            _ => Ok(None),
+        }
+    }
+
+    #[cfg(all(feature = "groq", not(feature = "gemini")))]
+    fn local_explanation(&self, _command: &str, _options: &ExplainOptions) -> Result<Option<String>, SlashcmdError> {
+        Ok(None)
+    }
+}
+
+pub struct Server<'a> {
+    backend: Backend,
+    config: &'a Config,
+    rules: SafetyRules,
+    /// Defaults from the command line; tools may override the style
+    options: ExplainOptions,
+}
+
+impl<'a> Server<'a> {
+    pub fn new(backend: Backend, config: &'a Config, rules: SafetyRules, options: ExplainOptions) -> Self {
+        Self {
+            backend,
+            config,
+            rules,
+            options,
+        }
+    }
+
+    /// Answer requests until stdin closes
+    pub fn run(&self) -> Result<(), String> {
+        let mut stdout = io::stdout().lock();
+        for line in io::stdin().lock().lines() {
+            let line = line.map_err(|e| format!("Failed to read stdin: {}", e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<Value>(&line) {
+                Ok(message) => self.handle(&message),
+                Err(e) => Some(error_response(Value::Null, PARSE_ERROR, &e.to_string())),
+            };
+            if let Some(response) = response {
+                writeln!(stdout, "{}", response).map_err(|e| format!("Failed to write stdout: {}", e))?;
+                stdout.flush().map_err(|e| format!("Failed to write stdout: {}", e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Response to one message; notifications (no id) get none
+    fn handle(&self, message: &Value) -> Option<Value> {
+        let id = message.get("id")?.clone();
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        tracing::debug!(method, "mcp request");
+
+        let result = match method {
+            "initialize" => {
+                let requested = params["protocolVersion"].as_str().unwrap_or_default();
+                let version = PROTOCOL_VERSIONS
+                    .iter()
+                    .find(|v| **v == requested)
+                    .unwrap_or(&PROTOCOL_VERSIONS[0]);
+                Ok(json!({
+                    "protocolVersion": version,
+                    "capabilities": { "tools": {} },
+                    "serverInfo": { "name": "slashcmd", "version": env!("CARGO_PKG_VERSION") },
+                }))
+            }
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": tools() })),
+            "tools/call" => self.call_tool(params["name"].as_str().unwrap_or_default(), &params["arguments"]),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+        };
+
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => error_response(id, code, &message),
+        })
+    }
+
+    /// Run a tool. Tool failures are results with `isError`, so the calling
+    /// model sees them; only an unknown tool is a protocol error.
+    fn call_tool(&self, name: &str, arguments: &Value) -> Result<Value, (i64, String)> {
+        let output = match name {
+            "generate_command" => self.generate_command(arguments),
+            "explain_command" => self.explain_command(arguments),
+            "search_history" => search_history(arguments),
+            _ => return Err((INVALID_PARAMS, format!("Unknown tool: {}", name))),
+        };
+        Ok(match output {
+            Ok(value) => json!({
+                "content": [{ "type": "text", "text": serde_json::to_string_pretty(&value).unwrap_or_default() }],
+                "structuredContent": value,
+                "isError": false,
+            }),
+            Err(message) => json!({
+                "content": [{ "type": "text", "text": message }],
+                "isError": true,
+            }),
+        })
+    }
+
+    fn generate_command(&self, arguments: &Value) -> Result<Value, String> {
+        let query = &secrets::scrub(required(arguments, "query")?.to_string(), "query");
+        let options = self.options_for(arguments)?;
+        let explain = arguments["explain"].as_bool().unwrap_or(true);
+
+        budget::enforce(&self.config.budget, 1, false)?;
+
+        let (result, explanation) = self.backend.generate(query, &options, explain).map_err(|e| e.to_string())?;
+        let verdict = self.rules.check(&result.command);
+        let refused = match verdict {
+            Verdict::Blocked => Some("blocked by policy"),
+            Verdict::Denied => Some("on the [safety] denylist"),
+            _ => None,
+        };
+        if let Some(reason) = refused {
+            return Err(format!("The generated command is {}, so it isn't returned.", reason));
+        }
+        let safety = verdict.label(Some(result.safe));
+        let entry = logs::create_entry(
+            query,
+            &result.command,
+            explanation.clone(),
+            &options.style,
+            result.provenance.clone(),
+        );
+        let _ = logs::save_log(&entry);
+        let _ = audit::record(AuditEvent::Generated, query, &result.command);
+
+        Ok(json!({
+            "command": result.command,
+            "safety": safety,
+            "explanation": explanation,
+        }))
+    }
+
+    fn explain_command(&self, arguments: &Value) -> Result<Value, String> {
+        let command = required(arguments, "command")?;
+        let options = self.options_for(arguments)?;
+
+        let explanation = self.backend.explain(command, &options).map_err(|e| e.to_string())?;
+        Ok(json!({
+            "explanation": explanation,
+            "safety": self.rules.check(command).label(None),
+        }))
+    }
+
+    /// The default options with the tool's `style`, if given
+    fn options_for(&self, arguments: &Value) -> Result<ExplainOptions, String> {
+        let mut options = self.options.clone();
+        if let Some(style) = arguments["style"].as_str() {
+            options.style = ExplainStyle::resolve(style, &self.config.styles)?;
+        }
+        Ok(options)
+    }
+}
+
+fn search_history(arguments: &Value) -> Result<Value, String> {
+    let query = required(arguments, "query")?;
+    let limit = arguments["limit"].as_u64().map_or(DEFAULT_SEARCH_LIMIT, |l| l as usize);
+    let entries = logs::recent_entries(usize::MAX);
+    let matches: Vec<Value> = search(&entries, query, limit)
+        .into_iter()
+        .map(|entry| {
+            json!({
+                "timestamp": entry.timestamp,
+                "query": entry.query,
+                "command": entry.command,
+                "exit_code": entry.exit_code,
+            })
+        })
+        .collect();
+    Ok(json!({ "matches": matches }))
+}
+
+/// Entries (newest first) whose query or command contains every word
+fn search<'e>(entries: &'e [LogEntry], query: &str, limit: usize) -> Vec<&'e LogEntry> {
+    let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    entries
+        .iter()
+        .filter(|entry| {
+            let text = format!("{}\n{}", entry.query, entry.command).to_lowercase();
+            words.iter().all(|word| text.contains(word.as_str()))
+        })
+        .take(limit)
+        .collect()
+}
+
+fn required<'v>(arguments: &'v Value, name: &str) -> Result<&'v str, String> {
+    arguments[name]
+        .as_str()
+        .filter(|value| !value.trim().is_empty())
+        .ok_or_else(|| format!("Missing required argument '{}'", name))
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn tools() -> Value {
+    let style = json!({
+        "type": "string",
+        "description": "Explanation style: typescript, python, ruby, rust, go, human, annotated, diagram, or a custom style",
+    });
+    json!([
+        {
+            "name": "generate_command",
+            "description": "Turn a natural language request into a shell command. Returns the command, \
+                a safety rating (safe, caution, confirm, danger, blocked) and an explanation. \
+                The command is not executed.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "What the command should do" },
+                    "style": style,
+                    "explain": { "type": "boolean", "description": "Include an explanation (default true)" },
+                },
+                "required": ["query"],
+            },
+        },
+        {
+            "name": "explain_command",
+            "description": "Explain what a shell command does, with a safety rating from local rules.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "command": { "type": "string", "description": "The shell command" },
+                    "style": style,
+                },
+                "required": ["command"],
+            },
+        },
+        {
+            "name": "search_history",
+            "description": "Search previously generated commands by words in the request or command.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "Words that must all appear" },
+                    "limit": { "type": "integer", "description": "Maximum results (default 10)" },
+                },
+                "required": ["query"],
+            },
+        },
+    ])
+}
+
+#[cfg(all(test, feature = "edge"))]
+mod tests {
+    use super::*;
+
+    fn with_server(test: impl FnOnce(&Server)) {
+        let config = Config::default();
+        let server = Server::new(
+            Backend::Edge(EdgeClient::with_test_jwt()),
+            &config,
+            SafetyRules::default(),
+            ExplainOptions {
+                style: ExplainStyle::Human,
+                verbosity: Default::default(),
+                lang: None,
+                generation: Default::default(),
+            },
+        );
+        test(&server);
+    }
+
+    #[test]
+    fn test_handle_protocol_messages() {
+        with_server(|server| {
+            let init = server
+                .handle(&json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "protocolVersion": "2024-11-05" } }))
+                .unwrap();
+            assert_eq!(init["result"]["protocolVersion"], "2024-11-05");
+            assert_eq!(init["result"]["serverInfo"]["name"], "slashcmd");
+
+            assert!(server.handle(&json!({ "jsonrpc": "2.0", "method": "notifications/initialized" })).is_none());
+
+            let list = server.handle(&json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" })).unwrap();
+            let names: Vec<&str> = list["result"]["tools"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter_map(|tool| tool["name"].as_str())
+                .collect();
+            assert_eq!(names, ["generate_command", "explain_command", "search_history"]);
+
+            let unknown = server.handle(&json!({ "jsonrpc": "2.0", "id": 3, "method": "resources/list" })).unwrap();
+            assert_eq!(unknown["error"]["code"], METHOD_NOT_FOUND);
+
+            let missing = server
+                .handle(&json!({ "jsonrpc": "2.0", "id": 4, "method": "tools/call", "params": { "name": "generate_command", "arguments": {} } }))
+                .unwrap();
+            assert_eq!(missing["result"]["isError"], true);
+        });
+    }
+
+    #[test]
+    fn test_search_matches_every_word() {
+        let entry = |query: &str, command: &str| logs::create_entry(query, command, None, &ExplainStyle::Human, None);
+        let entries = [
+            entry("list docker images", "docker images"),
+            entry("remove dangling images", "docker image prune"),
+            entry("largest files", "du -ah . | sort -rh | head"),
+        ];
+        let commands = |found: Vec<&LogEntry>| found.iter().map(|e| e.command.clone()).collect::<Vec<_>>();
+        assert_eq!(commands(search(&entries, "Docker images", 10)), ["docker images", "docker image prune"]);
+        assert_eq!(commands(search(&entries, "docker prune", 10)), ["docker image prune"]);
+        assert_eq!(search(&entries, "docker", 1).len(), 1);
+    }
+}
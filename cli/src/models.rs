@@ -0,0 +1,86 @@
+//! `slashcmd models`: what the provider offers
+//!
+//! In --local mode Groq models generate commands and Gemini models explain
+//! them; in edge mode the worker says what it runs. Each model is listed
+//! with the role it can fill for slashcmd and whether it's the one in use.
+
+use serde::{Deserialize, Serialize};
+
+use crate::highlight::dim;
+
+/// What slashcmd can use a model for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Command,
+    Explanation,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Model {
+    /// groq, gemini or edge
+    #[serde(default)]
+    pub provider: String,
+    pub id: String,
+    /// Empty for models slashcmd can't use (audio, embeddings, moderation)
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    #[serde(default)]
+    pub in_use: bool,
+}
+
+/// Groq also serves speech and moderation models, which can't write commands
+pub fn groq_can_generate(id: &str) -> bool {
+    const NOT_CHAT: &[&str] = &["whisper", "tts", "guard", "playai", "orpheus"];
+    let id = id.to_lowercase();
+    !NOT_CHAT.iter().any(|word| id.contains(word))
+}
+
+/// Print models grouped by provider, the ones in use first
+pub fn print(mut models: Vec<Model>, json: bool) {
+    models.sort_by(|a, b| {
+        (&a.provider, !a.in_use, a.roles.is_empty(), &a.id).cmp(&(&b.provider, !b.in_use, b.roles.is_empty(), &b.id))
+    });
+    if json {
+        println!("{}", serde_json::to_string(&models).unwrap_or_default());
+        return;
+    }
+
+    let width = models.iter().map(|m| m.id.len()).max().unwrap_or(0);
+    let mut provider = "";
+    for model in &models {
+        if model.provider != provider {
+            provider = &model.provider;
+            println!("{}:", provider);
+        }
+        let roles: Vec<&str> = model
+            .roles
+            .iter()
+            .map(|role| match role {
+                Role::Command => "commands",
+                Role::Explanation => "explanations",
+            })
+            .collect();
+        let marker = if model.in_use { "*" } else { " " };
+        if roles.is_empty() {
+            println!("{} {}", marker, dim(&format!("{:<width$}  not usable", model.id)));
+        } else {
+            println!("{} {:<width$}  {}", marker, model.id, roles.join(", "));
+        }
+    }
+    println!("\n{}", dim("* in use"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_groq_can_generate() {
+        assert!(groq_can_generate("moonshotai/kimi-k2-instruct-0905"));
+        assert!(groq_can_generate("llama-3.3-70b-versatile"));
+        assert!(!groq_can_generate("whisper-large-v3-turbo"));
+        assert!(!groq_can_generate("meta-llama/llama-guard-4-12b"));
+        assert!(!groq_can_generate("playai-tts"));
+    }
+}
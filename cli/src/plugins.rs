@@ -0,0 +1,154 @@
+//! External plugins (`slashcmd foo ...` runs `slashcmd-foo`)
+//!
+//! Like git, a first word that isn't a built-in command and names an
+//! executable `slashcmd-<word>` on PATH hands the request to that program,
+//! so new targets (SQL, jq filters, ...) can live outside this repo.
+//!
+//! The handshake is one JSON object each way. The plugin gets the rest of
+//! the arguments as its argv, `SLASHCMD_PLUGIN_PROTOCOL` in its environment
+//! and a `Request` on stdin:
+//!
+//! ```json
+//! {"protocol": 1, "query": "top 5 customers by revenue", "args": ["top", "5", ...],
+//!  "os": "linux", "shell": "sh", "cwd": "/home/me/shop"}
+//! ```
+//!
+//! and prints a `Response` on stdout, then exits 0:
+//!
+//! ```json
+//! {"command": "psql -c '...'", "explanation": "Sums orders per customer", "safe": true}
+//! ```
+//!
+//! Every field of the response is optional. Without `command` only the
+//! explanation is printed; `error` reports a failure the plugin understood.
+//! The command is shown, checked against the safety rules and confirmed
+//! like a generated one; `safe` only says the plugin believes it is
+//! read-only. Stderr is the user's terminal, for progress or questions.
+
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::exec::find_in_path;
+use crate::shell;
+
+/// Bumped only for changes existing plugins can't ignore
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Executable name prefix
+const PREFIX: &str = "slashcmd-";
+
+#[derive(Serialize, Debug)]
+pub struct Request<'a> {
+    pub protocol: u32,
+    /// The arguments joined with spaces
+    pub query: String,
+    pub args: &'a [String],
+    pub os: &'static str,
+    /// Shell the command should be written for (`--shell`)
+    pub shell: &'static str,
+    pub cwd: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Response {
+    pub command: Option<String>,
+    pub explanation: Option<String>,
+    /// The plugin's own judgement; the safety rules still apply
+    pub safe: bool,
+    pub error: Option<String>,
+}
+
+/// The plugin for `name`, if one is installed. Names that couldn't be a
+/// subcommand (spaces, paths, options) are never looked up.
+pub fn find(name: &str) -> Option<PathBuf> {
+    let valid = !name.is_empty()
+        && !name.starts_with('-')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return None;
+    }
+    let program = format!("{}{}{}", PREFIX, name, std::env::consts::EXE_SUFFIX);
+    find_in_path(&program)
+}
+
+/// Send `args` to the plugin at `path` and read its answer
+pub fn run(path: &Path, args: &[String]) -> Result<Response, String> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let request = Request {
+        protocol: PROTOCOL_VERSION,
+        query: args.join(" "),
+        args,
+        os: std::env::consts::OS,
+        shell: shell::target().name(),
+        cwd: std::env::current_dir()
+            .map(|d| d.display().to_string())
+            .unwrap_or_default(),
+    };
+    let json = serde_json::to_string(&request).map_err(|e| format!("Failed to encode request: {}", e))?;
+
+    let mut child = Command::new(path)
+        .args(args)
+        .env("SLASHCMD_PLUGIN_PROTOCOL", PROTOCOL_VERSION.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", name, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A plugin that doesn't read its stdin closes the pipe early
+        let _ = stdin.write_all(json.as_bytes()).and_then(|_| stdin.write_all(b"\n"));
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to run {}: {}", name, e))?;
+    if !output.status.success() {
+        return Err(format!("{} failed ({})", name, output.status));
+    }
+    parse_response(&String::from_utf8_lossy(&output.stdout)).map_err(|e| format!("{}: {}", name, e))
+}
+
+fn parse_response(stdout: &str) -> Result<Response, String> {
+    let response: Response =
+        serde_json::from_str(stdout.trim()).map_err(|e| format!("not a plugin response: {}", e))?;
+    if let Some(error) = response.error {
+        return Err(error);
+    }
+    if response.command.is_none() && response.explanation.is_none() {
+        return Err("empty response".to_string());
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response_and_names() {
+        let response = parse_response(r#"{"command": "jq '.items[]' data.json", "safe": true}"#).unwrap();
+        assert_eq!(response.command.as_deref(), Some("jq '.items[]' data.json"));
+        assert!(response.safe);
+        assert!(response.explanation.is_none());
+
+        // Unknown fields are ignored, for later protocol additions
+        let response = parse_response("{\"explanation\": \"Nothing to run\", \"extra\": 1}\n").unwrap();
+        assert_eq!(response.explanation.as_deref(), Some("Nothing to run"));
+
+        assert_eq!(
+            parse_response(r#"{"error": "no database configured"}"#).unwrap_err(),
+            "no database configured"
+        );
+        assert!(parse_response("{}").is_err());
+        assert!(parse_response("SELECT 1").is_err());
+
+        for name in ["", "-v", "../evil", "a b", "sql/x"] {
+            assert!(find(name).is_none(), "{:?}", name);
+        }
+    }
+}
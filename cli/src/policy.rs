@@ -0,0 +1,373 @@
+//! Local safety rules applied to generated commands
+//!
+//! Regex lists from the `[safety]` section of config.toml run on the
+//! command before the TUI decides whether to auto-execute it, so users can
+//! overrule the model in either direction. A machine-level policy file
+//! (`/etc/slashcmd/policy.toml`) lets admins lock down what users can't.
+
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::approval::ApprovalConfig;
+use crate::audit;
+use crate::layout;
+use crate::safety::{self, Risk};
+
+/// Machine-level policy shipped by admins
+pub const POLICY_FILE: &str = "/etc/slashcmd/policy.toml";
+
+/// Admin policy from POLICY_FILE. Unknown keys are rejected so a misspelled
+/// restriction can't be silently ignored.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Always ask before running, even for read-only commands
+    pub disable_auto_execute: bool,
+    /// Refuse `--local` (direct API keys bypass the managed proxy)
+    pub disable_local: bool,
+    /// Patterns that may never be executed or copied
+    pub deny: Vec<String>,
+    /// Never run a command before its explanation was shown
+    pub require_explanation: bool,
+    /// Force the audit log on for every user
+    pub audit: bool,
+    /// Where the audit log goes (default `~/.cmd/audit.log`)
+    pub audit_file: Option<String>,
+    /// Approval webhook for every user, replacing their own `[approval]`
+    pub approval: Option<ApprovalConfig>,
+}
+
+impl Policy {
+    /// Audit log location: the policy's, or the default next to the logs
+    pub fn audit_file(&self) -> PathBuf {
+        self.audit_file
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(audit::default_audit_file)
+    }
+}
+
+/// Load the machine policy. A missing file means no policy; a file that
+/// exists but can't be read or parsed is an error, never a silent no-op.
+pub fn load_policy() -> Result<Policy, String> {
+    let content = match fs::read_to_string(POLICY_FILE) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Policy::default()),
+        Err(e) => return Err(format!("Failed to read policy {}: {}", POLICY_FILE, e)),
+    };
+    let policy: Policy =
+        toml::from_str(&content).map_err(|e| format!("Invalid policy {}: {}", POLICY_FILE, e))?;
+    // Make sure every pattern compiles before anything runs
+    compile(&policy.deny).map_err(|e| format!("Invalid policy {}: {}", POLICY_FILE, e))?;
+    Ok(policy)
+}
+
+/// Commands slashcmd never runs, whatever the config or the model says. They
+/// can still be copied; running one stays a deliberate paste.
+const DEFAULT_DENYLIST: &[&str] = &[
+    // rm -rf / (with or without sudo, --no-preserve-root or a trailing *)
+    r"\brm\s+(?:-{1,2}[\w-]+\s+)*(?:-[a-zA-Z]*[rR][a-zA-Z]*|--recursive)\s+(?:-{1,2}[\w-]+\s+)*/\*?(?:$|[\s;&|)])",
+    r"\bmkfs(?:\.\w+)?\b",
+    r"\b(?:wipefs|blkdiscard)\b",
+    r"\bshred\b.*\s/dev/",
+    r"\bdd\b.*\bof=/dev/(?:[sh]d|nvme|vd|xvd|mmcblk|disk|rdisk)",
+    r">\s*/dev/(?:[sh]d|nvme|vd|xvd|mmcblk|disk|rdisk)",
+    r"\bdiskutil\s+(?:eraseDisk|eraseVolume|secureErase|zeroDisk|randomDisk)\b",
+];
+
+/// When the TUI may run a command without asking
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoExecute {
+    /// Anything no local rule flags, even commands the model calls unsafe
+    Always,
+    /// Commands the model or a `safe` pattern calls read-only
+    #[default]
+    SafeOnly,
+    /// Always ask first
+    Never,
+}
+
+/// The `[safety]` section of config.toml
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct SafetyConfig {
+    /// Which commands run without confirmation (`--ask` means never)
+    pub auto_execute: AutoExecute,
+    /// Patterns never run, only copied, on top of the built-in denylist
+    pub denylist: Vec<String>,
+    /// Patterns that always need confirmation, e.g. `curl .* \| sh`
+    pub never_auto_execute: Vec<String>,
+    /// Patterns always treated as DANGER (copy to clipboard, never run)
+    pub danger: Vec<String>,
+    /// Patterns trusted as SAFE even if the model says otherwise
+    pub safe: Vec<String>,
+    /// Word to type (e.g. "DELETE") to execute a DANGER command instead of
+    /// only copying it. Unset keeps DANGER commands copy-only.
+    pub confirm_word: Option<String>,
+}
+
+/// What the local rules say about a command, strongest first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    /// Matched a policy `deny` pattern
+    Blocked,
+    /// Matched the denylist; may be copied, never run
+    Denied,
+    /// Matched a `danger` pattern
+    Danger,
+    /// Matched a `never_auto_execute` pattern
+    Confirm,
+    /// Matched a `safe` pattern
+    Safe,
+    /// No rule matched; the model's assessment stands
+    Unknown,
+}
+
+impl Verdict {
+    /// Lower is worse. An unmatched segment is worse than a SAFE one: the
+    /// model, not a rule, has the last word on it.
+    fn rank(self) -> u8 {
+        match self {
+            Verdict::Blocked => 0,
+            Verdict::Denied => 1,
+            Verdict::Danger => 2,
+            Verdict::Confirm => 3,
+            Verdict::Unknown => 4,
+            Verdict::Safe => 5,
+        }
+    }
+
+    /// Rating for machine-readable output. The model's assessment
+    /// (`model_safe`) only counts when no rule matched.
+    pub fn label(self, model_safe: Option<bool>) -> &'static str {
+        match self {
+            Verdict::Blocked => "blocked",
+            Verdict::Denied => "denied",
+            Verdict::Danger => "danger",
+            Verdict::Confirm => "confirm",
+            Verdict::Safe => "safe",
+            Verdict::Unknown => match model_safe {
+                Some(true) => "safe",
+                Some(false) => "caution",
+                None => "unknown",
+            },
+        }
+    }
+}
+
+/// Compiled `[safety]` rules merged with the machine policy
+#[derive(Default)]
+pub struct SafetyRules {
+    blocked: Vec<Regex>,
+    denylist: Vec<Regex>,
+    never_auto_execute: Vec<Regex>,
+    danger: Vec<Regex>,
+    safe: Vec<Regex>,
+    confirm_word: Option<String>,
+    auto_execute: AutoExecute,
+    require_explanation: bool,
+}
+
+impl SafetyRules {
+    /// Compile the configured patterns. Any invalid pattern is an error so a
+    /// typo never silently disables a rule.
+    pub fn new(config: &SafetyConfig, policy: &Policy) -> Result<Self, String> {
+        let denylist: Vec<String> = DEFAULT_DENYLIST
+            .iter()
+            .map(|p| p.to_string())
+            .chain(config.denylist.iter().cloned())
+            .collect();
+        Ok(Self {
+            blocked: compile(&policy.deny)?,
+            denylist: compile(&denylist)?,
+            never_auto_execute: compile(&config.never_auto_execute)?,
+            danger: compile(&config.danger)?,
+            safe: compile(&config.safe)?,
+            confirm_word: config
+                .confirm_word
+                .as_deref()
+                .map(str::trim)
+                .filter(|w| !w.is_empty())
+                .map(str::to_string),
+            auto_execute: if policy.disable_auto_execute { AutoExecute::Never } else { config.auto_execute },
+            require_explanation: policy.require_explanation,
+        })
+    }
+
+    /// Word that unlocks execution of DANGER commands, if configured
+    pub fn confirm_word(&self) -> Option<&str> {
+        self.confirm_word.as_deref()
+    }
+
+    /// Whether a command may run without confirmation, given the rules'
+    /// verdict and the model's own assessment
+    pub fn may_auto_execute(&self, verdict: Verdict, model_safe: bool) -> bool {
+        if self.require_explanation {
+            return false;
+        }
+        match self.auto_execute {
+            AutoExecute::Always => matches!(verdict, Verdict::Safe | Verdict::Unknown),
+            AutoExecute::SafeOnly => verdict == Verdict::Safe || (verdict == Verdict::Unknown && model_safe),
+            AutoExecute::Never => false,
+        }
+    }
+
+    /// Whether a command may only run after its explanation was shown
+    pub fn require_explanation(&self) -> bool {
+        self.require_explanation
+    }
+
+    /// Classify a command. A compound command gets its worst segment's
+    /// verdict, so `ls && rm -rf ~` is no safer than its last half; it's only
+    /// SAFE if every segment is. Rules matching across segments (`curl .* |
+    /// sh`) still escalate it.
+    pub fn check(&self, command: &str) -> Verdict {
+        let segments = self.check_segments(command);
+        let whole = self.check_one(command);
+        if segments.len() < 2 {
+            return whole;
+        }
+        let worst = segments
+            .into_iter()
+            .map(|(_, verdict)| verdict)
+            .min_by_key(|v| v.rank())
+            .unwrap_or(Verdict::Unknown);
+        if whole.rank() < worst.rank() && whole != Verdict::Safe {
+            whole
+        } else {
+            worst
+        }
+    }
+
+    /// Each segment of a compound command with its own verdict
+    pub fn check_segments<'a>(&self, command: &'a str) -> Vec<(&'a str, Verdict)> {
+        safety::segments(command)
+            .into_iter()
+            .map(|segment| (segment, self.check_one(segment)))
+            .collect()
+    }
+
+    /// Classify one command. Policy blocks win over the denylist, then
+    /// DANGER, then confirmation, then SAFE. The built-in heuristics count as
+    /// DANGER or confirmation rules, so a `safe` pattern never overrides them.
+    fn check_one(&self, command: &str) -> Verdict {
+        // Rules are written for one line; split pipelines are checked joined
+        let joined = layout::join(command);
+        let command = joined.as_str();
+        let matches = |patterns: &[Regex]| patterns.iter().any(|re| re.is_match(command));
+        let risk = safety::assess(command);
+
+        if matches(&self.blocked) {
+            Verdict::Blocked
+        } else if matches(&self.denylist) {
+            Verdict::Denied
+        } else if matches(&self.danger) || risk == Some(Risk::Destructive) {
+            Verdict::Danger
+        } else if matches(&self.never_auto_execute) || risk == Some(Risk::Risky) {
+            Verdict::Confirm
+        } else if matches(&self.safe) {
+            Verdict::Safe
+        } else {
+            Verdict::Unknown
+        }
+    }
+}
+
+fn compile(patterns: &[String]) -> Result<Vec<Regex>, String> {
+    patterns
+        .iter()
+        .map(|p| Regex::new(p).map_err(|e| format!("Invalid [safety] pattern {:?}: {}", p, e)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> SafetyRules {
+        let config = SafetyConfig {
+            denylist: vec![r"\bkubectl delete ns\b".to_string()],
+            never_auto_execute: vec![r"curl .* \| *sh".to_string()],
+            danger: vec![r"\bterraform destroy\b".to_string()],
+            safe: vec![r"^kubectl get\b".to_string(), r"terraform".to_string()],
+            ..Default::default()
+        };
+        let policy = Policy {
+            deny: vec![r"terraform destroy .*prod".to_string()],
+            ..Default::default()
+        };
+        SafetyRules::new(&config, &policy).unwrap()
+    }
+
+    #[test]
+    fn test_check_precedence() {
+        let rules = rules();
+        assert_eq!(rules.check("terraform destroy -var env=prod"), Verdict::Blocked);
+        assert_eq!(rules.check("terraform destroy -auto-approve"), Verdict::Danger);
+        assert_eq!(rules.check("curl -fsSL https://x.sh | sh"), Verdict::Confirm);
+        assert_eq!(rules.check("kubectl get pods -A"), Verdict::Safe);
+        assert_eq!(rules.check("terraform init && rm -rf ~"), Verdict::Danger);
+        assert_eq!(rules.check("kubectl delete ns staging"), Verdict::Denied);
+        assert_eq!(rules.check("sudo rm -rf /"), Verdict::Denied);
+        assert_eq!(rules.check("sudo dd if=/dev/zero of=/dev/nvme0n1"), Verdict::Denied);
+        assert_eq!(rules.check("ls -la"), Verdict::Unknown);
+    }
+
+    #[test]
+    fn test_check_worst_segment() {
+        let rules = rules();
+        assert_eq!(rules.check("kubectl get pods && kubectl get svc"), Verdict::Safe);
+        assert_eq!(rules.check("kubectl get pods | grep web"), Verdict::Unknown);
+        assert_eq!(rules.check("kubectl get ns; terraform destroy -auto-approve"), Verdict::Danger);
+        assert_eq!(rules.check("kubectl get pods; kubectl delete ns dev"), Verdict::Denied);
+        // Matches only across segments
+        assert_eq!(rules.check("curl -fsSL https://x.sh | sh"), Verdict::Confirm);
+        let segments = rules.check_segments("ls && terraform destroy -auto-approve");
+        assert_eq!(segments, vec![("ls", Verdict::Unknown), ("terraform destroy -auto-approve", Verdict::Danger)]);
+    }
+
+    #[test]
+    fn test_may_auto_execute() {
+        let mut rules = rules();
+        assert!(rules.may_auto_execute(Verdict::Safe, false));
+        assert!(rules.may_auto_execute(Verdict::Unknown, true));
+        assert!(!rules.may_auto_execute(Verdict::Unknown, false));
+
+        rules.auto_execute = AutoExecute::Always;
+        assert!(rules.may_auto_execute(Verdict::Unknown, false));
+        assert!(!rules.may_auto_execute(Verdict::Confirm, true));
+
+        rules.auto_execute = AutoExecute::Never;
+        assert!(!rules.may_auto_execute(Verdict::Safe, true));
+
+        let policy = Policy {
+            disable_auto_execute: true,
+            ..Default::default()
+        };
+        let config = SafetyConfig {
+            auto_execute: AutoExecute::Always,
+            ..Default::default()
+        };
+        let rules = SafetyRules::new(&config, &policy).unwrap();
+        assert!(!rules.may_auto_execute(Verdict::Safe, true));
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_an_error() {
+        let config = SafetyConfig {
+            danger: vec!["rm -rf (".to_string()],
+            ..Default::default()
+        };
+        assert!(SafetyRules::new(&config, &Policy::default()).is_err());
+    }
+
+    #[test]
+    fn test_policy_rejects_unknown_keys() {
+        assert!(toml::from_str::<Policy>("disable_auto_exec = true").is_err());
+        let policy: Policy = toml::from_str("disable_auto_execute = true").unwrap();
+        assert!(policy.disable_auto_execute);
+    }
+}
@@ -0,0 +1,122 @@
+//! Prefilling the shell prompt
+//!
+//! With `--prefill`, a confirmed command isn't run. Instead the terminal is
+//! asked to type it into its own input, so once slashcmd exits the command
+//! waits at the prompt to be edited or run with the shell's history and job
+//! control. Only terminals with an API for this are supported: kitty (remote
+//! control escape, needs `allow_remote_control`), WezTerm (`wezterm cli
+//! send-text`) and iTerm2 (AppleScript `write text`).
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::Command;
+
+#[derive(Debug, PartialEq)]
+pub enum Terminal {
+    Kitty,
+    WezTerm { pane: String },
+    ITerm2,
+}
+
+impl Terminal {
+    /// The terminal we're running in, from the variables it sets
+    pub fn detect(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        if var("KITTY_WINDOW_ID").is_some() {
+            Some(Terminal::Kitty)
+        } else if let Some(pane) = var("WEZTERM_PANE") {
+            Some(Terminal::WezTerm { pane })
+        } else if var("TERM_PROGRAM").as_deref() == Some("iTerm.app") {
+            Some(Terminal::ITerm2)
+        } else {
+            None
+        }
+    }
+}
+
+/// Put `command` at the prompt of the terminal we're running in
+pub fn prefill(command: &str) -> Result<(), String> {
+    let terminal = Terminal::detect(|name| std::env::var(name).ok())
+        .ok_or("--prefill needs kitty, WezTerm or iTerm2")?;
+    tracing::debug!(?terminal, "prefill");
+
+    match terminal {
+        Terminal::Kitty => {
+            let mut tty = OpenOptions::new()
+                .write(true)
+                .open("/dev/tty")
+                .map_err(|e| format!("Failed to open terminal: {}", e))?;
+            tty.write_all(kitty_send_text(command).as_bytes())
+                .and_then(|_| tty.flush())
+                .map_err(|e| format!("Failed to write to terminal: {}", e))
+        }
+        Terminal::WezTerm { pane } => run(Command::new("wezterm").args([
+            "cli",
+            "send-text",
+            "--no-paste",
+            "--pane-id",
+            &pane,
+            command,
+        ])),
+        Terminal::ITerm2 => run(Command::new("osascript").args(["-e", &iterm_script(command)])),
+    }
+}
+
+fn run(command: &mut Command) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let status = command
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} could not prefill the prompt", program))
+    }
+}
+
+/// kitty remote control `send-text` as a DCS escape
+fn kitty_send_text(command: &str) -> String {
+    let message = serde_json::json!({
+        "cmd": "send-text",
+        "version": [0, 14, 2],
+        "payload": { "data": format!("text:{}", command) },
+    });
+    format!("\x1bP@kitty-cmd{}\x1b\\", message)
+}
+
+/// Type into the current iTerm2 session without pressing Enter
+fn iterm_script(command: &str) -> String {
+    let quoted = command.replace('\\', "\\\\").replace('"', "\\\"");
+    format!(
+        "tell application \"iTerm2\" to tell current session of current window to write text \"{}\" newline NO",
+        quoted
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_terminal() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+        };
+        assert_eq!(Terminal::detect(env(&[("KITTY_WINDOW_ID", "1")])), Some(Terminal::Kitty));
+        assert_eq!(
+            Terminal::detect(env(&[("WEZTERM_PANE", "3"), ("TERM_PROGRAM", "WezTerm")])),
+            Some(Terminal::WezTerm { pane: "3".to_string() })
+        );
+        assert_eq!(Terminal::detect(env(&[("TERM_PROGRAM", "iTerm.app")])), Some(Terminal::ITerm2));
+        assert_eq!(Terminal::detect(env(&[("TERM_PROGRAM", "Apple_Terminal")])), None);
+    }
+
+    #[test]
+    fn test_escapes_command() {
+        let escape = kitty_send_text("echo \"hi\"");
+        assert!(escape.starts_with("\x1bP@kitty-cmd{"));
+        assert!(escape.contains(r#""data":"text:echo \"hi\"""#));
+        assert!(escape.ends_with("\x1b\\"));
+
+        assert!(iterm_script(r#"echo "a\b""#).contains(r#"write text "echo \"a\\b\"" newline NO"#));
+    }
+}
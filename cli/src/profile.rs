@@ -0,0 +1,183 @@
+//! Tool preferences learned from shell history
+//!
+//! `slashcmd import-history` reads the user's shell history (after asking)
+//! and counts which of several interchangeable tools they actually run: rg
+//! or grep, eza or ls, podman or docker. Only the tools that beat the
+//! standard one are kept, in `~/.config/slashcmd/profile.json`, and added
+//! to every prompt. The commands themselves are neither stored nor sent.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::config_dir;
+use crate::logs;
+
+/// Interchangeable tools by purpose, the standard one last
+const ALTERNATIVES: &[(&str, &[&str])] = &[
+    ("to search file contents", &["rg", "ag", "ack", "grep"]),
+    ("to list files", &["eza", "exa", "lsd", "ls"]),
+    ("to find files", &["fd", "fdfind", "find"]),
+    ("to show files", &["bat", "batcat", "cat"]),
+    ("for disk usage", &["dust", "gdu", "ncdu", "du"]),
+    ("to replace text", &["sd", "sed"]),
+    ("for diffs", &["delta", "difft", "diff"]),
+    ("for processes", &["procs", "ps"]),
+    ("for containers", &["podman", "nerdctl", "docker"]),
+    ("for HTTP requests", &["xh", "http", "wget", "curl"]),
+    ("for JavaScript packages", &["pnpm", "yarn", "bun", "npm"]),
+    ("for Python packages", &["uv", "poetry", "pipx", "pip"]),
+    ("to edit files", &["nvim", "vim", "hx", "emacs", "micro", "nano"]),
+];
+
+/// Fewer uses than this is trying a tool out, not preferring it
+const MIN_USES: usize = 3;
+
+/// Words that run the word after them (`sudo rg`, `xargs rg`)
+pub const WRAPPERS: &[&str] = &["sudo", "doas", "command", "builtin", "exec", "time", "nohup", "nice", "xargs", "env"];
+
+/// A tool the user runs instead of the standard one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Preference {
+    pub tool: String,
+    pub instead_of: String,
+    /// "to search file contents"
+    pub purpose: String,
+    pub uses: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Profile {
+    /// Unix time of the import
+    pub imported: u64,
+    /// How many history entries it was learned from
+    pub commands: usize,
+    pub preferences: Vec<Preference>,
+}
+
+impl Profile {
+    /// Learn preferences from history entries
+    pub fn build(commands: &[String]) -> Self {
+        let counts = tool_counts(commands);
+        let preferences = ALTERNATIVES
+            .iter()
+            .filter_map(|(purpose, tools)| {
+                let standard = tools.last()?;
+                let (tool, uses) = tools
+                    .iter()
+                    .map(|tool| (*tool, counts.get(*tool).copied().unwrap_or(0)))
+                    // On a tie the standard tool (last) wins
+                    .max_by_key(|(_, uses)| *uses)?;
+                (tool != *standard && uses >= MIN_USES).then(|| Preference {
+                    tool: tool.to_string(),
+                    instead_of: standard.to_string(),
+                    purpose: purpose.to_string(),
+                    uses,
+                })
+            })
+            .collect();
+        Profile {
+            imported: logs::now(),
+            commands: commands.len(),
+            preferences,
+        }
+    }
+
+    /// Context for the model. None when the user runs the standard tools.
+    pub fn prompt(&self) -> Option<String> {
+        if self.preferences.is_empty() {
+            return None;
+        }
+        let tools: Vec<String> = self
+            .preferences
+            .iter()
+            .map(|p| format!("{} (not {}) {}", p.tool, p.instead_of, p.purpose))
+            .collect();
+        Some(format!(
+            "This user's usual tools, from their shell history: {}. Use them where they fit.",
+            tools.join(", ")
+        ))
+    }
+}
+
+/// Get the profile file path
+pub fn profile_file() -> PathBuf {
+    config_dir().join("profile.json")
+}
+
+/// The imported profile, if there is one
+pub fn load() -> Option<Profile> {
+    let content = fs::read_to_string(profile_file()).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Save the profile
+pub fn store(profile: &Profile) -> Result<(), String> {
+    let path = profile_file();
+    fs::create_dir_all(config_dir()).map_err(|e| format!("Failed to create config dir: {}", e))?;
+    let json = serde_json::to_string_pretty(profile).map_err(|e| format!("Failed to encode profile: {}", e))?;
+    fs::write(&path, json).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Forget the profile. Returns whether there was one.
+pub fn clear() -> Result<bool, String> {
+    let path = profile_file();
+    match fs::remove_file(&path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(format!("Failed to remove {}: {}", path.display(), e)),
+    }
+}
+
+/// How often each program is run, counting every stage of a pipeline
+fn tool_counts(commands: &[String]) -> HashMap<String, usize> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for command in commands {
+        for stage in command.split(['|', ';', '&', '\n', '(', '`']) {
+            let program = stage
+                .split_whitespace()
+                .find(|word| !WRAPPERS.contains(word) && !word.starts_with('-') && !word.contains('='));
+            if let Some(program) = program {
+                let name = program.rsplit('/').next().unwrap_or(program);
+                *counts.entry(name.to_string()).or_default() += 1;
+            }
+        }
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let history: Vec<String> = [
+            "rg TODO src",
+            "sudo rg -l foo /etc",
+            "git log | rg fix",
+            "grep -r bar .",
+            "eza -la",
+            "ls",
+            "ls -la",
+            "podman ps",
+            "docker ps",
+            "FOO=1 /usr/bin/podman images",
+            "podman run --rm alpine",
+        ]
+        .map(str::to_string)
+        .into();
+
+        let profile = Profile::build(&history);
+        assert_eq!(profile.commands, 11);
+        let tools: Vec<(&str, usize)> = profile.preferences.iter().map(|p| (p.tool.as_str(), p.uses)).collect();
+        // eza is used less than ls, so ls stays
+        assert_eq!(tools, [("rg", 3), ("podman", 3)]);
+        let prompt = profile.prompt().unwrap();
+        assert!(prompt.contains("rg (not grep) to search file contents"));
+        assert!(prompt.contains("podman (not docker) for containers"));
+
+        assert!(Profile::build(&["ls".to_string()]).prompt().is_none());
+    }
+}
@@ -0,0 +1,86 @@
+//! Retrying rate-limited requests
+//!
+//! Groq and the edge proxy answer 429 with a `Retry-After` header when a
+//! short wait will help. Those requests are retried after the wait, and the
+//! UI is told so it can show "rate limited, retrying in Ns" instead of an
+//! HTTP error. A 429 without `Retry-After` (e.g. an exhausted free tier)
+//! fails right away with the server's message.
+
+use serde::Serialize;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+use ureq::{Request, Response};
+
+use crate::error::{error_message, SlashcmdError};
+
+/// Retries before giving up
+const MAX_RETRIES: u32 = 3;
+
+/// Longer waits are reported instead of slept through
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// Most a `Retry-After` is believed; anything longer is a broken header
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// Send `body` as JSON with a fresh request from `request` each attempt,
+/// retrying 429 responses that carry `Retry-After`. `notify` receives each
+/// wait before it starts.
+pub fn send_json_with_retry<T: Serialize>(
+    request: impl Fn() -> Request,
+    body: &T,
+    notify: Option<&Sender<Duration>>,
+) -> Result<Response, SlashcmdError> {
+    let mut attempt = 0;
+    loop {
+        match request().send_json(body) {
+            Ok(response) => return Ok(response),
+            Err(ureq::Error::Status(429, response)) => {
+                let wait = response.header("Retry-After").and_then(parse_retry_after);
+                match wait {
+                    Some(wait) if attempt < MAX_RETRIES && wait <= MAX_WAIT => {
+                        tracing::debug!(wait_ms = wait.as_millis() as u64, attempt, "rate limited, retrying");
+                        if let Some(notify) = notify {
+                            let _ = notify.send(wait);
+                        }
+                        thread::sleep(wait);
+                        attempt += 1;
+                    }
+                    Some(wait) => {
+                        return Err(SlashcmdError::RateLimited(format!(
+                            "Rate limited, try again in {}s",
+                            wait.as_secs().max(1)
+                        )))
+                    }
+                    // No point waiting: the quota is used up
+                    None => return Err(SlashcmdError::Plan(error_message(response))),
+                }
+            }
+            Err(e) => return Err(SlashcmdError::from_ureq(e)),
+        }
+    }
+}
+
+/// `Retry-After` in seconds; Groq sends fractions like "1.5". Capped at
+/// MAX_RETRY_AFTER.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let secs = value.trim().parse::<f64>().ok()?;
+    let wait = Duration::try_from_secs_f64(secs).ok().or((secs > 0.0).then_some(MAX_RETRY_AFTER))?;
+    Some(wait.min(MAX_RETRY_AFTER))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("3"), Some(Duration::from_secs(3)));
+        assert_eq!(parse_retry_after(" 1.5 "), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_retry_after("-1"), None);
+        assert_eq!(parse_retry_after("NaN"), None);
+        assert_eq!(parse_retry_after("1e30"), Some(MAX_RETRY_AFTER));
+        assert_eq!(parse_retry_after("inf"), Some(MAX_RETRY_AFTER));
+    }
+}
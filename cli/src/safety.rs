@@ -0,0 +1,203 @@
+//! Built-in safety heuristics
+//!
+//! The model's `safe` flag is a guess, and a wrong `safe: true` on the wrong
+//! command is unrecoverable. These patterns run on every generated command,
+//! whatever the model or config said, and can only make a command look
+//! riskier: a match escalates it, no match leaves the assessment alone.
+//! Anything run through `sudo`, `doas` or `su` counts as risky, so it always gets a
+//! confirmation instead of a password prompt out of nowhere.
+
+use regex::Regex;
+use std::sync::OnceLock;
+
+use crate::profile::WRAPPERS;
+
+/// How bad a matched command is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Risk {
+    /// Destroys a system or disk; treated as DANGER
+    Destructive,
+    /// Fine often enough, but never without a confirmation
+    Risky,
+}
+
+// Flags such as `-f` or `--force`, in any number, between a command and its operands
+const FLAGS: &str = r"(?:-{1,2}[\w-]+\s+)*";
+
+// End of an operand: whitespace, a shell operator or the end of the line
+const END: &str = r"(?:$|[\s;&|)])";
+
+fn heuristics() -> &'static [(Regex, Risk)] {
+    static HEURISTICS: OnceLock<Vec<(Regex, Risk)>> = OnceLock::new();
+    HEURISTICS.get_or_init(|| {
+        let recursive_rm = format!(r"\brm\s+{FLAGS}(?:-[a-zA-Z]*[rR][a-zA-Z]*|--recursive)\s+{FLAGS}");
+        [
+            // rm -rf on /, home or everything in the current directory
+            (format!(r"{recursive_rm}(?:/|/\*|~/?|~/\*|\$HOME/?|\$HOME/\*|\*){END}"), Risk::Destructive),
+            (recursive_rm.clone(), Risk::Risky),
+            (r"\bmkfs(?:\.\w+)?\b".to_string(), Risk::Destructive),
+            // Raw writes to a disk (of=/dev/null and friends are fine)
+            (r"\bdd\b.*\bof=/dev/(?:[sh]d|nvme|vd|xvd|mmcblk|disk|rdisk|mapper/|md)".to_string(), Risk::Destructive),
+            (r">\s*/dev/(?:[sh]d|nvme|vd|xvd|mmcblk|disk|rdisk)".to_string(), Risk::Destructive),
+            (format!(r"\bchmod\s+{FLAGS}-[a-zA-Z]*R[a-zA-Z]*\s+{FLAGS}0?777\s+/\*?{END}"), Risk::Destructive),
+            // :(){ :|:& };: and renamed variants
+            (r"\(\)\s*\{[^}]*\|[^}]*&\s*\}\s*;".to_string(), Risk::Destructive),
+            // Piping a download straight into a shell
+            (r"\b(?:curl|wget)\b[^|]*\|\s*(?:sudo\s+)?(?:ba|z|da|k)?sh\b".to_string(), Risk::Risky),
+        ]
+        .into_iter()
+        .map(|(pattern, risk)| (Regex::new(&pattern).expect("built-in safety pattern"), risk))
+        .collect()
+    })
+}
+
+/// Programs that run their arguments as another user
+const ELEVATORS: &[&str] = &["sudo", "doas", "pkexec", "run0", "su"];
+
+/// Whether any segment runs with elevated privileges (and may ask for a password)
+pub fn elevates(command: &str) -> bool {
+    segments(command).iter().any(|segment| match segment.strip_prefix(['(', '{']) {
+        Some(group) => elevates(group.trim_end_matches([')', '}', ';', ' '])),
+        None => runs_elevated(segment),
+    })
+}
+
+/// Whether one segment's program is an elevator, by its name or path, or
+/// one behind wrappers like `nice -n 10` or `xargs -I {}`
+fn runs_elevated(segment: &str) -> bool {
+    for word in segment.split_whitespace() {
+        let program = word.rsplit('/').next().unwrap_or(word);
+        if ELEVATORS.contains(&program) {
+            return true;
+        }
+        // Assignments, and wrappers with their flags and arguments
+        let skipped = WRAPPERS.contains(&program)
+            || word.starts_with('-')
+            || word.contains('=')
+            || word == "{}"
+            || word.chars().all(|c| c.is_ascii_digit());
+        if !skipped {
+            return false;
+        }
+    }
+    false
+}
+
+/// Split a compound command into the commands it runs: on `&&`, `||`, `;`,
+/// `|`, `&` and newlines outside quotes. Subshells, `$(...)` and `{ ...; }`
+/// groups stay in one piece.
+pub fn segments(command: &str) -> Vec<&str> {
+    let bytes = command.as_bytes();
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        match (quote, b) {
+            (Some(b'\''), b'\'') | (Some(b'"'), b'"') => quote = None,
+            (Some(b'"'), b'\\') | (None, b'\\') => i += 1,
+            (Some(_), _) => {}
+            (None, b'\'' | b'"') => quote = Some(b),
+            (None, b'(' | b'{') => depth += 1,
+            (None, b')' | b'}') => depth = depth.saturating_sub(1),
+            (None, b';' | b'\n' | b'|' | b'&') if depth == 0 => {
+                let next = bytes.get(i + 1).copied();
+                // Redirections like 2>&1, &> and >| aren't separators
+                let redirect = match b {
+                    b'&' => next == Some(b'>') || (i > 0 && matches!(bytes[i - 1], b'>' | b'<')),
+                    b'|' => i > 0 && bytes[i - 1] == b'>',
+                    _ => false,
+                };
+                if !redirect {
+                    segments.push(&command[start..i]);
+                    // Two-character operators: &&, ||, |&
+                    if matches!((b, next), (b'&', Some(b'&')) | (b'|', Some(b'|' | b'&'))) {
+                        i += 1;
+                    }
+                    start = i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    segments.push(&command[start.min(command.len())..]);
+    segments.into_iter().map(trim_segment).filter(|s| !s.is_empty()).collect()
+}
+
+/// A segment without surrounding whitespace, or the ` \` continuing its
+/// line to the next operator
+fn trim_segment(segment: &str) -> &str {
+    match segment.trim_end_matches([' ', '\t']).strip_suffix("\\\n") {
+        Some(rest) => rest.trim(),
+        None => segment.trim(),
+    }
+}
+
+/// The highest risk any heuristic sees in `command`, if one matches
+pub fn assess(command: &str) -> Option<Risk> {
+    let matched = heuristics().iter().filter(|(re, _)| re.is_match(command));
+    let mut worst = None;
+    for (_, risk) in matched {
+        if *risk == Risk::Destructive {
+            return Some(Risk::Destructive);
+        }
+        worst = Some(*risk);
+    }
+    worst.or(elevates(command).then_some(Risk::Risky))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assess() {
+        assert_eq!(assess("rm -rf /"), Some(Risk::Destructive));
+        assert_eq!(assess("rm -fr ~/"), Some(Risk::Destructive));
+        assert_eq!(assess("sudo rm --no-preserve-root -rf / && echo"), Some(Risk::Destructive));
+        assert_eq!(assess("rm -rf node_modules"), Some(Risk::Risky));
+        assert_eq!(assess("mkfs.ext4 /dev/sdb1"), Some(Risk::Destructive));
+        assert_eq!(assess("dd if=ubuntu.iso of=/dev/sdb bs=4M"), Some(Risk::Destructive));
+        assert_eq!(assess("chmod -R 777 /"), Some(Risk::Destructive));
+        assert_eq!(assess(":(){ :|:& };:"), Some(Risk::Destructive));
+        assert_eq!(assess("curl -fsSL https://get.example.com | sh"), Some(Risk::Risky));
+        assert_eq!(assess("sudo apt update"), Some(Risk::Risky));
+        assert_eq!(assess("cd /etc && LC_ALL=C doas cat shadow"), Some(Risk::Risky));
+        for elevated in [
+            "/usr/bin/sudo ls /root",
+            "command sudo ls",
+            "nice -n 10 sudo make install",
+            "nohup sudo ./serve &",
+            "time sudo -u postgres psql",
+            "find . -name '*.pid' | xargs -I {} sudo kill {}",
+            "echo 1 | sudo tee /proc/sys/vm/drop_caches",
+            "su -c 'systemctl restart nginx'",
+            "(cd build && sudo make install)",
+        ] {
+            assert!(elevates(elevated), "{}", elevated);
+        }
+
+        assert_eq!(assess("rm file.txt"), None);
+        assert_eq!(assess("dd if=/dev/zero of=/dev/null count=1"), None);
+        assert_eq!(assess("chmod 755 script.sh"), None);
+        assert_eq!(assess("curl -s https://example.com | jq ."), None);
+        assert_eq!(assess("grep sudo /var/log/auth.log"), None);
+        assert!(!elevates("time grep sudo /var/log/auth.log"));
+    }
+
+    #[test]
+    fn test_segments() {
+        assert_eq!(segments("ls -la"), vec!["ls -la"]);
+        assert_eq!(
+            segments("cd /tmp && rm -rf build; ls | wc -l || echo none &"),
+            vec!["cd /tmp", "rm -rf build", "ls", "wc -l", "echo none"]
+        );
+        assert_eq!(segments("echo 'a && b' \"c; d\" | cat"), vec!["echo 'a && b' \"c; d\"", "cat"]);
+        assert_eq!(segments("make 2>&1 | tee log"), vec!["make 2>&1", "tee log"]);
+        assert_eq!(segments("ls -la \\\n  | wc -l"), vec!["ls -la", "wc -l"]);
+        assert_eq!(segments("(cd src && make) && echo $(date; uptime)"), vec!["(cd src && make)", "echo $(date; uptime)"]);
+    }
+}
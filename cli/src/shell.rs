@@ -0,0 +1,163 @@
+//! The shell generated commands are written for
+//!
+//! Commands are POSIX sh by default and run through `sh -c`. With `--shell
+//! powershell`, `cmd` or `nu` the model is asked for that syntax instead,
+//! the highlighter switches keywords, and confirmed commands run through
+//! `pwsh` (or Windows PowerShell), `cmd /C` or `nu -c`. Without `--shell`,
+//! a login shell of nu or pwsh (`$SHELL`) picks its own syntax, since
+//! nushell's structured pipelines have little in common with sh.
+
+use serde::Deserialize;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::exec::find_in_path;
+
+/// Shell chosen for this process (`set_target`)
+static TARGET: OnceLock<Shell> = OnceLock::new();
+
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    /// POSIX sh (bash and zsh run it too)
+    #[default]
+    Sh,
+    /// PowerShell 7 (`pwsh`) or Windows PowerShell 5
+    #[serde(alias = "pwsh")]
+    Powershell,
+    /// The Windows command prompt
+    Cmd,
+    /// Nushell
+    #[serde(alias = "nushell")]
+    Nu,
+}
+
+impl std::str::FromStr for Shell {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sh" | "bash" | "zsh" | "posix" => Ok(Shell::Sh),
+            "powershell" | "pwsh" => Ok(Shell::Powershell),
+            "cmd" | "cmd.exe" => Ok(Shell::Cmd),
+            "nu" | "nushell" => Ok(Shell::Nu),
+            _ => Err(format!("Unknown shell: {}. Use: sh, powershell, cmd, nu", s)),
+        }
+    }
+}
+
+impl Shell {
+    /// Context for the model. None for sh, which the prompts already assume.
+    pub fn prompt(&self) -> Option<&'static str> {
+        match self {
+            Shell::Sh => None,
+            Shell::Powershell => Some(
+                "Write the command for PowerShell, not sh or bash: use cmdlets and their parameters \
+                 (Get-ChildItem -Recurse, Select-String, Where-Object), $variables and PowerShell \
+                 pipelines. It runs on Windows unless the request says otherwise.",
+            ),
+            Shell::Cmd => Some(
+                "Write the command for the Windows command prompt (cmd.exe), not sh or PowerShell: \
+                 use dir, findstr, copy, del, %VARIABLES% and cmd syntax (&&, for /f, if exist).",
+            ),
+            Shell::Nu => Some(
+                "Write the command for Nushell (nu), not sh or bash: structured pipelines of nu \
+                 built-ins (ls | where size > 10mb | sort-by modified) rather than grep, awk, sed or \
+                 xargs, closures like { |row| ... }, $env.NAME for environment variables, and `;` \
+                 instead of `&&`. External programs are fine where nu has no built-in.",
+            ),
+        }
+    }
+
+    /// The name `--shell` and the config file use
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shell::Sh => "sh",
+            Shell::Powershell => "powershell",
+            Shell::Cmd => "cmd",
+            Shell::Nu => "nu",
+        }
+    }
+
+    /// The process that runs `command` in this shell on this machine
+    pub fn invocation(&self, command: &str) -> Command {
+        match self {
+            Shell::Sh => {
+                let mut sh = Command::new("sh");
+                sh.arg("-c").arg(command);
+                sh
+            }
+            Shell::Powershell => {
+                let program = if find_in_path("pwsh").is_some() { "pwsh" } else { "powershell" };
+                let mut pwsh = Command::new(program);
+                pwsh.args(["-NoLogo", "-NoProfile", "-Command", command]);
+                pwsh
+            }
+            Shell::Cmd => {
+                let mut cmd = Command::new("cmd");
+                cmd.args(["/D", "/C"]);
+                // cmd parses its own command line; quoting it again would
+                // leave literal backslashes in front of the command's quotes
+                #[cfg(windows)]
+                {
+                    use std::os::windows::process::CommandExt;
+                    cmd.raw_arg(command);
+                }
+                #[cfg(not(windows))]
+                cmd.arg(command);
+                cmd
+            }
+            Shell::Nu => {
+                let mut nu = Command::new("nu");
+                nu.arg("-c").arg(command);
+                nu
+            }
+        }
+    }
+}
+
+/// The shell `$SHELL` names when it isn't sh-compatible; sh otherwise
+pub fn from_env() -> Shell {
+    std::env::var("SHELL").map(|path| from_login_shell(&path)).unwrap_or_default()
+}
+
+fn from_login_shell(path: &str) -> Shell {
+    match path.rsplit(['/', '\\']).next().unwrap_or(path).trim_end_matches(".exe") {
+        "nu" => Shell::Nu,
+        "pwsh" | "powershell" => Shell::Powershell,
+        _ => Shell::Sh,
+    }
+}
+
+/// Generate and run commands for `shell` for the rest of this process
+pub fn set_target(shell: Shell) {
+    let _ = TARGET.set(shell);
+}
+
+/// Shell chosen with `set_target`, sh if none was
+pub fn target() -> Shell {
+    TARGET.get().copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_invocation() {
+        assert_eq!("PowerShell".parse::<Shell>(), Ok(Shell::Powershell));
+        assert_eq!("bash".parse::<Shell>(), Ok(Shell::Sh));
+        assert!("fish-ish".parse::<Shell>().is_err());
+        assert!(Shell::Sh.prompt().is_none());
+        assert_eq!(from_login_shell("/opt/homebrew/bin/nu"), Shell::Nu);
+        assert_eq!(from_login_shell("/usr/bin/pwsh"), Shell::Powershell);
+        assert_eq!(from_login_shell("/bin/zsh"), Shell::Sh);
+
+        let cmd = Shell::Cmd.invocation("dir /b");
+        assert_eq!(cmd.get_program(), "cmd");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["/D", "/C", "dir /b"]);
+
+        let sh = Shell::Sh.invocation("ls | wc -l");
+        assert_eq!(sh.get_args().collect::<Vec<_>>(), ["-c", "ls | wc -l"]);
+    }
+}
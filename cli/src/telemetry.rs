@@ -0,0 +1,162 @@
+//! Opt-in anonymous telemetry
+//!
+//! Off unless the user runs `slashcmd telemetry on`. Events carry only the
+//! provider, latency and success of each command generation, plus OS and
+//! version; never query or command text. They queue locally in
+//! `~/.cmd/telemetry.jsonl` and are sent to the edge service in batches.
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::config_dir;
+use crate::logs;
+
+const TELEMETRY_URL: &str = "https://groq-warm-proxy.gozdak.workers.dev/telemetry";
+
+/// Send once this many events are queued
+const BATCH_SIZE: usize = 20;
+
+/// Drop the oldest events beyond this if sending keeps failing
+const MAX_QUEUE: usize = 500;
+
+/// Where a command came from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Edge,
+    Groq,
+    Daemon,
+}
+
+/// One command generation
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Event {
+    /// Rounded down to the hour
+    pub hour: u64,
+    pub provider: Provider,
+    pub latency_ms: u64,
+    pub success: bool,
+    pub os: String,
+    pub version: String,
+}
+
+/// Stored opt-in state
+#[derive(Serialize, Deserialize, Default)]
+struct Settings {
+    enabled: bool,
+    /// Random id so batches from one install can be grouped, nothing more
+    install_id: String,
+}
+
+fn settings_file() -> PathBuf {
+    config_dir().join("telemetry.json")
+}
+
+/// Events waiting to be sent
+pub fn queue_file() -> PathBuf {
+    logs::logs_dir()
+        .parent()
+        .map(|dir| dir.join("telemetry.jsonl"))
+        .unwrap_or_else(|| PathBuf::from("telemetry.jsonl"))
+}
+
+fn load_settings() -> Settings {
+    fs::read_to_string(settings_file())
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Whether the user opted in
+pub fn enabled() -> bool {
+    load_settings().enabled
+}
+
+/// Opt in or out. Opting out also deletes anything still queued.
+pub fn set_enabled(enabled: bool) -> Result<(), String> {
+    let mut settings = load_settings();
+    settings.enabled = enabled;
+    if enabled && settings.install_id.is_empty() {
+        let mut bytes = [0u8; 16];
+        OsRng.fill_bytes(&mut bytes);
+        settings.install_id = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    }
+    if !enabled {
+        let _ = fs::remove_file(queue_file());
+    }
+
+    fs::create_dir_all(config_dir()).map_err(|e| format!("Failed to create config dir: {}", e))?;
+    let json = serde_json::to_string_pretty(&settings).map_err(|e| format!("Failed to encode settings: {}", e))?;
+    fs::write(settings_file(), json).map_err(|e| format!("Failed to save telemetry settings: {}", e))
+}
+
+/// Number of events waiting to be sent
+pub fn queued() -> usize {
+    fs::read_to_string(queue_file())
+        .map(|content| content.lines().count())
+        .unwrap_or(0)
+}
+
+/// Queue an event. A no-op unless telemetry is on.
+pub fn record(provider: Provider, latency: Duration, success: bool) {
+    if !enabled() {
+        return;
+    }
+    let event = Event {
+        hour: logs::now() / 3600 * 3600,
+        provider,
+        latency_ms: latency.as_millis() as u64,
+        success,
+        os: std::env::consts::OS.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    let Ok(line) = serde_json::to_string(&event) else {
+        return;
+    };
+    if logs::ensure_logs_dir().is_err() {
+        return;
+    }
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(queue_file()) {
+        let _ = writeln!(file, "{}", line);
+    }
+}
+
+/// Send queued events in the background once a batch is full. The queue is
+/// only cleared after the server accepted it.
+pub fn flush_in_background() {
+    let settings = load_settings();
+    if !settings.enabled || queued() < BATCH_SIZE {
+        return;
+    }
+    std::thread::spawn(move || {
+        let Ok(content) = fs::read_to_string(queue_file()) else {
+            return;
+        };
+        let events: Vec<Event> = content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+
+        let sent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .post(TELEMETRY_URL)
+            .send_json(serde_json::json!({
+                "install_id": settings.install_id,
+                "events": events,
+            }))
+            .is_ok();
+
+        if sent {
+            let _ = fs::remove_file(queue_file());
+        } else if events.len() > MAX_QUEUE {
+            let keep: Vec<&str> = content.lines().skip(events.len() - MAX_QUEUE).collect();
+            let _ = fs::write(queue_file(), keep.join("\n") + "\n");
+        }
+    });
+}
@@ -0,0 +1,202 @@
+//! Built-in templates for everyday requests
+//!
+//! "kill port 3000", "untar backup.tar.gz", "undo last commit": the most
+//! common requests have one right answer, so they're matched here by
+//! pattern and answered instantly without the network. Anything a template
+//! doesn't match exactly goes to the model as usual. Captured parameters
+//! (ports, files, branches) are shell-quoted into the command, and every
+//! command works with both BSD and GNU tools.
+
+use regex::Regex;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::logs::Provenance;
+use crate::prompt::CommandResult;
+
+/// Whether templates may answer this process's requests (`enable`)
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Politeness and framing in front of the actual request
+const PREAMBLE: &str = r"(?i)^(?:please\s+|can you\s+|how (?:do|can) i\s+|how to\s+|i want to\s+)*";
+
+/// Parameters too vague to put in a command ("compress this")
+const VAGUE: &[&str] = &["this", "that", "it", "them", "here", "everything", "all", "files", "folder", "list"];
+
+/// Pattern (case-insensitive, whole request), command with `{name}` for
+/// each named group, and whether it only reads
+const TEMPLATES: &[(&str, &str, bool)] = &[
+    // Ports and processes
+    (r"(?:kill|stop|free|close)(?: (?:the )?(?:process|app|server))?(?: (?:on|using|at))? port (?P<port>\d{1,5})", "kill $(lsof -ti tcp:{port})", false),
+    (r"(?:what(?:'s| is)|who(?:'s| is)|which process is) (?:running on|using|listening on|on) port (?P<port>\d{1,5})", "lsof -nP -i tcp:{port}", true),
+    (r"(?:list|show) (?:all )?(?:open|listening) ports", "lsof -nP -iTCP -sTCP:LISTEN", true),
+    (r"(?:what(?:'s| is) )?(?:using|eating) (?:the )?most (?:memory|ram)", "ps aux | sort -rnk 4 | head -n 10", true),
+    (r"(?:what(?:'s| is) )?(?:using|eating) (?:the )?most cpu", "ps aux | sort -rnk 3 | head -n 10", true),
+    (r"(?:find|show) (?:the )?process(?:es)? (?:named |called )?(?P<name>[\w.-]+)", "pgrep -fl {name}", true),
+    (r"kill (?:all )?(?:the )?(?P<name>[\w.-]+) process(?:es)?", "pkill {name}", false),
+    // Archives
+    (r"(?:tar|compress|archive) (?:the )?(?:folder |directory |dir )?(?P<dir>[\w.~/-]+)(?: (?:folder|directory|dir))?", "tar -czf {dir}.tar.gz {dir}", false),
+    (r"(?:untar|extract|unpack|decompress) (?P<file>\S+\.(?:tar\.gz|tgz|tar\.bz2|tbz2|tar\.xz|txz|tar))", "tar -xf {file}", false),
+    (r"(?:unzip|extract|unpack) (?P<file>\S+\.zip)", "unzip {file}", false),
+    (r"(?:list|show) (?:the )?(?:contents|files) (?:of|in) (?P<file>\S+\.(?:tar\.gz|tgz|tar\.bz2|tbz2|tar\.xz|txz|tar))", "tar -tf {file}", true),
+    (r"zip (?:the )?(?:folder |directory )?(?P<dir>[\w.~/-]+)", "zip -r {dir}.zip {dir}", false),
+    // Finding files
+    (r"(?:find|show|list) (?:the )?(?:large|big|huge) files", "find . -type f -size +100M -exec ls -lh {} +", true),
+    (r"(?:find|show|list) (?:the )?(?P<n>\d+) (?:largest|biggest) files", "find . -type f -exec du -k {} + | sort -rn | head -n {n}", true),
+    (r"(?:find|show|list) (?:the )?(?:largest|biggest) files", "find . -type f -exec du -k {} + | sort -rn | head -n 10", true),
+    (r"(?:find|show|list) files (?:named|called) (?P<name>\S+)", "find . -name {name}", true),
+    (r"(?:find|show|list) (?:all )?(?:files )?(?:modified|changed|edited) today", "find . -type f -mtime -1", true),
+    (r"(?:find|show|list) (?:all )?(?:files )?(?:modified|changed|edited) in the (?:last|past) (?P<days>\d+) days", "find . -type f -mtime -{days}", true),
+    (r"(?:search|grep|look) (?:for )?(?P<text>.+?) in (?:all )?(?:the )?files", "grep -rn {text} .", true),
+    (r"count (?:the )?files(?: in (?:this|the current) (?:folder|directory))?", "find . -type f | wc -l", true),
+    (r"count (?:the )?lines (?:of|in) (?:all )?(?:the )?\.?(?P<ext>[a-z0-9]{1,8}) files", "find . -type f -name '*.{ext}' -exec cat {} + | wc -l", true),
+    (r"(?:list|show) (?:all )?(?:hidden files|files including hidden(?: ones)?)", "ls -la", true),
+    // Disk space
+    (r"(?:show |check )?(?:disk usage|size) of (?:this|the current) (?:folder|directory)", "du -sh .", true),
+    (r"(?:show |check )?disk usage(?: by (?:folder|directory))?", "du -sh * | sort -h", true),
+    (r"(?:how much |show |check )?(?:free )?disk space(?: (?:is )?(?:left|free|available))?", "df -h", true),
+    (r"(?:how big is|size of) (?P<path>[\w.~/-]+)", "du -sh {path}", true),
+    // Cleanup
+    (r"(?:delete|remove) (?:all )?\.?ds_store files", "find . -name .DS_Store -type f -delete", false),
+    (r"(?:delete|remove) (?:all )?(?:the )?node_modules(?: folders| directories)?", "find . -name node_modules -type d -prune -exec rm -rf {} +", false),
+    // Docker
+    (r"(?:clean ?up|prune) docker|docker (?:clean ?up|prune)", "docker system prune", false),
+    (r"(?:remove|delete) (?:all )?stopped containers", "docker container prune", false),
+    (r"(?:remove|delete) (?:all )?(?:dangling|unused) (?:docker )?images", "docker image prune", false),
+    (r"(?:list|show) (?:the )?running (?:docker )?containers", "docker ps", true),
+    (r"(?:list|show) all (?:docker )?containers", "docker ps -a", true),
+    (r"stop all (?:the )?(?:running )?(?:docker )?containers", "docker stop $(docker ps -q)", false),
+    // Git
+    (r"undo (?:the )?last commit(?: (?:but )?keep(?:ing)? (?:the )?changes)?", "git reset --soft HEAD~1", false),
+    (r"(?:discard|throw away|revert) (?:all )?(?:my )?(?:local |uncommitted )?changes", "git restore .", false),
+    (r"unstage (?:all )?(?:the )?(?:files|changes)|undo git add", "git restore --staged .", false),
+    (r"(?:change|edit|fix|amend) (?:the )?last commit message", "git commit --amend", false),
+    (r"(?:show|what(?:'s| is)) (?:the )?current (?:git )?branch", "git branch --show-current", true),
+    (r"(?:show|list) (?:the )?recent commits", "git log --oneline -n 20", true),
+    (r"(?:show|list) (?:the )?last (?P<n>\d+) commits", "git log --oneline -n {n}", true),
+    (r"(?:show|what changed in) (?:the )?last commit", "git show --stat HEAD", true),
+    (r"(?:create|make|new) (?:a )?(?:git )?branch (?:called |named )?(?P<branch>[\w./-]+)", "git switch -c {branch}", false),
+    (r"(?:switch|change) to (?:the )?(?:git )?branch (?P<branch>[\w./-]+)", "git switch {branch}", false),
+    (r"(?:switch|change) to (?:the )?(?P<branch>[\w./-]+) branch", "git switch {branch}", false),
+    (r"(?:delete|remove) (?:all )?(?:local )?merged branches", "git branch --merged | grep -vE '^\\*|^[[:space:]]*(main|master)$' | xargs git branch -d", false),
+    // Everyday
+    (r"(?:follow|tail|watch) (?:the )?(?:log |file )?(?P<file>[\w.~/-]+\.(?:log|txt|out))", "tail -f {file}", true),
+    (r"(?:show )?(?:the )?last (?P<n>\d+) lines (?:of|in) (?P<file>[\w.~/-]+)", "tail -n {n} {file}", true),
+    (r"(?:show )?(?:the )?first (?P<n>\d+) lines (?:of|in) (?P<file>[\w.~/-]+)", "head -n {n} {file}", true),
+    (r"make (?P<file>[\w.~/-]+) executable", "chmod +x {file}", false),
+    (r"(?:create|make) (?:a )?(?:folder|directory) (?:called |named )?(?P<dir>[\w.~/-]+)", "mkdir -p {dir}", false),
+    (r"download (?P<url>https?://\S+)", "curl -LO {url}", false),
+    (r"(?:what(?:'s| is) )?my (?:public |external )?ip(?: address)?", "curl -s https://ifconfig.me", true),
+    (r"(?:generate|create|make) (?:a |an |new )*ssh key", "ssh-keygen -t ed25519", false),
+    (r"(?:serve|share) (?:this|the current) (?:folder|directory)(?: (?:over|on) http)?(?: on port (?P<port>\d{1,5}))?", "python3 -m http.server {port}", false),
+    (r"(?:show|print|list) (?:my )?path", "echo \"$PATH\" | tr ':' '\\n'", true),
+];
+
+/// Let templates answer requests in this process. Off for remote targets,
+/// other shells and --portable, which the templates don't target.
+pub fn enable(enabled: bool) {
+    let _ = ENABLED.set(enabled);
+}
+
+/// The template answer to `query`, if one matches it whole
+pub fn find(query: &str) -> Option<CommandResult> {
+    if !ENABLED.get().copied().unwrap_or(false) {
+        return None;
+    }
+    Some(CommandResult {
+        provenance: Some(Provenance::new("offline", "template", Duration::ZERO)),
+        ..find_in(query)?
+    })
+}
+
+fn patterns() -> &'static [(Regex, &'static str, bool)] {
+    static PATTERNS: OnceLock<Vec<(Regex, &'static str, bool)>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        TEMPLATES
+            .iter()
+            .map(|(pattern, command, safe)| {
+                let re = Regex::new(&format!(r"{}(?:{})$", PREAMBLE, pattern)).expect("built-in template pattern");
+                (re, *command, *safe)
+            })
+            .collect()
+    })
+}
+
+fn find_in(query: &str) -> Option<CommandResult> {
+    let query = query.split_whitespace().collect::<Vec<_>>().join(" ");
+    let query = query.trim_end_matches(['?', '.', '!']);
+    for (re, command, safe) in patterns() {
+        let Some(captures) = re.captures(query) else {
+            continue;
+        };
+        let mut filled = command.to_string();
+        let mut vague = false;
+        for name in re.capture_names().flatten() {
+            let value = captures.name(name).map(|m| m.as_str()).unwrap_or_default();
+            // Optional groups fall back to the tool's own default
+            let value = match (name, value) {
+                ("port", "") => "8000",
+                (_, value) => value.trim_end_matches('/'),
+            };
+            vague |= VAGUE.contains(&value.to_lowercase().as_str());
+            filled = filled.replace(&format!("{{{}}}", name), &quote(value));
+        }
+        if !vague {
+            return Some(CommandResult {
+                command: filled,
+                safe: *safe,
+                ..Default::default()
+            });
+        }
+    }
+    None
+}
+
+/// Single-quote a parameter unless it's plain
+fn quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_./:@%+=,-~".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(query: &str) -> Option<String> {
+        find_in(query).map(|result| result.command)
+    }
+
+    #[test]
+    fn test_find_in() {
+        assert_eq!(command("kill port 3000").as_deref(), Some("kill $(lsof -ti tcp:3000)"));
+        assert_eq!(command("Please kill the process on port 8080").as_deref(), Some("kill $(lsof -ti tcp:8080)"));
+        assert_eq!(command("how do I untar backup.tar.gz?").as_deref(), Some("tar -xf backup.tar.gz"));
+        assert_eq!(command("compress folder src/").as_deref(), Some("tar -czf src.tar.gz src"));
+        assert_eq!(command("undo last commit").as_deref(), Some("git reset --soft HEAD~1"));
+        assert_eq!(command("switch to branch feature/login").as_deref(), Some("git switch feature/login"));
+        assert_eq!(command("show last 5 commits").as_deref(), Some("git log --oneline -n 5"));
+        assert_eq!(command("search for TODO: fix in files").as_deref(), Some("grep -rn 'TODO: fix' ."));
+        assert_eq!(command("find the 5 largest files").as_deref(), Some("find . -type f -exec du -k {} + | sort -rn | head -n 5"));
+        assert_eq!(command("serve this folder").as_deref(), Some("python3 -m http.server 8000"));
+        assert!(find_in("list running containers").unwrap().safe);
+        assert!(!find_in("clean up docker").unwrap().safe);
+
+        // Vague or partial requests go to the model
+        assert_eq!(command("compress this"), None);
+        assert_eq!(command("switch to zsh"), None);
+        assert_eq!(command("kill port 3000 and restart the server"), None);
+        assert_eq!(command("find large files modified this week"), None);
+    }
+
+    #[test]
+    fn test_patterns_compile() {
+        assert_eq!(patterns().len(), TEMPLATES.len());
+    }
+}
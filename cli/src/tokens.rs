@@ -0,0 +1,177 @@
+//! Keeping the context sent with a query within a token budget
+//!
+//! Queries pick up context from several places: attached @files, the output
+//! of the previous --then step, earlier requests, the cluster, the shell.
+//! Together they can outgrow the model's context window, and every token is
+//! paid for. Before a query is sent, its context blocks are cut to fit
+//! `max_context_tokens`. The user's own words are never cut. Short facts fit
+//! whole; the big blocks share what is left evenly. Logs and files keep
+//! their head and tail, where the errors are; long listings are sampled.
+
+use std::sync::OnceLock;
+
+/// Tokens of context a query may carry unless config.toml says otherwise
+pub const DEFAULT_CONTEXT_TOKENS: usize = 4000;
+
+/// A rough average for English and code; close enough for budgeting
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Below this a cut-down block says too little to be worth sending
+const MIN_BLOCK_TOKENS: usize = 24;
+
+/// Lines a listing needs before it's sampled rather than cut at the middle
+const MIN_LISTING_LINES: usize = 20;
+
+/// Where `prompt::with_context` starts each block
+const CONTEXT_SEPARATOR: &str = "\n\nContext: ";
+
+/// Set by `limit_context`
+static LIMIT: OnceLock<usize> = OnceLock::new();
+
+/// Send at most `max_tokens` of query and context (`max_context_tokens`)
+pub fn limit_context(max_tokens: usize) {
+    let _ = LIMIT.set(max_tokens);
+}
+
+/// The budget from `limit_context`, or the default
+pub fn limit() -> usize {
+    LIMIT.get().copied().unwrap_or(DEFAULT_CONTEXT_TOKENS)
+}
+
+/// Estimated tokens in `text`
+pub fn estimate(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// `query` with its context blocks cut down so the whole fits `max_tokens`
+pub fn fit(query: &str, max_tokens: usize) -> String {
+    if estimate(query) <= max_tokens {
+        return query.to_string();
+    }
+    let mut parts = query.split(CONTEXT_SEPARATOR);
+    let words = parts.next().unwrap_or_default();
+    let blocks: Vec<&str> = parts.collect();
+
+    // Smallest first, each taking what it needs up to an even share of what's left
+    let mut order: Vec<usize> = (0..blocks.len()).collect();
+    order.sort_by_key(|&i| estimate(blocks[i]));
+    let mut left = max_tokens.saturating_sub(estimate(words) + blocks.len() * estimate(CONTEXT_SEPARATOR));
+    let mut shares = vec![0; blocks.len()];
+    for (n, &i) in order.iter().enumerate() {
+        shares[i] = estimate(blocks[i]).min(left / (blocks.len() - n));
+        left -= shares[i];
+    }
+
+    let mut fitted = words.to_string();
+    for (block, share) in blocks.iter().zip(shares) {
+        if estimate(block) <= share {
+            fitted = crate::prompt::with_context(&fitted, block);
+        } else if let Some(shrunk) = shrink(block, share) {
+            tracing::debug!(tokens = estimate(block), share, "context block cut");
+            fitted = crate::prompt::with_context(&fitted, &shrunk);
+        } else {
+            tracing::debug!(tokens = estimate(block), share, "context block left out");
+        }
+    }
+    fitted
+}
+
+/// One block cut to about `max_tokens`, keeping its first line (what the
+/// block is) and any code fence around the rest. None if too little fits.
+fn shrink(block: &str, max_tokens: usize) -> Option<String> {
+    if max_tokens < MIN_BLOCK_TOKENS {
+        return None;
+    }
+    let (header, body) = block.split_once('\n').unwrap_or(("", block));
+    let (open, body, close) = match body.strip_prefix("```").and_then(|b| b.split_once('\n')) {
+        Some((lang, inner)) if inner.ends_with("```") => {
+            (format!("```{}\n", lang), inner.trim_end_matches("```").trim_end(), "\n```")
+        }
+        _ => (String::new(), body, ""),
+    };
+    let budget = max_tokens.saturating_sub(estimate(header) + estimate(&open) + estimate(close) + 1);
+    let body = if is_listing(body) { sample(body, budget) } else { head_tail(body, budget) };
+    let header = if header.is_empty() { String::new() } else { format!("{}\n", header) };
+    Some(format!("{}{}{}{}", header, open, body, close))
+}
+
+/// Many short lines and no errors in them: `ls`, `find`, `kubectl get` output
+fn is_listing(text: &str) -> bool {
+    let lines = text.lines().count();
+    lines >= MIN_LISTING_LINES
+        && text.chars().count() / lines <= 80
+        && !text.lines().any(|line| {
+            let line = line.to_lowercase();
+            line.contains("error") || line.contains("warn") || line.contains("fail")
+        })
+}
+
+/// The first and last lines of `text` within `max_tokens`, a third for the
+/// head and the rest for the tail
+fn head_tail(text: &str, max_tokens: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    // Less the line saying what was left out
+    let budget = max_tokens.saturating_sub(8) * CHARS_PER_TOKEN;
+    let mut used = 0;
+    let mut head = 0;
+    while head < lines.len() && used + lines[head].chars().count() < budget / 3 {
+        used += lines[head].chars().count() + 1;
+        head += 1;
+    }
+    let mut tail = lines.len();
+    while tail > head && used + lines[tail - 1].chars().count() < budget {
+        used += lines[tail - 1].chars().count() + 1;
+        tail -= 1;
+    }
+    if tail == head {
+        return text.to_string();
+    }
+    if head == 0 && tail == lines.len() {
+        // One huge line
+        let start: String = text.chars().take(budget).collect();
+        return format!("{} [...]", start);
+    }
+    let mut kept: Vec<String> = lines[..head].iter().map(|line| line.to_string()).collect();
+    kept.push(format!("[... {} lines left out ...]", tail - head));
+    kept.extend(lines[tail..].iter().map(|line| line.to_string()));
+    kept.join("\n")
+}
+
+/// Lines spread evenly through `text` within `max_tokens`
+fn sample(text: &str, max_tokens: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let per_line = text.chars().count() / lines.len() + 1;
+    let keep = (max_tokens * CHARS_PER_TOKEN / per_line).saturating_sub(1).clamp(1, lines.len());
+    let mut kept: Vec<&str> = (0..keep).map(|i| lines[i * lines.len() / keep]).collect();
+    let note = format!("[... {} of {} lines shown ...]", keep, lines.len());
+    kept.push(&note);
+    kept.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::with_context;
+
+    #[test]
+    fn test_fit() {
+        let query = with_context("delete the old builds", "The cluster is prod.");
+        assert_eq!(fit(&query, 100), query);
+
+        let log: Vec<String> = (0..500).map(|i| format!("{} ERROR step {} went wrong", i, i)).collect();
+        let listing: Vec<String> = (0..500).map(|i| format!("src/file{}.rs", i)).collect();
+        let query = with_context(&query, &format!("I just ran `make` and it printed:\n{}", log.join("\n")));
+        let query = with_context(&query, &format!("Contents of files.txt:\n```\n{}\n```", listing.join("\n")));
+        let fitted = fit(&query, 400);
+        assert!(estimate(&fitted) <= 400);
+        assert!(fitted.starts_with("delete the old builds\n\nContext: The cluster is prod."));
+
+        // The log keeps its first and last lines, the listing a sample
+        assert!(fitted.contains("\n0 ERROR step 0") && fitted.contains("499 ERROR step 499 went wrong"));
+        assert!(fitted.contains("lines left out ..."));
+        assert!(fitted.contains("src/file0.rs\n") && fitted.contains("of 500 lines shown ...]\n```"));
+
+        // Words beyond the budget crowd the context out
+        assert_eq!(fit(&query, 5), "delete the old builds");
+    }
+}
@@ -2,199 +2,442 @@
 //!
 //! The command and prompt stay at a fixed position at the bottom.
 //! Explanation appears ABOVE them without shifting.
+//!
+//! Plain mode (`--plain`, `plain = true`, or TERM=dumb) skips the layout:
+//! no cursor movement, spinner or redraws, just lines printed in order and
+//! answers read a line at a time, which screen readers and braille displays
+//! can follow.
 
 use crossterm::{
     cursor::{MoveToColumn, MoveUp},
     event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
     execute,
-    style::{Color, Print, ResetColor, SetForegroundColor},
+    style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor},
     terminal::{self, Clear, ClearType},
 };
-use std::io::{self, Write};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
 use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
+use std::sync::OnceLock;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use crate::audit::{self, AuditEvent};
+use crate::clipboard;
+use crate::context;
+use crate::coreutils;
+use crate::diff;
+#[cfg(feature = "edge")]
 use crate::edge::EdgeClient;
+use crate::error::SlashcmdError;
+#[cfg(feature = "gemini")]
 use crate::gemini::GeminiClient;
-use crate::groq::GroqClient;
-use crate::highlight::{format_safety, highlight};
-use crate::ipc::{ExplainStyle, IpcClient, IpcRequest};
-use crate::logs;
-use crate::prompt::CommandResult;
+#[cfg(feature = "groq")]
+use crate::groq::{self, GroqClient};
+use crate::highlight::{banner, dim, format_safety, highlight, highlight_command, level, palette, Level, Palette};
+use crate::ipc::{ExplainOptions, ExplainStyle, Verbosity};
+#[cfg(feature = "groq")]
+use crate::ipc::Generation;
+#[cfg(any(feature = "groq", feature = "gemini"))]
+use crate::ipc::{self, IpcClient, IpcRequest};
+use crate::layout;
+use crate::logs::{self, Provenance};
+use crate::policy::{SafetyRules, Verdict};
+use crate::prompt::{self, CommandResult};
+use crate::syntax;
+use crate::telemetry::{self, Provider};
+use crate::templates;
+use crate::walkthrough;
+use crate::wrap;
+
+const RUN_PROMPT: &str = "Press Enter to run, r to refine, Ctrl+C to cancel... ";
+
+/// RUN_PROMPT for a pipeline that can be walked through stage by stage
+const RUN_PROMPT_STAGES: &str = "Press Enter to run, ←/→ for each stage, r to refine, Ctrl+C to cancel... ";
+
+/// Spinner frames, one per 100ms tick while we wait for the command
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// Shown instead of RUN_PROMPT when policy requires an explanation we don't have
+const NO_EXPLANATION_PROMPT: &str =
+    "Explanation required by policy but unavailable. r to refine, Ctrl+C to cancel... ";
+
+/// Earlier queries offered by Up in the refine line
+const QUERY_HISTORY_LEN: usize = 100;
+
+/// Set by `use_plain`
+static PLAIN: OnceLock<bool> = OnceLock::new();
+
+/// Print a linear transcript instead of the redrawn layout
+pub fn use_plain(plain: bool) {
+    let _ = PLAIN.set(plain);
+}
+
+fn plain() -> bool {
+    PLAIN.get().copied().unwrap_or(false) || std::env::var("TERM").is_ok_and(|term| term == "dumb")
+}
 
 pub enum TuiResult {
     Execute(String),
     Cancel,
+    /// Generate again: `refinement` amends the query that produced `command`
+    Refine { refinement: String, command: String },
+    /// Generate again with the user's answer to the model's question
+    Clarify { question: String, answer: String },
 }
 
 /// Command source - either direct Groq API or edge proxy
 pub enum CommandSource {
+    #[cfg(feature = "groq")]
     Direct { groq_api_key: String },
+    #[cfg(feature = "edge")]
     Edge { token: Option<String> },
 }
 
+#[cfg(feature = "groq")]
 pub fn run_interactive(
     query: String,
     groq_api_key: String,
     gemini_api_key: Option<String>,
-    style: ExplainStyle,
-) -> Result<TuiResult, String> {
-    run_interactive_impl(query, CommandSource::Direct { groq_api_key }, gemini_api_key, style)
-}
-
-pub fn run_interactive_edge(
-    query: String,
-    gemini_api_key: Option<String>,
-    style: ExplainStyle,
-) -> Result<TuiResult, String> {
-    run_interactive_impl(query, CommandSource::Edge { token: None }, gemini_api_key, style)
+    options: ExplainOptions,
+    rules: &SafetyRules,
+    previous_command: Option<&str>,
+) -> Result<TuiResult, SlashcmdError> {
+    let source = CommandSource::Direct { groq_api_key };
+    run_interactive_impl(query, source, gemini_api_key, options, rules, previous_command)
 }
 
+#[cfg(feature = "edge")]
 pub fn run_interactive_edge_auth(
     query: String,
     token: String,
-    style: ExplainStyle,
-) -> Result<TuiResult, String> {
-    run_interactive_impl(query, CommandSource::Edge { token: Some(token) }, None, style)
+    options: ExplainOptions,
+    rules: &SafetyRules,
+    previous_command: Option<&str>,
+) -> Result<TuiResult, SlashcmdError> {
+    let source = CommandSource::Edge { token: Some(token) };
+    run_interactive_impl(query, source, None, options, rules, previous_command)
 }
 
 fn run_interactive_impl(
     query: String,
     source: CommandSource,
     _gemini_api_key: Option<String>,
-    style: ExplainStyle,
-) -> Result<TuiResult, String> {
+    options: ExplainOptions,
+    rules: &SafetyRules,
+    previous_command: Option<&str>,
+) -> Result<TuiResult, SlashcmdError> {
+    let style = &options.style;
+
     // If user explicitly asked for explanation, always wait for confirmation
     let force_wait = query.to_lowercase().contains("explain");
 
+    // Whatever explains the command explains its stages too
+    let stage_explainer = stage_explainer(&source, _gemini_api_key.as_deref());
+
     // Channels for command (both modes) and explanation (edge mode only initially)
-    let (cmd_tx, cmd_rx) = mpsc::channel::<Result<CommandResult, String>>();
+    let (cmd_tx, cmd_rx) = mpsc::channel::<Result<CommandResult, SlashcmdError>>();
+    // Rate-limit waits, shown in the status line while we wait for the command
+    let (wait_tx, wait_rx) = mpsc::channel::<Duration>();
 
     let query_clone = query.clone();
 
-    // Track if we're in edge mode and extract token
-    let (is_edge_mode, edge_token) = match &source {
-        CommandSource::Edge { token } => (true, token.clone()),
-        _ => (false, None),
+    // A built-in template answers common requests without the model.
+    // Refinements always go to the model.
+    let template = match previous_command {
+        Some(_) => None,
+        None => templates::find(prompt::strip_context(&query)),
     };
+    let templated = template.is_some();
 
     // For edge mode: create explanation channel upfront (SSE sends to it)
     // For direct mode: we'll create it later when spawning Gemini thread
-    let edge_exp_rx = if is_edge_mode {
-        let (exp_tx, exp_rx) = mpsc::channel::<Result<String, String>>();
-
-        let style_str = match style {
-            ExplainStyle::Typescript => "typescript",
-            ExplainStyle::Python => "python",
-            ExplainStyle::Ruby => "ruby",
-            ExplainStyle::Human => "human",
-        };
-        let style_owned = style_str.to_string();
-        let token_for_thread = edge_token.clone();
+    let edge_exp_rx = match source {
+        #[cfg(feature = "edge")]
+        CommandSource::Edge { token } => {
+            let (exp_tx, exp_rx) = mpsc::channel::<Result<String, String>>();
 
-        thread::spawn(move || {
-            let client = match token_for_thread {
-                Some(t) => EdgeClient::new(t),
-                None => EdgeClient::with_test_jwt(),
-            };
-            match client.query_streaming(&query_clone, &style_owned, cmd_tx, exp_tx) {
-                Ok(_) => {}
-                Err(e) => eprintln!("Edge stream error: {}", e),
-            }
-        });
+            let options_owned = options.clone();
+            thread::spawn(move || {
+                let client = match token {
+                    Some(t) => EdgeClient::new(t),
+                    None => EdgeClient::with_test_jwt(),
+                }
+                .with_rate_limit_notice(wait_tx);
+                // A template's command still gets the worker's explanation
+                if let Some(found) = template {
+                    let command = found.command.clone();
+                    let _ = cmd_tx.send(Ok(found));
+                    let _ = exp_tx.send(client.explain(&command, &options_owned).map_err(|e| e.to_string()));
+                    return;
+                }
+                // Failures before the command arrives (login, quota, network) end the wait
+                let err_tx = cmd_tx.clone();
+                if let Err(e) = client.query_streaming(&query_clone, &options_owned, cmd_tx, exp_tx) {
+                    let _ = err_tx.send(Err(e));
+                }
+            });
 
-        Some(exp_rx)
-    } else {
-        // Direct mode: spawn Groq call
-        if let CommandSource::Direct { groq_api_key } = source {
+            Some(exp_rx)
+        }
+        #[cfg(feature = "groq")]
+        CommandSource::Direct { groq_api_key } => {
+            // Direct mode: spawn Groq call
+            let generation = options.generation;
             thread::spawn(move || {
-                let _ = cmd_tx.send(get_command(&query_clone, &groq_api_key));
+                let result = match template {
+                    Some(found) => Ok(found),
+                    None => get_command(&query_clone, &groq_api_key, &generation, wait_tx),
+                };
+                let _ = cmd_tx.send(result);
             });
+            None
         }
-        None
     };
+    let is_edge_mode = edge_exp_rx.is_some();
+    let provider = if templated {
+        "template"
+    } else if is_edge_mode {
+        "edge"
+    } else {
+        "groq"
+    };
+
+    if plain() {
+        let pending = Pending { cmd_rx, wait_rx, edge_exp_rx, gemini_api_key: _gemini_api_key, previous_command, force_wait };
+        return run_plain(&query, pending, &options, rules);
+    }
 
     let mut stdout = io::stdout();
     terminal::enable_raw_mode().map_err(|e| format!("Terminal error: {}", e))?;
 
-    // Show loading
-    execute!(
-        stdout,
-        MoveToColumn(0),
-        Clear(ClearType::CurrentLine),
-        SetForegroundColor(Color::DarkGrey),
-        Print("Generating command..."),
-        ResetColor,
-    ).ok();
-    stdout.flush().ok();
+    // Wait for command + safety from Groq. Rate-limit waits extend the deadline.
+    // (Direct mode records its own telemetry, telling daemon from Groq apart.)
+    // The spinner and clock keep moving so a slow model doesn't look hung.
+    let started = Instant::now();
+    let mut deadline = started + Duration::from_secs(30);
+    let mut retry_at: Option<Instant> = None;
+    let mut tick = 0;
+    let cmd_result = loop {
+        let now = Instant::now();
+        let status = match retry_at.filter(|at| *at > now) {
+            Some(at) => format!("Rate limited, retrying in {}s...", (at - now).as_secs_f64().ceil()),
+            None => "Generating command...".to_string(),
+        };
+        execute!(
+            stdout,
+            MoveToColumn(0),
+            Clear(ClearType::CurrentLine),
+            SetForegroundColor(muted()),
+            Print(format!(
+                "{} {} {:.1}s ({})",
+                SPINNER[tick % SPINNER.len()],
+                status,
+                started.elapsed().as_secs_f64(),
+                provider
+            )),
+            ResetColor,
+        ).ok();
+        stdout.flush().ok();
+        tick += 1;
 
-    // Wait for command + safety from Groq
-    let cmd_result = match cmd_rx.recv_timeout(Duration::from_secs(30)) {
-        Ok(Ok(result)) => result,
-        Ok(Err(e)) => {
-            terminal::disable_raw_mode().ok();
-            execute!(stdout, Print("\r\n")).ok();
-            return Err(e);
+        match cmd_rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(Ok(result)) => {
+                if is_edge_mode && !templated {
+                    telemetry::record(Provider::Edge, started.elapsed(), true);
+                }
+                break result;
+            }
+            Ok(Err(e)) => {
+                if is_edge_mode {
+                    telemetry::record(Provider::Edge, started.elapsed(), false);
+                }
+                terminal::disable_raw_mode().ok();
+                execute!(stdout, Print("\r\n")).ok();
+                return Err(e);
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) if Instant::now() < deadline => {}
+            Err(_) => {
+                terminal::disable_raw_mode().ok();
+                execute!(stdout, Print("\r\n")).ok();
+                return Err(SlashcmdError::Network("timed out waiting for a command".to_string()));
+            }
         }
-        Err(_) => {
-            terminal::disable_raw_mode().ok();
-            execute!(stdout, Print("\r\n")).ok();
-            return Err("Timeout".to_string());
+
+        if let Ok(wait) = wait_rx.try_recv() {
+            deadline += wait;
+            retry_at = Some(Instant::now() + wait);
         }
     };
 
-    let command = cmd_result.command;
-    let is_safe = cmd_result.safe;
+    // An ambiguous request: put the model's question to the user
+    if let Some(question) = cmd_result.question {
+        execute!(
+            stdout,
+            MoveToColumn(0),
+            Clear(ClearType::CurrentLine),
+            Print(format!("? {}\r\n", question)),
+        )
+        .ok();
+        for (i, option) in cmd_result.options.iter().enumerate() {
+            execute!(
+                stdout,
+                SetForegroundColor(muted()),
+                Print(format!("  {}) ", i + 1)),
+                ResetColor,
+                Print(format!("{}\r\n", option)),
+            )
+            .ok();
+        }
+        let answer = read_line(&mut stdout, "Answer: ", QueryHistory::new(Vec::new()));
+        execute!(stdout, Print("\r\n")).ok();
+        terminal::disable_raw_mode().ok();
+        return Ok(match answer {
+            Some(answer) => TuiResult::Clarify { answer: chosen(&cmd_result.options, &answer), question },
+            None => TuiResult::Cancel,
+        });
+    }
 
-    // Auto-execute safe commands immediately (unless user asked to explain)
-    if is_safe && !force_wait {
+    // What's shown is what runs: a long pipeline gets a line per stage
+    let width = terminal::size().map_or(layout::MAX_WIDTH, |(cols, _)| layout::MAX_WIDTH.min(cols as usize));
+    let command = layout::split(&layout::normalize(&cmd_result.command), width);
+    let command_rows = command.lines().count() as u16;
+    clipboard::generated(&command);
+
+    // After a refinement, show what changed
+    if let Some(diff) = previous_command.and_then(|previous| diff::render(previous, &command)) {
         execute!(
             stdout,
             MoveToColumn(0),
             Clear(ClearType::CurrentLine),
-            SetForegroundColor(Color::Cyan),
-            Print(&command),
+            SetForegroundColor(muted()),
+            Print("Changed: "),
             ResetColor,
+            Print(diff),
+            Print("\r\n"),
+        ).ok();
+    }
+
+    // Say which cluster or cloud account the command would act on
+    for text in context::kube_banner(&command).into_iter().chain(context::cloud_banner(&command)) {
+        execute!(
+            stdout,
+            MoveToColumn(0),
+            Clear(ClearType::CurrentLine),
+            Print(banner(&text)),
+            Print("\r\n"),
+        ).ok();
+    }
+
+    // Flags this machine's sed, date, stat... don't have, and what won't parse
+    let mut lint = coreutils::lint(&command);
+    lint.extend(syntax::check(&command, prompt::strip_context(&query)));
+    for problem in &lint {
+        execute!(
+            stdout,
+            MoveToColumn(0),
+            Clear(ClearType::CurrentLine),
+            Print(level(Level::Caution, &format!("⚠ {}", problem))),
+            Print("\r\n"),
+        ).ok();
+    }
+
+    // Local [safety] rules overrule the model in either direction
+    let verdict = rules.check(&command);
+
+    // Policy-denied commands are shown but never run or copied
+    if verdict == Verdict::Blocked {
+        execute!(
+            stdout,
+            MoveToColumn(0),
+            Clear(ClearType::CurrentLine),
+            Print(crlf(&level(Level::Danger, &command))),
+            Print("\r\n"),
+            Print(level(Level::Danger, "⛔ Blocked by policy.")),
             Print("\r\n"),
         ).ok();
         stdout.flush().ok();
         terminal::disable_raw_mode().ok();
-        save_log(&query, &command, None, style);
+        save_log(&query, &command, None, style, cmd_result.provenance.as_ref());
+        return Ok(TuiResult::Cancel);
+    }
+
+    // Denylisted commands are never run; copying is all that's offered
+    if verdict == Verdict::Denied {
+        execute!(
+            stdout,
+            MoveToColumn(0),
+            Clear(ClearType::CurrentLine),
+            Print(level(Level::Danger, "⛔ On the denylist, slashcmd won't run this.")),
+            Print("\r\n"),
+        ).ok();
+        confirm_danger(&mut stdout, &command, None);
+        terminal::disable_raw_mode().ok();
+        save_log(&query, &command, None, style, cmd_result.provenance.as_ref());
+        return Ok(TuiResult::Cancel);
+    }
+
+    // Auto-execute what `auto_execute` allows (unless user asked to explain,
+    // a flag looks wrong for this machine, the command may not do what was
+    // asked, or policy wants a confirmation or an explanation first)
+    if rules.may_auto_execute(verdict, cmd_result.safe) && !force_wait && lint.is_empty() {
+        execute!(
+            stdout,
+            MoveToColumn(0),
+            Clear(ClearType::CurrentLine),
+            Print(crlf(&highlight_command(&command))),
+            Print("\r\n"),
+        ).ok();
+        stdout.flush().ok();
+        terminal::disable_raw_mode().ok();
+        save_log(&query, &command, None, style, cmd_result.provenance.as_ref());
         return Ok(TuiResult::Execute(command));
     }
 
     // Set up explanation channel
     // For edge mode: already have edge_exp_rx from SSE stream
     // For direct mode: spawn Gemini thread if we have API key
+    // Partial text only comes from Gemini; the edge stream sends it whole
+    let (partial_tx, partial_rx) = mpsc::channel::<String>();
     let explanation_rx: Option<mpsc::Receiver<Result<String, String>>> = if is_edge_mode {
         edge_exp_rx
-    } else if let Some(ref gemini_key) = _gemini_api_key {
-        let (exp_tx, exp_rx) = mpsc::channel();
-        let cmd = command.clone();
-        let key = gemini_key.clone();
-        let s = style;
-        thread::spawn(move || {
-            let _ = exp_tx.send(get_explanation(&cmd, &key, s));
-        });
-        Some(exp_rx)
     } else {
-        None
+        spawn_explanation(&command, _gemini_api_key.as_deref(), &options, partial_tx)
     };
 
     let has_explanation = explanation_rx.is_some();
 
-    // Pre-allocate space for explanation (only if we're fetching one)
-    const RESERVED_LINES: u16 = 15;
+    // Pre-allocate space for explanation (only if we're fetching one),
+    // sized for the verbosity but never taller than the terminal
+    let wanted_lines: u16 = match options.verbosity {
+        Verbosity::Terse => 8,
+        Verbosity::Normal => 15,
+        Verbosity::Eli5 => 30,
+    };
+    let reserved_lines = match terminal::size() {
+        Ok((_, rows)) => wanted_lines.min(rows.saturating_sub(3 + command_rows).max(3)),
+        Err(_) => wanted_lines,
+    };
 
     execute!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine)).ok();
 
+    // A danger rule applies even without an explanation to flag it
+    if verdict == Verdict::Danger && !has_explanation {
+        let word = rules.confirm_word().filter(|_| !rules.require_explanation());
+        let run = confirm_danger(&mut stdout, &command, word);
+        terminal::disable_raw_mode().ok();
+        save_log(&query, &command, None, style, cmd_result.provenance.as_ref());
+        return Ok(if run { TuiResult::Execute(command) } else { TuiResult::Cancel });
+    }
+
     if has_explanation {
         // Print placeholder lines (dim dots to show space is reserved)
-        for _ in 0..RESERVED_LINES {
+        for _ in 0..reserved_lines {
             execute!(
                 stdout,
-                SetForegroundColor(Color::DarkGrey),
+                SetForegroundColor(muted()),
                 Print("·"),
                 ResetColor,
                 Print("\r\n"),
@@ -204,178 +447,121 @@ fn run_interactive_impl(
         execute!(stdout, Print("\r\n")).ok();
     }
 
+    // ←/→ walk through the stages of a pipeline, a sentence each
+    let stages = walkthrough::stages(&command);
+    let stage_explainer = stage_explainer.filter(|_| stages.len() > 1);
+    let run_prompt = if stage_explainer.is_some() { RUN_PROMPT_STAGES } else { RUN_PROMPT };
+    let mut walk: Option<usize> = None;
+    // None while the stage is being explained
+    let mut stage_notes: HashMap<usize, Option<Result<String, String>>> = HashMap::new();
+    let (stage_tx, stage_rx) = mpsc::channel::<(usize, Result<String, String>)>();
+
     // Print command + prompt
     let loading_text = if has_explanation {
         "Loading explanation..."
+    } else if rules.require_explanation() {
+        NO_EXPLANATION_PROMPT
     } else {
-        "Press Enter to run, Ctrl+C to cancel... "
+        run_prompt
     };
     execute!(
         stdout,
-        SetForegroundColor(Color::Cyan),
-        Print(&command),
-        ResetColor,
+        Print(crlf(&highlight_command(&command))),
         Print("\r\n"),
-        SetForegroundColor(Color::DarkGrey),
+        SetForegroundColor(muted()),
         Print(loading_text),
         ResetColor,
     ).ok();
     stdout.flush().ok();
 
+    // Whatever is on the prompt line, to put back after a cancelled refine
+    let mut prompt_text = loading_text;
     let mut explanation_text: Option<String> = None;
     let mut explanation_printed = false;
 
+    // Compound commands list their segments' ratings above the explanation
+    let badges = segment_badges(&command, rules, cmd_result.safe);
+    let render = |exp: &str| match &badges {
+        Some(badges) => format!("{}\n{}", badges, format_explanation(exp, style)),
+        None => format_explanation(exp, style),
+    };
+
     loop {
         // Check for explanation (only for non-safe commands that need confirmation)
         if let Some(ref rx) = explanation_rx {
             if !explanation_printed {
+                // Show what has streamed in so far, keeping the loading prompt
+                if let Some(partial) = partial_rx.try_iter().last() {
+                    fill_reserved(&mut stdout, &render(&partial), reserved_lines, command_rows);
+                    execute!(
+                        stdout,
+                        Clear(ClearType::CurrentLine),
+                        Print(crlf(&highlight_command(&command))),
+                        Print("\r\n"),
+                        Clear(ClearType::CurrentLine),
+                        SetForegroundColor(muted()),
+                        Print(loading_text),
+                        ResetColor,
+                    ).ok();
+                    stdout.flush().ok();
+                }
+
                 match rx.try_recv() {
                     Ok(Ok(exp)) => {
-                        let is_danger = exp.contains("[DANGER]");
-                        let formatted = format_explanation(&exp, style);
-                        let exp_lines: Vec<&str> = formatted.lines().collect();
-
-                        // Move cursor up to the reserved space
-                        // (current position is after prompt, so go up: 1 prompt + 1 command + 1 blank + RESERVED_LINES)
-                        let lines_to_go_up = 2 + 1 + RESERVED_LINES;
-                        execute!(stdout, MoveUp(lines_to_go_up), MoveToColumn(0)).ok();
-
-                        // Fill in explanation (overwrite placeholder lines)
-                        for line in exp_lines.iter().take(RESERVED_LINES as usize) {
-                            execute!(
-                                stdout,
-                                Clear(ClearType::CurrentLine),
-                                Print(*line),
-                                Print("\r\n"),
-                            ).ok();
-                        }
-
-                        // Clear any remaining placeholder lines
-                        for _ in exp_lines.len()..RESERVED_LINES as usize {
-                            execute!(stdout, Clear(ClearType::CurrentLine), Print("\r\n")).ok();
-                        }
-
-                        // Skip blank line, move to command line
-                        execute!(stdout, Print("\r\n")).ok();
+                        let is_danger = verdict == Verdict::Danger
+                            || (verdict != Verdict::Safe && exp.contains("[DANGER]"));
+                        fill_reserved(&mut stdout, &render(&exp), reserved_lines, command_rows);
 
                         // DANGER: Show command and wait for Enter to copy to clipboard
                         if is_danger {
-                            execute!(
-                                stdout,
-                                Clear(ClearType::CurrentLine),
-                                SetForegroundColor(Color::Red),
-                                Print(&command),
-                                ResetColor,
-                                Print("\r\n"),
-                                Clear(ClearType::CurrentLine),
-                                SetForegroundColor(Color::Red),
-                                Print("⚠️  DANGER: "),
-                                ResetColor,
-                                SetForegroundColor(Color::DarkGrey),
-                                Print("Press Enter to copy to clipboard, Ctrl+C to cancel... "),
-                                ResetColor,
-                            ).ok();
-                            stdout.flush().ok();
-
-                            // Wait for Enter key
-                            loop {
-                                if let Ok(true) = event::poll(std::time::Duration::from_millis(100)) {
-                                    if let Ok(Event::Key(key_event)) = event::read() {
-                                        match key_event.code {
-                                            KeyCode::Enter => {
-                                                // Copy to clipboard (macOS)
-                                                if let Ok(mut child) = std::process::Command::new("pbcopy")
-                                                    .stdin(std::process::Stdio::piped())
-                                                    .spawn()
-                                                {
-                                                    if let Some(stdin) = child.stdin.as_mut() {
-                                                        let _ = stdin.write_all(command.as_bytes());
-                                                    }
-                                                    let _ = child.wait();
-                                                }
-
-                                                execute!(
-                                                    stdout,
-                                                    MoveToColumn(0),
-                                                    Clear(ClearType::CurrentLine),
-                                                    SetForegroundColor(Color::Red),
-                                                    Print("⚠️  Copied to clipboard. Paste to run.\r\n"),
-                                                    ResetColor,
-                                                ).ok();
-                                                stdout.flush().ok();
-                                                break;
-                                            }
-                                            KeyCode::Char('c') if key_event.modifiers.contains(event::KeyModifiers::CONTROL) => {
-                                                execute!(
-                                                    stdout,
-                                                    MoveToColumn(0),
-                                                    Clear(ClearType::CurrentLine),
-                                                    SetForegroundColor(Color::DarkGrey),
-                                                    Print("Cancelled.\r\n"),
-                                                    ResetColor,
-                                                ).ok();
-                                                stdout.flush().ok();
-                                                break;
-                                            }
-                                            KeyCode::Esc => {
-                                                execute!(
-                                                    stdout,
-                                                    MoveToColumn(0),
-                                                    Clear(ClearType::CurrentLine),
-                                                    SetForegroundColor(Color::DarkGrey),
-                                                    Print("Cancelled.\r\n"),
-                                                    ResetColor,
-                                                ).ok();
-                                                stdout.flush().ok();
-                                                break;
-                                            }
-                                            _ => {}
-                                        }
-                                    }
-                                }
-                            }
-
+                            let run = confirm_danger(&mut stdout, &command, rules.confirm_word());
                             terminal::disable_raw_mode().ok();
-                            save_log(&query, &command, Some(exp), style);
-                            return Ok(TuiResult::Cancel);
+                            save_log(&query, &command, Some(exp), style, cmd_result.provenance.as_ref());
+                            return Ok(if run { TuiResult::Execute(command) } else { TuiResult::Cancel });
                         }
 
                         // CAUTION: Show command and wait for confirmation
                         execute!(
                             stdout,
                             Clear(ClearType::CurrentLine),
-                            SetForegroundColor(Color::Cyan),
-                            Print(&command),
-                            ResetColor,
+                            Print(crlf(&highlight_command(&command))),
                             Print("\r\n"),
                             Clear(ClearType::CurrentLine),
-                            SetForegroundColor(Color::DarkGrey),
-                            Print("Press Enter to run, Ctrl+C to cancel... "),
+                            SetForegroundColor(muted()),
+                            Print(run_prompt),
                             ResetColor,
                         ).ok();
                         stdout.flush().ok();
 
+                        prompt_text = run_prompt;
                         explanation_text = Some(exp);
                         explanation_printed = true;
                     }
                     Ok(Err(_)) => {
                         // Explanation failed - clear placeholder and show simple prompt
-                        let lines_to_go_up = 2 + 1 + RESERVED_LINES;
+                        let lines_to_go_up = command_rows + 1 + 1 + reserved_lines;
                         execute!(stdout, MoveUp(lines_to_go_up), MoveToColumn(0)).ok();
-                        for _ in 0..RESERVED_LINES {
+                        for _ in 0..reserved_lines {
                             execute!(stdout, Clear(ClearType::CurrentLine), Print("\r\n")).ok();
                         }
+                        if verdict == Verdict::Danger {
+                            execute!(stdout, Print("\r\n")).ok();
+                            let word = rules.confirm_word().filter(|_| !rules.require_explanation());
+                            let run = confirm_danger(&mut stdout, &command, word);
+                            terminal::disable_raw_mode().ok();
+                            save_log(&query, &command, None, style, cmd_result.provenance.as_ref());
+                            return Ok(if run { TuiResult::Execute(command) } else { TuiResult::Cancel });
+                        }
+                        prompt_text = if rules.require_explanation() { NO_EXPLANATION_PROMPT } else { run_prompt };
                         execute!(
                             stdout,
                             Print("\r\n"),
                             Clear(ClearType::CurrentLine),
-                            SetForegroundColor(Color::Cyan),
-                            Print(&command),
-                            ResetColor,
+                            Print(crlf(&highlight_command(&command))),
                             Print("\r\n"),
                             Clear(ClearType::CurrentLine),
-                            SetForegroundColor(Color::DarkGrey),
-                            Print("Press Enter to run, Ctrl+C to cancel... "),
+                            SetForegroundColor(muted()),
+                            Print(prompt_text),
                             ResetColor,
                         ).ok();
                         stdout.flush().ok();
@@ -389,22 +575,545 @@ fn run_interactive_impl(
             }
         }
 
+        if let Ok((index, note)) = stage_rx.try_recv() {
+            stage_notes.insert(index, Some(note));
+            if walk == Some(index) {
+                draw_stage(&mut stdout, &command, command_rows, &stages, index, stage_notes[&index].as_ref());
+            }
+        }
+
         // Poll for keys
         if event::poll(Duration::from_millis(100)).unwrap_or(false) {
             if let Ok(Event::Key(key_event)) = event::read() {
                 match key_event {
+                    // Policy: nothing runs until the explanation is on screen
+                    KeyEvent { code: KeyCode::Enter, .. }
+                        if rules.require_explanation() && explanation_text.is_none() => {}
                     KeyEvent { code: KeyCode::Enter, .. } => {
                         terminal::disable_raw_mode().ok();
                         execute!(stdout, Print("\r\n")).ok();
-                        save_log(&query, &command, explanation_text, style);
+                        save_log(&query, &command, explanation_text, style, cmd_result.provenance.as_ref());
                         return Ok(TuiResult::Execute(command));
                     }
+                    // Not while the explanation may still redraw the command
+                    KeyEvent { code: code @ (KeyCode::Left | KeyCode::Right), .. }
+                        if explanation_printed || !has_explanation =>
+                    {
+                        let Some((explain, model)) = &stage_explainer else {
+                            continue;
+                        };
+                        let last = stages.len() - 1;
+                        let index = match (walk, code) {
+                            (None, KeyCode::Left) => last,
+                            (None, _) => 0,
+                            (Some(i), KeyCode::Left) => i.saturating_sub(1),
+                            (Some(i), _) => (i + 1).min(last),
+                        };
+                        walk = Some(index);
+                        let note = stage_notes.entry(index).or_insert_with(|| {
+                            let stage = command[stages[index].clone()].to_string();
+                            let stage_options = walkthrough::stage_options(&command, &stage, &options);
+                            let (explain, model, tx) = (explain.clone(), *model, stage_tx.clone());
+                            thread::spawn(move || {
+                                let note =
+                                    walkthrough::explain_stage(&stage, &stage_options, model, |s, o| explain(s, o));
+                                let _ = tx.send((index, note));
+                            });
+                            None
+                        });
+                        draw_stage(&mut stdout, &command, command_rows, &stages, index, note.as_ref());
+                    }
                     KeyEvent { code: KeyCode::Char('c'), modifiers: KeyModifiers::CONTROL, .. } |
                     KeyEvent { code: KeyCode::Esc, .. } => {
                         terminal::disable_raw_mode().ok();
                         execute!(stdout, Print("\r\n")).ok();
                         return Ok(TuiResult::Cancel);
                     }
+                    KeyEvent { code: KeyCode::Char('r'), modifiers: KeyModifiers::NONE, .. } => {
+                        if let Some(refinement) = read_refinement(&mut stdout) {
+                            terminal::disable_raw_mode().ok();
+                            execute!(stdout, Print("\r\n")).ok();
+                            return Ok(TuiResult::Refine { refinement, command });
+                        }
+                        execute!(
+                            stdout,
+                            MoveToColumn(0),
+                            Clear(ClearType::CurrentLine),
+                            SetForegroundColor(muted()),
+                            Print(prompt_text),
+                            ResetColor,
+                        ).ok();
+                        stdout.flush().ok();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// A request in flight, handed to the plain transcript
+struct Pending<'a> {
+    cmd_rx: mpsc::Receiver<Result<CommandResult, SlashcmdError>>,
+    wait_rx: mpsc::Receiver<Duration>,
+    /// The edge stream's explanation; None in direct mode
+    edge_exp_rx: Option<mpsc::Receiver<Result<String, String>>>,
+    gemini_api_key: Option<String>,
+    previous_command: Option<&'a str>,
+    force_wait: bool,
+}
+
+/// The same decisions as the layout, as a transcript: each step is printed
+/// once, on its own line, and answers are typed and confirmed with Enter
+fn run_plain(
+    query: &str,
+    pending: Pending,
+    options: &ExplainOptions,
+    rules: &SafetyRules,
+) -> Result<TuiResult, SlashcmdError> {
+    let style = &options.style;
+    let is_edge_mode = pending.edge_exp_rx.is_some();
+    println!("Generating command...");
+    let started = Instant::now();
+    let mut deadline = started + Duration::from_secs(30);
+    let cmd_result = loop {
+        match pending.cmd_rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(result) => {
+                if is_edge_mode {
+                    telemetry::record(Provider::Edge, started.elapsed(), result.is_ok());
+                }
+                break result?;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) if Instant::now() < deadline => {}
+            Err(_) => return Err(SlashcmdError::Network("timed out waiting for a command".to_string())),
+        }
+        if let Ok(wait) = pending.wait_rx.try_recv() {
+            deadline += wait;
+            println!("Rate limited, retrying in {} seconds.", wait.as_secs_f64().ceil());
+        }
+    };
+    if let Some(question) = cmd_result.question {
+        println!("Question: {}", question);
+        for (i, option) in cmd_result.options.iter().enumerate() {
+            println!("  {}) {}", i + 1, option);
+        }
+        return Ok(match ask("Answer (a number or your own words, Enter to cancel): ") {
+            answer if answer.is_empty() => TuiResult::Cancel,
+            answer => TuiResult::Clarify { answer: chosen(&cmd_result.options, &answer), question },
+        });
+    }
+    let command = layout::normalize(&cmd_result.command);
+    clipboard::generated(&command);
+
+    println!("Command: {}", command);
+    if let Some(change) = pending.previous_command.and_then(|previous| diff::describe(previous, &command)) {
+        println!("Changed: {}", change);
+    }
+    for text in context::kube_banner(&command).into_iter().chain(context::cloud_banner(&command)) {
+        println!("{}", text);
+    }
+    let mut lint = coreutils::lint(&command);
+    lint.extend(syntax::check(&command, prompt::strip_context(query)));
+    for problem in &lint {
+        println!("Warning: {}", problem);
+    }
+
+    let verdict = rules.check(&command);
+    if verdict == Verdict::Blocked {
+        println!("Blocked by policy.");
+        save_log(query, &command, None, style, cmd_result.provenance.as_ref());
+        return Ok(TuiResult::Cancel);
+    }
+    if verdict == Verdict::Denied {
+        println!("On the denylist, slashcmd won't run this.");
+        if ask("Copy it to the clipboard? (y/n) ").eq_ignore_ascii_case("y") {
+            let _ = clipboard::copy(&command);
+            println!("Copied.");
+        }
+        save_log(query, &command, None, style, cmd_result.provenance.as_ref());
+        return Ok(TuiResult::Cancel);
+    }
+    if rules.may_auto_execute(verdict, cmd_result.safe) && !pending.force_wait && lint.is_empty() {
+        save_log(query, &command, None, style, cmd_result.provenance.as_ref());
+        return Ok(TuiResult::Execute(command));
+    }
+
+    // The explanation arrives whole: partial text would be read out piecemeal
+    let (partial_tx, _partial_rx) = mpsc::channel::<String>();
+    let explanation_rx = match pending.edge_exp_rx {
+        Some(rx) => Some(rx),
+        None => spawn_explanation(&command, pending.gemini_api_key.as_deref(), options, partial_tx),
+    };
+    let explanation = explanation_rx.and_then(|rx| {
+        println!("Explaining...");
+        rx.recv_timeout(Duration::from_secs(60)).ok()?.ok()
+    });
+    if let Some(badges) = segment_badges(&command, rules, cmd_result.safe) {
+        println!("Parts:\n{}", badges);
+    }
+    match &explanation {
+        Some(text) => println!("Explanation:\n{}", plain_explanation(text)),
+        None if rules.require_explanation() => {
+            println!("An explanation is required by policy but unavailable.");
+            return Ok(match ask("Refinement (Enter to cancel): ") {
+                refinement if refinement.is_empty() => TuiResult::Cancel,
+                refinement => TuiResult::Refine { refinement, command },
+            });
+        }
+        None => {}
+    }
+
+    let is_danger = verdict == Verdict::Danger
+        || (verdict != Verdict::Safe && explanation.as_deref().is_some_and(|e| e.contains("[DANGER]")));
+    if is_danger {
+        println!("DANGER: this command is destructive.");
+        let word = rules.confirm_word().filter(|_| explanation.is_some() || !rules.require_explanation());
+        let answer = match word {
+            Some(word) => ask(&format!("Type {} to run it, c to copy it to the clipboard, Enter to cancel: ", word)),
+            None => ask("Type c to copy it to the clipboard, Enter to cancel: "),
+        };
+        save_log(query, &command, explanation, style, cmd_result.provenance.as_ref());
+        if word.is_some_and(|w| answer == w) {
+            return Ok(TuiResult::Execute(command));
+        }
+        if answer.eq_ignore_ascii_case("c") {
+            let _ = clipboard::copy(&command);
+            println!("Copied to clipboard. Paste to run.");
+        } else {
+            println!("Cancelled.");
+        }
+        return Ok(TuiResult::Cancel);
+    }
+
+    match ask("Run it? (y to run, r to refine, Enter to cancel) ").to_lowercase().as_str() {
+        "y" | "yes" => {
+            save_log(query, &command, explanation, style, cmd_result.provenance.as_ref());
+            Ok(TuiResult::Execute(command))
+        }
+        "r" => match ask("Refinement: ") {
+            refinement if refinement.is_empty() => Ok(TuiResult::Cancel),
+            refinement => Ok(TuiResult::Refine { refinement, command }),
+        },
+        _ => {
+            println!("Cancelled.");
+            Ok(TuiResult::Cancel)
+        }
+    }
+}
+
+/// Print `prompt` and read the answer line, trimmed. Empty on end of input.
+fn ask(prompt: &str) -> String {
+    print!("{}", prompt);
+    io::stdout().flush().ok();
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).ok();
+    answer.trim().to_string()
+}
+
+/// An explanation without markdown fences and emphasis, which a screen
+/// reader would otherwise spell out
+fn plain_explanation(exp: &str) -> String {
+    exp.lines()
+        .filter(|line| !line.starts_with("```"))
+        .map(|line| line.replace("**", ""))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Read a refinement on the prompt line, in raw mode. Up and Down walk
+/// through earlier queries like shell history. None if the user backs out.
+fn read_refinement(stdout: &mut io::Stdout) -> Option<String> {
+    read_line(stdout, "Refine: ", QueryHistory::new(recent_queries()))
+}
+
+/// The option an answer picks by number, or the answer as typed
+fn chosen(options: &[String], answer: &str) -> String {
+    match answer.parse::<usize>().ok().and_then(|n| options.get(n.wrapping_sub(1))) {
+        Some(option) => option.clone(),
+        None => answer.to_string(),
+    }
+}
+
+/// Read a line after `label`, in raw mode, with `history` on Up and Down.
+/// None if the user backs out.
+fn read_line(stdout: &mut io::Stdout, label: &str, mut history: QueryHistory) -> Option<String> {
+    let mut input = String::new();
+    loop {
+        execute!(
+            stdout,
+            MoveToColumn(0),
+            Clear(ClearType::CurrentLine),
+            SetForegroundColor(muted()),
+            Print(label),
+            ResetColor,
+            Print(&input),
+        ).ok();
+        stdout.flush().ok();
+
+        let key = match event::read() {
+            Ok(Event::Key(key)) => key,
+            Ok(_) => continue,
+            Err(_) => return None,
+        };
+        match key.code {
+            KeyCode::Enter if !input.trim().is_empty() => return Some(input.trim().to_string()),
+            KeyCode::Esc => return None,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return None,
+            KeyCode::Up => {
+                if let Some(query) = history.older(&input) {
+                    input = query;
+                }
+            }
+            KeyCode::Down => {
+                if let Some(query) = history.newer() {
+                    input = query;
+                }
+            }
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            _ => {}
+        }
+    }
+}
+
+/// Distinct queries from the local history, newest first, without the
+/// context we appended when sending them
+fn recent_queries() -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    logs::recent_entries(QUERY_HISTORY_LEN)
+        .into_iter()
+        .map(|entry| prompt::strip_context(&entry.query).to_string())
+        .filter(|query| seen.insert(query.clone()))
+        .collect()
+}
+
+/// Position while browsing earlier queries with Up and Down
+struct QueryHistory {
+    /// Newest first
+    entries: Vec<String>,
+    /// Index into `entries`; None while editing the draft
+    pos: Option<usize>,
+    /// What was typed before the first Up, restored by Down
+    draft: String,
+}
+
+impl QueryHistory {
+    fn new(entries: Vec<String>) -> Self {
+        Self {
+            entries,
+            pos: None,
+            draft: String::new(),
+        }
+    }
+
+    /// One entry further back; None at the oldest
+    fn older(&mut self, current: &str) -> Option<String> {
+        let next = self.pos.map_or(0, |p| p + 1);
+        let entry = self.entries.get(next)?.clone();
+        if self.pos.is_none() {
+            self.draft = current.to_string();
+        }
+        self.pos = Some(next);
+        Some(entry)
+    }
+
+    /// One entry forward, then the draft; None when already on the draft
+    fn newer(&mut self) -> Option<String> {
+        match self.pos? {
+            0 => {
+                self.pos = None;
+                Some(std::mem::take(&mut self.draft))
+            }
+            p => {
+                self.pos = Some(p - 1);
+                Some(self.entries[p - 1].clone())
+            }
+        }
+    }
+}
+
+/// Write an explanation over the reserved lines above the command, from the
+/// prompt line where the cursor sits, and leave the cursor on the command line.
+/// Lines are wrapped to the terminal width first, so every row is one line.
+fn fill_reserved(stdout: &mut io::Stdout, formatted: &str, reserved_lines: u16, command_rows: u16) {
+    let width = terminal::size().map(|(cols, _)| cols as usize).unwrap_or(80);
+    let exp_lines = wrap::fit(formatted, width, reserved_lines as usize);
+
+    // Move cursor up to the reserved space
+    // (current position is after prompt, so go up: 1 prompt + the command + 1 blank + reserved_lines)
+    let lines_to_go_up = 1 + command_rows + 1 + reserved_lines;
+    execute!(stdout, MoveUp(lines_to_go_up), MoveToColumn(0)).ok();
+
+    // Fill in explanation (overwrite placeholder lines)
+    for line in exp_lines.iter().take(reserved_lines as usize) {
+        execute!(
+            stdout,
+            Clear(ClearType::CurrentLine),
+            Print(line),
+            Print("\r\n"),
+        ).ok();
+    }
+
+    // Clear any remaining placeholder lines
+    for _ in exp_lines.len()..reserved_lines as usize {
+        execute!(stdout, Clear(ClearType::CurrentLine), Print("\r\n")).ok();
+    }
+
+    // Skip blank line, move to command line
+    execute!(stdout, Print("\r\n")).ok();
+}
+
+/// Explains one pipeline stage off the UI thread, with the model name its
+/// answers are cached under
+type StageExplainer = (Arc<dyn Fn(&str, &ExplainOptions) -> Result<String, String> + Send + Sync>, &'static str);
+
+#[cfg_attr(not(feature = "gemini"), allow(unused_variables))]
+fn stage_explainer(source: &CommandSource, gemini_api_key: Option<&str>) -> Option<StageExplainer> {
+    match source {
+        #[cfg(feature = "edge")]
+        CommandSource::Edge { token } => {
+            let token = token.clone();
+            let explain = move |stage: &str, options: &ExplainOptions| {
+                let client = match &token {
+                    Some(t) => EdgeClient::new(t.clone()),
+                    None => EdgeClient::with_test_jwt(),
+                };
+                client.explain(stage, options).map_err(|e| e.to_string())
+            };
+            Some((Arc::new(explain), "edge"))
+        }
+        #[cfg(feature = "groq")]
+        CommandSource::Direct { .. } => {
+            #[cfg(feature = "gemini")]
+            if let Some(key) = gemini_api_key {
+                let key = key.to_string();
+                let explain = move |stage: &str, options: &ExplainOptions| {
+                    GeminiClient::new(key.clone()).explain(stage, options).map_err(|e| e.to_string())
+                };
+                return Some((Arc::new(explain), crate::gemini::MODEL));
+            }
+            None
+        }
+    }
+}
+
+/// Redraw the command with stage `index` in reverse video, and what the
+/// stage does (`note`, None while it's explained) on the prompt line
+fn draw_stage(
+    stdout: &mut io::Stdout,
+    command: &str,
+    command_rows: u16,
+    stages: &[Range<usize>],
+    index: usize,
+    note: Option<&Result<String, String>>,
+) {
+    let stage = stages[index].clone();
+    let shown = format!(
+        "{}{}{}{}{}",
+        highlight_command(&command[..stage.start]),
+        SetAttribute(Attribute::Reverse),
+        &command[stage.clone()],
+        SetAttribute(Attribute::Reset),
+        highlight_command(&command[stage.end..]),
+    );
+    let note = match note {
+        None => "Explaining this stage...".to_string(),
+        Some(Ok(sentence)) => sentence.clone(),
+        Some(Err(e)) => format!("No explanation: {}", e),
+    };
+    // One row, or the cursor math above is off
+    let width = terminal::size().map(|(cols, _)| cols as usize).unwrap_or(80);
+    let line = format!("{}/{} {}", index + 1, stages.len(), note);
+    let line = wrap::fit(&line, width, 1).into_iter().next().unwrap_or_default();
+    execute!(
+        stdout,
+        MoveUp(command_rows),
+        MoveToColumn(0),
+        Clear(ClearType::FromCursorDown),
+        Print(crlf(&shown)),
+        Print("\r\n"),
+        Print(line),
+    ).ok();
+    stdout.flush().ok();
+}
+
+/// `text` for printing in raw mode, where a bare `\n` doesn't return to
+/// the first column
+fn crlf(text: &str) -> String {
+    text.replace('\n', "\r\n")
+}
+
+/// DANGER commands normally aren't executed: show the command in red and copy
+/// it to the clipboard on Enter so running it is a deliberate paste. With a
+/// `confirm_word` set in `[safety]`, typing that word exactly runs it instead.
+/// Returns true if the command should be executed.
+fn confirm_danger(stdout: &mut io::Stdout, command: &str, confirm_word: Option<&str>) -> bool {
+    let prompt = match confirm_word {
+        Some(word) => format!("Type {} to run, Enter to copy to clipboard, Esc to cancel: ", word),
+        None => "Press Enter to copy to clipboard, Ctrl+C to cancel... ".to_string(),
+    };
+    execute!(
+        stdout,
+        Clear(ClearType::CurrentLine),
+        Print(crlf(&level(Level::Danger, command))),
+        Print("\r\n"),
+    ).ok();
+
+    let mut typed = String::new();
+    let mut redraw = true;
+    loop {
+        if redraw {
+            execute!(
+                stdout,
+                MoveToColumn(0),
+                Clear(ClearType::CurrentLine),
+                Print(level(Level::Danger, "⚠️  DANGER:")),
+                Print(" "),
+                SetForegroundColor(muted()),
+                Print(&prompt),
+                ResetColor,
+                Print(&typed),
+            ).ok();
+            stdout.flush().ok();
+            redraw = false;
+        }
+
+        if let Ok(true) = event::poll(Duration::from_millis(100)) {
+            if let Ok(Event::Key(key_event)) = event::read() {
+                match key_event.code {
+                    KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        finish_danger(stdout, &dim("Cancelled."));
+                        return false;
+                    }
+                    KeyCode::Esc => {
+                        finish_danger(stdout, &dim("Cancelled."));
+                        return false;
+                    }
+                    // Only exactly the configured word runs the command
+                    KeyCode::Enter if confirm_word.is_some_and(|w| typed == w) => {
+                        finish_danger(stdout, &level(Level::Danger, "⚠️  Running."));
+                        return true;
+                    }
+                    KeyCode::Enter if typed.is_empty() => {
+                        let _ = clipboard::copy(command);
+                        finish_danger(stdout, &level(Level::Danger, "⚠️  Copied to clipboard. Paste to run."));
+                        return false;
+                    }
+                    KeyCode::Enter => {
+                        // Wrong word: start over rather than guessing intent
+                        typed.clear();
+                        redraw = true;
+                    }
+                    KeyCode::Backspace if confirm_word.is_some() => {
+                        typed.pop();
+                        redraw = true;
+                    }
+                    KeyCode::Char(c) if confirm_word.is_some() => {
+                        typed.push(c);
+                        redraw = true;
+                    }
                     _ => {}
                 }
             }
@@ -412,7 +1121,51 @@ fn run_interactive_impl(
     }
 }
 
-fn format_explanation(exp: &str, style: ExplainStyle) -> String {
+/// Replace the DANGER prompt line with a final (styled) status message
+fn finish_danger(stdout: &mut io::Stdout, message: &str) {
+    execute!(
+        stdout,
+        MoveToColumn(0),
+        Clear(ClearType::CurrentLine),
+        Print(message),
+        Print("\r\n"),
+    ).ok();
+    stdout.flush().ok();
+}
+
+/// Secondary text: dark grey, or the terminal's own color when the palette
+/// asks for full contrast
+fn muted() -> Color {
+    match palette() {
+        Palette::Standard => Color::DarkGrey,
+        _ => Color::Reset,
+    }
+}
+
+/// One badge line per segment of a compound command, e.g. `[CAUTION] rm -rf
+/// build`. Segments no rule matched carry the model's rating of the whole.
+fn segment_badges(command: &str, rules: &SafetyRules, model_safe: bool) -> Option<String> {
+    let segments = rules.check_segments(command);
+    if segments.len() < 2 {
+        return None;
+    }
+    let lines: Vec<String> = segments
+        .iter()
+        .map(|(segment, verdict)| {
+            let badge = match verdict {
+                Verdict::Blocked | Verdict::Denied | Verdict::Danger => "[DANGER]",
+                Verdict::Confirm => "[CAUTION]",
+                Verdict::Safe => "[SAFE]",
+                Verdict::Unknown if model_safe => "[SAFE]",
+                Verdict::Unknown => "[CAUTION]",
+            };
+            format_safety(&format!("{} {}", badge, segment))
+        })
+        .collect();
+    Some(lines.join("\n"))
+}
+
+fn format_explanation(exp: &str, style: &ExplainStyle) -> String {
     let mut result = String::new();
     let mut in_code_block = false;
     let mut code_buffer = String::new();
@@ -439,40 +1192,136 @@ fn format_explanation(exp: &str, style: ExplainStyle) -> String {
     result.trim_end().to_string()
 }
 
-fn get_command(query: &str, api_key: &str) -> Result<CommandResult, String> {
+#[cfg(feature = "groq")]
+fn get_command(
+    query: &str,
+    api_key: &str,
+    generation: &Generation,
+    wait_tx: mpsc::Sender<Duration>,
+) -> Result<CommandResult, SlashcmdError> {
+    let started = Instant::now();
     if let Some(mut s) = IpcClient::try_connect() {
-        let cmd = IpcClient::send_request(&mut s, &IpcRequest::Command { query: query.into() })?;
-        // Daemon returns just command string for now, assume safe=false (conservative)
-        return Ok(CommandResult { command: cmd, safe: false });
+        let request = IpcRequest::Command {
+            query: query.into(),
+            client: Some(ipc::client_id()),
+            generation: *generation,
+        };
+        let cmd = IpcClient::send_request(&mut s, &request);
+        telemetry::record(Provider::Daemon, started.elapsed(), cmd.is_ok());
+        let provenance = Provenance {
+            model: Some(groq::GROQ_MODEL.to_string()),
+            daemon: true,
+            ..Provenance::new("local", "groq", started.elapsed())
+        };
+        // Don't auto-trust the daemon's safety call; assume safe=false (conservative)
+        return Ok(CommandResult {
+            safe: false,
+            provenance: Some(provenance),
+            ..prompt::parse_response(&cmd?)?
+        });
     }
-    GroqClient::new(api_key.into()).query(query)
+    let result = GroqClient::new(api_key.into())
+        .with_rate_limit_notice(wait_tx)
+        .query(query, generation);
+    telemetry::record(Provider::Groq, started.elapsed(), result.is_ok());
+    result
+}
+
+/// Explain in the background with Gemini, if we have a key. The text so far
+/// goes to `partial_tx` as it streams in, the finished explanation to the
+/// returned channel.
+#[cfg(feature = "gemini")]
+fn spawn_explanation(
+    command: &str,
+    gemini_api_key: Option<&str>,
+    options: &ExplainOptions,
+    partial_tx: mpsc::Sender<String>,
+) -> Option<mpsc::Receiver<Result<String, String>>> {
+    let (exp_tx, exp_rx) = mpsc::channel();
+    let cmd = command.to_string();
+    let key = gemini_api_key?.to_string();
+    let opts = options.clone();
+    thread::spawn(move || {
+        let _ = exp_tx.send(get_explanation(&cmd, &key, &opts, partial_tx).map_err(|e| e.to_string()));
+    });
+    Some(exp_rx)
+}
+
+#[cfg(not(feature = "gemini"))]
+fn spawn_explanation(
+    _command: &str,
+    _gemini_api_key: Option<&str>,
+    _options: &ExplainOptions,
+    _partial_tx: mpsc::Sender<String>,
+) -> Option<mpsc::Receiver<Result<String, String>>> {
+    None
 }
 
-fn get_explanation(cmd: &str, api_key: &str, style: ExplainStyle) -> Result<String, String> {
+#[cfg(feature = "gemini")]
+fn get_explanation(
+    cmd: &str,
+    api_key: &str,
+    options: &ExplainOptions,
+    partial_tx: mpsc::Sender<String>,
+) -> Result<String, SlashcmdError> {
+    let mut so_far = String::new();
+    let on_chunk = |piece: &str| {
+        so_far.push_str(piece);
+        let _ = partial_tx.send(so_far.clone());
+    };
     if let Some(mut s) = IpcClient::try_connect() {
-        return IpcClient::send_request(&mut s, &IpcRequest::Explain { command: cmd.into(), style });
+        let request = IpcRequest::Explain {
+            command: cmd.into(),
+            style: options.style.clone(),
+            verbosity: options.verbosity,
+            lang: options.lang.clone(),
+            generation: options.generation,
+            stream: true,
+        };
+        return IpcClient::send_request_streaming(&mut s, &request, on_chunk);
     }
-    GeminiClient::new(api_key.into()).explain(cmd, style)
+    GeminiClient::new(api_key.into()).explain_streaming(cmd, options, on_chunk)
 }
 
-fn save_log(query: &str, command: &str, explanation: Option<String>, style: ExplainStyle) {
-    let entry = logs::create_entry(query, command, explanation, style);
+fn save_log(
+    query: &str,
+    command: &str,
+    explanation: Option<String>,
+    style: &ExplainStyle,
+    provenance: Option<&Provenance>,
+) {
+    let entry = logs::create_entry(query, command, explanation, style, provenance.cloned());
     let _ = logs::save_log(&entry);
+    let _ = audit::record(AuditEvent::Generated, prompt::strip_context(query), command);
 }
 
-/// Get command via edge proxy
-fn get_command_edge(query: &str) -> Result<CommandResult, String> {
-    EdgeClient::with_test_jwt().query(query)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-/// Get command and explanation via edge proxy (SSE)
-fn get_command_and_explanation_edge(query: &str, style: ExplainStyle) -> Result<(CommandResult, Option<String>), String> {
-    let style_str = match style {
-        ExplainStyle::Typescript => "typescript",
-        ExplainStyle::Python => "python",
-        ExplainStyle::Ruby => "ruby",
-        ExplainStyle::Human => "human",
-    };
-    let response = EdgeClient::with_test_jwt().query_with_explanation(query, style_str)?;
-    Ok((response.command, response.explanation))
+    #[test]
+    fn test_plain_explanation() {
+        let exp = "**[CAUTION]** Deletes build output\n```typescript\nfs.rm(\"build\")\n```";
+        assert_eq!(plain_explanation(exp), "[CAUTION] Deletes build output\nfs.rm(\"build\")");
+    }
+
+    #[test]
+    fn test_chosen() {
+        let options = vec!["src/".to_string(), "the whole repo".to_string()];
+        assert_eq!(chosen(&options, "2"), "the whole repo");
+        assert_eq!(chosen(&options, "0"), "0");
+        assert_eq!(chosen(&options, "only tests/"), "only tests/");
+    }
+
+    #[test]
+    fn test_query_history() {
+        let mut history = QueryHistory::new(vec!["newest".to_string(), "oldest".to_string()]);
+        assert_eq!(history.newer(), None);
+        assert_eq!(history.older("draft").as_deref(), Some("newest"));
+        assert_eq!(history.older("newest").as_deref(), Some("oldest"));
+        assert_eq!(history.older("oldest"), None);
+        assert_eq!(history.newer().as_deref(), Some("newest"));
+        assert_eq!(history.newer().as_deref(), Some("draft"));
+        assert_eq!(history.newer(), None);
+    }
 }
@@ -0,0 +1,218 @@
+//! `slashcmd self-update` and the new-version notice
+//!
+//! Releases are the GitHub releases install.sh downloads from: a
+//! `slashcmd-<platform>` binary next to its `.sha256`. The download must
+//! match that checksum, then goes next to the running executable and is
+//! renamed over it, so an interrupted update leaves the old binary working.
+//! A daemon left from the old version restarts on the next request.
+//!
+//! With `update_check = true` in config.toml, requests from a terminal look
+//! for a new release at most once a day, in the background, and mention it
+//! on a later run.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::error::SlashcmdError;
+use crate::highlight;
+use crate::ipc::{self, VERSION};
+use crate::logs;
+
+const REPO: &str = "lgandecki/slashcmd";
+
+/// How often the background check runs
+const CHECK_INTERVAL_SECS: u64 = 24 * 60 * 60;
+
+/// Larger than any release binary; guards against a runaway download
+const MAX_BINARY_BYTES: u64 = 100 * 1024 * 1024;
+
+/// Result of the last background check
+#[derive(Serialize, Deserialize, Default)]
+struct UpdateState {
+    checked: u64,
+    latest: Option<String>,
+}
+
+fn state_file() -> PathBuf {
+    logs::logs_dir()
+        .parent()
+        .map(|dir| dir.join("update.json"))
+        .unwrap_or_else(|| PathBuf::from("update.json"))
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(5))
+        .timeout_read(Duration::from_secs(60))
+        .build()
+}
+
+/// Tag of the newest release, e.g. "v0.3.0"
+pub fn latest_version() -> Result<String, SlashcmdError> {
+    #[derive(Deserialize)]
+    struct Release {
+        tag_name: String,
+    }
+    let release: Release = agent()
+        .get(&format!("https://api.github.com/repos/{}/releases/latest", REPO))
+        .set("Accept", "application/vnd.github+json")
+        .call()
+        .map_err(SlashcmdError::from_ureq)?
+        .into_json()
+        .map_err(|e| SlashcmdError::Parse(e.to_string()))?;
+    Ok(release.tag_name)
+}
+
+/// Whether release `tag` is newer than this binary
+pub fn is_newer(tag: &str) -> bool {
+    ipc::is_older(VERSION, tag.trim_start_matches('v'))
+}
+
+/// Download release `tag` for this platform, check it and replace the
+/// running executable. Returns the path replaced.
+pub fn install(tag: &str) -> Result<PathBuf, SlashcmdError> {
+    let platform = platform(std::env::consts::OS, std::env::consts::ARCH).ok_or_else(|| {
+        SlashcmdError::Config(format!(
+            "No prebuilt binary for {} {}; build from source: https://github.com/{}",
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+            REPO
+        ))
+    })?;
+    let url = format!(
+        "https://github.com/{}/releases/download/{}/slashcmd-{}",
+        REPO, tag, platform
+    );
+
+    let checksum = agent()
+        .get(&format!("{}.sha256", url))
+        .call()
+        .map_err(SlashcmdError::from_ureq)?
+        .into_string()
+        .map_err(|e| SlashcmdError::Network(e.to_string()))?;
+    let expected = parse_checksum(&checksum)
+        .ok_or_else(|| SlashcmdError::Parse(format!("No SHA-256 checksum in {}.sha256", url)))?;
+
+    let mut binary = Vec::new();
+    agent()
+        .get(&url)
+        .call()
+        .map_err(SlashcmdError::from_ureq)?
+        .into_reader()
+        .take(MAX_BINARY_BYTES)
+        .read_to_end(&mut binary)
+        .map_err(|e| SlashcmdError::Network(e.to_string()))?;
+    let actual: String = Sha256::digest(&binary).iter().map(|b| format!("{:02x}", b)).collect();
+    if actual != expected {
+        return Err(SlashcmdError::Other(format!(
+            "Checksum mismatch for {}: expected {}, got {}. Nothing was replaced.",
+            url, expected, actual
+        )));
+    }
+
+    replace_executable(&binary)
+}
+
+/// Write `binary` beside the running executable and rename it into place
+fn replace_executable(binary: &[u8]) -> Result<PathBuf, SlashcmdError> {
+    let exe = std::env::current_exe()
+        .and_then(|path| path.canonicalize())
+        .map_err(|e| SlashcmdError::Other(format!("Can't locate the running executable: {}", e)))?;
+    let dir = exe
+        .parent()
+        .ok_or_else(|| SlashcmdError::Other(format!("{} has no directory", exe.display())))?;
+    let staged = dir.join(format!(".slashcmd-update-{}", std::process::id()));
+
+    let result = (|| {
+        fs::write(&staged, binary)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+        }
+        fs::rename(&staged, &exe)
+    })();
+    result.map_err(|e| {
+        let _ = fs::remove_file(&staged);
+        SlashcmdError::Other(format!(
+            "Failed to replace {}: {}. If it's in a system directory, update with sudo or reinstall.",
+            exe.display(),
+            e
+        ))
+    })?;
+    Ok(exe)
+}
+
+/// Mention a newer release an earlier check found, and check again in the
+/// background once the last check is a day old
+pub fn notify() {
+    let state: UpdateState = fs::read_to_string(state_file())
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    if let Some(latest) = state.latest.as_deref().filter(|latest| is_newer(latest)) {
+        eprintln!(
+            "{}",
+            highlight::dim(&format!(
+                "slashcmd {} is available (you have {}): slashcmd self-update",
+                latest, VERSION
+            ))
+        );
+    }
+    if logs::now().saturating_sub(state.checked) >= CHECK_INTERVAL_SECS {
+        std::thread::spawn(move || {
+            let state = UpdateState {
+                checked: logs::now(),
+                latest: latest_version().ok().or(state.latest),
+            };
+            if let Ok(json) = serde_json::to_string(&state) {
+                let _ = fs::write(state_file(), json);
+            }
+        });
+    }
+}
+
+/// Release asset suffix install.sh uses for this OS and architecture
+fn platform(os: &str, arch: &str) -> Option<&'static str> {
+    match (os, arch) {
+        ("macos", "aarch64") => Some("darwin-arm64"),
+        ("macos", "x86_64") => Some("darwin-x64"),
+        ("linux", "x86_64") => Some("linux-x64"),
+        ("linux", "aarch64") => Some("linux-arm64"),
+        _ => None,
+    }
+}
+
+/// The hash from `sha256sum` output ("<hash>  <file>") or a bare hash
+fn parse_checksum(text: &str) -> Option<String> {
+    let hash = text.split_whitespace().next()?.to_lowercase();
+    (hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())).then_some(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_platform_and_checksum() {
+        assert_eq!(platform("macos", "aarch64"), Some("darwin-arm64"));
+        assert_eq!(platform("linux", "x86_64"), Some("linux-x64"));
+        assert_eq!(platform("windows", "x86_64"), None);
+
+        let hash = "9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08";
+        assert_eq!(
+            parse_checksum(&format!("{}  slashcmd-linux-x64\n", hash)),
+            Some(hash.to_lowercase())
+        );
+        assert_eq!(parse_checksum(hash), Some(hash.to_lowercase()));
+        assert_eq!(parse_checksum("Not Found"), None);
+        assert_eq!(parse_checksum(""), None);
+
+        assert!(is_newer("v999.0.0"));
+        assert!(!is_newer(&format!("v{}", VERSION)));
+    }
+}
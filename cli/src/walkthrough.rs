@@ -0,0 +1,108 @@
+//! Stage-by-stage walkthrough of a pipeline in the TUI
+//!
+//! Left and Right move a highlight across the stages of `find … | xargs … |
+//! sort …` and the prompt line shows what the highlighted stage does, in
+//! one sentence. Each stage is explained the first time it's highlighted,
+//! in the context of the whole command, and kept: for the session, and in
+//! the explanation cache where there is one.
+
+use std::ops::Range;
+
+use crate::ipc::{CustomStyle, ExplainOptions, ExplainStyle, Verbosity};
+use crate::layout;
+
+/// Byte ranges of the stages in `command`, between top-level `|`, `&&` and
+/// `||`. Fewer than two means there's nothing to walk through.
+pub fn stages(command: &str) -> Vec<Range<usize>> {
+    let bytes = command.as_bytes();
+    let mut stages = Vec::new();
+    let mut start = 0;
+    for at in layout::operators(command).into_iter().chain([command.len()]) {
+        let stage = trimmed(command, start..at);
+        if !stage.is_empty() {
+            stages.push(stage);
+        }
+        // `&&`, `||` and `|&` are two bytes
+        let two = bytes.get(at + 1).is_some_and(|b| matches!(b, b'|' | b'&'));
+        start = (at + if two { 2 } else { 1 }).min(command.len());
+    }
+    stages
+}
+
+/// `range` without surrounding whitespace or a ` \` line continuation
+fn trimmed(command: &str, range: Range<usize>) -> Range<usize> {
+    let text = &command[range.clone()];
+    let mut end = text.trim_end().len();
+    if text[..end].ends_with('\\') {
+        end = text[..end - 1].trim_end().len();
+    }
+    let start = end - text[..end].trim_start().len();
+    range.start + start..range.start + end
+}
+
+/// Options asking for one sentence about `stage`, as part of `command`
+pub fn stage_options(command: &str, stage: &str, options: &ExplainOptions) -> ExplainOptions {
+    let prompt = format!(
+        "This is one stage of the command `{}`. In a single short sentence, say what `{}` does \
+         with its input in that command. Plain prose, no code.",
+        layout::join(command),
+        stage
+    );
+    ExplainOptions {
+        style: ExplainStyle::Custom(CustomStyle {
+            name: "stage".to_string(),
+            prompt,
+            keywords: Vec::new(),
+            line_comment: None,
+        }),
+        verbosity: Verbosity::Terse,
+        lang: options.lang.clone(),
+        generation: options.generation,
+    }
+}
+
+/// The stage explanation from the cache, or from `explain` (then cached)
+#[cfg_attr(not(feature = "gemini"), allow(unused_variables))]
+pub fn explain_stage<F>(stage: &str, options: &ExplainOptions, model: &str, explain: F) -> Result<String, String>
+where
+    F: Fn(&str, &ExplainOptions) -> Result<String, String>,
+{
+    #[cfg(feature = "gemini")]
+    if let Some(cached) = crate::explain_cache::get(stage, options, model) {
+        return Ok(cached);
+    }
+    let sentence = one_line(&explain(stage, options)?);
+    #[cfg(feature = "gemini")]
+    crate::explain_cache::put(stage, options, model, &sentence);
+    Ok(sentence)
+}
+
+/// The first line of prose in an explanation, without its safety label
+fn one_line(explanation: &str) -> String {
+    let line = explanation
+        .lines()
+        .map(|line| line.trim().trim_matches('*').trim())
+        .find(|line| !line.is_empty() && !line.starts_with("```"))
+        .unwrap_or_default();
+    let line = match line.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+        Some((label, rest)) if ["SAFE", "CAUTION", "DANGER"].contains(&label) => rest,
+        _ => line,
+    };
+    line.trim_start_matches('*').trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stages() {
+        let command = "find . -name '*.rs' | xargs wc -l \\\n  | sort -rn && echo 'a | b'";
+        let found: Vec<&str> = stages(command).into_iter().map(|r| &command[r]).collect();
+        assert_eq!(found, ["find . -name '*.rs'", "xargs wc -l", "sort -rn", "echo 'a | b'"]);
+        assert_eq!(stages("ls -la").len(), 1);
+
+        assert_eq!(one_line("**[CAUTION]** Counts lines in each file.\n```ts\nx\n```"), "Counts lines in each file.");
+        assert_eq!(one_line("\nSorts numerically, largest first."), "Sorts numerically, largest first.");
+    }
+}
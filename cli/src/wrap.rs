@@ -0,0 +1,171 @@
+//! Fitting colored text to the terminal width
+//!
+//! The TUI reserves a fixed number of rows for the explanation and moves the
+//! cursor by counting lines, so a line the terminal wraps on its own throws
+//! every count off. Lines are wrapped here first, at spaces where possible,
+//! by display width: ANSI escapes take no columns, CJK and most emoji take
+//! two, combining marks none. Colors carry over to continuation rows.
+
+const RESET: &str = "\x1b[0m";
+
+/// Columns a character takes in a terminal (a small wcwidth)
+fn char_width(c: char) -> usize {
+    let cp = c as u32;
+    match cp {
+        0..=0x1F | 0x7F..=0x9F => 0,
+        // Combining marks, zero-width spaces and joiners, variation selectors
+        0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x200B..=0x200F | 0x20D0..=0x20FF
+        | 0xFE00..=0xFE0F | 0xE0100..=0xE01EF => 0,
+        // East Asian wide and fullwidth
+        0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF | 0xFE30..=0xFE4F | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6 | 0x20000..=0x3FFFD => 2,
+        // Emoji shown in emoji presentation
+        0x231A..=0x231B | 0x23E9..=0x23EC | 0x23F0 | 0x23F3 | 0x25FD..=0x25FE | 0x2614..=0x2615
+        | 0x2648..=0x2653 | 0x267F | 0x2693 | 0x26A1 | 0x26AA..=0x26AB | 0x26BD..=0x26BE
+        | 0x26C4..=0x26C5 | 0x26CE | 0x26D4 | 0x26EA | 0x26F2..=0x26F5 | 0x26FA | 0x26FD | 0x2705
+        | 0x270A..=0x270B | 0x2728 | 0x274C | 0x274E | 0x2753..=0x2755 | 0x2757 | 0x2795..=0x2797
+        | 0x27B0 | 0x27BF | 0x2B1B..=0x2B1C | 0x2B50 | 0x2B55 | 0x1F300..=0x1F64F
+        | 0x1F680..=0x1F6FF | 0x1F900..=0x1F9FF | 0x1FA70..=0x1FAFF => 2,
+        _ => 1,
+    }
+}
+
+/// Consume the rest of an escape sequence after ESC, returning it
+fn skip_escape(chars: &mut std::str::Chars) -> String {
+    let mut seq = String::from('\x1b');
+    if let Some(c) = chars.next() {
+        seq.push(c);
+        if c == '[' {
+            // CSI: parameters, then a final byte in @..~
+            for c in chars.by_ref() {
+                seq.push(c);
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    seq
+}
+
+/// Rows of at most `width` columns for one line. Breaks at the last space
+/// that fits, or mid-word when a word is wider than the row.
+pub fn wrap(line: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut rows = Vec::new();
+    let mut row = String::new();
+    let mut row_width = 0;
+    // SGR sequences in effect, re-applied at the start of each continuation
+    let mut active = String::new();
+    // Byte offset, columns and SGR state at the last space in `row`
+    let mut last_space: Option<(usize, usize, String)> = None;
+
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            let seq = skip_escape(&mut chars);
+            if seq == RESET || seq == "\x1b[m" {
+                active.clear();
+            } else if seq.ends_with('m') {
+                active.push_str(&seq);
+            }
+            row.push_str(&seq);
+            continue;
+        }
+        let w = char_width(c);
+        if row_width + w > width && row_width > 0 {
+            let (rest, rest_width) = match last_space.take() {
+                Some((at, cols, state)) => {
+                    let rest = format!("{}{}", state, &row[at + 1..]);
+                    row.truncate(at);
+                    (rest, row_width - cols - 1)
+                }
+                None => (active.clone(), 0),
+            };
+            if !active.is_empty() {
+                row.push_str(RESET);
+            }
+            rows.push(std::mem::replace(&mut row, rest));
+            row_width = rest_width;
+            // The space a row breaks at isn't carried over
+            if c == ' ' && row_width == 0 {
+                continue;
+            }
+        }
+        if c == ' ' {
+            last_space = Some((row.len(), row_width, active.clone()));
+        }
+        row.push(c);
+        row_width += w;
+    }
+    rows.push(row);
+    rows
+}
+
+/// `text` wrapped to `width` in at most `max_rows` rows. When it doesn't
+/// fit, the last row ends in "…".
+pub fn fit(text: &str, width: usize, max_rows: usize) -> Vec<String> {
+    let mut rows: Vec<String> = text.lines().flat_map(|line| wrap(line, width)).collect();
+    if rows.len() > max_rows && max_rows > 0 {
+        rows.truncate(max_rows);
+        let last = rows.pop().unwrap_or_default();
+        let shortened = wrap(&last, width.saturating_sub(1)).swap_remove(0);
+        rows.push(format!("{}{}…", shortened, RESET));
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Columns `text` takes, ignoring ANSI escapes
+    fn display_width(text: &str) -> usize {
+        let mut width = 0;
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                skip_escape(&mut chars);
+            } else {
+                width += char_width(c);
+            }
+        }
+        width
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("ls -la"), 6);
+        assert_eq!(display_width("\x1b[32m[SAFE]\x1b[0m ok"), 9);
+        assert_eq!(display_width("日本語"), 6);
+        assert_eq!(display_width("⛔ no"), 5);
+        assert_eq!(display_width("e\u{301}"), 1);
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("lists all files here", 10), vec!["lists all", "files here"]);
+        assert_eq!(wrap("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("abcd efgh", 4), vec!["abcd", "efgh"]);
+        assert_eq!(wrap("日本語です", 5), vec!["日本", "語で", "す"]);
+        // Color continues on the next row and is closed on the first
+        assert_eq!(
+            wrap("\x1b[31mred red red\x1b[0m", 8),
+            vec!["\x1b[31mred red\x1b[0m", "\x1b[31mred\x1b[0m"]
+        );
+        for row in wrap("\x1b[1;38;5;81mfind\x1b[0m . -name '*.log' -mtime +30 -delete", 12) {
+            assert!(display_width(&row) <= 12);
+        }
+    }
+
+    #[test]
+    fn test_fit() {
+        let rows = fit("one two three four five six", 10, 2);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], "one two");
+        assert!(rows[1].ends_with('…'));
+        assert!(display_width(&rows[1]) <= 10);
+        assert_eq!(fit("short\nlines", 10, 5), vec!["short", "lines"]);
+    }
+}
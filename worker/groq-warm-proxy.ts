@@ -4,8 +4,18 @@
  * Endpoints:
  * - /ping           - Keep connections warm
  * - /command        - SSE stream: command + explanation
+ * - /followup       - Question about a command's captured output
+ * - /explain        - Explanation for a given command (MCP server mode)
+ * - /share          - Publish a command + explanation, returns a short URL
+ * - /s/:id          - View a shared command
+ * - /snippets       - Team snippet library (GET, PUT with base_version)
+ * - /sync           - End-to-end encrypted history items (GET, POST)
+ * - /billing        - Personalized checkout (free) or billing portal (pro) URL
+ * - /org/join       - Join an organization's team plan (admins invite members first)
+ * - /telemetry      - Opt-in anonymous latency/failure events from the CLI
  * - /auth/start     - Start CLI auth flow
  * - /auth/poll      - Poll for auth completion
+ * - /auth/device    - Enter a device code from any browser (headless login)
  * - /auth/callback  - Clerk redirect callback
  * - /webhook/clerk  - Clerk webhook handler
  * - /v1/*           - Legacy Groq proxy
@@ -13,6 +23,8 @@
 
 export interface Env {
   RATE_LIMITS: KVNamespace;
+  // Telemetry batches and their rate limits, kept apart so they can't crowd out the rest
+  TELEMETRY: KVNamespace;
   JWT_SECRET: string;
   GROQ_API_KEY: string;
   GEMINI_API_KEY: string;
@@ -63,7 +75,7 @@ async function createJWT(payload: object, secret: string): Promise<string> {
 }
 
 // Verify JWT signature and return payload
-async function verifyJWT(token: string, secret: string): Promise<{ sub: string; tier: string } | null> {
+async function verifyJWT(token: string, secret: string): Promise<{ sub: string; org?: string; username?: string } | null> {
   try {
     const [headerB64, payloadB64, sigB64] = token.split('.');
     if (!headerB64 || !payloadB64 || !sigB64) return null;
@@ -91,8 +103,10 @@ async function verifyJWT(token: string, secret: string): Promise<{ sub: string;
 
     const payload = JSON.parse(base64UrlDecode(payloadB64));
     if (payload.exp && payload.exp < Date.now() / 1000) return null;
+    // Single-purpose tokens (billing sessions in browser URLs) are not API credentials
+    if (payload.purpose) return null;
 
-    return { sub: payload.sub, tier: payload.tier || 'free' };
+    return { sub: payload.sub, org: payload.org, username: payload.username };
   } catch {
     return null;
   }
@@ -131,7 +145,15 @@ async function checkUsage(kv: KVNamespace, userId: string, tier: string): Promis
 }
 
 // Increment usage count
-async function incrementUsage(kv: KVNamespace, userId: string, tier: string): Promise<void> {
+async function incrementUsage(kv: KVNamespace, userId: string, tier: string, org?: string): Promise<void> {
+  // Org usage is always tracked so admins can see it centrally
+  if (org) {
+    const orgKey = `orgusage:${org}`;
+    const orgData = await kv.get<UsageData>(orgKey, 'json') || { total: 0 };
+    orgData.total++;
+    await kv.put(orgKey, JSON.stringify(orgData));
+  }
+
   // Don't track pro users (unlimited)
   if (tier === 'pro') return;
 
@@ -141,6 +163,16 @@ async function incrementUsage(kv: KVNamespace, userId: string, tier: string): Pr
   await kv.put(key, JSON.stringify(data));
 }
 
+// Organization record, created and managed by the org's admins
+interface OrgData {
+  name: string;
+  tier: string;
+  seats: number;
+  members: string[];
+  // User ids (e.g. "github:123") an admin has invited; joining uses up the invite
+  invited?: string[];
+}
+
 // Get user's tier from KV (set by webhook)
 async function getUserTier(kv: KVNamespace, userId: string): Promise<string> {
   const tier = await kv.get(`tier:${userId}`);
@@ -152,9 +184,34 @@ async function setUserTier(kv: KVNamespace, userId: string, tier: string): Promi
   await kv.put(`tier:${userId}`, tier);
 }
 
+// Authenticated user. Tier and org membership are read from KV on every
+// request rather than trusted from the token, so upgrades, downgrades and
+// removal from an org apply immediately.
+async function getUser(token: string, env: Env): Promise<{ sub: string; tier: string; org?: string; username?: string } | null> {
+  const claims = await verifyJWT(token, env.JWT_SECRET);
+  if (!claims) return null;
+
+  // Tokens minted by /org/join before membership moved to KV carry the slug
+  const slug = await env.RATE_LIMITS.get(`member:${claims.sub}`) ?? claims.org;
+  if (slug) {
+    const org = await env.RATE_LIMITS.get(`org:${slug}`, 'json') as OrgData | null;
+    if (org?.members.includes(claims.sub)) {
+      return { sub: claims.sub, tier: org.tier, org: slug, username: claims.username };
+    }
+  }
+
+  const tier = await getUserTier(env.RATE_LIMITS, claims.sub);
+  return { sub: claims.sub, tier, username: claims.username };
+}
+
 // ============ Groq Call ============
 
-async function getCommand(query: string, apiKey: string): Promise<{ command: string; safe: boolean }> {
+async function getCommand(query: string, examples: { query: string; command: string }[], apiKey: string): Promise<{ command?: string; safe?: boolean; question?: string; options?: string[] }> {
+  // Opt-in personalization: the CLI sends a few of the user's past queries→commands
+  const history = examples.length
+    ? `\nThis user's recent requests. Prefer the same tools, flags and style when they fit:\n${examples.slice(0, 5).map((e) => `"${e.query}" → ${e.command}`).join('\n')}\n`
+    : '';
+
   const prompt = `You are a macOS CLI assistant. Convert the user's request to a shell command.
 
 User request: "${query}"
@@ -168,7 +225,7 @@ Examples:
 {"command": "rm -rf *.tmp", "safe": false}
 {"command": "git status", "safe": true}
 {"command": "npm install", "safe": false}
-
+${history}
 Respond with ONLY the JSON object, no markdown:`;
 
   const response = await fetch(`${GROQ_BASE}/v1/chat/completions`, {
@@ -198,31 +255,53 @@ Respond with ONLY the JSON object, no markdown:`;
 
 // ============ Gemini Call ============
 
-async function getExplanation(command: string, style: string, apiKey: string): Promise<string> {
+// Detail level per verbosity: sentence count, pseudocode guidance, output budget
+const verbosityLevels: Record<string, { sentences: string; extra: string; maxTokens: number }> = {
+  terse: { sentences: '1 short sentence', extra: 'Keep the pseudocode to 1-3 lines.', maxTokens: 200 },
+  normal: { sentences: '2-3 short sentences', extra: '', maxTokens: 500 },
+  eli5: {
+    sentences: '3-5 beginner-friendly sentences',
+    extra: 'Assume no shell experience: define every flag, operator and concept, and comment each pseudocode step.',
+    maxTokens: 1200,
+  },
+};
+
+async function getExplanation(command: string, style: string, verbosity: string, lang: string | undefined, stylePrompt: string | undefined, apiKey: string): Promise<string> {
   const stylePrompts: Record<string, string> = {
     typescript: 'Use TypeScript-style pseudocode with types',
     python: 'Use Python-style pseudocode',
     ruby: 'Use Ruby-style pseudocode',
+    rust: 'Use Rust-style pseudocode',
+    go: 'Use Go-style pseudocode',
     human: 'Use plain English, no code',
+    annotated: 'Inside a code block, split the command into one segment per line (program, then each flag with its value, operators like | at the end of the line), each followed by " # " and a short explanation. Copy the segments exactly',
+    diagram: 'Inside a code block, list each pipeline stage exactly as written, one per line (operators like | at the end of the line), each followed by " # " and what it outputs',
   };
 
-  const prompt = `Explain this shell command in 2-3 short sentences, then show what it does as pseudocode.
+  const level = verbosityLevels[verbosity] || verbosityLevels.normal;
+  // Localize prose only; safety tags stay in English so clients can parse them
+  const language = lang && lang !== 'en'
+    ? `\nWrite the explanation and pseudocode comments in the language with code "${lang}". Keep the **[SAFE]**/**[CAUTION]**/**[DANGER]** tag, the command and code identifiers in English.`
+    : '';
+
+  const prompt = `Explain this shell command in ${level.sentences}, then show what it does as pseudocode.
+${level.extra}
 
 Command: ${command}
 
 Format:
 1. Start with safety: **[SAFE]** for read-only, **[CAUTION]** for writes/changes, **[DANGER]** for destructive
-2. Brief explanation (2-3 sentences max)
-3. ${stylePrompts[style] || stylePrompts.typescript}
+2. Brief explanation (${level.sentences} max)
+3. ${stylePrompts[style] || stylePrompt || stylePrompts.typescript}
 
-Keep it concise. No markdown headers.`;
+Keep it concise. No markdown headers.${language}`;
 
   const response = await fetch(`${GEMINI_URL}?key=${apiKey}`, {
     method: 'POST',
     headers: { 'Content-Type': 'application/json' },
     body: JSON.stringify({
       contents: [{ parts: [{ text: prompt }] }],
-      generationConfig: { temperature: 0.3, maxOutputTokens: 500 },
+      generationConfig: { temperature: 0.3, maxOutputTokens: level.maxTokens },
     }),
   });
 
@@ -230,6 +309,86 @@ Keep it concise. No markdown headers.`;
   return data.candidates?.[0]?.content?.parts?.[0]?.text || 'Explanation unavailable';
 }
 
+// Follow-up on a command's captured output (summarize / why did it fail)
+async function getFollowUp(
+  body: { command: string; output: string; exit_code: number; question: string },
+  apiKey: string,
+): Promise<string> {
+  const task = body.question === 'why_failed'
+    ? 'Explain why this command failed in 1-3 short sentences, then suggest a fixed command in a ```bash code block if one would help.'
+    : 'Summarize this output in 2-4 short sentences: what it shows and anything notable (errors, warnings, unusually large or small values).';
+
+  const prompt = `A developer ran this shell command.
+
+Command: \`${body.command}\`
+Exit code: ${body.exit_code}
+
+Output (may be truncated to the last part):
+\`\`\`
+${body.output.slice(-16384)}
+\`\`\`
+
+${task}
+Be concise. No markdown headers.`;
+
+  const response = await fetch(`${GEMINI_URL}?key=${apiKey}`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({
+      contents: [{ parts: [{ text: prompt }] }],
+      generationConfig: { temperature: 0.3, maxOutputTokens: 600 },
+    }),
+  });
+
+  const data = await response.json() as any;
+  return data.candidates?.[0]?.content?.parts?.[0]?.text || 'Follow-up unavailable';
+}
+
+// ============ Telemetry ============
+
+const TELEMETRY_MAX_BYTES = 128 * 1024;
+const TELEMETRY_MAX_EVENTS = 500;
+const TELEMETRY_BATCHES_PER_HOUR = 10;
+
+// One CLI event (cli/src/telemetry.rs), copied field by field so nothing else is stored
+interface TelemetryEvent {
+  hour: number;
+  provider: 'edge' | 'groq' | 'daemon';
+  latency_ms: number;
+  success: boolean;
+  os: string;
+  version: string;
+}
+
+function parseTelemetryEvent(value: unknown): TelemetryEvent | null {
+  const e = value as Record<string, unknown> | null;
+  if (typeof e !== 'object' || e === null) return null;
+  const short = (s: unknown) => typeof s === 'string' && s.length > 0 && s.length <= 32;
+  if (!Number.isSafeInteger(e.hour) || (e.hour as number) < 0) return null;
+  if (e.provider !== 'edge' && e.provider !== 'groq' && e.provider !== 'daemon') return null;
+  if (!Number.isSafeInteger(e.latency_ms) || (e.latency_ms as number) < 0) return null;
+  if (typeof e.success !== 'boolean' || !short(e.os) || !short(e.version)) return null;
+  return {
+    hour: e.hour as number,
+    provider: e.provider as TelemetryEvent['provider'],
+    latency_ms: e.latency_ms as number,
+    success: e.success,
+    os: e.os as string,
+    version: e.version as string,
+  };
+}
+
+// Count a batch against both the sender's IP and install id for this hour
+async function telemetryAllowed(kv: KVNamespace, ip: string, installId: string): Promise<boolean> {
+  const hour = Math.floor(Date.now() / 3600_000);
+  for (const key of [`rl:ip:${ip}:${hour}`, `rl:install:${installId}:${hour}`]) {
+    const count = parseInt(await kv.get(key) ?? '0', 10);
+    if (count >= TELEMETRY_BATCHES_PER_HOUR) return false;
+    await kv.put(key, String(count + 1), { expirationTtl: 2 * 3600 });
+  }
+  return true;
+}
+
 // ============ SSE Helper ============
 
 function sseEvent(event: string, data: object): string {
@@ -285,11 +444,45 @@ export default {
         expirationTtl: 600,
       });
 
-      return new Response(JSON.stringify({ session_id: sessionId, auth_url: authUrl }), {
+      // Short code for machines without a browser (no 0/O/1/I to misread)
+      const alphabet = 'ABCDEFGHJKLMNPQRSTUVWXYZ23456789';
+      const random = crypto.getRandomValues(new Uint8Array(8));
+      const chars = Array.from(random, b => alphabet[b % alphabet.length]).join('');
+      const userCode = `${chars.slice(0, 4)}-${chars.slice(4)}`;
+      await env.RATE_LIMITS.put(`device:${userCode}`, sessionId, { expirationTtl: 600 });
+
+      return new Response(JSON.stringify({
+        session_id: sessionId,
+        auth_url: authUrl,
+        user_code: userCode,
+        verification_url: `${url.origin}/auth/device`,
+      }), {
         headers: { 'Content-Type': 'application/json' },
       });
     }
 
+    // ---- AUTH: Device code entry ----
+    if (url.pathname === '/auth/device' && request.method === 'GET') {
+      const code = url.searchParams.get('code');
+      if (!code) {
+        return new Response(`<!doctype html>
+<title>slashcmd login</title>
+<form style="font-family:sans-serif;margin:4em auto;max-width:20em">
+  <p>Enter the code shown in your terminal:</p>
+  <input name="code" autofocus autocomplete="off" placeholder="ABCD-EFGH" style="font-size:1.5em;width:100%">
+  <p><button>Continue</button></p>
+</form>`, { headers: { 'Content-Type': 'text/html; charset=utf-8' } });
+      }
+
+      const normalized = code.toUpperCase().replace(/[^A-Z0-9]/g, '');
+      const userCode = `${normalized.slice(0, 4)}-${normalized.slice(4)}`;
+      const sessionId = await env.RATE_LIMITS.get(`device:${userCode}`);
+      if (!sessionId) {
+        return new Response('Unknown or expired code. Run `slashcmd login` again.', { status: 404 });
+      }
+      return Response.redirect(`${SITE_URL}/cli-auth?session=${sessionId}`, 302);
+    }
+
     // ---- AUTH: Poll for completion ----
     if (url.pathname === '/auth/poll' && request.method === 'GET') {
       const sessionId = url.searchParams.get('session');
@@ -381,7 +574,7 @@ export default {
           status: 401, headers: { 'Content-Type': 'application/json' },
         });
       }
-      const user = await verifyJWT(auth.slice(7), env.JWT_SECRET);
+      const user = await getUser(auth.slice(7), env);
       if (!user) {
         return new Response(JSON.stringify({ error: 'Invalid token', upgrade_url: `${SITE_URL}/upgrade` }), {
           status: 401, headers: { 'Content-Type': 'application/json' },
@@ -402,8 +595,9 @@ export default {
       }
 
       // Get query and style from request body
-      const body = await request.json() as { query: string; style?: string };
-      const { query, style = 'typescript' } = body;
+      // style_prompt carries the instruction for a user-defined (config.toml) style
+      const body = await request.json() as { query: string; style?: string; verbosity?: string; lang?: string; style_prompt?: string; examples?: { query: string; command: string }[] };
+      const { query, style = 'typescript', verbosity = 'normal', lang, style_prompt: stylePrompt, examples = [] } = body;
 
       // Create SSE stream
       const { readable, writable } = new TransformStream();
@@ -420,12 +614,15 @@ export default {
       ctx.waitUntil((async () => {
         try {
           // 1. Get command from Groq (fast)
-          const cmdResult = await getCommand(query, env.GROQ_API_KEY);
+          const cmdResult = await getCommand(query, examples, env.GROQ_API_KEY);
           await writer.write(encoder.encode(sseEvent('command', cmdResult)));
 
-          // 2. Get explanation from Gemini (slower, but streams after command)
-          const explanation = await getExplanation(cmdResult.command, style, env.GEMINI_API_KEY);
-          await writer.write(encoder.encode(sseEvent('explanation', { text: explanation })));
+          // 2. Get explanation from Gemini (slower, but streams after command).
+          // A clarifying question has nothing to explain yet.
+          if (!cmdResult.question) {
+            const explanation = await getExplanation(cmdResult.command ?? '', style, verbosity, lang, stylePrompt, env.GEMINI_API_KEY);
+            await writer.write(encoder.encode(sseEvent('explanation', { text: explanation })));
+          }
 
           // 3. Send usage info
           await writer.write(encoder.encode(sseEvent('usage', {
@@ -444,7 +641,7 @@ export default {
         }
 
         // Increment usage count
-        await incrementUsage(env.RATE_LIMITS, user.sub, user.tier);
+        await incrementUsage(env.RATE_LIMITS, user.sub, user.tier, user.org);
       })());
 
       return new Response(readable, {
@@ -460,6 +657,203 @@ export default {
       });
     }
 
+    // ---- FOLLOWUP: Question about a command's captured output ----
+    if (url.pathname === '/followup' && request.method === 'POST') {
+      const auth = request.headers.get('Authorization');
+      const user = auth?.startsWith('Bearer ') ? await getUser(auth.slice(7), env) : null;
+      if (!user) {
+        return new Response(JSON.stringify({ error: 'Unauthorized', upgrade_url: `${SITE_URL}/upgrade` }), {
+          status: 401, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      const usageInfo = await checkUsage(env.RATE_LIMITS, user.sub, user.tier);
+      if (!usageInfo.allowed) {
+        return new Response(JSON.stringify({
+          error: 'Free tier limit reached',
+          usage: usageInfo.usage,
+          limit: usageInfo.limit,
+          upgrade_url: `${SITE_URL}/upgrade`,
+        }), {
+          status: 429, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      const body = await request.json() as { command: string; output: string; exit_code: number; question: string };
+      const text = await getFollowUp(body, env.GEMINI_API_KEY);
+      ctx.waitUntil(incrementUsage(env.RATE_LIMITS, user.sub, user.tier, user.org));
+
+      return new Response(JSON.stringify({ text }), {
+        headers: { 'Content-Type': 'application/json' },
+      });
+    }
+
+    // ---- EXPLAIN: Explanation for a command the client already has ----
+    if (url.pathname === '/explain' && request.method === 'POST') {
+      const auth = request.headers.get('Authorization');
+      const user = auth?.startsWith('Bearer ') ? await getUser(auth.slice(7), env) : null;
+      if (!user) {
+        return new Response(JSON.stringify({ error: 'Unauthorized', upgrade_url: `${SITE_URL}/upgrade` }), {
+          status: 401, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      const usageInfo = await checkUsage(env.RATE_LIMITS, user.sub, user.tier);
+      if (!usageInfo.allowed) {
+        return new Response(JSON.stringify({
+          error: 'Free tier limit reached',
+          usage: usageInfo.usage,
+          limit: usageInfo.limit,
+          upgrade_url: `${SITE_URL}/upgrade`,
+        }), {
+          status: 429, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      const body = await request.json() as { command: string; style?: string; verbosity?: string; lang?: string; style_prompt?: string };
+      const { command, style = 'typescript', verbosity = 'normal', lang, style_prompt: stylePrompt } = body;
+      const text = await getExplanation(command, style, verbosity, lang, stylePrompt, env.GEMINI_API_KEY);
+      ctx.waitUntil(incrementUsage(env.RATE_LIMITS, user.sub, user.tier, user.org));
+
+      return new Response(JSON.stringify({ text }), {
+        headers: { 'Content-Type': 'application/json' },
+      });
+    }
+
+    // ---- SHARE: Publish a command + explanation ----
+    if (url.pathname === '/share' && request.method === 'POST') {
+      const auth = request.headers.get('Authorization');
+      const user = auth?.startsWith('Bearer ') ? await getUser(auth.slice(7), env) : null;
+      if (!user) {
+        return new Response(JSON.stringify({ error: 'Unauthorized' }), {
+          status: 401, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      const body = await request.json() as { query: string; command: string; explanation?: string };
+      if (!body.command || body.command.length > 4000 || (body.explanation?.length ?? 0) > 20000) {
+        return new Response(JSON.stringify({ error: 'Invalid share' }), {
+          status: 400, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      const id = crypto.randomUUID().replace(/-/g, '').slice(0, 10);
+      await env.RATE_LIMITS.put(`share:${id}`, JSON.stringify({
+        query: body.query,
+        command: body.command,
+        explanation: body.explanation ?? null,
+        created: Date.now(),
+      }), { expirationTtl: 60 * 60 * 24 * 90 });
+
+      return new Response(JSON.stringify({ url: `${url.origin}/s/${id}` }), {
+        headers: { 'Content-Type': 'application/json' },
+      });
+    }
+
+    // ---- SHARE: View a shared command ----
+    if (url.pathname.startsWith('/s/') && request.method === 'GET') {
+      const id = url.pathname.slice(3);
+      const shared = await env.RATE_LIMITS.get(`share:${id}`, 'json') as {
+        query: string;
+        command: string;
+        explanation: string | null;
+      } | null;
+      if (!shared) {
+        return new Response('Not found', { status: 404 });
+      }
+
+      const text = [
+        `# ${shared.query}`,
+        '',
+        shared.command,
+        '',
+        shared.explanation ?? '',
+        '',
+        `Generated with slashcmd - ${SITE_URL}`,
+      ].join('\n');
+      return new Response(text, {
+        headers: { 'Content-Type': 'text/plain; charset=utf-8' },
+      });
+    }
+
+    // ---- SNIPPETS: Team-shared snippet library (Pro) ----
+    if (url.pathname === '/snippets' && (request.method === 'GET' || request.method === 'PUT')) {
+      const auth = request.headers.get('Authorization');
+      const user = auth?.startsWith('Bearer ') ? await getUser(auth.slice(7), env) : null;
+      if (!user) {
+        return new Response(JSON.stringify({ error: 'Unauthorized' }), {
+          status: 401, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+      if (user.tier !== 'pro') {
+        return new Response(JSON.stringify({ error: 'Team snippets require Pro', upgrade_url: `${SITE_URL}/upgrade` }), {
+          status: 403, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      // Team membership is assigned by an admin; without one the library is personal
+      const team = user.org ? `org:${user.org}` : await env.RATE_LIMITS.get(`team:${user.sub}`) || user.sub;
+      const key = `snippets:${team}`;
+      const library = await env.RATE_LIMITS.get(key, 'json') as { snippets: Record<string, unknown>; version: number } | null
+        ?? { snippets: {}, version: 0 };
+
+      if (request.method === 'GET') {
+        return new Response(JSON.stringify(library), {
+          headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      const body = await request.json() as { snippets: Record<string, unknown>; base_version: number };
+      // Optimistic concurrency: the client merges and retries on conflict
+      if (body.base_version !== library.version) {
+        return new Response(JSON.stringify(library), {
+          status: 409, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+      const version = library.version + 1;
+      await env.RATE_LIMITS.put(key, JSON.stringify({ snippets: body.snippets, version }));
+      return new Response(JSON.stringify({ version }), {
+        headers: { 'Content-Type': 'application/json' },
+      });
+    }
+
+    // ---- SYNC: Encrypted history blobs (the key never leaves the client) ----
+    if (url.pathname === '/sync' && (request.method === 'GET' || request.method === 'POST')) {
+      const auth = request.headers.get('Authorization');
+      const user = auth?.startsWith('Bearer ') ? await getUser(auth.slice(7), env) : null;
+      if (!user) {
+        return new Response(JSON.stringify({ error: 'Unauthorized' }), {
+          status: 401, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      const key = `sync:${user.sub}`;
+      const stored = await env.RATE_LIMITS.get(key, 'json') as Record<string, string> | null ?? {};
+
+      if (request.method === 'GET') {
+        const items = Object.entries(stored).map(([id, blob]) => ({ id, blob }));
+        return new Response(JSON.stringify({ items }), {
+          headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      const body = await request.json() as { items: Array<{ id: string; blob: string }> };
+      for (const item of body.items) {
+        // Ids are content hashes, so an existing id is the same entry
+        if (!(item.id in stored)) stored[item.id] = item.blob;
+      }
+      const json = JSON.stringify(stored);
+      if (json.length > 20 * 1024 * 1024) {
+        return new Response(JSON.stringify({ error: 'Sync storage full' }), {
+          status: 413, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+      await env.RATE_LIMITS.put(key, json);
+      return new Response(JSON.stringify({ stored: Object.keys(stored).length }), {
+        headers: { 'Content-Type': 'application/json' },
+      });
+    }
+
     // ---- WEBHOOK: Clerk events ----
     if (url.pathname === '/webhook/clerk' && request.method === 'POST') {
       // Verify webhook signature (Svix)
@@ -523,7 +917,7 @@ export default {
         });
       }
 
-      const user = await verifyJWT(auth.slice(7), env.JWT_SECRET);
+      const user = await getUser(auth.slice(7), env);
       if (!user) {
         return new Response(JSON.stringify({ error: 'Invalid token' }), {
           status: 401, headers: { 'Content-Type': 'application/json' },
@@ -531,13 +925,130 @@ export default {
       }
 
       const usageInfo = await checkUsage(env.RATE_LIMITS, user.sub, user.tier);
+      const org = user.org ? await env.RATE_LIMITS.get(`org:${user.org}`, 'json') as OrgData | null : null;
 
       return new Response(JSON.stringify({
         user: user.sub,
+        username: user.username,
         tier: user.tier,
         usage: usageInfo.usage,
         limit: usageInfo.limit,
         remaining: user.tier === 'pro' ? -1 : usageInfo.limit - usageInfo.usage,
+        org: org && {
+          slug: user.org,
+          name: org.name,
+          tier: org.tier,
+          seats: org.seats,
+          used: org.members.length,
+        },
+      }), {
+        headers: { 'Content-Type': 'application/json' },
+      });
+    }
+
+    // ---- TELEMETRY: Anonymous, opt-in event batches ----
+    if (url.pathname === '/telemetry' && request.method === 'POST') {
+      const declared = Number(request.headers.get('Content-Length') ?? 0);
+      const text = declared > TELEMETRY_MAX_BYTES ? '' : await request.text();
+      if (declared > TELEMETRY_MAX_BYTES || text.length > TELEMETRY_MAX_BYTES) {
+        return new Response(JSON.stringify({ error: 'Batch too large' }), {
+          status: 413, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      let body: { install_id?: unknown; events?: unknown };
+      try {
+        body = JSON.parse(text);
+      } catch {
+        body = {};
+      }
+      const installId = typeof body.install_id === 'string' && /^[0-9a-f]{32}$/.test(body.install_id) ? body.install_id : null;
+      const events = Array.isArray(body.events) && body.events.length <= TELEMETRY_MAX_EVENTS
+        ? body.events.map(parseTelemetryEvent)
+        : [];
+      if (!installId || events.length === 0 || events.some((e) => e === null)) {
+        return new Response(JSON.stringify({ error: 'Invalid batch' }), {
+          status: 400, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      const ip = request.headers.get('CF-Connecting-IP') || 'unknown';
+      if (!await telemetryAllowed(env.TELEMETRY, ip, installId)) {
+        return new Response(JSON.stringify({ error: 'Too many batches' }), {
+          status: 429, headers: { 'Content-Type': 'application/json', 'Retry-After': '3600' },
+        });
+      }
+
+      const day = new Date().toISOString().slice(0, 10);
+      await env.TELEMETRY.put(`telemetry:${day}:${crypto.randomUUID()}`, JSON.stringify({ install_id: installId, events }), {
+        expirationTtl: 60 * 60 * 24 * 90,
+      });
+      return new Response(JSON.stringify({ ok: true }), {
+        headers: { 'Content-Type': 'application/json' },
+      });
+    }
+
+    // ---- BILLING: Personalized checkout / portal link ----
+    if (url.pathname === '/billing' && request.method === 'GET') {
+      const auth = request.headers.get('Authorization');
+      const user = auth?.startsWith('Bearer ') ? await getUser(auth.slice(7), env) : null;
+      if (!user) {
+        return new Response(JSON.stringify({ error: 'Unauthorized' }), {
+          status: 401, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      // Short-lived so a leaked link (shell history, screenshots) is useless
+      const session = await createJWT({
+        sub: user.sub,
+        purpose: 'billing',
+        exp: Math.floor(Date.now() / 1000) + 10 * 60,
+      }, env.JWT_SECRET);
+      const tier = await getUserTier(env.RATE_LIMITS, user.sub);
+      const page = tier === 'pro' ? 'billing' : 'upgrade';
+
+      return new Response(JSON.stringify({ url: `${SITE_URL}/${page}?session=${session}`, tier }), {
+        headers: { 'Content-Type': 'application/json' },
+      });
+    }
+
+    // ---- ORG: Join a team plan ----
+    if (url.pathname === '/org/join' && request.method === 'POST') {
+      const auth = request.headers.get('Authorization');
+      const user = auth?.startsWith('Bearer ') ? await getUser(auth.slice(7), env) : null;
+      if (!user) {
+        return new Response(JSON.stringify({ error: 'Unauthorized' }), {
+          status: 401, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+
+      const { org: slug } = await request.json() as { org: string };
+      const org = await env.RATE_LIMITS.get(`org:${slug}`, 'json') as OrgData | null;
+      if (!org) {
+        return new Response(JSON.stringify({ error: `Unknown organization '${slug}'` }), {
+          status: 404, headers: { 'Content-Type': 'application/json' },
+        });
+      }
+      if (!org.members.includes(user.sub)) {
+        if (!org.invited?.includes(user.sub)) {
+          return new Response(JSON.stringify({ error: `Not invited to '${slug}'; ask an admin to invite ${user.sub}` }), {
+            status: 403, headers: { 'Content-Type': 'application/json' },
+          });
+        }
+        if (org.members.length >= org.seats) {
+          return new Response(JSON.stringify({ error: `No seats left in '${slug}'; ask an admin to add more` }), {
+            status: 403, headers: { 'Content-Type': 'application/json' },
+          });
+        }
+        org.members.push(user.sub);
+        org.invited = org.invited.filter((id) => id !== user.sub);
+        await env.RATE_LIMITS.put(`org:${slug}`, JSON.stringify(org));
+      }
+      // No org token: getUser checks this membership on every request
+      await env.RATE_LIMITS.put(`member:${user.sub}`, slug);
+
+      return new Response(JSON.stringify({
+        org: { slug, name: org.name, tier: org.tier, seats: org.seats, used: org.members.length },
       }), {
         headers: { 'Content-Type': 'application/json' },
       });
@@ -549,7 +1060,7 @@ export default {
       if (!auth?.startsWith('Bearer ')) {
         return new Response('Unauthorized', { status: 401 });
       }
-      const user = await verifyJWT(auth.slice(7), env.JWT_SECRET);
+      const user = await getUser(auth.slice(7), env);
       if (!user) {
         return new Response('Invalid token', { status: 401 });
       }
@@ -571,7 +1082,7 @@ export default {
         body: request.body,
       });
 
-      ctx.waitUntil(incrementUsage(env.RATE_LIMITS, user.sub, user.tier));
+      ctx.waitUntil(incrementUsage(env.RATE_LIMITS, user.sub, user.tier, user.org));
 
       return new Response(groqResponse.body, {
         status: groqResponse.status,